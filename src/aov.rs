@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{lpe::LightPathExpression, path::Contribution, post, spectrum::Spectrum};
+
+// Arbitrary output variables: additional image layers that each receive the
+// subset of path contributions they accept. Path lengths count vertices, as
+// with --max-path-length, so the direct layer holds emitters seen from the
+// camera, of length 2, and surfaces lit by them, of length 3, and the
+// indirect layer holds every longer path.
+#[derive(Debug)]
+pub enum Aov {
+    Direct,
+    Indirect,
+    Lpe(String, LightPathExpression),
+    PathLength(usize),
+    Acceptance,
+    SampleCount,
+}
+
+impl Aov {
+    pub fn configure(config: &AovConfig) -> Aov {
+        match config {
+            AovConfig::Direct => Aov::Direct,
+            AovConfig::Indirect => Aov::Indirect,
+            AovConfig::Lpe(config) => Aov::Lpe(config.name.clone(), config.expression.clone()),
+            AovConfig::Acceptance => Aov::Acceptance,
+            AovConfig::SampleCount => Aov::SampleCount,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Aov::Direct => String::from("direct"),
+            Aov::Indirect => String::from("indirect"),
+            Aov::Lpe(name, _) => name.clone(),
+            Aov::PathLength(path_length) => format!("length{}", path_length),
+            Aov::Acceptance => String::from("acceptance"),
+            Aov::SampleCount => String::from("samples"),
+        }
+    }
+
+    pub fn accepts(&self, contribution: &Contribution) -> bool {
+        match self {
+            Aov::Direct => contribution.path_length <= 3,
+            Aov::Indirect => contribution.path_length > 3,
+            Aov::Lpe(_, expression) => expression.matches(&contribution.labels),
+            Aov::PathLength(path_length) => contribution.path_length == *path_length,
+            Aov::Acceptance | Aov::SampleCount => false,
+        }
+    }
+
+    // Statistics are recorded directly by the integrator rather than splatted,
+    // and are resolved into display values without scaling or tone mapping.
+    pub fn is_statistic(&self) -> bool {
+        matches!(self, Aov::Acceptance | Aov::SampleCount)
+    }
+
+    pub fn resolve(&self, value: Spectrum) -> Spectrum {
+        match self {
+            // Accepted mutations are counted in r and all mutations in g.
+            Aov::Acceptance if value.g > 0.0 => post::heat_map(value.r / value.g),
+            // The count itself, which HDR formats hold exactly.
+            Aov::SampleCount => value,
+            _ => Spectrum::black(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum AovConfig {
+    Direct,
+    Indirect,
+    Lpe(LpeAovConfig),
+    Acceptance,
+    SampleCount,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LpeAovConfig {
+    pub name: String,
+    pub expression: LightPathExpression,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Aov;
+    use crate::{path::Contribution, post, spectrum::Spectrum, vector::Point2};
+
+    #[test]
+    fn test_direct_indirect() {
+        let contribution = |path_length| {
+            Contribution::new(Spectrum::fill(1.0), Point2::new(0.0, 0.0), path_length)
+        };
+        assert!(Aov::Direct.accepts(&contribution(2)));
+        assert!(Aov::Direct.accepts(&contribution(3)));
+        assert!(!Aov::Direct.accepts(&contribution(4)));
+        assert!(!Aov::Indirect.accepts(&contribution(2)));
+        assert!(!Aov::Indirect.accepts(&contribution(3)));
+        assert!(Aov::Indirect.accepts(&contribution(4)));
+        assert!(Aov::PathLength(4).accepts(&contribution(4)));
+        assert!(!Aov::PathLength(4).accepts(&contribution(5)));
+    }
+
+    #[test]
+    fn test_acceptance_resolve() {
+        let rate = |accepted, total| Spectrum {
+            r: accepted,
+            g: total,
+            b: 0.0,
+        };
+        assert_eq!(Aov::Acceptance.resolve(rate(0.0, 0.0)), Spectrum::black());
+        assert_eq!(Aov::Acceptance.resolve(rate(0.0, 4.0)), post::heat_map(0.0));
+        assert_eq!(Aov::Acceptance.resolve(rate(4.0, 4.0)), post::heat_map(1.0));
+    }
+}
@@ -0,0 +1,119 @@
+use std::fs::File;
+
+use crate::{pdf::Pdf, sampler::Sampler, types::Float};
+
+// The transmission of a lens aperture given by a grayscale image, which is
+// stretched over the square circumscribing the lens. Lens positions are
+// sampled in proportion to the transmission, which shapes the bokeh of
+// out-of-focus highlights after it. Positions are in units of the lens
+// radius, with y pointing up.
+#[derive(Debug)]
+pub struct ApertureImage {
+    width: usize,
+    height: usize,
+    rows: Pdf,
+    columns: Vec<Pdf>,
+    row_weights: Vec<Float>,
+    values: Vec<Float>,
+    total: Float,
+}
+
+impl ApertureImage {
+    pub fn load(path: &str) -> Result<ApertureImage, String> {
+        let m = |e: png::DecodingError| format!("{}: {}", path, e);
+        let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder.read_info().map_err(m)?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(m)?;
+        let samples: Vec<Float> = match info.bit_depth {
+            png::BitDepth::Sixteen => buffer[..info.buffer_size()]
+                .chunks_exact(2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]) as Float / 65535.0)
+                .collect(),
+            _ => buffer[..info.buffer_size()]
+                .iter()
+                .map(|b| *b as Float / 255.0)
+                .collect(),
+        };
+        // Only the first channel is read, which is the gray level of a
+        // grayscale image and the red level of a color one.
+        let channels = info.color_type.samples();
+        let values = samples.iter().step_by(channels).copied().collect();
+        ApertureImage::new(info.width as usize, info.height as usize, values)
+    }
+
+    pub fn new(width: usize, height: usize, values: Vec<Float>) -> Result<ApertureImage, String> {
+        let row_weights: Vec<Float> = values.chunks(width).map(|row| row.iter().sum()).collect();
+        let total: Float = row_weights.iter().sum();
+        if total <= 0.0 {
+            return Err(String::from("the aperture image is completely opaque"));
+        }
+        let columns = values
+            .chunks(width)
+            .zip(&row_weights)
+            .map(|(row, weight)| {
+                if *weight > 0.0 {
+                    Pdf::new(row)
+                } else {
+                    Pdf::new(&vec![1.0; width])
+                }
+            })
+            .collect();
+        Ok(ApertureImage {
+            width,
+            height,
+            rows: Pdf::new(&row_weights),
+            columns,
+            row_weights,
+            values,
+            total,
+        })
+    }
+
+    pub fn sample(&self, sampler: &mut dyn Sampler) -> (Float, Float) {
+        let (row, y) = self.rows.invert(sampler.sample(0.0..1.0));
+        let (column, x) = self.columns[row].invert(sampler.sample(0.0..1.0));
+        let x = (column as Float + x) / self.width as Float;
+        let y = (row as Float + y) / self.height as Float;
+        (2.0 * x - 1.0, 1.0 - 2.0 * y)
+    }
+
+    // The density of the sampled positions, per unit area.
+    pub fn density(&self, x: Float, y: Float) -> Float {
+        if !(-1.0..1.0).contains(&x) || !(-1.0..1.0).contains(&y) {
+            return 0.0;
+        }
+        let column = (((x + 1.0) / 2.0) * self.width as Float) as usize;
+        let row = (((1.0 - y) / 2.0) * self.height as Float) as usize;
+        let (column, row) = (column.min(self.width - 1), row.min(self.height - 1));
+        if self.row_weights[row] <= 0.0 {
+            return 0.0;
+        }
+        // The square has an area of 4 and holds width * height texels.
+        let texel_area = 4.0 / (self.width * self.height) as Float;
+        self.values[row * self.width + column] / (self.total * texel_area)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ApertureImage;
+    use crate::sampler::test::MockSampler;
+
+    #[test]
+    fn test_aperture_image() {
+        // Transmits only through the top right quadrant.
+        let aperture = ApertureImage::new(2, 2, vec![0.0, 1.0, 0.0, 0.0]).unwrap();
+        assert_eq!(aperture.density(0.5, 0.5), 1.0);
+        assert_eq!(aperture.density(-0.5, 0.5), 0.0);
+        assert_eq!(aperture.density(0.5, -0.5), 0.0);
+        let mut sampler = MockSampler::new();
+        sampler.add(0.5);
+        sampler.add(0.5);
+        let (x, y) = aperture.sample(&mut sampler);
+        assert!((x - 0.5).abs() < 1e-12 && (y - 0.5).abs() < 1e-12);
+        assert!(ApertureImage::new(1, 1, vec![0.0]).is_err());
+    }
+}
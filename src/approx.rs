@@ -1,3 +1,23 @@
+use crate::types::Float;
+
 pub trait ApproxEq {
-    fn approx_eq(&self, other: Self, tolerance: f64) -> bool;
+    fn approx_eq(&self, other: Self, tolerance: Float) -> bool;
 }
+
+// Loosens the tolerance of a test written for double precision to what single
+// precision can resolve when the math core uses it.
+#[cfg(all(test, not(feature = "f32")))]
+pub fn loosen(double: f64) -> Float {
+    double
+}
+#[cfg(all(test, feature = "f32"))]
+pub fn loosen(double: f64) -> Float {
+    double.max(1e-3) as Float
+}
+
+// The step of the finite differences of tests, coarser in single precision,
+// which cannot resolve a small step of a value near one.
+#[cfg(all(test, not(feature = "f32")))]
+pub const STEP: Float = 1e-6;
+#[cfg(all(test, feature = "f32"))]
+pub const STEP: Float = 1e-3;
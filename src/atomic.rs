@@ -0,0 +1,62 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug)]
+pub struct AtomicF64 {
+    bits: AtomicU64,
+}
+
+impl AtomicF64 {
+    pub fn new(value: f64) -> AtomicF64 {
+        AtomicF64 {
+            bits: AtomicU64::new(value.to_bits()),
+        }
+    }
+
+    pub fn load(&self) -> f64 {
+        f64::from_bits(self.bits.load(Ordering::Relaxed))
+    }
+
+    pub fn add(&self, value: f64) {
+        let mut current = self.bits.load(Ordering::Relaxed);
+        loop {
+            let next = (f64::from_bits(current) + value).to_bits();
+            match self.bits.compare_exchange_weak(
+                current,
+                next,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::AtomicF64;
+
+    #[test]
+    fn test_atomic_f64_load() {
+        let a = AtomicF64::new(1.5);
+        assert_eq!(a.load(), 1.5);
+    }
+
+    #[test]
+    fn test_atomic_f64_add() {
+        let a = AtomicF64::new(0.0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        a.add(0.5);
+                    }
+                });
+            }
+        });
+        assert_eq!(a.load(), 2000.0);
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{
+    ray::Ray,
+    types::Float,
+    vector::{Point3, Vector3},
+};
+
+// An axis-aligned bounding box. The empty box has its minimum above its
+// maximum, so that it is the identity of `union`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Bounds3 {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Bounds3 {
+    pub fn new(min: Point3, max: Point3) -> Bounds3 {
+        Bounds3 { min, max }
+    }
+
+    pub fn empty() -> Bounds3 {
+        Bounds3 {
+            min: Point3::new(Float::INFINITY, Float::INFINITY, Float::INFINITY),
+            max: Point3::new(
+                Float::NEG_INFINITY,
+                Float::NEG_INFINITY,
+                Float::NEG_INFINITY,
+            ),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn union(&self, other: Bounds3) -> Bounds3 {
+        Bounds3 {
+            min: Point3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: Point3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    pub fn center(&self) -> Point3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn diagonal(&self) -> Vector3 {
+        self.max - self.min
+    }
+
+    // The radius of the sphere about the center that encloses the box.
+    pub fn radius(&self) -> Float {
+        self.diagonal().len() * 0.5
+    }
+
+    pub fn surface_area(&self) -> Float {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let d = self.diagonal();
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    // Whether the ray passes through the box within its interval, by the
+    // slab test, given the reciprocal of its direction.
+    pub fn hit(&self, ray: &Ray, inverse_direction: Vector3) -> bool {
+        let mut t_min = ray.t_min;
+        let mut t_max = ray.t_max;
+        let axes = [
+            (self.min.x, self.max.x, ray.origin.x, inverse_direction.x),
+            (self.min.y, self.max.y, ray.origin.y, inverse_direction.y),
+            (self.min.z, self.max.z, ray.origin.z, inverse_direction.z),
+        ];
+        for (min, max, origin, inverse) in axes {
+            // A ray parallel to the slab is within it everywhere or nowhere.
+            if inverse.is_infinite() {
+                if origin < min || origin > max {
+                    return false;
+                }
+                continue;
+            }
+            let t0 = (min - origin) * inverse;
+            let t1 = (max - origin) * inverse;
+            let (near, far) = if t0 <= t1 { (t0, t1) } else { (t1, t0) };
+            if near > t_min {
+                t_min = near;
+            }
+            if far < t_max {
+                t_max = far;
+            }
+            if t_min > t_max {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bounds3;
+    use crate::{
+        ray::Ray,
+        vector::{Point3, Vector3},
+    };
+
+    #[test]
+    fn test_bounds_union() {
+        let a = Bounds3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 2.0, 3.0));
+        let b = Bounds3::new(Point3::new(-1.0, 1.0, 1.0), Point3::new(0.5, 4.0, 2.0));
+        let c = a.union(b);
+        assert_eq!(c.min, Point3::new(-1.0, 0.0, 0.0));
+        assert_eq!(c.max, Point3::new(1.0, 4.0, 3.0));
+        assert_eq!(c.center(), Point3::new(0.0, 2.0, 1.5));
+        assert_eq!(Bounds3::empty().union(a), a);
+        assert!(Bounds3::empty().is_empty());
+        assert!(!a.is_empty());
+    }
+
+    #[test]
+    fn test_bounds_hit() {
+        let bounds = Bounds3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 2.0, 3.0));
+        assert_eq!(bounds.surface_area(), 22.0);
+        assert_eq!(Bounds3::empty().surface_area(), 0.0);
+        let inverse = |d: Vector3| Vector3::new(1.0 / d.x, 1.0 / d.y, 1.0 / d.z);
+        let direction = Vector3::new(1.0, 0.0, 0.0);
+        let mut ray = Ray::new(Point3::new(-1.0, 1.0, 1.0), direction);
+        assert!(bounds.hit(&ray, inverse(direction)));
+        ray.t_max = 0.5;
+        assert!(!bounds.hit(&ray, inverse(direction)));
+        let ray = Ray::new(Point3::new(-1.0, 3.0, 1.0), direction);
+        assert!(!bounds.hit(&ray, inverse(direction)));
+        // Along a side of the box.
+        let ray = Ray::new(Point3::new(-1.0, 0.0, 1.0), direction);
+        assert!(bounds.hit(&ray, inverse(direction)));
+    }
+}
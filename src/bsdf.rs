@@ -1,92 +1,346 @@
-use std::{f64::consts::PI, fmt};
+use std::fmt;
+
+use crate::types::consts::PI;
 
 use crate::{
     approx::ApproxEq,
+    pdf::PdfValue,
+    polarization::Mueller,
+    profile::{self, Subsystem},
     sampler::Sampler,
     spectrum::Spectrum,
-    types::PathType,
+    types::{Float, PathType},
     util::{self},
     vector::Vector3,
 };
 
 #[derive(Debug)]
 pub struct Bsdf {
-    pub bxdfs: Vec<Box<dyn Bxdf>>,
+    pub bxdfs: Vec<AnyBxdf>,
 }
 
-pub trait Bxdf: fmt::Debug {
+pub trait Bxdf: fmt::Debug + Send + Sync {
     fn evaluate(&self, wo: Vector3, wi: Vector3, context: EvaluationContext) -> Spectrum;
-    fn sampling_pdf(&self, wo: Vector3, wi: Vector3, path_type: PathType) -> Option<f64>;
-    fn pdf(&self, wo: Vector3, wi: Vector3, path_type: PathType) -> Option<f64>;
+    fn sampling_pdf(&self, wo: Vector3, wi: Vector3, path_type: PathType) -> Option<Float>;
+    fn pdf(&self, wo: Vector3, wi: Vector3, path_type: PathType) -> PdfValue;
     fn sample_direction(
         &self,
         wx: Vector3,
         path_type: PathType,
         sampler: &mut dyn Sampler,
     ) -> Option<Vector3>;
+    fn scattering(&self) -> Scattering;
+    // The width of the lobe on the scale of the GGX alpha, from zero for a
+    // specular lobe to one for a diffuse one.
+    fn roughness(&self) -> Float;
+    // The direction of specular transmission, for lobes that have one.
+    fn transmit(&self, wx: Vector3) -> Option<Vector3>;
+    // A rough lobe in place of a lobe narrower than the given roughness.
+    fn regularize(&self, alpha: Float) -> Option<AnyBxdf>;
+    // The Mueller matrices of each channel for a specular lobe that keeps
+    // the polarization of light scattered between the directions. Other
+    // lobes scatter light diffusely enough to leave it unpolarized.
+    fn mueller(&self, wo: Vector3, wi: Vector3) -> Option<[Mueller; 3]>;
+}
+
+// The lobes, dispatched statically on the hot path of shading. A new lobe
+// implements `Bxdf` and joins the enum, or, until it is worth a variant of
+// its own, is boxed as `Custom` and dispatched dynamically.
+#[derive(Debug)]
+pub enum AnyBxdf {
+    Diffuse(DiffuseBrdf),
+    Specular(SpecularBrdf),
+    Microfacet(MicrofacetBrdf),
+    Sheen(SheenBrdf),
+    Conductor(ConductorBrdf),
+    Dielectric(DielectricBxdf),
+    RoughDielectric(RoughDielectricBxdf),
+    #[allow(dead_code)]
+    Custom(Box<dyn Bxdf>),
+}
+
+impl Bxdf for AnyBxdf {
+    fn evaluate(&self, wo: Vector3, wi: Vector3, context: EvaluationContext) -> Spectrum {
+        match self {
+            AnyBxdf::Diffuse(bxdf) => bxdf.evaluate(wo, wi, context),
+            AnyBxdf::Specular(bxdf) => bxdf.evaluate(wo, wi, context),
+            AnyBxdf::Microfacet(bxdf) => bxdf.evaluate(wo, wi, context),
+            AnyBxdf::Sheen(bxdf) => bxdf.evaluate(wo, wi, context),
+            AnyBxdf::Conductor(bxdf) => bxdf.evaluate(wo, wi, context),
+            AnyBxdf::Dielectric(bxdf) => bxdf.evaluate(wo, wi, context),
+            AnyBxdf::RoughDielectric(bxdf) => bxdf.evaluate(wo, wi, context),
+            AnyBxdf::Custom(bxdf) => bxdf.evaluate(wo, wi, context),
+        }
+    }
+
+    fn sampling_pdf(&self, wo: Vector3, wi: Vector3, path_type: PathType) -> Option<Float> {
+        match self {
+            AnyBxdf::Diffuse(bxdf) => bxdf.sampling_pdf(wo, wi, path_type),
+            AnyBxdf::Specular(bxdf) => bxdf.sampling_pdf(wo, wi, path_type),
+            AnyBxdf::Microfacet(bxdf) => bxdf.sampling_pdf(wo, wi, path_type),
+            AnyBxdf::Sheen(bxdf) => bxdf.sampling_pdf(wo, wi, path_type),
+            AnyBxdf::Conductor(bxdf) => bxdf.sampling_pdf(wo, wi, path_type),
+            AnyBxdf::Dielectric(bxdf) => bxdf.sampling_pdf(wo, wi, path_type),
+            AnyBxdf::RoughDielectric(bxdf) => bxdf.sampling_pdf(wo, wi, path_type),
+            AnyBxdf::Custom(bxdf) => bxdf.sampling_pdf(wo, wi, path_type),
+        }
+    }
+
+    fn pdf(&self, wo: Vector3, wi: Vector3, path_type: PathType) -> PdfValue {
+        match self {
+            AnyBxdf::Diffuse(bxdf) => bxdf.pdf(wo, wi, path_type),
+            AnyBxdf::Specular(bxdf) => bxdf.pdf(wo, wi, path_type),
+            AnyBxdf::Microfacet(bxdf) => bxdf.pdf(wo, wi, path_type),
+            AnyBxdf::Sheen(bxdf) => bxdf.pdf(wo, wi, path_type),
+            AnyBxdf::Conductor(bxdf) => bxdf.pdf(wo, wi, path_type),
+            AnyBxdf::Dielectric(bxdf) => bxdf.pdf(wo, wi, path_type),
+            AnyBxdf::RoughDielectric(bxdf) => bxdf.pdf(wo, wi, path_type),
+            AnyBxdf::Custom(bxdf) => bxdf.pdf(wo, wi, path_type),
+        }
+    }
+
+    fn sample_direction(
+        &self,
+        wx: Vector3,
+        path_type: PathType,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Vector3> {
+        match self {
+            AnyBxdf::Diffuse(bxdf) => bxdf.sample_direction(wx, path_type, sampler),
+            AnyBxdf::Specular(bxdf) => bxdf.sample_direction(wx, path_type, sampler),
+            AnyBxdf::Microfacet(bxdf) => bxdf.sample_direction(wx, path_type, sampler),
+            AnyBxdf::Sheen(bxdf) => bxdf.sample_direction(wx, path_type, sampler),
+            AnyBxdf::Conductor(bxdf) => bxdf.sample_direction(wx, path_type, sampler),
+            AnyBxdf::Dielectric(bxdf) => bxdf.sample_direction(wx, path_type, sampler),
+            AnyBxdf::RoughDielectric(bxdf) => bxdf.sample_direction(wx, path_type, sampler),
+            AnyBxdf::Custom(bxdf) => bxdf.sample_direction(wx, path_type, sampler),
+        }
+    }
+
+    fn scattering(&self) -> Scattering {
+        match self {
+            AnyBxdf::Diffuse(bxdf) => bxdf.scattering(),
+            AnyBxdf::Specular(bxdf) => bxdf.scattering(),
+            AnyBxdf::Microfacet(bxdf) => bxdf.scattering(),
+            AnyBxdf::Sheen(bxdf) => bxdf.scattering(),
+            AnyBxdf::Conductor(bxdf) => bxdf.scattering(),
+            AnyBxdf::Dielectric(bxdf) => bxdf.scattering(),
+            AnyBxdf::RoughDielectric(bxdf) => bxdf.scattering(),
+            AnyBxdf::Custom(bxdf) => bxdf.scattering(),
+        }
+    }
+
+    fn roughness(&self) -> Float {
+        match self {
+            AnyBxdf::Diffuse(bxdf) => bxdf.roughness(),
+            AnyBxdf::Specular(bxdf) => bxdf.roughness(),
+            AnyBxdf::Microfacet(bxdf) => bxdf.roughness(),
+            AnyBxdf::Sheen(bxdf) => bxdf.roughness(),
+            AnyBxdf::Conductor(bxdf) => bxdf.roughness(),
+            AnyBxdf::Dielectric(bxdf) => bxdf.roughness(),
+            AnyBxdf::RoughDielectric(bxdf) => bxdf.roughness(),
+            AnyBxdf::Custom(bxdf) => bxdf.roughness(),
+        }
+    }
+
+    fn transmit(&self, wx: Vector3) -> Option<Vector3> {
+        match self {
+            AnyBxdf::Diffuse(bxdf) => bxdf.transmit(wx),
+            AnyBxdf::Specular(bxdf) => bxdf.transmit(wx),
+            AnyBxdf::Microfacet(bxdf) => bxdf.transmit(wx),
+            AnyBxdf::Sheen(bxdf) => bxdf.transmit(wx),
+            AnyBxdf::Conductor(bxdf) => bxdf.transmit(wx),
+            AnyBxdf::Dielectric(bxdf) => bxdf.transmit(wx),
+            AnyBxdf::RoughDielectric(bxdf) => bxdf.transmit(wx),
+            AnyBxdf::Custom(bxdf) => bxdf.transmit(wx),
+        }
+    }
+
+    fn regularize(&self, alpha: Float) -> Option<AnyBxdf> {
+        match self {
+            AnyBxdf::Diffuse(bxdf) => bxdf.regularize(alpha),
+            AnyBxdf::Specular(bxdf) => bxdf.regularize(alpha),
+            AnyBxdf::Microfacet(bxdf) => bxdf.regularize(alpha),
+            AnyBxdf::Sheen(bxdf) => bxdf.regularize(alpha),
+            AnyBxdf::Conductor(bxdf) => bxdf.regularize(alpha),
+            AnyBxdf::Dielectric(bxdf) => bxdf.regularize(alpha),
+            AnyBxdf::RoughDielectric(bxdf) => bxdf.regularize(alpha),
+            AnyBxdf::Custom(bxdf) => bxdf.regularize(alpha),
+        }
+    }
+
+    fn mueller(&self, wo: Vector3, wi: Vector3) -> Option<[Mueller; 3]> {
+        match self {
+            AnyBxdf::Diffuse(bxdf) => bxdf.mueller(wo, wi),
+            AnyBxdf::Specular(bxdf) => bxdf.mueller(wo, wi),
+            AnyBxdf::Microfacet(bxdf) => bxdf.mueller(wo, wi),
+            AnyBxdf::Sheen(bxdf) => bxdf.mueller(wo, wi),
+            AnyBxdf::Conductor(bxdf) => bxdf.mueller(wo, wi),
+            AnyBxdf::Dielectric(bxdf) => bxdf.mueller(wo, wi),
+            AnyBxdf::RoughDielectric(bxdf) => bxdf.mueller(wo, wi),
+            AnyBxdf::Custom(bxdf) => bxdf.mueller(wo, wi),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Scattering {
+    Diffuse,
+    Specular,
+}
+
+// A direction sampled from a BSDF, with the value of the BSDF for it and
+// the density of sampling it. The density of a specular sample is the
+// discrete probability of choosing its lobe and event, and its value is
+// that of the delta distribution with respect to projected solid angle.
+#[derive(Debug, Copy, Clone)]
+pub struct BsdfSample {
+    pub wi: Vector3,
+    pub value: Spectrum,
+    pub pdf: Float,
+    pub is_specular: bool,
+}
+
+impl BsdfSample {
+    // Whether the sample carries no light, so that the path ends.
+    pub fn is_empty(&self) -> bool {
+        self.pdf == 0.0 || self.value.is_black()
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct EvaluationContext {
-    pub geometry_term: f64,
+    pub geometry_term: Float,
     pub path_type: PathType,
 }
 
 impl Bsdf {
     pub fn evaluate(&self, wo: Vector3, wi: Vector3, context: EvaluationContext) -> Spectrum {
+        let _timer = profile::time(Subsystem::BsdfEvaluation);
         self.bxdfs
             .iter()
             .map(|bxdf| bxdf.evaluate(wo, wi, context))
             .fold(Spectrum::black(), |a, b| a + b)
     }
 
-    pub fn sample_direction(
+    // With several lobes, the scattering type is that of the lobe that
+    // contributes the most for this pair of directions.
+    pub fn scattering(&self, wo: Vector3, wi: Vector3, context: EvaluationContext) -> Scattering {
+        self.dominant(wo, wi, context)
+            .map(Bxdf::scattering)
+            .unwrap_or(Scattering::Diffuse)
+    }
+
+    // Likewise the roughness of the dominant lobe.
+    pub fn roughness(&self, wo: Vector3, wi: Vector3, context: EvaluationContext) -> Float {
+        self.dominant(wo, wi, context)
+            .map(Bxdf::roughness)
+            .unwrap_or(1.0)
+    }
+
+    fn dominant(&self, wo: Vector3, wi: Vector3, context: EvaluationContext) -> Option<&AnyBxdf> {
+        if self.bxdfs.len() == 1 {
+            return self.bxdfs.first();
+        }
+        self.bxdfs
+            .iter()
+            .map(|bxdf| (bxdf.evaluate(wo, wi, context).luminance(), bxdf))
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, bxdf)| bxdf)
+    }
+
+    pub fn transmit(&self, wx: Vector3) -> Option<Vector3> {
+        self.bxdfs.iter().find_map(|bxdf| bxdf.transmit(wx))
+    }
+
+    pub fn mueller(&self, wo: Vector3, wi: Vector3) -> Option<[Mueller; 3]> {
+        self.bxdfs.iter().find_map(|bxdf| bxdf.mueller(wo, wi))
+    }
+
+    // Widens the specular and near-specular lobes to the given roughness,
+    // trading bias for paths that are otherwise hard or impossible to
+    // sample, as in the path space regularization of Kaplanyan and
+    // Dachsbacher (2013).
+    pub fn regularize(self, alpha: Float) -> Bsdf {
+        let bxdfs = self
+            .bxdfs
+            .into_iter()
+            .map(|bxdf| bxdf.regularize(alpha).unwrap_or(bxdf))
+            .collect();
+        Bsdf { bxdfs }
+    }
+
+    pub fn sample(
         &self,
         wx: Vector3,
         path_type: PathType,
         sampler: &mut dyn Sampler,
-    ) -> Option<Vector3> {
-        let length = self.bxdfs.len() as f64;
+    ) -> Option<BsdfSample> {
+        let length = self.bxdfs.len() as Float;
         let r = sampler.sample(0.0..length).floor();
-        let i = r as usize;
-        self.bxdfs[i].sample_direction(wx, path_type, sampler)
+        let bxdf = &self.bxdfs[r as usize];
+        let sampled = bxdf.sample_direction(wx, path_type, sampler)?;
+        // The other methods take the direction toward the camera first.
+        let (wo, wi) = match path_type {
+            PathType::Camera => (wx, sampled),
+            PathType::Light => (sampled, wx),
+        };
+        let context = EvaluationContext {
+            geometry_term: 1.0,
+            path_type,
+        };
+        let sample = match self.pdf(wo, wi, path_type) {
+            PdfValue::Finite(p) if !bxdf.pdf(wo, wi, path_type).is_delta() => BsdfSample {
+                wi: sampled,
+                value: self.evaluate(wo, wi, context),
+                pdf: p,
+                is_specular: false,
+            },
+            _ => BsdfSample {
+                wi: sampled,
+                value: bxdf.evaluate(wo, wi, context),
+                pdf: bxdf.sampling_pdf(wo, wi, path_type).unwrap_or(1.0) / length,
+                is_specular: true,
+            },
+        };
+        Some(sample)
     }
 
-    pub fn sampling_pdf(&self, wo: Vector3, wi: Vector3, path_type: PathType) -> Option<f64> {
+    pub fn sampling_pdf(&self, wo: Vector3, wi: Vector3, path_type: PathType) -> Option<Float> {
         let mut count = 0;
         let mut sum = 0.0;
         for bxdf in &self.bxdfs {
             let result = bxdf.sampling_pdf(wo, wi, path_type);
             if result.is_some() {
-                count = count + 1;
+                count += 1;
             }
             let p = result.unwrap_or(0.0);
-            sum = sum + p;
+            sum += p;
         }
         if count > 0 {
-            let length = self.bxdfs.len() as f64;
+            let length = self.bxdfs.len() as Float;
             Some(sum / length)
         } else {
             None
         }
     }
 
-    pub fn pdf(&self, wo: Vector3, wi: Vector3, path_type: PathType) -> Option<f64> {
+    // The density is finite as soon as a single lobe has a finite density,
+    // with the delta lobes contributing nothing to it.
+    pub fn pdf(&self, wo: Vector3, wi: Vector3, path_type: PathType) -> PdfValue {
         let mut count = 0;
         let mut sum = 0.0;
         for bxdf in &self.bxdfs {
-            let result = bxdf.pdf(wo, wi, path_type);
-            if result.is_some() {
-                count = count + 1;
+            if let PdfValue::Finite(p) = bxdf.pdf(wo, wi, path_type) {
+                count += 1;
+                sum += p;
             }
-            let p = result.unwrap_or(0.0);
-            sum = sum + p;
         }
         if count > 0 {
-            let length = self.bxdfs.len() as f64;
-            Some(sum / length)
+            let length = self.bxdfs.len() as Float;
+            PdfValue::Finite(sum / length)
         } else {
-            None
+            PdfValue::Delta
         }
     }
 }
@@ -112,17 +366,17 @@ impl Bxdf for DiffuseBrdf {
         }
     }
 
-    fn sampling_pdf(&self, _: Vector3, _: Vector3, _: PathType) -> Option<f64> {
+    fn sampling_pdf(&self, _: Vector3, _: Vector3, _: PathType) -> Option<Float> {
         None
     }
 
-    fn pdf(&self, wo: Vector3, wi: Vector3, _: PathType) -> Option<f64> {
+    fn pdf(&self, wo: Vector3, wi: Vector3, _: PathType) -> PdfValue {
         let p = if util::same_hemisphere(self.normal, wo, wi) {
             util::abs_cos_theta(self.normal, wi) / PI
         } else {
             0.0
         };
-        Some(p)
+        PdfValue::Finite(p)
     }
 
     fn sample_direction(
@@ -138,6 +392,26 @@ impl Bxdf for DiffuseBrdf {
             Some(-wi)
         }
     }
+
+    fn scattering(&self) -> Scattering {
+        Scattering::Diffuse
+    }
+
+    fn roughness(&self) -> Float {
+        1.0
+    }
+
+    fn mueller(&self, _: Vector3, _: Vector3) -> Option<[Mueller; 3]> {
+        None
+    }
+
+    fn transmit(&self, _: Vector3) -> Option<Vector3> {
+        None
+    }
+
+    fn regularize(&self, _: Float) -> Option<AnyBxdf> {
+        None
+    }
 }
 
 #[derive(Debug)]
@@ -163,79 +437,434 @@ impl Bxdf for SpecularBrdf {
         }
     }
 
-    fn sampling_pdf(&self, _: Vector3, _: Vector3, _: PathType) -> Option<f64> {
+    fn sampling_pdf(&self, _: Vector3, _: Vector3, _: PathType) -> Option<Float> {
+        None
+    }
+
+    fn pdf(&self, _: Vector3, _: Vector3, _: PathType) -> PdfValue {
+        PdfValue::Delta
+    }
+
+    fn sample_direction(&self, wx: Vector3, _: PathType, _: &mut dyn Sampler) -> Option<Vector3> {
+        Some(util::reflect(wx, self.normal))
+    }
+
+    fn scattering(&self) -> Scattering {
+        Scattering::Specular
+    }
+
+    fn roughness(&self) -> Float {
+        0.0
+    }
+
+    fn mueller(&self, wo: Vector3, wi: Vector3) -> Option<[Mueller; 3]> {
+        let d1 = wo.norm().dot(self.normal);
+        let d2 = wi.norm().dot(self.normal);
+        util::equals(d1, d2, 0.0001).then(|| [Mueller::mirror(); 3])
+    }
+
+    fn transmit(&self, _: Vector3) -> Option<Vector3> {
+        None
+    }
+
+    fn regularize(&self, alpha: Float) -> Option<AnyBxdf> {
+        Some(AnyBxdf::Microfacet(MicrofacetBrdf::new(
+            self.normal,
+            alpha,
+            self.scale,
+            1.0,
+        )))
+    }
+}
+
+// The GGX distribution of microfacet normals at the given angle from the
+// macroscopic normal.
+fn ggx_distribution(alpha: Float, cos_theta_h: Float) -> Float {
+    let a2 = util::sqr(alpha);
+    a2 / (PI * util::sqr(util::sqr(cos_theta_h) * (a2 - 1.0) + 1.0))
+}
+
+// The Smith masking function of the GGX distribution for a direction at the
+// given angle from the macroscopic normal.
+fn ggx_masking(alpha: Float, cos_theta: Float) -> Float {
+    let a2 = util::sqr(alpha);
+    2.0 * cos_theta / (cos_theta + (a2 + (1.0 - a2) * util::sqr(cos_theta)).sqrt())
+}
+
+// Samples a microfacet normal in proportion to the distribution, projected
+// onto the macroscopic normal.
+fn ggx_sample(alpha: Float, normal: Vector3, sampler: &mut dyn Sampler) -> Vector3 {
+    let u1 = sampler.sample(0.0..1.0);
+    let u2 = sampler.sample(0.0..1.0);
+    let tan2_theta = util::sqr(alpha) * u1 / (1.0 - u1);
+    let cos_theta = 1.0 / (1.0 + tan2_theta).sqrt();
+    let sin_theta = util::safe_sqrt(1.0 - util::sqr(cos_theta));
+    let phi = 2.0 * PI * u2;
+    let (x, y, z) = util::orthonormal_basis(normal);
+    x * (sin_theta * phi.cos()) + y * (sin_theta * phi.sin()) + z * cos_theta
+}
+
+// Rough reflection from a surface of microscopic mirrors with the GGX
+// distribution of normals, whose Fresnel reflectance follows Schlick's
+// approximation from its value at normal incidence. The surface reflects on
+// both sides, like the diffuse lobe.
+#[derive(Debug)]
+pub struct MicrofacetBrdf {
+    normal: Vector3,
+    alpha: Float,
+    f0: Spectrum,
+    scale: Float,
+}
+
+impl MicrofacetBrdf {
+    pub fn new(normal: Vector3, alpha: Float, f0: Spectrum, scale: Float) -> MicrofacetBrdf {
+        MicrofacetBrdf {
+            normal,
+            alpha,
+            f0,
+            scale,
+        }
+    }
+
+    // The normal on the side of the given direction.
+    fn facing(&self, w: Vector3) -> Vector3 {
+        if w.dot(self.normal) < 0.0 {
+            -self.normal
+        } else {
+            self.normal
+        }
+    }
+
+    // The half vector, with the cosines of the directions with the normal,
+    // for a pair of directions on the same side of the surface.
+    fn half_vector(&self, wo: Vector3, wi: Vector3) -> Option<(Vector3, Float, Float)> {
+        let (wo, wi) = (wo.norm(), wi.norm());
+        let n = self.facing(wo);
+        let (cos_o, cos_i) = (wo.dot(n), wi.dot(n));
+        if cos_o <= 0.0 || cos_i <= 0.0 {
+            return None;
+        }
+        Some(((wo + wi).norm(), cos_o, cos_i))
+    }
+}
+
+impl Bxdf for MicrofacetBrdf {
+    fn evaluate(&self, wo: Vector3, wi: Vector3, _: EvaluationContext) -> Spectrum {
+        let Some((h, cos_o, cos_i)) = self.half_vector(wo, wi) else {
+            return Spectrum::black();
+        };
+        let d = ggx_distribution(self.alpha, h.dot(self.normal).abs());
+        let g = ggx_masking(self.alpha, cos_o) * ggx_masking(self.alpha, cos_i);
+        let schlick = (1.0 - h.dot(wi.norm()).clamp(0.0, 1.0)).powi(5);
+        let f = self.f0 + (Spectrum::fill(1.0) + self.f0 * -1.0) * schlick;
+        f * (self.scale * d * g / (4.0 * cos_o * cos_i))
+    }
+
+    fn sampling_pdf(&self, _: Vector3, _: Vector3, _: PathType) -> Option<Float> {
         None
     }
 
-    fn pdf(&self, _: Vector3, _: Vector3, _: PathType) -> Option<f64> {
+    // Half vectors are sampled in proportion to the distribution, projected
+    // onto the normal. Reflection is symmetric, so the density is the same
+    // in both directions.
+    fn pdf(&self, wo: Vector3, wi: Vector3, _: PathType) -> PdfValue {
+        let p = match self.half_vector(wo, wi) {
+            Some((h, _, _)) => {
+                let cos_theta_h = h.dot(self.normal).abs();
+                ggx_distribution(self.alpha, cos_theta_h) * cos_theta_h
+                    / (4.0 * h.dot(wi.norm()).abs())
+            }
+            None => 0.0,
+        };
+        PdfValue::Finite(p)
+    }
+
+    fn sample_direction(
+        &self,
+        wx: Vector3,
+        _: PathType,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Vector3> {
+        let h = ggx_sample(self.alpha, self.facing(wx), sampler);
+        let wi = util::reflect(wx.norm(), h);
+        util::same_hemisphere(self.normal, wx, wi).then_some(wi)
+    }
+
+    fn scattering(&self) -> Scattering {
+        Scattering::Diffuse
+    }
+
+    fn roughness(&self) -> Float {
+        self.alpha
+    }
+
+    fn mueller(&self, _: Vector3, _: Vector3) -> Option<[Mueller; 3]> {
         None
     }
 
+    fn transmit(&self, _: Vector3) -> Option<Vector3> {
+        None
+    }
+
+    fn regularize(&self, alpha: Float) -> Option<AnyBxdf> {
+        (self.alpha < alpha).then(|| {
+            AnyBxdf::Microfacet(MicrofacetBrdf::new(self.normal, alpha, self.f0, self.scale))
+        })
+    }
+}
+
+// The retro-reflective sheen of fabrics, from fibers that stand out of the
+// surface, with the "Charlie" distribution of Estevez and Kulla (2017) and
+// the visibility term of Neubelt and Pettineo (2013). Directions are sampled
+// from the cosine-weighted hemisphere.
+#[derive(Debug)]
+pub struct SheenBrdf {
+    normal: Vector3,
+    tint: Spectrum,
+    roughness: Float,
+}
+
+impl SheenBrdf {
+    pub fn new(normal: Vector3, tint: Spectrum, roughness: Float) -> SheenBrdf {
+        SheenBrdf {
+            normal,
+            tint,
+            roughness,
+        }
+    }
+}
+
+impl Bxdf for SheenBrdf {
+    fn evaluate(&self, wo: Vector3, wi: Vector3, _: EvaluationContext) -> Spectrum {
+        if !util::same_hemisphere(self.normal, wo, wi) {
+            return Spectrum::black();
+        }
+        let cos_o = util::abs_cos_theta(self.normal, wo);
+        let cos_i = util::abs_cos_theta(self.normal, wi);
+        let h = (wo.norm() + wi.norm()).norm();
+        let sin_theta_h = util::safe_sqrt(1.0 - util::sqr(h.dot(self.normal)));
+        let inverse_roughness = 1.0 / self.roughness;
+        let d = (2.0 + inverse_roughness) * sin_theta_h.powf(inverse_roughness) / (2.0 * PI);
+        let v = 1.0 / (4.0 * (cos_o + cos_i - cos_o * cos_i));
+        self.tint * (d * v)
+    }
+
+    fn sampling_pdf(&self, _: Vector3, _: Vector3, _: PathType) -> Option<Float> {
+        None
+    }
+
+    fn pdf(&self, wo: Vector3, wi: Vector3, _: PathType) -> PdfValue {
+        let p = if util::same_hemisphere(self.normal, wo, wi) {
+            util::abs_cos_theta(self.normal, wi) / PI
+        } else {
+            0.0
+        };
+        PdfValue::Finite(p)
+    }
+
+    fn sample_direction(
+        &self,
+        wo: Vector3,
+        _: PathType,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Vector3> {
+        let wi = util::cosine_sample_hemisphere(self.normal, sampler);
+        if util::same_hemisphere(self.normal, wi, wo) {
+            Some(wi)
+        } else {
+            Some(-wi)
+        }
+    }
+
+    fn scattering(&self) -> Scattering {
+        Scattering::Diffuse
+    }
+
+    fn roughness(&self) -> Float {
+        self.roughness.min(1.0)
+    }
+
+    fn mueller(&self, _: Vector3, _: Vector3) -> Option<[Mueller; 3]> {
+        None
+    }
+
+    fn transmit(&self, _: Vector3) -> Option<Vector3> {
+        None
+    }
+
+    fn regularize(&self, _: Float) -> Option<AnyBxdf> {
+        None
+    }
+}
+
+// A perfectly smooth metal, which reflects by the Fresnel equations for its
+// complex index of refraction in each channel.
+#[derive(Debug)]
+pub struct ConductorBrdf {
+    normal: Vector3,
+    eta: Spectrum,
+    k: Spectrum,
+}
+
+impl ConductorBrdf {
+    pub fn new(normal: Vector3, eta: Spectrum, k: Spectrum) -> ConductorBrdf {
+        ConductorBrdf { normal, eta, k }
+    }
+
+    fn reflectance(&self, cos_theta: Float) -> Spectrum {
+        Spectrum {
+            r: util::fresnel_conductor(cos_theta, self.eta.r, self.k.r),
+            g: util::fresnel_conductor(cos_theta, self.eta.g, self.k.g),
+            b: util::fresnel_conductor(cos_theta, self.eta.b, self.k.b),
+        }
+    }
+}
+
+impl Bxdf for ConductorBrdf {
+    fn evaluate(&self, wo: Vector3, wi: Vector3, context: EvaluationContext) -> Spectrum {
+        let d1 = wo.norm().dot(self.normal);
+        let d2 = wi.norm().dot(self.normal);
+        if util::equals(d1, d2, 0.0001) {
+            self.reflectance(d1) / context.geometry_term
+        } else {
+            Spectrum::black()
+        }
+    }
+
+    fn sampling_pdf(&self, _: Vector3, _: Vector3, _: PathType) -> Option<Float> {
+        None
+    }
+
+    fn pdf(&self, _: Vector3, _: Vector3, _: PathType) -> PdfValue {
+        PdfValue::Delta
+    }
+
     fn sample_direction(&self, wx: Vector3, _: PathType, _: &mut dyn Sampler) -> Option<Vector3> {
         Some(util::reflect(wx, self.normal))
     }
+
+    fn scattering(&self) -> Scattering {
+        Scattering::Specular
+    }
+
+    fn roughness(&self) -> Float {
+        0.0
+    }
+
+    fn mueller(&self, wo: Vector3, wi: Vector3) -> Option<[Mueller; 3]> {
+        let d1 = wo.norm().dot(self.normal);
+        let d2 = wi.norm().dot(self.normal);
+        if !util::equals(d1, d2, 0.0001) {
+            return None;
+        }
+        let cos_theta = d1.abs();
+        Some([
+            Mueller::fresnel_reflection(cos_theta, self.eta.r, self.k.r),
+            Mueller::fresnel_reflection(cos_theta, self.eta.g, self.k.g),
+            Mueller::fresnel_reflection(cos_theta, self.eta.b, self.k.b),
+        ])
+    }
+
+    fn transmit(&self, _: Vector3) -> Option<Vector3> {
+        None
+    }
+
+    fn regularize(&self, alpha: Float) -> Option<AnyBxdf> {
+        Some(AnyBxdf::Microfacet(MicrofacetBrdf::new(
+            self.normal,
+            alpha,
+            self.reflectance(1.0),
+            1.0,
+        )))
+    }
 }
 
+// A smooth interface between dielectrics. A dispersive interface, whose
+// index of refraction differs between channels, refracts each channel in a
+// direction of its own, and transmission samples one of them uniformly.
 #[derive(Debug)]
 pub struct DielectricBxdf {
     scale: Spectrum,
     normal: Vector3,
-    eta: f64,
+    // The indices of refraction that are sampled between, with the channels
+    // that each carries.
+    channels: [(Float, Spectrum); 3],
+    channel_count: usize,
 }
 
 impl DielectricBxdf {
-    pub fn new(normal: Vector3, scale: Spectrum, eta: f64) -> DielectricBxdf {
-        DielectricBxdf { normal, scale, eta }
+    pub fn new(normal: Vector3, scale: Spectrum, eta: Float) -> DielectricBxdf {
+        DielectricBxdf::dispersive(normal, scale, Spectrum::fill(eta))
+    }
+
+    pub fn dispersive(normal: Vector3, scale: Spectrum, eta: Spectrum) -> DielectricBxdf {
+        let channel = |r, g, b| Spectrum { r, g, b };
+        if eta.r == eta.g && eta.g == eta.b {
+            let channels = [(eta.g, Spectrum::fill(1.0)); 3];
+            return DielectricBxdf {
+                scale,
+                normal,
+                channels,
+                channel_count: 1,
+            };
+        }
+        DielectricBxdf {
+            scale,
+            normal,
+            channels: [
+                (eta.r, channel(1.0, 0.0, 0.0)),
+                (eta.g, channel(0.0, 1.0, 0.0)),
+                (eta.b, channel(0.0, 0.0, 1.0)),
+            ],
+            channel_count: 3,
+        }
+    }
+
+    fn channels(&self) -> &[(Float, Spectrum)] {
+        &self.channels[..self.channel_count]
     }
 
     fn evaluate_internal(&self, wi: Vector3, wt: Vector3, adjoint: bool) -> Spectrum {
+        let cos_theta = util::cos_theta(self.normal, wi);
         let reflection = util::reflect(wi.norm(), self.normal);
-        if wt.norm().approx_eq(reflection, 1e-6) {
-            let cos_theta = util::cos_theta(self.normal, wi);
-            let r = util::fresnel_dielectric(cos_theta, self.eta);
-            self.scale * r
-        } else {
-            let refraction = util::refract(wi.norm(), self.normal.norm(), self.eta);
-            if refraction.is_none() {
-                return Spectrum::black();
+        let reflects = wt.norm().approx_eq(reflection, 1e-6);
+        let mut value = Spectrum::black();
+        for &(eta, mask) in self.channels() {
+            let r = util::fresnel_dielectric(cos_theta, eta);
+            if reflects {
+                value = value + mask.mul(self.scale) * r;
+                continue;
             }
-            if wt.norm().approx_eq(refraction.unwrap(), 1e-6) {
-                let cos_theta = util::cos_theta(self.normal, wi);
-                let r = util::fresnel_dielectric(cos_theta, self.eta);
+            let refraction = util::refract(wi.norm(), self.normal.norm(), eta);
+            if refraction.is_some_and(|refraction| wt.norm().approx_eq(refraction, 1e-6)) {
                 let t = 1.0 - r;
-                let eta_actual = if cos_theta < 0.0 {
-                    1.0 / self.eta
-                } else {
-                    self.eta
-                };
+                let eta_actual = if cos_theta < 0.0 { 1.0 / eta } else { eta };
                 let adjoint_factor = if adjoint { util::sqr(eta_actual) } else { 1.0 };
-                self.scale * t / adjoint_factor
-            } else {
-                Spectrum::black()
+                value = value + mask.mul(self.scale) * t / adjoint_factor;
             }
         }
+        value
     }
 
-    fn sampling_pdf_internal(&self, wi: Vector3, wt: Vector3) -> Option<f64> {
+    fn sampling_pdf_internal(&self, wi: Vector3, wt: Vector3) -> Option<Float> {
+        let cos_theta = util::cos_theta(self.normal, wi);
         let reflection = util::reflect(wi.norm(), self.normal);
-        if wt.norm().approx_eq(reflection, 1e-6) {
-            let cos_theta = util::cos_theta(self.normal, wi);
-            let r = util::fresnel_dielectric(cos_theta, self.eta);
-            Some(r)
-        } else {
-            let refraction = util::refract(wi.norm(), self.normal.norm(), self.eta);
-            if refraction.is_none() {
-                return None;
-            }
-            if wt.norm().approx_eq(refraction.unwrap(), 1e-6) {
-                let cos_theta = util::cos_theta(self.normal, wi);
-                let r = util::fresnel_dielectric(cos_theta, self.eta);
-                let t = 1.0 - r;
-                Some(t)
+        let reflects = wt.norm().approx_eq(reflection, 1e-6);
+        let mut pdf = None;
+        for &(eta, _) in self.channels() {
+            let r = util::fresnel_dielectric(cos_theta, eta);
+            let p = if reflects {
+                r
             } else {
-                None
-            }
+                let refraction = util::refract(wi.norm(), self.normal.norm(), eta);
+                match refraction {
+                    Some(refraction) if wt.norm().approx_eq(refraction, 1e-6) => 1.0 - r,
+                    _ => continue,
+                }
+            };
+            pdf = Some(pdf.unwrap_or(0.0) + p / self.channel_count as Float);
         }
+        pdf
     }
 }
 
@@ -248,15 +877,15 @@ impl Bxdf for DielectricBxdf {
         result / context.geometry_term
     }
 
-    fn sampling_pdf(&self, wo: Vector3, wi: Vector3, path_type: PathType) -> Option<f64> {
+    fn sampling_pdf(&self, wo: Vector3, wi: Vector3, path_type: PathType) -> Option<Float> {
         match path_type {
             PathType::Camera => self.sampling_pdf_internal(wo, wi),
             PathType::Light => self.sampling_pdf_internal(wi, wo),
         }
     }
 
-    fn pdf(&self, _: Vector3, _: Vector3, _: PathType) -> Option<f64> {
-        None
+    fn pdf(&self, _: Vector3, _: Vector3, _: PathType) -> PdfValue {
+        PdfValue::Delta
     }
 
     fn sample_direction(
@@ -266,29 +895,238 @@ impl Bxdf for DielectricBxdf {
         sampler: &mut dyn Sampler,
     ) -> Option<Vector3> {
         // TODO: disable reflection when internal to object; use flags?
+        // One sample chooses both the channel and the event.
+        let count = self.channel_count as Float;
+        let u = sampler.sample(0.0..1.0) * count;
+        let channel = (u.floor() as usize).min(self.channel_count - 1);
+        let (eta, _) = self.channels[channel];
         let cos_theta_i = util::cos_theta(self.normal, wx);
-        let r = util::fresnel_dielectric(cos_theta_i, self.eta);
-        if sampler.sample(0.0..1.0) < r {
+        let r = util::fresnel_dielectric(cos_theta_i, eta);
+        if u - (channel as Float) < r {
             Some(util::reflect(wx, self.normal))
         } else {
-            util::refract(wx.norm(), self.normal.norm(), self.eta)
+            util::refract(wx.norm(), self.normal.norm(), eta)
+        }
+    }
+
+    fn scattering(&self) -> Scattering {
+        Scattering::Specular
+    }
+
+    fn roughness(&self) -> Float {
+        0.0
+    }
+
+    // Light arrives along wi, from the side its cosine is taken on.
+    fn mueller(&self, wo: Vector3, wi: Vector3) -> Option<[Mueller; 3]> {
+        let cos_theta = util::cos_theta(self.normal, wi);
+        let eta = [0, 1, 2].map(|c| self.channels[c.min(self.channel_count - 1)].0);
+        if wo
+            .norm()
+            .approx_eq(util::reflect(wi.norm(), self.normal), 1e-6)
+        {
+            return Some(eta.map(|eta| {
+                let (cos_theta, eta) = if cos_theta < 0.0 {
+                    (-cos_theta, 1.0 / eta)
+                } else {
+                    (cos_theta, eta)
+                };
+                Mueller::fresnel_reflection(cos_theta, eta, 0.0)
+            }));
         }
+        let refracts = self.channels().iter().any(|&(eta, _)| {
+            util::refract(wi.norm(), self.normal.norm(), eta)
+                .is_some_and(|refraction| wo.norm().approx_eq(refraction, 1e-6))
+        });
+        refracts.then(|| eta.map(|eta| Mueller::fresnel_transmission(cos_theta, eta)))
+    }
+
+    // A dispersive interface has no single direction of transmission.
+    fn transmit(&self, wx: Vector3) -> Option<Vector3> {
+        if self.channel_count > 1 {
+            return None;
+        }
+        util::refract(wx.norm(), self.normal.norm(), self.channels[0].0)
+    }
+
+    // The rough interface takes the index of refraction of the middle
+    // channel, so regularization gives up dispersion.
+    fn regularize(&self, alpha: Float) -> Option<AnyBxdf> {
+        let (eta, _) = self.channels[self.channel_count / 2];
+        Some(AnyBxdf::RoughDielectric(RoughDielectricBxdf::new(
+            self.normal,
+            self.scale,
+            eta,
+            alpha,
+        )))
+    }
+}
+
+// A rough interface between dielectrics, after Walter et al. (2007), whose
+// microfacets reflect and refract in proportion to their Fresnel
+// reflectance. The value does not depend on the direction of transport,
+// since the scaling of radiance by the square of the relative index of
+// refraction is undone by the adjoint.
+#[derive(Debug)]
+pub struct RoughDielectricBxdf {
+    normal: Vector3,
+    scale: Spectrum,
+    eta: Float,
+    alpha: Float,
+}
+
+impl RoughDielectricBxdf {
+    pub fn new(normal: Vector3, scale: Spectrum, eta: Float, alpha: Float) -> RoughDielectricBxdf {
+        RoughDielectricBxdf {
+            normal,
+            scale,
+            eta,
+            alpha,
+        }
+    }
+
+    // The microfacet normal that scatters between the directions, on the
+    // outer side of the surface, with the index of refraction on the side of
+    // `wi` relative to that on the side of `wo`.
+    fn microfacet_normal(&self, wo: Vector3, wi: Vector3) -> Option<(Vector3, Float)> {
+        let (cos_o, cos_i) = (wo.dot(self.normal), wi.dot(self.normal));
+        if cos_o == 0.0 || cos_i == 0.0 {
+            return None;
+        }
+        let eta = if cos_o * cos_i > 0.0 {
+            1.0
+        } else if cos_o > 0.0 {
+            self.eta
+        } else {
+            1.0 / self.eta
+        };
+        let h = wo + wi * eta;
+        if h.is_zero() {
+            return None;
+        }
+        let h = if h.dot(self.normal) < 0.0 {
+            -h.norm()
+        } else {
+            h.norm()
+        };
+        // Microfacets that face away from either direction are not seen.
+        if h.dot(wo) * cos_o <= 0.0 || h.dot(wi) * cos_i <= 0.0 {
+            return None;
+        }
+        Some((h, eta))
+    }
+
+    // The density of sampling `wi` from `wo`.
+    fn density(&self, wo: Vector3, wi: Vector3) -> Float {
+        let (wo, wi) = (wo.norm(), wi.norm());
+        let Some((h, eta)) = self.microfacet_normal(wo, wi) else {
+            return 0.0;
+        };
+        let cos_theta_h = h.dot(self.normal);
+        let p = ggx_distribution(self.alpha, cos_theta_h) * cos_theta_h;
+        let r = util::fresnel_dielectric(wo.dot(h), self.eta);
+        if util::same_hemisphere(self.normal, wo, wi) {
+            r * p / (4.0 * wo.dot(h).abs())
+        } else {
+            let denominator = wi.dot(h) + wo.dot(h) / eta;
+            (1.0 - r) * p * wi.dot(h).abs() / util::sqr(denominator)
+        }
+    }
+}
+
+impl Bxdf for RoughDielectricBxdf {
+    fn evaluate(&self, wo: Vector3, wi: Vector3, _: EvaluationContext) -> Spectrum {
+        let (wo, wi) = (wo.norm(), wi.norm());
+        let Some((h, eta)) = self.microfacet_normal(wo, wi) else {
+            return Spectrum::black();
+        };
+        let cos_o = util::abs_cos_theta(self.normal, wo);
+        let cos_i = util::abs_cos_theta(self.normal, wi);
+        let d = ggx_distribution(self.alpha, h.dot(self.normal));
+        let g = ggx_masking(self.alpha, cos_o) * ggx_masking(self.alpha, cos_i);
+        let r = util::fresnel_dielectric(wo.dot(h), self.eta);
+        if util::same_hemisphere(self.normal, wo, wi) {
+            self.scale * (r * d * g / (4.0 * cos_o * cos_i))
+        } else {
+            let denominator = wi.dot(h) + wo.dot(h) / eta;
+            let t = (1.0 - r) * d * g * (wi.dot(h) * wo.dot(h)).abs()
+                / (cos_o * cos_i * util::sqr(denominator));
+            self.scale * (t / util::sqr(eta))
+        }
+    }
+
+    fn sampling_pdf(&self, _: Vector3, _: Vector3, _: PathType) -> Option<Float> {
+        None
+    }
+
+    fn pdf(&self, wo: Vector3, wi: Vector3, path_type: PathType) -> PdfValue {
+        match path_type {
+            PathType::Camera => PdfValue::Finite(self.density(wo, wi)),
+            PathType::Light => PdfValue::Finite(self.density(wi, wo)),
+        }
+    }
+
+    fn sample_direction(
+        &self,
+        wx: Vector3,
+        _: PathType,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Vector3> {
+        let wx = wx.norm();
+        let h = ggx_sample(self.alpha, self.normal, sampler);
+        let r = util::fresnel_dielectric(wx.dot(h), self.eta);
+        let wi = if sampler.sample(0.0..1.0) < r {
+            util::reflect(wx, h)
+        } else {
+            util::refract(wx, h, self.eta)?
+        };
+        (self.density(wx, wi) > 0.0).then_some(wi)
+    }
+
+    fn scattering(&self) -> Scattering {
+        Scattering::Diffuse
+    }
+
+    fn roughness(&self) -> Float {
+        self.alpha
+    }
+
+    fn mueller(&self, _: Vector3, _: Vector3) -> Option<[Mueller; 3]> {
+        None
+    }
+
+    fn transmit(&self, _: Vector3) -> Option<Vector3> {
+        None
+    }
+
+    fn regularize(&self, alpha: Float) -> Option<AnyBxdf> {
+        (self.alpha < alpha).then(|| {
+            AnyBxdf::RoughDielectric(RoughDielectricBxdf::new(
+                self.normal,
+                self.scale,
+                self.eta,
+                alpha,
+            ))
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Bxdf, DielectricBxdf, DiffuseBrdf, SpecularBrdf};
+    use super::{
+        AnyBxdf, Bxdf, ConductorBrdf, DielectricBxdf, DiffuseBrdf, MicrofacetBrdf,
+        RoughDielectricBxdf, SheenBrdf, SpecularBrdf,
+    };
     use crate::{
         approx::ApproxEq,
-        bsdf::{Bsdf, EvaluationContext},
+        bsdf::{Bsdf, EvaluationContext, Scattering},
+        pdf::PdfValue,
         sampler::test::MockSampler,
         spectrum::Spectrum,
-        types::PathType,
+        types::{consts::PI, Float, PathType},
         util,
         vector::Vector3,
     };
-    use std::f64::consts::PI;
 
     #[test]
     fn test_diffuse_brdf_evaluate_same_hemisphere() {
@@ -330,7 +1168,7 @@ mod tests {
         let wo = Vector3::new(1.0, 1.0, 0.0);
         let wi = Vector3::new(-1.0, 1.0, 0.0);
         let actual = brdf.pdf(wo, wi, PathType::Camera);
-        let expected = Some(util::abs_cos_theta(normal, wi) / PI);
+        let expected = PdfValue::Finite(util::abs_cos_theta(normal, wi) / PI);
         assert_eq!(actual, expected);
     }
 
@@ -342,7 +1180,7 @@ mod tests {
         let wo = Vector3::new(1.0, 1.0, 0.0);
         let wi = Vector3::new(-1.0, -1.0, 0.0);
         let actual = brdf.pdf(wo, wi, PathType::Camera);
-        let expected = Some(0.0);
+        let expected = PdfValue::Finite(0.0);
         assert_eq!(actual, expected);
     }
 
@@ -391,6 +1229,161 @@ mod tests {
         assert_eq!(actual, scale);
     }
 
+    #[test]
+    fn test_microfacet_brdf() {
+        let normal = Vector3::new(0.0, 1.0, 0.0);
+        let brdf = MicrofacetBrdf::new(normal, 0.2, Spectrum::fill(1.0), 1.0);
+        let context = EvaluationContext {
+            geometry_term: 1.0,
+            path_type: PathType::Camera,
+        };
+        let wo = Vector3::new(0.5, Float::sqrt(3.0) / 2.0, 0.0);
+        // Integrated over the hemisphere, the density is at most one, less
+        // what is reflected below the horizon, and a perfect reflector
+        // reflects no more than it receives.
+        let steps = 400;
+        let (mut density, mut albedo) = (0.0, 0.0);
+        for i in 0..steps {
+            let theta = (i as Float + 0.5) / steps as Float * PI / 2.0;
+            for j in 0..steps {
+                let phi = (j as Float + 0.5) / steps as Float * 2.0 * PI;
+                let wi = Vector3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let d_omega =
+                    theta.sin() * (PI / 2.0 / steps as Float) * (2.0 * PI / steps as Float);
+                if let PdfValue::Finite(p) = brdf.pdf(wo, wi, PathType::Camera) {
+                    density += p * d_omega;
+                }
+                albedo += brdf.evaluate(wo, wi, context).r * theta.cos() * d_omega;
+                let reverse = brdf.evaluate(wi, wo, context);
+                assert!(reverse.approx_eq(brdf.evaluate(wo, wi, context), 1e-9));
+            }
+        }
+        assert!(density > 0.9 && density < 1.0 + 1e-3);
+        assert!(albedo > 0.85 && albedo < 1.0);
+        // The other side of the surface reflects alike.
+        let wi = Vector3::new(-0.5, -0.5, 0.0);
+        let below = brdf.evaluate(-wo, wi, context);
+        let above = brdf.evaluate(wo, -wi, context);
+        assert!(below.approx_eq(above, 1e-12));
+    }
+
+    #[test]
+    fn test_sheen_brdf() {
+        let normal = Vector3::new(0.0, 1.0, 0.0);
+        let brdf = SheenBrdf::new(normal, Spectrum::fill(1.0), 0.5);
+        let context = EvaluationContext {
+            geometry_term: 1.0,
+            path_type: PathType::Camera,
+        };
+        let wo = Vector3::new(0.5, Float::sqrt(3.0) / 2.0, 0.0);
+        let steps = 400;
+        let (mut density, mut albedo) = (0.0, 0.0);
+        for i in 0..steps {
+            let theta = (i as Float + 0.5) / steps as Float * PI / 2.0;
+            for j in 0..steps {
+                let phi = (j as Float + 0.5) / steps as Float * 2.0 * PI;
+                let wi = Vector3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let d_omega =
+                    theta.sin() * (PI / 2.0 / steps as Float) * (2.0 * PI / steps as Float);
+                if let PdfValue::Finite(p) = brdf.pdf(wo, wi, PathType::Camera) {
+                    density += p * d_omega;
+                }
+                albedo += brdf.evaluate(wo, wi, context).r * theta.cos() * d_omega;
+                let reverse = brdf.evaluate(wi, wo, context);
+                assert!(reverse.approx_eq(brdf.evaluate(wo, wi, context), 1e-9));
+            }
+        }
+        assert!((density - 1.0).abs() < 1e-3);
+        assert!(albedo > 0.0 && albedo < 1.0);
+        // Fabric brightens toward grazing angles.
+        let grazing = Vector3::new(0.99, 0.1, 0.0).norm();
+        let retro = brdf.evaluate(grazing, grazing, context);
+        let head_on = brdf.evaluate(normal, normal, context);
+        assert!(retro.r > head_on.r);
+        assert!(brdf.evaluate(wo, -wo, context).is_black());
+    }
+
+    #[test]
+    fn test_rough_dielectric_bxdf() {
+        let normal = Vector3::new(0.0, 1.0, 0.0);
+        let bxdf = RoughDielectricBxdf::new(normal, Spectrum::fill(1.0), 1.5, 0.3);
+        let context = EvaluationContext {
+            geometry_term: 1.0,
+            path_type: PathType::Camera,
+        };
+        // From either side, the density integrates to at most one over the
+        // sphere, and the interface scatters no more than it receives, in
+        // all directions from light arriving in one.
+        for w in [
+            Vector3::new(0.5, Float::sqrt(3.0) / 2.0, 0.0),
+            Vector3::new(0.5, -Float::sqrt(3.0) / 2.0, 0.0),
+        ] {
+            let steps = 400;
+            let (mut density, mut energy) = (0.0, 0.0);
+            for i in 0..2 * steps {
+                let theta = (i as Float + 0.5) / steps as Float * PI / 2.0;
+                for j in 0..steps {
+                    let phi = (j as Float + 0.5) / steps as Float * 2.0 * PI;
+                    let wi = Vector3::new(
+                        theta.sin() * phi.cos(),
+                        theta.cos(),
+                        theta.sin() * phi.sin(),
+                    );
+                    let d_omega =
+                        theta.sin() * (PI / 2.0 / steps as Float) * (2.0 * PI / steps as Float);
+                    if let PdfValue::Finite(p) = bxdf.pdf(w, wi, PathType::Camera) {
+                        density += p * d_omega;
+                    }
+                    let cos_theta = theta.cos().abs();
+                    energy += bxdf.evaluate(wi, w, context).r * cos_theta * d_omega;
+                }
+            }
+            assert!(density > 0.85 && density < 1.0 + 1e-3);
+            assert!(energy > 0.85 && energy < 1.0 + 1e-3);
+        }
+        // Sampled directions have a density.
+        let wo = Vector3::new(0.5, Float::sqrt(3.0) / 2.0, 0.0);
+        let mut sampler = MockSampler::new();
+        for u in [0.1, 0.5, 0.9] {
+            sampler.add(u);
+            sampler.add(0.25);
+            sampler.add(u);
+            let wi = bxdf
+                .sample_direction(wo, PathType::Camera, &mut sampler)
+                .unwrap();
+            match bxdf.pdf(wo, wi, PathType::Camera) {
+                PdfValue::Finite(p) => assert!(p > 0.0),
+                PdfValue::Delta => panic!(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_conductor_brdf_evaluate() {
+        let normal = Vector3::new(0.0, 1.0, 0.0);
+        let eta = Spectrum::fill(0.2);
+        let k = Spectrum::fill(3.9);
+        let brdf = ConductorBrdf::new(normal, eta, k);
+        let wo = Vector3::new(1.0, 1.0, 0.0);
+        let context = EvaluationContext {
+            geometry_term: 2.0,
+            path_type: PathType::Camera,
+        };
+        let actual = brdf.evaluate(wo, Vector3::new(-1.0, 1.0, 0.0), context);
+        let r = util::fresnel_conductor(Float::sqrt(0.5), 0.2, 3.9);
+        assert!(actual.approx_eq(Spectrum::fill(r / 2.0), 1e-12));
+        let actual = brdf.evaluate(wo, Vector3::new(-1.0, 2.0, 0.0), context);
+        assert!(actual.is_black());
+    }
+
     #[test]
     fn test_specular_brdf_evaluate_inexact() {
         let scale = Spectrum::fill(0.8);
@@ -414,7 +1407,7 @@ mod tests {
         let wo = Vector3::new(1.0, 1.0, 0.0);
         let wi = Vector3::new(-1.0, 1.0, 0.0);
         let actual = brdf.pdf(wo, wi, PathType::Camera);
-        assert_eq!(actual, None);
+        assert_eq!(actual, PdfValue::Delta);
     }
 
     #[test]
@@ -437,9 +1430,9 @@ mod tests {
         let scale = Spectrum::fill(1.0);
         let eta = 1.6;
         let theta_i = 30.0 * PI / 180.0;
-        let wi = Vector3::new(-f64::sin(theta_i), f64::cos(theta_i), 0.0);
+        let wi = Vector3::new(-Float::sin(theta_i), Float::cos(theta_i), 0.0);
         let theta_t = 18.20996 * PI / 180.0;
-        let mut expected_wt = Vector3::new(f64::sin(theta_t), -f64::cos(theta_t), 0.0);
+        let mut expected_wt = Vector3::new(Float::sin(theta_t), -Float::cos(theta_t), 0.0);
         let bxdf = DielectricBxdf::new(normal, scale, eta);
         let mut sampler = MockSampler::new();
 
@@ -451,7 +1444,7 @@ mod tests {
         let mut wt = bxdf.sample_direction(wi, path_type, &mut sampler).unwrap();
         assert!(wt.approx_eq(expected_wt, 1e-5));
         let mut pdf = bxdf.sampling_pdf(wi, wt, path_type).unwrap();
-        let r = 0.0549528214871777;
+        let r = 0.054952823;
         assert!(util::equals(pdf, 1.0 - r, 1e-5));
         let geometry_term = 0.4; // arbitrary
         let mut context = EvaluationContext {
@@ -483,7 +1476,7 @@ mod tests {
         // Refraction
         sampler.add(0.5);
         wt = bxdf.sample_direction(wi, path_type, &mut sampler).unwrap();
-        expected_wt = Vector3::new(f64::sin(theta_t), -f64::cos(theta_t), 0.0);
+        expected_wt = Vector3::new(Float::sin(theta_t), -Float::cos(theta_t), 0.0);
         assert!(wt.approx_eq(expected_wt, 1e-5));
         pdf = bxdf.sampling_pdf(wt, wi, path_type).unwrap();
         assert!(util::equals(pdf, 1.0 - r, 1e-5));
@@ -503,6 +1496,95 @@ mod tests {
         assert!(e.approx_eq(expected_e, 1e-5));
     }
 
+    #[test]
+    fn test_dispersive_dielectric_bxdf() {
+        let normal = Vector3::new(0.0, 1.0, 0.0);
+        let eta = Spectrum {
+            r: 1.5,
+            g: 1.6,
+            b: 1.7,
+        };
+        let bxdf = DielectricBxdf::dispersive(normal, Spectrum::fill(1.0), eta);
+        let theta_i = 30.0 * PI / 180.0;
+        let wi = Vector3::new(-Float::sin(theta_i), Float::cos(theta_i), 0.0);
+        let path_type = PathType::Camera;
+        let context = EvaluationContext {
+            geometry_term: 1.0,
+            path_type,
+        };
+        let fresnel = |eta| util::fresnel_dielectric(Float::cos(theta_i), eta);
+        let mut sampler = MockSampler::new();
+
+        // The middle third of the sample refracts the green channel alone.
+        sampler.add(0.5);
+        let wt = bxdf.sample_direction(wi, path_type, &mut sampler).unwrap();
+        let theta_t = 18.20996 * PI / 180.0;
+        let expected_wt = Vector3::new(Float::sin(theta_t), -Float::cos(theta_t), 0.0);
+        assert!(wt.approx_eq(expected_wt, 1e-5));
+        let t = 1.0 - fresnel(1.6);
+        let pdf = bxdf.sampling_pdf(wi, wt, path_type).unwrap();
+        assert!(util::equals(pdf, t / 3.0, 1e-5));
+        let e = bxdf.evaluate(wi, wt, context);
+        let expected_e = Spectrum {
+            r: 0.0,
+            g: t / util::sqr(1.6),
+            b: 0.0,
+        };
+        assert!(e.approx_eq(expected_e, 1e-5));
+
+        // Blue is refracted more strongly than green.
+        sampler.add(0.9);
+        let wb = bxdf.sample_direction(wi, path_type, &mut sampler).unwrap();
+        assert!(wb.x < wt.x);
+        let e = bxdf.evaluate(wi, wb, context);
+        assert_eq!(e.r, 0.0);
+        assert_eq!(e.g, 0.0);
+        assert!(e.b > 0.0);
+
+        // Reflection is shared by the channels, each with its own Fresnel
+        // reflectance.
+        sampler.add(0.01);
+        let wr = bxdf.sample_direction(wi, path_type, &mut sampler).unwrap();
+        assert!(wr.approx_eq(Vector3::new(-wi.x, wi.y, 0.0), 1e-5));
+        let pdf = bxdf.sampling_pdf(wi, wr, path_type).unwrap();
+        let expected_pdf = (fresnel(1.5) + fresnel(1.6) + fresnel(1.7)) / 3.0;
+        assert!(util::equals(pdf, expected_pdf, 1e-5));
+        let e = bxdf.evaluate(wi, wr, context);
+        let expected_e = Spectrum {
+            r: fresnel(1.5),
+            g: fresnel(1.6),
+            b: fresnel(1.7),
+        };
+        assert!(e.approx_eq(expected_e, 1e-5));
+
+        assert!(bxdf.transmit(wi).is_none());
+    }
+
+    #[test]
+    fn test_dielectric_mueller() {
+        let normal = Vector3::new(0.0, 1.0, 0.0);
+        let bxdf = DielectricBxdf::new(normal, Spectrum::fill(1.0), 1.6);
+        let theta_i = 30.0 * PI / 180.0;
+        let wi = Vector3::new(-Float::sin(theta_i), Float::cos(theta_i), 0.0);
+        let wr = Vector3::new(Float::sin(theta_i), Float::cos(theta_i), 0.0);
+        let theta_t = 18.20996 * PI / 180.0;
+        let wt = Vector3::new(Float::sin(theta_t), -Float::cos(theta_t), 0.0);
+        let fresnel = util::fresnel_dielectric(Float::cos(theta_i), 1.6);
+
+        let reflection = bxdf.mueller(wr, wi).unwrap();
+        assert!(util::equals(reflection[0].intensity(), fresnel, 1e-5));
+        let transmission = bxdf.mueller(wt, wi).unwrap();
+        assert!(util::equals(
+            transmission[1].intensity(),
+            1.0 - fresnel,
+            1e-5
+        ));
+        assert!(bxdf.mueller(wi, wi).is_none());
+        assert!(DiffuseBrdf::new(normal, Spectrum::fill(1.0))
+            .mueller(wr, wi)
+            .is_none());
+    }
+
     #[test]
     fn test_bsdf_evaluate() {
         let scale = Spectrum::fill(0.8);
@@ -512,7 +1594,7 @@ mod tests {
         let wo = Vector3::new(1.0, 1.0, 0.0);
         let wi = Vector3::new(-1.0, 1.0, 0.0);
         let bsdf = Bsdf {
-            bxdfs: vec![Box::new(brdf1), Box::new(brdf2)],
+            bxdfs: vec![AnyBxdf::Diffuse(brdf1), AnyBxdf::Specular(brdf2)],
         };
         let context = EvaluationContext {
             geometry_term: 1.0,
@@ -521,6 +1603,14 @@ mod tests {
         let actual = bsdf.evaluate(wo, wi, context);
         let expected = scale + (scale / PI);
         assert_eq!(actual, expected);
+        // A lobe boxed as a custom one is dispatched all the same.
+        let bsdf = Bsdf {
+            bxdfs: vec![
+                AnyBxdf::Diffuse(DiffuseBrdf::new(normal, scale)),
+                AnyBxdf::Custom(Box::new(SpecularBrdf::new(normal, scale))),
+            ],
+        };
+        assert_eq!(bsdf.evaluate(wo, wi, context), expected);
     }
 
     #[test]
@@ -532,29 +1622,65 @@ mod tests {
         let wo = Vector3::new(1.0, 1.0, 0.0);
         let wi = Vector3::new(-1.0, 1.0, 0.0);
         let bsdf = Bsdf {
-            bxdfs: vec![Box::new(brdf1), Box::new(brdf2)],
+            bxdfs: vec![AnyBxdf::Diffuse(brdf1), AnyBxdf::Specular(brdf2)],
         };
         let actual = bsdf.pdf(wo, wi, PathType::Camera);
-        let expected = Some((util::abs_cos_theta(normal, wi) / PI) / 2.0);
+        let expected = PdfValue::Finite((util::abs_cos_theta(normal, wi) / PI) / 2.0);
         assert_eq!(actual, expected);
     }
 
     #[test]
-    fn test_bsdf_sample_direction() {
+    fn test_bsdf_sample() {
         let scale = Spectrum::fill(0.8);
         let normal = Vector3::new(0.0, 1.0, 0.0);
         let brdf1 = DiffuseBrdf::new(normal, scale);
         let brdf2 = SpecularBrdf::new(normal, scale);
         let wo = Vector3::new(1.0, 1.0, 0.0);
         let bsdf = Bsdf {
-            bxdfs: vec![Box::new(brdf1), Box::new(brdf2)],
+            bxdfs: vec![AnyBxdf::Diffuse(brdf1), AnyBxdf::Specular(brdf2)],
         };
         let mut sampler = MockSampler::new();
         sampler.add(0.9);
-        let actual = bsdf
-            .sample_direction(wo, PathType::Camera, &mut sampler)
-            .unwrap();
-        let expected = util::reflect(wo, normal);
-        assert_eq!(actual, expected);
+        let actual = bsdf.sample(wo, PathType::Camera, &mut sampler).unwrap();
+        assert_eq!(actual.wi, util::reflect(wo, normal));
+        assert!(actual.is_specular);
+        assert_eq!(actual.pdf, 0.5);
+        assert_eq!(actual.value, scale);
+        // The diffuse lobe samples the cosine-weighted hemisphere.
+        sampler.add(0.1);
+        sampler.add(0.5);
+        sampler.add(0.5);
+        let actual = bsdf.sample(wo, PathType::Camera, &mut sampler).unwrap();
+        assert!(!actual.is_specular);
+        let expected = util::abs_cos_theta(normal, actual.wi) / PI / 2.0;
+        assert!(util::equals(actual.pdf, expected, 1e-12));
+        assert!(actual.value.approx_eq(scale / PI, 1e-12));
+    }
+
+    #[test]
+    fn test_bsdf_regularize() {
+        let scale = Spectrum::fill(0.8);
+        let normal = Vector3::new(0.0, 1.0, 0.0);
+        let bsdf = Bsdf {
+            bxdfs: vec![
+                AnyBxdf::Specular(SpecularBrdf::new(normal, scale)),
+                AnyBxdf::Dielectric(DielectricBxdf::new(normal, scale, 1.5)),
+            ],
+        };
+        let wo = Vector3::new(1.0, 1.0, 0.0);
+        let wi = Vector3::new(-1.2, 1.0, 0.0);
+        assert_eq!(bsdf.pdf(wo, wi, PathType::Camera), PdfValue::Delta);
+        let bsdf = bsdf.regularize(0.1);
+        let context = EvaluationContext {
+            geometry_term: 1.0,
+            path_type: PathType::Camera,
+        };
+        // Directions near the mirror direction may now be connected.
+        match bsdf.pdf(wo, wi, PathType::Camera) {
+            PdfValue::Finite(p) => assert!(p > 0.0),
+            PdfValue::Delta => panic!("regularized BSDF has a delta density"),
+        }
+        assert!(!bsdf.evaluate(wo, wi, context).is_black());
+        assert_eq!(bsdf.scattering(wo, wi, context), Scattering::Diffuse);
     }
 }
@@ -0,0 +1,306 @@
+use crate::{bounds::Bounds3, ray::Ray, types::Float, vector::Vector3};
+
+// The number of candidate split planes along an axis.
+const BUCKET_COUNT: usize = 12;
+// The cost of visiting a node, relative to that of intersecting a primitive.
+const TRAVERSAL_COST: Float = 0.125;
+const MAX_LEAF_SIZE: usize = 4;
+// Deeper nodes are made leaves, so that traversal needs no more than a fixed
+// stack.
+pub const MAX_DEPTH: usize = 60;
+
+// A bounding volume hierarchy over primitives given by their bounds, split by
+// the surface area heuristic. The nodes are laid out flat in depth-first
+// order, each interior node followed by its first child.
+#[derive(Clone, Debug, Default)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    // The indices of the primitives, in the order of the leaves.
+    indices: Vec<usize>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BvhNode {
+    pub bounds: Bounds3,
+    // The first primitive of a leaf, or the second child of an interior
+    // node.
+    pub offset: usize,
+    // The number of primitives of a leaf, or zero for an interior node.
+    pub count: usize,
+    // The axis along which an interior node is split.
+    pub axis: usize,
+}
+
+// The nodes of a hierarchy and the primitives of its leaves, held in memory
+// or read from a file as they are visited.
+pub trait BvhNodes {
+    fn node_count(&self) -> usize;
+    fn node(&self, index: usize) -> BvhNode;
+    // The primitive at the given position in the order of the leaves.
+    fn primitive(&self, position: usize) -> usize;
+}
+
+struct Primitive {
+    index: usize,
+    bounds: Bounds3,
+    center: Vector3,
+}
+
+impl Bvh {
+    pub fn build(bounds: &[Bounds3]) -> Bvh {
+        let mut primitives: Vec<Primitive> = bounds
+            .iter()
+            .enumerate()
+            .map(|(index, bounds)| Primitive {
+                index,
+                bounds: *bounds,
+                center: bounds.center(),
+            })
+            .collect();
+        let mut bvh = Bvh {
+            nodes: Vec::new(),
+            indices: Vec::with_capacity(bounds.len()),
+        };
+        if !primitives.is_empty() {
+            bvh.build_node(&mut primitives, 0);
+        }
+        bvh
+    }
+
+    pub fn bounds(&self) -> Bounds3 {
+        self.nodes
+            .first()
+            .map(|node| node.bounds)
+            .unwrap_or(Bounds3::empty())
+    }
+
+    pub fn traverse(&self, ray: &mut Ray, visit: impl FnMut(usize, &mut Ray)) {
+        traverse(self, ray, visit);
+    }
+
+    fn build_node(&mut self, primitives: &mut [Primitive], depth: usize) -> usize {
+        let index = self.nodes.len();
+        let bounds = primitives
+            .iter()
+            .fold(Bounds3::empty(), |bounds, p| bounds.union(p.bounds));
+        self.nodes.push(BvhNode {
+            bounds,
+            offset: self.indices.len(),
+            count: primitives.len(),
+            axis: 0,
+        });
+        let Some((axis, middle)) = self.split(primitives, bounds, depth) else {
+            self.indices.extend(primitives.iter().map(|p| p.index));
+            return index;
+        };
+        let (first, second) = primitives.split_at_mut(middle);
+        self.build_node(first, depth + 1);
+        let second_child = self.build_node(second, depth + 1);
+        self.nodes[index] = BvhNode {
+            bounds,
+            offset: second_child,
+            count: 0,
+            axis,
+        };
+        index
+    }
+
+    // Partitions the primitives at the cheapest of the planes between
+    // buckets along the axis over which their centers spread the most,
+    // returning the axis and the size of the first part, or nothing if a
+    // leaf would be cheaper.
+    fn split(
+        &self,
+        primitives: &mut [Primitive],
+        bounds: Bounds3,
+        depth: usize,
+    ) -> Option<(usize, usize)> {
+        let count = primitives.len();
+        if count == 1 || depth >= MAX_DEPTH {
+            return None;
+        }
+        let centers = primitives.iter().fold(Bounds3::empty(), |bounds, p| {
+            bounds.union(Bounds3::new(p.center, p.center))
+        });
+        let spread = centers.diagonal();
+        let axis = if spread.x >= spread.y && spread.x >= spread.z {
+            0
+        } else if spread.y >= spread.z {
+            1
+        } else {
+            2
+        };
+        let (min, extent) = (component(centers.min, axis), component(spread, axis));
+        if extent <= 0.0 {
+            // The centers coincide, and no plane separates them.
+            return (count > MAX_LEAF_SIZE).then_some((axis, count / 2));
+        }
+        let bucket = |p: &Primitive| {
+            let b = (component(p.center, axis) - min) / extent * BUCKET_COUNT as Float;
+            usize::min(b as usize, BUCKET_COUNT - 1)
+        };
+        let mut buckets = [(0, Bounds3::empty()); BUCKET_COUNT];
+        for p in primitives.iter() {
+            let (n, b) = &mut buckets[bucket(p)];
+            *n += 1;
+            *b = b.union(p.bounds);
+        }
+        // The cost of splitting after each bucket, from the primitives below
+        // and above the plane.
+        let mut costs = [0.0; BUCKET_COUNT - 1];
+        let (mut n, mut b) = (0, Bounds3::empty());
+        for (i, cost) in costs.iter_mut().enumerate() {
+            n += buckets[i].0;
+            b = b.union(buckets[i].1);
+            *cost = n as Float * b.surface_area();
+        }
+        let (mut n, mut b) = (0, Bounds3::empty());
+        for i in (1..BUCKET_COUNT).rev() {
+            n += buckets[i].0;
+            b = b.union(buckets[i].1);
+            costs[i - 1] += n as Float * b.surface_area();
+        }
+        let area = bounds.surface_area();
+        let (best, cost) = costs
+            .iter()
+            .enumerate()
+            .map(|(i, cost)| (i, TRAVERSAL_COST + cost / area))
+            .min_by(|a, b| a.1.total_cmp(&b.1))?;
+        if count <= MAX_LEAF_SIZE && cost >= count as Float {
+            return None;
+        }
+        let mut middle = 0;
+        for i in 0..count {
+            if bucket(&primitives[i]) <= best {
+                primitives.swap(i, middle);
+                middle += 1;
+            }
+        }
+        Some((axis, middle))
+    }
+}
+
+impl BvhNodes for Bvh {
+    fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn node(&self, index: usize) -> BvhNode {
+        self.nodes[index]
+    }
+
+    fn primitive(&self, position: usize) -> usize {
+        self.indices[position]
+    }
+}
+
+// Visits each primitive whose bounds the ray passes through, nearer nodes
+// first, with the ray as the visits narrow it, so that those beyond a hit
+// already found are skipped.
+pub fn traverse(nodes: &impl BvhNodes, ray: &mut Ray, mut visit: impl FnMut(usize, &mut Ray)) {
+    if nodes.node_count() == 0 {
+        return;
+    }
+    let d = ray.direction;
+    let inverse = Vector3::new(1.0 / d.x, 1.0 / d.y, 1.0 / d.z);
+    let negative = [inverse.x < 0.0, inverse.y < 0.0, inverse.z < 0.0];
+    let mut stack = [0; MAX_DEPTH + 2];
+    let mut size = 1;
+    while size > 0 {
+        size -= 1;
+        let index = stack[size];
+        let node = nodes.node(index);
+        if !node.bounds.hit(ray, inverse) {
+            continue;
+        }
+        if node.count > 0 {
+            for position in node.offset..node.offset + node.count {
+                visit(nodes.primitive(position), ray);
+            }
+        } else {
+            let (near, far) = if negative[node.axis] {
+                (node.offset, index + 1)
+            } else {
+                (index + 1, node.offset)
+            };
+            stack[size] = far;
+            stack[size + 1] = near;
+            size += 2;
+        }
+    }
+}
+
+fn component(v: Vector3, axis: usize) -> Float {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bvh;
+    use crate::{
+        bounds::Bounds3,
+        ray::Ray,
+        types::Float,
+        vector::{Point3, Vector3},
+    };
+
+    #[test]
+    fn test_bvh() {
+        // Small boxes along a curve, and one large box across them.
+        let mut bounds: Vec<Bounds3> = (0..200)
+            .map(|i| {
+                let t = i as Float * 0.05;
+                let center = Point3::new(t.cos() * 5.0, t.sin() * 5.0, t);
+                let r = Vector3::new(0.2, 0.2, 0.2);
+                Bounds3::new(center - r, center + r)
+            })
+            .collect();
+        bounds.push(Bounds3::new(
+            Point3::new(-1.0, -1.0, 0.0),
+            Point3::new(1.0, 1.0, 10.0),
+        ));
+        let bvh = Bvh::build(&bounds);
+        assert!(bvh.nodes.len() > 1);
+        let mut indices = bvh.indices.clone();
+        indices.sort();
+        assert_eq!(indices, (0..bounds.len()).collect::<Vec<_>>());
+        let total = bounds
+            .iter()
+            .fold(Bounds3::empty(), |total, b| total.union(*b));
+        assert_eq!(bvh.bounds(), total);
+
+        // Every box the ray passes through is visited, and others rarely.
+        for i in 0..50 {
+            let t = i as Float * 0.2;
+            let origin = Point3::new(0.0, 0.0, t);
+            let direction = Vector3::new(t.cos(), t.sin(), 0.1);
+            let mut ray = Ray::new(origin, direction);
+            let inverse = Vector3::new(
+                1.0 / ray.direction.x,
+                1.0 / ray.direction.y,
+                1.0 / ray.direction.z,
+            );
+            let mut visited = Vec::new();
+            bvh.traverse(&mut ray, |index, _| visited.push(index));
+            let expected: Vec<usize> = (0..bounds.len())
+                .filter(|&i| bounds[i].hit(&ray, inverse))
+                .collect();
+            assert!(expected.iter().all(|i| visited.contains(i)));
+            assert!(visited.len() < bounds.len() / 4);
+        }
+
+        // A visit that narrows the ray ends the traversal of what is beyond.
+        let mut ray = Ray::new(Point3::new(0.0, 0.0, -5.0), Vector3::new(0.0, 0.0, 1.0));
+        let mut visited = Vec::new();
+        bvh.traverse(&mut ray, |index, ray| {
+            visited.push(index);
+            ray.t_max = 5.0;
+        });
+        assert!(visited.contains(&200) && visited.len() <= 4);
+        assert!(Bvh::build(&[]).bounds().is_empty());
+    }
+}
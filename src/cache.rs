@@ -0,0 +1,487 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    mem,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        LazyLock, Mutex,
+    },
+    time::SystemTime,
+};
+
+use crate::{progress::report, spectrum::Spectrum, types::Float};
+
+const TILE_SIZE: usize = 64;
+const PAGE_SIZE: usize = 64 << 10;
+const DEFAULT_BUDGET: usize = 256 << 20;
+
+static TEXTURES: LazyLock<TextureCache> = LazyLock::new(|| TextureCache::new(DEFAULT_BUDGET));
+static GEOMETRY: LazyLock<PageCache> = LazyLock::new(|| PageCache::new(DEFAULT_BUDGET));
+
+// The cache shared by all image textures.
+pub fn textures() -> &'static TextureCache {
+    &TEXTURES
+}
+
+// The cache shared by all packed meshes.
+pub fn geometry() -> &'static PageCache {
+    &GEOMETRY
+}
+
+// Image textures are decoded on demand, a tile at a time, and the least
+// recently used tiles are evicted once the decoded texels exceed the memory
+// budget. PNG rows can only be decoded in order, so a miss decodes the band
+// of rows holding the tile, and every tile of the band is kept.
+pub struct TextureCache {
+    budget: AtomicUsize,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct CacheStatistics {
+    pub files: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub peak_bytes: usize,
+}
+
+#[derive(Default)]
+struct CacheState {
+    files: Vec<TextureFile>,
+    tiles: HashMap<TileKey, Tile>,
+    // The tiles by the time they were last used, oldest first.
+    recency: BTreeMap<u64, TileKey>,
+    clock: u64,
+    bytes: usize,
+    statistics: CacheStatistics,
+}
+
+// A texture, and the column and row of one of its tiles.
+type TileKey = (usize, usize, usize);
+
+struct TextureFile {
+    path: String,
+    width: usize,
+    height: usize,
+    gamma: Float,
+}
+
+struct Tile {
+    width: usize,
+    texels: Vec<Spectrum>,
+    last_used: u64,
+}
+
+impl TextureCache {
+    pub fn new(budget: usize) -> TextureCache {
+        TextureCache {
+            budget: AtomicUsize::new(budget),
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    pub fn set_budget(&self, budget: usize) {
+        self.budget.store(budget, Ordering::Relaxed);
+    }
+
+    // Checks that the image can be decoded and returns its index, width and
+    // height, without decoding any of it.
+    pub fn register(&self, path: &str, gamma: Float) -> Result<(usize, usize, usize), String> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(index) = state
+            .files
+            .iter()
+            .position(|f| f.path == path && f.gamma == gamma)
+        {
+            let file = &state.files[index];
+            return Ok((index, file.width, file.height));
+        }
+        let reader = open(path)?;
+        let info = reader.info();
+        if info.interlaced {
+            return Err(format!("{}: interlaced textures are not supported", path));
+        }
+        let (width, height) = (info.width as usize, info.height as usize);
+        state.files.push(TextureFile {
+            path: String::from(path),
+            width,
+            height,
+            gamma,
+        });
+        state.statistics.files = state.files.len();
+        Ok((state.files.len() - 1, width, height))
+    }
+
+    pub fn texel(&self, texture: usize, x: usize, y: usize) -> Spectrum {
+        let mut state = self.state.lock().unwrap();
+        let key = (texture, x / TILE_SIZE, y / TILE_SIZE);
+        state.clock += 1;
+        let clock = state.clock;
+        let state = &mut *state;
+        if let Some(tile) = state.tiles.get_mut(&key) {
+            state.recency.remove(&tile.last_used);
+            state.recency.insert(clock, key);
+            tile.last_used = clock;
+            state.statistics.hits += 1;
+            return tile.texels[(y % TILE_SIZE) * tile.width + x % TILE_SIZE];
+        }
+
+        state.statistics.misses += 1;
+        let file = &state.files[texture];
+        let band = decode_band(file, key.2).unwrap_or_else(|e| {
+            // A texture that could be registered but no longer decoded
+            // renders black rather than stopping the render.
+            report(&format!("warning: {}", e));
+            empty_band(file, key.2)
+        });
+        // The requested tile is inserted last, as the most recently used.
+        let (mut requested, others): (Vec<_>, Vec<_>) = band
+            .into_iter()
+            .enumerate()
+            .partition(|(column, _)| *column == key.1);
+        let (_, tile) = requested.pop().unwrap();
+        let value = tile.texels[(y % TILE_SIZE) * tile.width + x % TILE_SIZE];
+        for (column, tile) in others.into_iter().chain([(key.1, tile)]) {
+            state.insert((texture, column, key.2), tile);
+        }
+        state.evict(self.budget.load(Ordering::Relaxed));
+        value
+    }
+
+    pub fn statistics(&self) -> CacheStatistics {
+        self.state.lock().unwrap().statistics
+    }
+}
+
+impl CacheState {
+    fn insert(&mut self, key: TileKey, mut tile: Tile) {
+        self.clock += 1;
+        tile.last_used = self.clock;
+        self.bytes += tile_bytes(&tile);
+        self.recency.insert(tile.last_used, key);
+        if let Some(old) = self.tiles.insert(key, tile) {
+            self.bytes -= tile_bytes(&old);
+            self.recency.remove(&old.last_used);
+        }
+        self.statistics.peak_bytes = self.statistics.peak_bytes.max(self.bytes);
+    }
+
+    // The most recently used tile is always kept, however small the budget.
+    fn evict(&mut self, budget: usize) {
+        while self.bytes > budget && self.tiles.len() > 1 {
+            let Some((_, key)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some(tile) = self.tiles.remove(&key) {
+                self.bytes -= tile_bytes(&tile);
+                self.statistics.evictions += 1;
+            }
+        }
+    }
+}
+
+// Files are read on demand, a page at a time, and the least recently used
+// pages are evicted once they exceed the memory budget, as image textures are
+// decoded a tile at a time.
+pub struct PageCache {
+    budget: AtomicUsize,
+    state: Mutex<PageState>,
+}
+
+#[derive(Default)]
+struct PageState {
+    files: Vec<PageFile>,
+    pages: HashMap<PageKey, Page>,
+    // The pages by the time they were last used, oldest first.
+    recency: BTreeMap<u64, PageKey>,
+    clock: u64,
+    bytes: usize,
+    statistics: CacheStatistics,
+}
+
+// A file, and the index of one of its pages.
+type PageKey = (usize, u64);
+
+struct PageFile {
+    path: String,
+    file: File,
+    length: u64,
+    modified: Option<SystemTime>,
+}
+
+struct Page {
+    bytes: Vec<u8>,
+    last_used: u64,
+}
+
+impl PageCache {
+    pub fn new(budget: usize) -> PageCache {
+        PageCache {
+            budget: AtomicUsize::new(budget),
+            state: Mutex::new(PageState::default()),
+        }
+    }
+
+    pub fn set_budget(&self, budget: usize) {
+        self.budget.store(budget, Ordering::Relaxed);
+    }
+
+    // Opens the file and returns its index and length, without reading any
+    // of it. A file written again since it was registered is registered
+    // anew, so that the pages of the old one are no longer read.
+    pub fn register(&self, path: &str) -> Result<(usize, u64), String> {
+        let m = |e: std::io::Error| format!("{}: {}", path, e);
+        let file = File::open(path).map_err(m)?;
+        let metadata = file.metadata().map_err(m)?;
+        let (length, modified) = (metadata.len(), metadata.modified().ok());
+        let mut state = self.state.lock().unwrap();
+        if let Some(index) = state
+            .files
+            .iter()
+            .position(|f| f.path == path && f.length == length && f.modified == modified)
+        {
+            return Ok((index, length));
+        }
+        state.files.push(PageFile {
+            path: String::from(path),
+            file,
+            length,
+            modified,
+        });
+        state.statistics.files = state.files.len();
+        Ok((state.files.len() - 1, length))
+    }
+
+    // Fills the buffer from the file at the given offset, which the caller
+    // keeps within the file.
+    pub fn read(&self, file: usize, offset: u64, buffer: &mut [u8]) {
+        let mut state = self.state.lock().unwrap();
+        let entry = &state.files[file];
+        assert!(
+            offset + buffer.len() as u64 <= entry.length,
+            "{}: read past the end",
+            entry.path
+        );
+        let mut filled = 0;
+        while filled < buffer.len() {
+            let position = offset + filled as u64;
+            let key = (file, position / PAGE_SIZE as u64);
+            let start = (position % PAGE_SIZE as u64) as usize;
+            let page = state.page(key, self.budget.load(Ordering::Relaxed));
+            let n = usize::min(buffer.len() - filled, page.len() - start);
+            buffer[filled..filled + n].copy_from_slice(&page[start..start + n]);
+            filled += n;
+        }
+    }
+
+    pub fn statistics(&self) -> CacheStatistics {
+        self.state.lock().unwrap().statistics
+    }
+}
+
+impl PageState {
+    // The bytes of the page, read on a miss.
+    fn page(&mut self, key: PageKey, budget: usize) -> &[u8] {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some(page) = self.pages.get_mut(&key) {
+            self.recency.remove(&page.last_used);
+            self.recency.insert(clock, key);
+            page.last_used = clock;
+            self.statistics.hits += 1;
+        } else {
+            self.statistics.misses += 1;
+            // Packed files are written once and read only, so a page that can
+            // no longer be read leaves nothing sensible to render.
+            let bytes = read_page(&self.files[key.0], key.1)
+                .unwrap_or_else(|e| panic!("{}: {}", self.files[key.0].path, e));
+            self.bytes += bytes.len();
+            self.recency.insert(clock, key);
+            self.pages.insert(
+                key,
+                Page {
+                    bytes,
+                    last_used: clock,
+                },
+            );
+            self.statistics.peak_bytes = self.statistics.peak_bytes.max(self.bytes);
+            self.evict(budget);
+        }
+        &self.pages[&key].bytes
+    }
+
+    // The most recently used page is always kept, however small the budget.
+    fn evict(&mut self, budget: usize) {
+        while self.bytes > budget && self.pages.len() > 1 {
+            let Some((_, key)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some(page) = self.pages.remove(&key) {
+                self.bytes -= page.bytes.len();
+                self.statistics.evictions += 1;
+            }
+        }
+    }
+}
+
+fn read_page(file: &PageFile, page: u64) -> std::io::Result<Vec<u8>> {
+    let start = page * PAGE_SIZE as u64;
+    let mut bytes = vec![0; u64::min(PAGE_SIZE as u64, file.length - start) as usize];
+    let mut reader = &file.file;
+    reader.seek(SeekFrom::Start(start))?;
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn tile_bytes(tile: &Tile) -> usize {
+    tile.texels.len() * mem::size_of::<Spectrum>()
+}
+
+fn open(path: &str) -> Result<png::Reader<File>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    decoder.read_info().map_err(|e| format!("{}: {}", path, e))
+}
+
+// Decodes the given row of tiles, from left to right.
+fn decode_band(file: &TextureFile, row: usize) -> Result<Vec<Tile>, String> {
+    let m = |e: png::DecodingError| format!("{}: {}", file.path, e);
+    let mut reader = open(&file.path)?;
+    let channels = reader.output_color_type().0.samples();
+    let start = row * TILE_SIZE;
+    let end = usize::min(start + TILE_SIZE, file.height);
+    let mut tiles = empty_band(file, row);
+    for y in 0..end {
+        let row = reader
+            .next_row()
+            .map_err(m)?
+            .ok_or_else(|| format!("{}: truncated image", file.path))?;
+        if y < start {
+            continue;
+        }
+        for (x, texel) in row.data().chunks_exact(channels).enumerate() {
+            let value = |c: usize| (texel[c] as Float / 255.0).powf(file.gamma);
+            let spectrum = match channels {
+                1 | 2 => Spectrum::fill(value(0)),
+                _ => Spectrum {
+                    r: value(0),
+                    g: value(1),
+                    b: value(2),
+                },
+            };
+            let tile = &mut tiles[x / TILE_SIZE];
+            tile.texels[(y - start) * tile.width + x % TILE_SIZE] = spectrum;
+        }
+    }
+    Ok(tiles)
+}
+
+fn empty_band(file: &TextureFile, row: usize) -> Vec<Tile> {
+    let height = usize::min(TILE_SIZE, file.height - row * TILE_SIZE);
+    (0..file.width.div_ceil(TILE_SIZE))
+        .map(|column| {
+            let width = usize::min(TILE_SIZE, file.width - column * TILE_SIZE);
+            Tile {
+                width,
+                texels: vec![Spectrum::black(); width * height],
+                last_used: 0,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs::File, io::BufWriter};
+
+    use super::{PageCache, TextureCache, PAGE_SIZE, TILE_SIZE};
+
+    // A gray ramp along x with a red line along the top row.
+    fn write_texture(path: &str, width: usize, height: usize) {
+        let file = BufWriter::new(File::create(path).unwrap());
+        let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut data = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let value = (x % 256) as u8;
+                data.extend(if y == 0 { [255, 0, 0] } else { [value; 3] });
+            }
+        }
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&data).unwrap();
+    }
+
+    #[test]
+    fn test_texture_cache() {
+        let path = env::temp_dir().join("mmlt-test-texture-cache.png");
+        let path = path.to_str().unwrap();
+        write_texture(path, 3 * TILE_SIZE, 2 * TILE_SIZE);
+        // Room for a single band of three tiles.
+        let tile_bytes = TILE_SIZE * TILE_SIZE * std::mem::size_of::<crate::spectrum::Spectrum>();
+        let cache = TextureCache::new(3 * tile_bytes);
+        let (texture, width, height) = cache.register(path, 1.0).unwrap();
+        assert_eq!((width, height), (3 * TILE_SIZE, 2 * TILE_SIZE));
+        assert_eq!(cache.register(path, 1.0).unwrap().0, texture);
+
+        assert_eq!(cache.texel(texture, 5, 0).r, 1.0);
+        assert_eq!(cache.texel(texture, 5, 0).g, 0.0);
+        assert_eq!(cache.texel(texture, 2 * TILE_SIZE, 1).g, 128.0 / 255.0);
+        let statistics = cache.statistics();
+        assert_eq!((statistics.hits, statistics.misses), (2, 1));
+
+        // The second band evicts the first.
+        assert_eq!(cache.texel(texture, 7, TILE_SIZE + 1).g, 7.0 / 255.0);
+        let statistics = cache.statistics();
+        assert_eq!(statistics.misses, 2);
+        assert_eq!(statistics.evictions, 3);
+        assert_eq!(statistics.peak_bytes, 6 * tile_bytes);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_page_cache() {
+        let path = env::temp_dir().join("mmlt-test-page-cache.bin");
+        let path = path.to_str().unwrap();
+        let bytes: Vec<u8> = (0..2 * PAGE_SIZE + 100).map(|i| (i % 251) as u8).collect();
+        std::fs::write(path, &bytes).unwrap();
+        // Room for two pages.
+        let cache = PageCache::new(2 * PAGE_SIZE);
+        let (file, length) = cache.register(path).unwrap();
+        assert_eq!(length, bytes.len() as u64);
+        assert_eq!(cache.register(path).unwrap().0, file);
+
+        // A read across the end of a page reads both.
+        let mut buffer = [0; 16];
+        let offset = PAGE_SIZE - 8;
+        cache.read(file, offset as u64, &mut buffer);
+        assert_eq!(buffer, bytes[offset..offset + 16]);
+        cache.read(file, 3, &mut buffer[..4]);
+        assert_eq!(buffer[..4], bytes[3..7]);
+        let statistics = cache.statistics();
+        assert_eq!((statistics.hits, statistics.misses), (1, 2));
+
+        // The short last page evicts the least recently used one, the second.
+        let offset = 2 * PAGE_SIZE + 90;
+        cache.read(file, offset as u64, &mut buffer[..10]);
+        assert_eq!(buffer[..10], bytes[offset..]);
+        let statistics = cache.statistics();
+        assert_eq!(statistics.evictions, 1);
+        assert_eq!(statistics.peak_bytes, 2 * PAGE_SIZE + 100);
+        cache.read(file, 0, &mut buffer[..1]);
+        assert_eq!(cache.statistics().misses, 3);
+        cache.read(file, PAGE_SIZE as u64, &mut buffer[..1]);
+        assert_eq!(cache.statistics().misses, 4);
+
+        // A file written again is registered anew.
+        std::fs::write(path, &bytes[..100]).unwrap();
+        let (rewritten, length) = cache.register(path).unwrap();
+        assert_ne!(rewritten, file);
+        assert_eq!(length, 100);
+        std::fs::remove_file(path).unwrap();
+    }
+}
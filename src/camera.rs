@@ -1,25 +1,50 @@
-use std::{f64::consts::PI, fmt};
+use std::fmt;
+
+use crate::types::consts::PI;
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    aperture::ApertureImage,
     approx::ApproxEq,
+    bounds::Bounds3,
     geometry::Geometry,
     interaction::{CameraInteraction, Interaction},
-    ray::Ray,
+    pdf::PdfValue,
+    polarization::{Polarization, PolarizationConfig},
+    ray::{Ray, RayKind},
     sampler::Sampler,
+    scene::SceneGeometry,
+    shape::{AnyShape, Shape},
     spectrum::Spectrum,
+    types::Float,
     util,
-    vector::{Point2, Point3, Point3Config, Vector3},
+    vector::{Point2, Point3, Point3Config, Vector3, Vector3Config},
 };
 
-pub trait Camera: fmt::Debug {
-    fn importance(&self, point: Point3, direction: Vector3) -> Spectrum;
-    fn positional_pdf(&self, point: Point3) -> Option<f64>;
-    fn directional_pdf(&self, direction: Vector3) -> Option<f64>;
-    fn sample_interaction(&self, sampler: &mut dyn Sampler) -> Interaction;
-    fn intersect(&self, ray: Ray) -> Option<Interaction>;
+const ROLLING_SHUTTER_ITERATIONS: usize = 8;
+
+pub trait Camera: fmt::Debug + Send + Sync {
+    fn importance(&self, geometry: &Geometry, direction: Vector3) -> Spectrum;
+    fn positional_pdf(&self, geometry: &Geometry) -> PdfValue;
+    fn directional_pdf(&self, geometry: &Geometry, direction: Vector3) -> PdfValue;
+    fn sample_interaction(&self, sampler: &mut dyn Sampler) -> Interaction<'_>;
+    // A point on the camera through which to connect the given point.
+    fn sample_point(&self, reference: Point3, sampler: &mut dyn Sampler) -> Point3;
+    fn intersect(&self, ray: Ray) -> Option<Interaction<'_>>;
     fn id(&self) -> &String;
+    // Whether transport keeps track of polarization, and how the camera
+    // measures it.
+    fn polarization(&self) -> Option<&Polarization>;
+    // Restricts the film samples to a single pixel, for debugging it. The
+    // densities stay those of sampling the whole film, so that paths are
+    // weighted as in a full render, and the paths started from the film
+    // must account for the higher density of its samples.
+    fn lock_pixel(&mut self, x: usize, y: usize) -> Result<(), String>;
+    // Whether rays of the given kind may hit the camera.
+    fn is_visible(&self, _kind: RayKind) -> bool {
+        true
+    }
 }
 
 #[derive(Debug)]
@@ -29,36 +54,51 @@ pub struct PinholeCamera {
     v: Vector3,
     w: Vector3,
     origin: Point3,
-    distance: f64,
-    pixel_width: f64,
-    pixel_height: f64,
+    distance: Float,
+    pixel_width: Float,
+    pixel_height: Float,
+    vignetting: Option<Vignetting>,
+    // The factor of the exposure settings.
+    exposure: Float,
+    rolling_shutter: Option<RollingShutter>,
+    polarization: Option<Polarization>,
+    locked_pixel: Option<(usize, usize)>,
 }
 
 impl Camera for PinholeCamera {
-    fn importance(&self, _point: Point3, direction: Vector3) -> Spectrum {
+    fn importance(&self, _geometry: &Geometry, direction: Vector3) -> Spectrum {
         let c = direction.norm().dot(self.w);
         let a = self.pixel_width * self.pixel_height;
         let c4 = c * c * c * c;
         let d2 = self.distance * self.distance;
-        Spectrum::fill(d2 / (a * c4))
+        let v = self
+            .vignetting
+            .as_ref()
+            .map(|v| v.factor(c, self.radial_distance(direction)))
+            .unwrap_or(1.0);
+        Spectrum::fill(self.exposure * v * d2 / (a * c4))
     }
 
-    fn positional_pdf(&self, _: Point3) -> Option<f64> {
-        Some(1.0)
+    fn positional_pdf(&self, _: &Geometry) -> PdfValue {
+        PdfValue::Delta
     }
 
-    fn directional_pdf(&self, direction: Vector3) -> Option<f64> {
+    fn directional_pdf(&self, _geometry: &Geometry, direction: Vector3) -> PdfValue {
         let c = direction.norm().dot(self.w);
         let d = self.distance / c;
         let d2 = d * d;
         let a = self.pixel_width * self.pixel_height;
         let p = d2 / (a * c);
-        Some(p)
+        PdfValue::Finite(p)
     }
 
-    fn sample_interaction(&self, sampler: &mut dyn Sampler) -> Interaction {
-        let x = sampler.sample(0.0..self.pixel_width);
-        let y = sampler.sample(0.0..self.pixel_height);
+    fn sample_interaction(&self, sampler: &mut dyn Sampler) -> Interaction<'_> {
+        let (x, y) = sample_film(
+            sampler,
+            self.pixel_width,
+            self.pixel_height,
+            self.locked_pixel,
+        );
         let u = self.u * (x - self.pixel_width / 2.0);
         let v = -self.v * (y - self.pixel_height / 2.0);
         let w = self.w * self.distance;
@@ -67,17 +107,28 @@ impl Camera for PinholeCamera {
         let camera_interaction = CameraInteraction {
             camera: self,
             geometry: Geometry {
-                point: self.origin,
+                point: self.origin_at(y),
                 direction,
                 normal: self.w,
+                t: 0.0,
+                uv: Point2::new(x / self.pixel_width, y / self.pixel_height),
+                // The point stays put across the film, whose axes orient it.
+                dpdu: self.u,
+                dpdv: -self.v,
+                surface: None,
             },
             pixel_coordinates,
         };
         Interaction::Camera(camera_interaction)
     }
 
-    fn intersect(&self, ray: Ray) -> Option<Interaction> {
-        let o = self.origin - ray.origin;
+    fn sample_point(&self, reference: Point3, _sampler: &mut dyn Sampler) -> Point3 {
+        self.exposure_origin(reference)
+    }
+
+    fn intersect(&self, ray: Ray) -> Option<Interaction<'_>> {
+        let origin = self.exposure_origin(ray.origin);
+        let o = origin - ray.origin;
         let t = if ray.direction.x != 0.0 && o.x != 0.0 {
             o.x / ray.direction.x
         } else if ray.direction.y != 0.0 && o.y != 0.0 {
@@ -89,29 +140,34 @@ impl Camera for PinholeCamera {
         };
         let i = ray.origin + t * ray.direction;
         let tolerance = 1e-6;
-        if !i.approx_eq(self.origin, tolerance) {
+        if !i.approx_eq(origin, tolerance) || !ray.contains(t) {
             return None;
         }
-        let d = (ray.origin - self.origin).norm();
+        let d = (ray.origin - origin).norm();
         let screen_center = self.w * self.distance;
         let wd = self.w.dot(d);
         if wd == 0.0 {
             return None;
         }
-        let t = self.w.dot(screen_center) / wd;
-        if t <= 0.0 {
+        let s = self.w.dot(screen_center) / wd;
+        if s <= 0.0 {
             return None;
         }
-        let p = t * d - screen_center;
+        let p = s * d - screen_center;
         let px = self.u.dot(p) + self.pixel_width * 0.5;
         let py = -self.v.dot(p) + self.pixel_height * 0.5;
         if (0.0..self.pixel_width).contains(&px) && (0.0..self.pixel_height).contains(&py) {
             let camera_interaction = CameraInteraction {
                 camera: self,
                 geometry: Geometry {
-                    point: self.origin,
-                    direction: ray.origin - self.origin,
+                    point: origin,
+                    direction: ray.origin - origin,
                     normal: self.w,
+                    t,
+                    uv: Point2::new(px / self.pixel_width, py / self.pixel_height),
+                    dpdu: self.u,
+                    dpdv: -self.v,
+                    surface: None,
                 },
                 pixel_coordinates: Point2::new(px, py),
             };
@@ -125,6 +181,15 @@ impl Camera for PinholeCamera {
     fn id(&self) -> &String {
         &self.id
     }
+
+    fn polarization(&self) -> Option<&Polarization> {
+        self.polarization.as_ref()
+    }
+
+    fn lock_pixel(&mut self, x: usize, y: usize) -> Result<(), String> {
+        self.locked_pixel = Some((x, y));
+        Ok(())
+    }
 }
 
 impl PinholeCamera {
@@ -132,22 +197,50 @@ impl PinholeCamera {
         config: PinholeCameraConfig,
         image_width: usize,
         image_height: usize,
-    ) -> PinholeCamera {
-        let origin = Vector3::configure(&config.origin);
+        geometry: &SceneGeometry,
+    ) -> Result<PinholeCamera, String> {
         let fov = config.field_of_view.configure();
-        let look_at = Vector3::configure(&config.look_at);
-        PinholeCamera::new(origin, look_at, fov, image_width, image_height)
+        let (origin, look_at) = configure_view(
+            &config.origin,
+            &config.look_at,
+            config.framing.as_ref(),
+            fov,
+            image_width,
+            image_height,
+            geometry,
+        )?;
+        let mut camera = PinholeCamera::new(origin, look_at, fov, image_width, image_height);
+        camera.vignetting = config
+            .vignetting
+            .as_ref()
+            .map(Vignetting::configure)
+            .transpose()?;
+        camera.exposure = config
+            .exposure
+            .as_ref()
+            .map(ExposureConfig::factor)
+            .transpose()?
+            .unwrap_or(1.0);
+        camera.rolling_shutter = config
+            .rolling_shutter
+            .as_ref()
+            .map(RollingShutter::configure);
+        camera.polarization = config
+            .polarization
+            .as_ref()
+            .map(|c| Polarization::configure(c, camera.u, camera.v));
+        Ok(camera)
     }
 
     pub fn new(
         origin: Point3,
         look_at: Point3,
-        field_of_view: f64,
+        field_of_view: Float,
         image_width: usize,
         image_height: usize,
     ) -> PinholeCamera {
-        let pixel_width = image_width as f64;
-        let pixel_height = image_height as f64;
+        let pixel_width = image_width as Float;
+        let pixel_height = image_height as Float;
         let distance = pixel_height / (2.0 * (field_of_view / 2.0).tan());
         let direction = look_at - origin;
         let (u, v, w) = util::orthonormal_basis(direction);
@@ -160,8 +253,633 @@ impl PinholeCamera {
             distance,
             pixel_width,
             pixel_height,
+            vignetting: None,
+            exposure: 1.0,
+            rolling_shutter: None,
+            polarization: None,
+            locked_pixel: None,
+        }
+    }
+
+    // The position of the camera when the given row was exposed.
+    fn origin_at(&self, y: Float) -> Point3 {
+        match &self.rolling_shutter {
+            Some(shutter) => self.origin + shutter.offset(y / self.pixel_height),
+            None => self.origin,
+        }
+    }
+
+    // The position of the camera when the row that sees the given point was
+    // exposed. The row depends on the position in turn, so the two are
+    // found together by fixed-point iteration, which converges quickly as
+    // long as the camera moves little compared to its distance to the point.
+    fn exposure_origin(&self, point: Point3) -> Point3 {
+        if self.rolling_shutter.is_none() {
+            return self.origin;
+        }
+        let mut origin = self.origin;
+        for _ in 0..ROLLING_SHUTTER_ITERATIONS {
+            let d = point - origin;
+            let wd = self.w.dot(d);
+            if wd <= 0.0 {
+                break;
+            }
+            let y = -self.v.dot(d) * (self.distance / wd) + self.pixel_height * 0.5;
+            origin = self.origin_at(y.clamp(0.0, self.pixel_height));
+        }
+        origin
+    }
+
+    // Distance of the image plane point hit by the direction from the image
+    // center, relative to the half diagonal of the image.
+    fn radial_distance(&self, direction: Vector3) -> Float {
+        let d = direction.norm();
+        let p = d * (self.distance / d.dot(self.w));
+        let x = self.u.dot(p);
+        let y = self.v.dot(p);
+        let half_diagonal = 0.5 * Float::hypot(self.pixel_width, self.pixel_height);
+        Float::hypot(x, y) / half_diagonal
+    }
+}
+
+// A CMOS sensor reads its rows out one after the other, from the top of the
+// image to the bottom, so a camera moving during the readout sees each row
+// from a different position. The camera moves at a constant velocity, with
+// the first row exposed at its configured position. The slight change in
+// the footprint of a pixel on the scene caused by the motion is neglected.
+#[derive(Debug)]
+pub struct RollingShutter {
+    velocity: Vector3,
+    readout_time: Float,
+}
+
+impl RollingShutter {
+    pub fn configure(config: &RollingShutterConfig) -> RollingShutter {
+        RollingShutter {
+            velocity: Vector3::configure(&config.velocity),
+            readout_time: config.readout_time,
+        }
+    }
+
+    // The displacement of the camera when the row at the given fraction of
+    // the image height was exposed.
+    fn offset(&self, row: Float) -> Vector3 {
+        self.velocity * (self.readout_time * row)
+    }
+}
+
+// A camera with a lens of finite size, which keeps in focus only
+// the points at the focal distance from it. Rays leave a point on the lens
+// towards the point on the plane of focus seen through the center of the
+// lens by the film position, so the image is that of a pinhole camera at
+// the center of the lens whenever the lens radius is zero.
+#[derive(Debug)]
+pub struct ThinLensCamera {
+    id: String,
+    u: Vector3,
+    v: Vector3,
+    w: Vector3,
+    origin: Point3,
+    distance: Float,
+    pixel_width: Float,
+    pixel_height: Float,
+    lens_radius: Float,
+    focal_distance: Float,
+    aperture: Option<ApertureImage>,
+    cat_eye: Float,
+    vignetting: Option<Vignetting>,
+    // The factor of the exposure settings.
+    exposure: Float,
+    polarization: Option<Polarization>,
+    locked_pixel: Option<(usize, usize)>,
+}
+
+impl Camera for ThinLensCamera {
+    fn importance(&self, geometry: &Geometry, direction: Vector3) -> Spectrum {
+        let point = geometry.point;
+        let Some(film) = self.film_point(point, direction) else {
+            return Spectrum::black();
+        };
+        if !self.barrel_transmits(point, film) {
+            return Spectrum::black();
+        }
+        let c = direction.norm().dot(self.w);
+        let a = self.pixel_width * self.pixel_height;
+        let c4 = c * c * c * c;
+        let d2 = self.distance * self.distance;
+        let v = self
+            .vignetting
+            .as_ref()
+            .map(|v| v.factor(c, self.radial_distance(film)))
+            .unwrap_or(1.0);
+        Spectrum::fill(self.exposure * v * d2 * self.lens_density(point) / (a * c4))
+    }
+
+    fn positional_pdf(&self, geometry: &Geometry) -> PdfValue {
+        PdfValue::Finite(self.lens_density(geometry.point))
+    }
+
+    fn directional_pdf(&self, _geometry: &Geometry, direction: Vector3) -> PdfValue {
+        let c = direction.norm().dot(self.w);
+        if c <= 0.0 {
+            return PdfValue::Finite(0.0);
+        }
+        let d = self.distance / c;
+        let a = self.pixel_width * self.pixel_height;
+        PdfValue::Finite(d * d / (a * c))
+    }
+
+    fn sample_interaction(&self, sampler: &mut dyn Sampler) -> Interaction<'_> {
+        let (x, y) = sample_film(
+            sampler,
+            self.pixel_width,
+            self.pixel_height,
+            self.locked_pixel,
+        );
+        let (lens_x, lens_y) = match &self.aperture {
+            Some(aperture) => aperture.sample(sampler),
+            None => util::concentric_sample_disk(sampler),
+        };
+        let point = self.origin + (self.u * lens_x + self.v * lens_y) * self.lens_radius;
+        let u = self.u * (x - self.pixel_width / 2.0);
+        let v = -self.v * (y - self.pixel_height / 2.0);
+        let w = self.w * self.distance;
+        let focus = self.origin + (u + v + w) * (self.focal_distance / self.distance);
+        let camera_interaction = CameraInteraction {
+            camera: self,
+            geometry: Geometry {
+                point,
+                direction: (focus - point).norm(),
+                normal: self.w,
+                t: 0.0,
+                uv: Point2::new(x / self.pixel_width, y / self.pixel_height),
+                dpdu: self.u,
+                dpdv: -self.v,
+                surface: None,
+            },
+            pixel_coordinates: Point2::new(x, y),
+        };
+        Interaction::Camera(camera_interaction)
+    }
+
+    fn sample_point(&self, _reference: Point3, sampler: &mut dyn Sampler) -> Point3 {
+        self.sample_interaction(sampler).geometry().point
+    }
+
+    fn intersect(&self, ray: Ray) -> Option<Interaction<'_>> {
+        let wd = ray.direction.dot(self.w);
+        if wd >= 0.0 {
+            return None;
+        }
+        let t = (self.origin - ray.origin).dot(self.w) / wd;
+        if !ray.contains(t) {
+            return None;
+        }
+        let point = ray.origin + ray.direction * t;
+        if self.lens_density(point) == 0.0 {
+            return None;
+        }
+        let direction = ray.origin - point;
+        let film = self.film_point(point, direction)?;
+        let pixel_coordinates = self.pixel_coordinates(film);
+        let camera_interaction = CameraInteraction {
+            camera: self,
+            geometry: Geometry {
+                point,
+                direction,
+                normal: self.w,
+                t,
+                uv: Point2::new(
+                    pixel_coordinates.x / self.pixel_width,
+                    pixel_coordinates.y / self.pixel_height,
+                ),
+                dpdu: self.u,
+                dpdv: -self.v,
+                surface: None,
+            },
+            pixel_coordinates,
+        };
+        Some(Interaction::Camera(camera_interaction))
+    }
+
+    fn id(&self) -> &String {
+        &self.id
+    }
+
+    fn polarization(&self) -> Option<&Polarization> {
+        self.polarization.as_ref()
+    }
+
+    fn lock_pixel(&mut self, x: usize, y: usize) -> Result<(), String> {
+        self.locked_pixel = Some((x, y));
+        Ok(())
+    }
+}
+
+impl ThinLensCamera {
+    pub fn configure(
+        config: ThinLensCameraConfig,
+        image_width: usize,
+        image_height: usize,
+        geometry: &SceneGeometry,
+    ) -> Result<ThinLensCamera, String> {
+        if config.lens_radius <= 0.0 {
+            return Err(String::from("the lens radius must be positive"));
+        }
+        let fov = config.field_of_view.configure();
+        let (origin, look_at) = configure_view(
+            &config.origin,
+            &config.look_at,
+            config.framing.as_ref(),
+            fov,
+            image_width,
+            image_height,
+            geometry,
+        )?;
+        let focal_distance = match (&config.focus_on, config.focal_distance) {
+            (Some(id), _) => {
+                let ray = Ray::new(origin, look_at - origin);
+                geometry
+                    .distance_along(id, ray)
+                    .ok_or_else(|| format!("focus target '{}' is not an object or light", id))?
+            }
+            (None, Some(focal_distance)) => focal_distance,
+            (None, None) => (look_at - origin).len(),
+        };
+        if focal_distance <= 0.0 {
+            return Err(String::from("the focal distance must be positive"));
+        }
+        let aperture = match &config.aperture {
+            Some(path) => Some(ApertureImage::load(path)?),
+            None => None,
+        };
+        let mut camera = ThinLensCamera::new(
+            origin,
+            look_at,
+            fov,
+            image_width,
+            image_height,
+            config.lens_radius,
+            focal_distance,
+        );
+        camera.aperture = aperture;
+        camera.cat_eye = config.cat_eye.unwrap_or(0.0);
+        camera.vignetting = config
+            .vignetting
+            .as_ref()
+            .map(Vignetting::configure)
+            .transpose()?;
+        camera.exposure = config
+            .exposure
+            .as_ref()
+            .map(ExposureConfig::factor)
+            .transpose()?
+            .unwrap_or(1.0);
+        camera.polarization = config
+            .polarization
+            .as_ref()
+            .map(|c| Polarization::configure(c, camera.u, camera.v));
+        Ok(camera)
+    }
+
+    pub fn new(
+        origin: Point3,
+        look_at: Point3,
+        field_of_view: Float,
+        image_width: usize,
+        image_height: usize,
+        lens_radius: Float,
+        focal_distance: Float,
+    ) -> ThinLensCamera {
+        let pixel_width = image_width as Float;
+        let pixel_height = image_height as Float;
+        let distance = pixel_height / (2.0 * (field_of_view / 2.0).tan());
+        let (u, v, w) = util::orthonormal_basis(look_at - origin);
+        ThinLensCamera {
+            id: String::from("camera"),
+            u,
+            v,
+            w,
+            origin,
+            distance,
+            pixel_width,
+            pixel_height,
+            lens_radius,
+            focal_distance,
+            aperture: None,
+            cat_eye: 0.0,
+            vignetting: None,
+            exposure: 1.0,
+            polarization: None,
+            locked_pixel: None,
         }
     }
+
+    // The position on the lens in units of the lens radius.
+    fn lens_position(&self, point: Point3) -> (Float, Float) {
+        let p = (point - self.origin) / self.lens_radius;
+        (self.u.dot(p), self.v.dot(p))
+    }
+
+    // The density of the sampled points on the lens, per unit area.
+    fn lens_density(&self, point: Point3) -> Float {
+        let (x, y) = self.lens_position(point);
+        let area = self.lens_radius * self.lens_radius;
+        match &self.aperture {
+            Some(aperture) => aperture.density(x, y) / area,
+            None if Float::hypot(x, y) <= 1.0 => 1.0 / (PI * area),
+            None => 0.0,
+        }
+    }
+
+    // Off axis, the lens barrel occludes part of the aperture, which is
+    // modeled as a second circular stop the size of the lens displaced
+    // towards the edge of the image by `cat_eye` lens radii at its corners.
+    // What remains of a round aperture is the cat-eye shape of the bokeh
+    // towards the corners of the image.
+    fn barrel_transmits(&self, point: Point3, film: Vector3) -> bool {
+        if self.cat_eye == 0.0 {
+            return true;
+        }
+        let (x, y) = self.lens_position(point);
+        let half_diagonal = 0.5 * Float::hypot(self.pixel_width, self.pixel_height);
+        let scale = self.cat_eye / half_diagonal;
+        let offset_x = self.u.dot(film) * scale;
+        let offset_y = self.v.dot(film) * scale;
+        Float::hypot(x - offset_x, y - offset_y) <= 1.0
+    }
+
+    // The point on the film, relative to its center, that sees the given
+    // direction from the given point on the lens, if there is one.
+    fn film_point(&self, point: Point3, direction: Vector3) -> Option<Vector3> {
+        let d = direction.norm();
+        let c = d.dot(self.w);
+        if c <= 0.0 {
+            return None;
+        }
+        let focus = point + d * (self.focal_distance / c) - self.origin;
+        let film = focus * (self.distance / self.focal_distance);
+        let x = self.u.dot(film);
+        let y = self.v.dot(film);
+        let inside = x.abs() < self.pixel_width / 2.0 && y.abs() < self.pixel_height / 2.0;
+        inside.then_some(film)
+    }
+
+    fn pixel_coordinates(&self, film: Vector3) -> Point2 {
+        Point2::new(
+            self.u.dot(film) + self.pixel_width * 0.5,
+            -self.v.dot(film) + self.pixel_height * 0.5,
+        )
+    }
+
+    fn radial_distance(&self, film: Vector3) -> Float {
+        let half_diagonal = 0.5 * Float::hypot(self.pixel_width, self.pixel_height);
+        Float::hypot(self.u.dot(film), self.v.dot(film)) / half_diagonal
+    }
+}
+
+// A camera on the surface of an object, whose film is the surface coordinates
+// of its shape, for baking the light the object receives into a texture such
+// as a lightmap. Each pixel measures the irradiance of the patch of surface
+// it covers, from the hemisphere above it. Only the rays reaching the camera
+// see it, so that the object is otherwise rendered as usual.
+#[derive(Debug)]
+pub struct BakeCamera {
+    id: String,
+    shape: AnyShape,
+    pixel_width: Float,
+    pixel_height: Float,
+}
+
+impl Camera for BakeCamera {
+    // A pixel covers the area of the surface that the shape maps to its part
+    // of the film, so averaging the irradiance over it divides by the area
+    // that the surface coordinates stretch to at the point.
+    fn importance(&self, geometry: &Geometry, direction: Vector3) -> Spectrum {
+        let area = geometry.dpdu.cross(geometry.dpdv).len();
+        if direction.dot(geometry.normal) <= 0.0 || area == 0.0 {
+            return Spectrum::black();
+        }
+        Spectrum::fill(1.0 / area)
+    }
+
+    fn positional_pdf(&self, _: &Geometry) -> PdfValue {
+        PdfValue::Finite(1.0 / self.shape.area())
+    }
+
+    fn directional_pdf(&self, geometry: &Geometry, direction: Vector3) -> PdfValue {
+        let c = direction.norm().dot(geometry.normal);
+        PdfValue::Finite(Float::max(c, 0.0) / PI)
+    }
+
+    fn sample_interaction(&self, sampler: &mut dyn Sampler) -> Interaction<'_> {
+        let geometry = self.shape.sample_geometry(sampler);
+        let direction = util::cosine_sample_hemisphere(geometry.normal, sampler);
+        let camera_interaction = CameraInteraction {
+            camera: self,
+            geometry: Geometry {
+                direction,
+                // Rays leave the point as they leave the other cameras, so
+                // that the object the camera lies on is not hit again.
+                surface: None,
+                ..geometry
+            },
+            pixel_coordinates: self.pixel_coordinates(geometry.uv),
+        };
+        Interaction::Camera(camera_interaction)
+    }
+
+    fn sample_point(&self, _reference: Point3, sampler: &mut dyn Sampler) -> Point3 {
+        self.shape.sample_geometry(sampler).point
+    }
+
+    fn intersect(&self, ray: Ray) -> Option<Interaction<'_>> {
+        let geometry = self.shape.intersect(ray)?;
+        // Rays from inside the object reach its inner surface, which the
+        // camera does not see.
+        if ray.direction.dot(geometry.normal) >= 0.0 {
+            return None;
+        }
+        let camera_interaction = CameraInteraction {
+            camera: self,
+            geometry: Geometry {
+                direction: ray.origin - geometry.point,
+                surface: None,
+                ..geometry
+            },
+            pixel_coordinates: self.pixel_coordinates(geometry.uv),
+        };
+        Some(Interaction::Camera(camera_interaction))
+    }
+
+    fn id(&self) -> &String {
+        &self.id
+    }
+
+    fn polarization(&self) -> Option<&Polarization> {
+        None
+    }
+
+    // Points are sampled over the area of the surface, which cannot be
+    // restricted to the patch of a pixel.
+    fn lock_pixel(&mut self, _x: usize, _y: usize) -> Result<(), String> {
+        Err(String::from("a bake camera cannot be locked to a pixel"))
+    }
+
+    fn is_visible(&self, kind: RayKind) -> bool {
+        kind == RayKind::Camera
+    }
+}
+
+impl BakeCamera {
+    pub fn configure(
+        config: BakeCameraConfig,
+        image_width: usize,
+        image_height: usize,
+        geometry: &SceneGeometry,
+    ) -> Result<BakeCamera, String> {
+        let object = geometry
+            .objects
+            .iter()
+            .find(|object| *object.id() == config.object)
+            .ok_or_else(|| format!("bake target '{}' is not an object", config.object))?;
+        Ok(BakeCamera::new(
+            object.shape().clone(),
+            image_width,
+            image_height,
+        ))
+    }
+
+    pub fn new(shape: AnyShape, image_width: usize, image_height: usize) -> BakeCamera {
+        BakeCamera {
+            id: String::from("camera"),
+            shape,
+            pixel_width: image_width as Float,
+            pixel_height: image_height as Float,
+        }
+    }
+
+    fn pixel_coordinates(&self, uv: Point2) -> Point2 {
+        Point2::new(uv.x * self.pixel_width, uv.y * self.pixel_height)
+    }
+}
+
+// A point on the film, anywhere on it or within the pixel it is locked to.
+fn sample_film(
+    sampler: &mut dyn Sampler,
+    width: Float,
+    height: Float,
+    locked_pixel: Option<(usize, usize)>,
+) -> (Float, Float) {
+    match locked_pixel {
+        Some((x, y)) => {
+            let (x, y) = (x as Float, y as Float);
+            (sampler.sample(x..x + 1.0), sampler.sample(y..y + 1.0))
+        }
+        None => (sampler.sample(0.0..width), sampler.sample(0.0..height)),
+    }
+}
+
+// The position and target of a camera, after resolving a target given by ID
+// and framing the scene if asked to.
+fn configure_view(
+    origin: &Point3Config,
+    look_at: &TargetConfig,
+    framing: Option<&FramingConfig>,
+    field_of_view: Float,
+    image_width: usize,
+    image_height: usize,
+    geometry: &SceneGeometry,
+) -> Result<(Point3, Point3), String> {
+    let origin = Point3::configure(origin);
+    let look_at = look_at.configure(geometry)?;
+    // Without any geometry to frame, the configured view is kept.
+    let bounds = geometry.bounds;
+    match framing.filter(|_| !bounds.is_empty()) {
+        Some(framing) => {
+            let aspect_ratio = image_width as Float / image_height as Float;
+            Ok(Framing::configure(framing).frame(
+                &bounds,
+                look_at - origin,
+                field_of_view,
+                aspect_ratio,
+            ))
+        }
+        None => Ok((origin, look_at)),
+    }
+}
+
+// Frames the whole scene: the camera looks at the center of the scene bounds
+// along the configured viewing direction, backed off until the sphere
+// enclosing the bounds fits the narrower field of view, enlarged by `margin`.
+#[derive(Debug)]
+pub struct Framing {
+    margin: Float,
+}
+
+impl Framing {
+    pub fn configure(config: &FramingConfig) -> Framing {
+        Framing {
+            margin: config.margin.unwrap_or(1.1),
+        }
+    }
+
+    pub fn frame(
+        &self,
+        bounds: &Bounds3,
+        direction: Vector3,
+        field_of_view: Float,
+        aspect_ratio: Float,
+    ) -> (Point3, Point3) {
+        let look_at = bounds.center();
+        let half_height = (field_of_view / 2.0).tan();
+        let half_angle = Float::min(field_of_view / 2.0, (half_height * aspect_ratio).atan());
+        let distance = self.margin * bounds.radius() / half_angle.sin();
+        (look_at - direction.norm() * distance, look_at)
+    }
+}
+
+// Natural vignetting is the cos^4 falloff of a real lens. Mechanical
+// vignetting models the lens barrel occluding the image toward its corners,
+// fading from full transmission at `start` to none at `end`, both expressed
+// relative to the half diagonal of the image.
+#[derive(Debug)]
+pub struct Vignetting {
+    natural: bool,
+    mechanical: Option<(Float, Float)>,
+}
+
+impl Vignetting {
+    pub fn configure(config: &VignettingConfig) -> Result<Vignetting, String> {
+        if let Some(m) = &config.mechanical {
+            // Also rejects NaN, which compares false either way.
+            if !(0.0 <= m.start && m.start < m.end) {
+                return Err(String::from(
+                    "mechanical vignetting must start at or beyond 0 and before its end",
+                ));
+            }
+        }
+        Ok(Vignetting {
+            natural: config.natural.unwrap_or(true),
+            mechanical: config.mechanical.as_ref().map(|m| (m.start, m.end)),
+        })
+    }
+
+    pub fn factor(&self, cos_theta: Float, radial_distance: Float) -> Float {
+        let natural = if self.natural {
+            util::sqr(util::sqr(cos_theta))
+        } else {
+            1.0
+        };
+        let mechanical = match self.mechanical {
+            Some((start, end)) if radial_distance > start => {
+                let t = ((radial_distance - start) / (end - start)).clamp(0.0, 1.0);
+                1.0 - t * t * (3.0 - 2.0 * t)
+            }
+            _ => 1.0,
+        };
+        natural * mechanical
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -169,23 +887,152 @@ impl PinholeCamera {
 #[serde(rename_all = "snake_case")]
 pub enum CameraConfig {
     Pinhole(PinholeCameraConfig),
+    ThinLens(ThinLensCameraConfig),
+    Bake(BakeCameraConfig),
 }
 
 impl CameraConfig {
-    pub fn configure(self, image_width: usize, image_height: usize) -> impl Camera {
+    pub fn configure(
+        self,
+        image_width: usize,
+        image_height: usize,
+        geometry: &SceneGeometry,
+    ) -> Result<Box<dyn Camera>, String> {
         match self {
-            CameraConfig::Pinhole(config) => {
-                PinholeCamera::configure(config, image_width, image_height)
-            }
+            CameraConfig::Pinhole(config) => Ok(Box::new(PinholeCamera::configure(
+                config,
+                image_width,
+                image_height,
+                geometry,
+            )?)),
+            CameraConfig::ThinLens(config) => Ok(Box::new(ThinLensCamera::configure(
+                config,
+                image_width,
+                image_height,
+                geometry,
+            )?)),
+            CameraConfig::Bake(config) => Ok(Box::new(BakeCamera::configure(
+                config,
+                image_width,
+                image_height,
+                geometry,
+            )?)),
         }
     }
 }
 
+// One of several cameras of a scene, selected by name.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NamedCameraConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub camera: CameraConfig,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PinholeCameraConfig {
     origin: Point3Config,
-    look_at: Point3Config,
+    look_at: TargetConfig,
+    field_of_view: FieldOfViewConfig,
+    vignetting: Option<VignettingConfig>,
+    exposure: Option<ExposureConfig>,
+    framing: Option<FramingConfig>,
+    rolling_shutter: Option<RollingShutterConfig>,
+    polarization: Option<PolarizationConfig>,
+}
+
+// The focal distance is given either directly or as the ID of an object or
+// light to focus on, in which case it is the distance to the first point of
+// it along the view axis. Without either, the camera focuses on `look_at`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ThinLensCameraConfig {
+    origin: Point3Config,
+    look_at: TargetConfig,
     field_of_view: FieldOfViewConfig,
+    lens_radius: Float,
+    focal_distance: Option<Float>,
+    focus_on: Option<String>,
+    // A PNG image of the transmission of the aperture.
+    aperture: Option<String>,
+    cat_eye: Option<Float>,
+    vignetting: Option<VignettingConfig>,
+    exposure: Option<ExposureConfig>,
+    framing: Option<FramingConfig>,
+    polarization: Option<PolarizationConfig>,
+}
+
+// The object whose surface is baked, by its ID.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BakeCameraConfig {
+    object: String,
+}
+
+// A point given either literally or as the ID of an object or light, which
+// stands for the center of its bounds.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum TargetConfig {
+    Point(Point3Config),
+    Id(String),
+}
+
+impl TargetConfig {
+    pub fn configure(&self, geometry: &SceneGeometry) -> Result<Point3, String> {
+        match self {
+            TargetConfig::Point(config) => Ok(Point3::configure(config)),
+            TargetConfig::Id(id) => geometry
+                .bounds_of(id)
+                .map(|bounds| bounds.center())
+                .ok_or_else(|| format!("camera target '{}' is not an object or light", id)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RollingShutterConfig {
+    velocity: Vector3Config,
+    readout_time: Float,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FramingConfig {
+    margin: Option<Float>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VignettingConfig {
+    natural: Option<bool>,
+    mechanical: Option<MechanicalVignettingConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MechanicalVignettingConfig {
+    start: Float,
+    end: Float,
+}
+
+// The settings of a photographic exposure, ISO 100, one second and f/1 by
+// default, whose factor is the saturation-based exposure of ISO 12232: a
+// luminance of 1.2 * 2^EV100 maps to one, as a photograph taken with the
+// settings would have it.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExposureConfig {
+    iso: Option<Float>,
+    shutter_time: Option<Float>,
+    f_number: Option<Float>,
+}
+
+impl ExposureConfig {
+    pub fn factor(&self) -> Result<Float, String> {
+        let iso = self.iso.unwrap_or(100.0);
+        let shutter_time = self.shutter_time.unwrap_or(1.0);
+        let f_number = self.f_number.unwrap_or(1.0);
+        if iso <= 0.0 || shutter_time <= 0.0 || f_number <= 0.0 {
+            return Err(String::from("exposure settings must be positive"));
+        }
+        let ev100 = Float::log2(f_number * f_number / shutter_time * 100.0 / iso);
+        Ok(1.0 / (1.2 * Float::powf(2.0, ev100)))
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -197,31 +1044,64 @@ pub enum AngleUnitConfig {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct FieldOfViewConfig {
-    value: f64,
+    value: Float,
     unit: AngleUnitConfig,
 }
 
-impl FieldOfViewConfig {
-    pub fn configure(&self) -> f64 {
-        match self.unit {
-            AngleUnitConfig::Degrees => self.value * (PI / 180.0),
-            AngleUnitConfig::Radians => self.value,
+impl AngleUnitConfig {
+    pub fn radians(&self, value: Float) -> Float {
+        match self {
+            AngleUnitConfig::Degrees => value * (PI / 180.0),
+            AngleUnitConfig::Radians => value,
         }
     }
 }
 
+impl FieldOfViewConfig {
+    pub fn configure(&self) -> Float {
+        self.unit.radians(self.value)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::PinholeCamera;
+    use super::{
+        BakeCamera, Framing, MechanicalVignettingConfig, PinholeCamera, RollingShutter,
+        ThinLensCamera, Vignetting, VignettingConfig,
+    };
     use crate::{
-        camera::{AngleUnitConfig, Camera, FieldOfViewConfig, PinholeCameraConfig},
+        approx::{loosen, ApproxEq},
+        bounds::Bounds3,
+        camera::{
+            AngleUnitConfig, Camera, CameraConfig, ExposureConfig, FieldOfViewConfig,
+            PinholeCameraConfig, TargetConfig,
+        },
+        geometry::Geometry,
         interaction::Interaction,
-        ray::Ray,
+        object::ObjectConfig,
+        pdf::PdfValue,
+        ray::{Ray, RayKind},
         sampler::test::MockSampler,
+        scene::SceneGeometry,
+        shape::Placement,
         spectrum::Spectrum,
-        vector::{Point3, Point3Config, Vector3},
+        types::{consts::PI, Float},
+        vector::{Point2, Point3, Point3Config, Vector3},
     };
-    use std::f64::consts::PI;
+
+    // A point on a camera, for the methods that need only its position.
+    fn at(point: Point3) -> Geometry {
+        Geometry {
+            point,
+            direction: Vector3::new(0.0, 0.0, 1.0),
+            normal: Vector3::new(0.0, 0.0, 1.0),
+            t: 0.0,
+            uv: Point2::new(0.0, 0.0),
+            dpdu: Vector3::new(1.0, 0.0, 0.0),
+            dpdv: Vector3::new(0.0, 1.0, 0.0),
+            surface: None,
+        }
+    }
 
     #[test]
     fn test_pinhole_camera_configure() {
@@ -231,28 +1111,39 @@ mod tests {
                 y: 0.0,
                 z: 0.0,
             },
-            look_at: Point3Config {
+            look_at: TargetConfig::Point(Point3Config {
                 x: 0.0,
                 y: 0.0,
                 z: 50.0,
-            },
+            }),
             field_of_view: FieldOfViewConfig {
                 value: 60.0,
                 unit: AngleUnitConfig::Degrees,
             },
+            vignetting: None,
+            exposure: None,
+            framing: None,
+            rolling_shutter: None,
+            polarization: None,
         };
         let image_width = 512;
         let image_height = 512;
-        let camera = PinholeCamera::configure(config, image_width, image_height);
+        let geometry = SceneGeometry {
+            lights: &[],
+            objects: &[],
+            bounds: Bounds3::empty(),
+        };
+        let camera =
+            PinholeCamera::configure(config, image_width, image_height, &geometry).unwrap();
         assert_eq!(camera.id, "camera");
         let origin = Vector3::new(0.0, 0.0, 0.0);
         assert_eq!(camera.origin, origin);
-        let h = image_height as f64;
-        let w = image_width as f64;
+        let h = image_height as Float;
+        let w = image_width as Float;
         let field_of_view = 60.0 * PI / 180.0;
         let a = field_of_view / 2.0;
         let distance = h / (2.0 * a.tan());
-        assert_eq!(camera.distance, distance);
+        assert!((camera.distance / distance - 1.0).abs() < loosen(1e-12));
         assert_eq!(camera.pixel_height, h);
         assert_eq!(camera.pixel_width, w);
         assert_eq!(camera.u, Vector3::new(1.0, 0.0, 0.0));
@@ -271,11 +1162,11 @@ mod tests {
         let camera = PinholeCamera::new(origin, look_at, field_of_view, image_width, image_height);
         assert_eq!(camera.id, "camera");
         assert_eq!(camera.origin, origin);
-        let h = image_height as f64;
-        let w = image_width as f64;
+        let h = image_height as Float;
+        let w = image_width as Float;
         let a = field_of_view / 2.0;
         let distance = h / (2.0 * a.tan());
-        assert_eq!(camera.distance, distance);
+        assert!((camera.distance / distance - 1.0).abs() < loosen(1e-12));
         assert_eq!(camera.pixel_height, h);
         assert_eq!(camera.pixel_width, w);
         assert_eq!(camera.u, Vector3::new(1.0, 0.0, 0.0));
@@ -294,14 +1185,116 @@ mod tests {
         let d = Vector3::new(0.0, 0.25, 1.0);
         let direction = (look_at - origin).norm();
         let c = d.norm().dot(direction);
-        let w = image_width as f64;
-        let h = image_height as f64;
+        let w = image_width as Float;
+        let h = image_height as Float;
         let a = w * h;
         let half_fov = field_of_view / 2.0;
         let distance = h / (2.0 * half_fov.tan());
         let i = (distance * distance) / (a * c * c * c * c);
         let importance = Spectrum::fill(i);
-        assert_eq!(camera.importance(origin, d), importance);
+        assert!(camera
+            .importance(&at(origin), d)
+            .approx_eq(importance, loosen(1e-12)));
+    }
+
+    #[test]
+    fn test_pinhole_camera_importance_natural_vignetting() {
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let look_at = Vector3::new(0.0, 0.0, 50.0);
+        let field_of_view = 60.0 * PI / 180.0;
+        let mut camera = PinholeCamera::new(origin, look_at, field_of_view, 512, 512);
+        camera.vignetting = Some(Vignetting {
+            natural: true,
+            mechanical: None,
+        });
+        let d = Vector3::new(0.0, 0.25, 1.0);
+        let a = 512.0 * 512.0;
+        let i = (camera.distance * camera.distance) / a;
+        assert!(camera
+            .importance(&at(origin), d)
+            .approx_eq(Spectrum::fill(i), 1e-12));
+    }
+
+    #[test]
+    fn test_target_by_id() {
+        let object: ObjectConfig = serde_yaml::from_str(
+            "
+type: geometric
+id: ball
+shape: { type: sphere, center: { x: 1.0, y: 2.0, z: 3.0 }, radius: 0.5 }
+material:
+  type: matte
+  texture: { type: constant, spectrum: { r: 0.5, g: 0.5, b: 0.5 } }
+",
+        )
+        .unwrap();
+        let objects = [object.configure(None, Placement::identity()).unwrap()];
+        let geometry = SceneGeometry {
+            lights: &[],
+            objects: &objects,
+            bounds: Bounds3::empty(),
+        };
+        let target: TargetConfig = serde_yaml::from_str("ball").unwrap();
+        assert_eq!(
+            target.configure(&geometry).unwrap(),
+            Point3::new(1.0, 2.0, 3.0)
+        );
+        let target: TargetConfig = serde_yaml::from_str("{ x: 4.0, y: 5.0, z: 6.0 }").unwrap();
+        assert_eq!(
+            target.configure(&geometry).unwrap(),
+            Point3::new(4.0, 5.0, 6.0)
+        );
+        let target: TargetConfig = serde_yaml::from_str("missing").unwrap();
+        assert!(target.configure(&geometry).is_err());
+    }
+
+    #[test]
+    fn test_framing() {
+        let bounds = Bounds3::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(3.0, 1.0, 1.0));
+        let framing = Framing { margin: 1.0 };
+        let field_of_view = 60.0 * PI / 180.0;
+        let direction = Vector3::new(0.0, 0.0, -2.0);
+        let (origin, look_at) = framing.frame(&bounds, direction, field_of_view, 2.0);
+        assert_eq!(look_at, Point3::new(1.0, 0.0, 0.0));
+        // The vertical field of view is the narrower one, so the bounding
+        // sphere touches its edges.
+        let distance = bounds.radius() / (field_of_view / 2.0).sin();
+        assert!(origin.approx_eq(Point3::new(1.0, 0.0, distance), 1e-12));
+    }
+
+    #[test]
+    fn test_vignetting_mechanical() {
+        let vignetting = Vignetting {
+            natural: false,
+            mechanical: Some((0.5, 1.0)),
+        };
+        assert_eq!(vignetting.factor(0.9, 0.25), 1.0);
+        assert_eq!(vignetting.factor(0.9, 0.75), 0.5);
+        assert_eq!(vignetting.factor(0.9, 1.5), 0.0);
+    }
+
+    #[test]
+    fn test_vignetting_configure() {
+        let config = |start, end| VignettingConfig {
+            natural: None,
+            mechanical: Some(MechanicalVignettingConfig { start, end }),
+        };
+        assert!(Vignetting::configure(&config(0.0, 1.0)).is_ok());
+        assert!(Vignetting::configure(&config(1.0, 0.5)).is_err());
+        assert!(Vignetting::configure(&config(0.5, 0.5)).is_err());
+        assert!(Vignetting::configure(&config(-0.5, 1.0)).is_err());
+        assert!(Vignetting::configure(&config(Float::NAN, 1.0)).is_err());
+    }
+
+    #[test]
+    fn test_pinhole_camera_radial_distance() {
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let look_at = Vector3::new(0.0, 0.0, 50.0);
+        let field_of_view = 60.0 * PI / 180.0;
+        let camera = PinholeCamera::new(origin, look_at, field_of_view, 400, 300);
+        assert_eq!(camera.radial_distance(camera.w), 0.0);
+        let corner = camera.u * 200.0 + camera.v * 150.0 + camera.w * camera.distance;
+        assert!((camera.radial_distance(corner) - 1.0).abs() < 1e-12);
     }
 
     #[test]
@@ -315,15 +1308,16 @@ mod tests {
         let r = Vector3::new(0.0, 0.25, 1.0);
         let direction = (look_at - origin).norm();
         let c = r.norm().dot(direction);
-        let w = image_width as f64;
-        let h = image_height as f64;
+        let w = image_width as Float;
+        let h = image_height as Float;
         let a = w * h;
         let half_fov = field_of_view / 2.0;
         let distance = h / (2.0 * half_fov.tan());
         let d = distance / c;
-        let pdf = Some((d * d) / (a * c));
-        assert_eq!(camera.directional_pdf(r), pdf);
-        assert_eq!(camera.positional_pdf(origin), Some(1.0));
+        let pdf = (d * d) / (a * c);
+        let actual = camera.directional_pdf(&at(origin), r).value();
+        assert!((actual - pdf).abs() < loosen(1e-12));
+        assert_eq!(camera.positional_pdf(&at(origin)), PdfValue::Delta);
     }
 
     #[test]
@@ -341,7 +1335,7 @@ mod tests {
         let direction = (look_at - origin).norm();
         match interaction {
             Interaction::Camera(camera_interaction) => {
-                let h = image_height as f64;
+                let h = image_height as Float;
                 let half_fov = field_of_view / 2.0;
                 let distance = h / (2.0 * half_fov.tan());
                 assert_eq!(camera_interaction.pixel_coordinates.x, 256.0);
@@ -382,6 +1376,130 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_thin_lens_camera_focus() {
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let look_at = Vector3::new(0.0, 0.0, 50.0);
+        let field_of_view = 60.0 * PI / 180.0;
+        let camera = ThinLensCamera::new(origin, look_at, field_of_view, 512, 512, 2.0, 20.0);
+        // Every point on the lens sees the same point on the plane of focus
+        // through a given film position.
+        let mut focus = Vec::new();
+        for (lens_x, lens_y) in [(0.5, 0.5), (0.1, 0.8), (0.9, 0.3)] {
+            let mut sampler = MockSampler::new();
+            sampler.add(0.25);
+            sampler.add(0.75);
+            sampler.add(lens_x);
+            sampler.add(lens_y);
+            let geometry = camera.sample_interaction(&mut sampler).geometry();
+            let t = (20.0 - geometry.point.z) / geometry.direction.z;
+            focus.push(geometry.point + geometry.direction * t);
+        }
+        assert!(focus[0].approx_eq(focus[1], loosen(1e-9)));
+        assert!(focus[0].approx_eq(focus[2], loosen(1e-9)));
+
+        // A ray towards a point on the lens lands on the pixel it was
+        // sampled from.
+        let mut sampler = MockSampler::new();
+        sampler.add(0.25);
+        sampler.add(0.75);
+        sampler.add(0.1);
+        sampler.add(0.8);
+        let geometry = camera.sample_interaction(&mut sampler).geometry();
+        let ray_origin = geometry.point + geometry.direction * 30.0;
+        let ray = Ray::new(ray_origin, geometry.point - ray_origin);
+        match camera.intersect(ray) {
+            Some(Interaction::Camera(camera_interaction)) => {
+                assert!(camera_interaction
+                    .geometry
+                    .point
+                    .approx_eq(geometry.point, loosen(1e-9)));
+                let pixel_coordinates = camera_interaction.pixel_coordinates;
+                assert!((pixel_coordinates.x - 128.0).abs() < loosen(1e-6) * 128.0);
+                assert!((pixel_coordinates.y - 384.0).abs() < loosen(1e-6) * 384.0);
+            }
+            _ => panic!("expected camera interaction"),
+        }
+        let pdf = camera.positional_pdf(&geometry).value();
+        assert!((pdf - 1.0 / (4.0 * PI)).abs() < loosen(1e-12));
+    }
+
+    #[test]
+    fn test_thin_lens_camera_focus_on() {
+        let camera: CameraConfig = serde_yaml::from_str(
+            "
+type: thin_lens
+origin: { x: 0.0, y: 0.0, z: 0.0 }
+look_at: { x: 0.0, y: 0.0, z: 1.0 }
+field_of_view: { value: 60.0, unit: degrees }
+lens_radius: 0.5
+focus_on: ball
+",
+        )
+        .unwrap();
+        let object: ObjectConfig = serde_yaml::from_str(
+            "
+type: geometric
+id: ball
+shape: { type: sphere, center: { x: 0.0, y: 0.0, z: 10.0 }, radius: 2.0 }
+material:
+  type: matte
+  texture: { type: constant, spectrum: { r: 0.5, g: 0.5, b: 0.5 } }
+",
+        )
+        .unwrap();
+        let objects = [object.configure(None, Placement::identity()).unwrap()];
+        let geometry = SceneGeometry {
+            lights: &[],
+            objects: &objects,
+            bounds: Bounds3::empty(),
+        };
+        match camera {
+            CameraConfig::ThinLens(config) => {
+                let camera = ThinLensCamera::configure(config, 64, 64, &geometry).unwrap();
+                assert!((camera.focal_distance - 8.0).abs() < 1e-9);
+            }
+            _ => panic!("expected a thin lens camera"),
+        }
+    }
+
+    #[test]
+    fn test_pinhole_camera_rolling_shutter() {
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let look_at = Vector3::new(0.0, 0.0, 10.0);
+        let field_of_view = 60.0 * PI / 180.0;
+        let mut camera = PinholeCamera::new(origin, look_at, field_of_view, 512, 512);
+        camera.rolling_shutter = Some(RollingShutter {
+            velocity: Vector3::new(1.0, 0.0, 0.0),
+            readout_time: 0.5,
+        });
+        let sample = |y| {
+            let mut sampler = MockSampler::new();
+            sampler.add(0.5);
+            sampler.add(y);
+            camera.sample_interaction(&mut sampler).geometry().point
+        };
+        assert!(sample(0.0).approx_eq(origin, 1e-9));
+        assert!(sample(1.0).approx_eq(Point3::new(0.5, 0.0, 0.0), 1e-9));
+
+        // A connection through the sampled point is seen by the camera from
+        // that point, in the row that was exposed there.
+        let point = Point3::new(1.0, -2.0, 10.0);
+        let camera_point = camera.sample_point(point, &mut MockSampler::new());
+        let ray = Ray::new(point, camera_point - point);
+        match camera.intersect(ray) {
+            Some(Interaction::Camera(camera_interaction)) => {
+                assert!(camera_interaction
+                    .geometry
+                    .point
+                    .approx_eq(camera_point, 1e-9));
+                let y = camera_interaction.pixel_coordinates.y;
+                assert!(camera.origin_at(y).approx_eq(camera_point, 1e-6));
+            }
+            _ => panic!("expected camera interaction"),
+        }
+    }
+
     #[test]
     fn test_pinhole_camera_intersect_miss() {
         let origin = Point3::new(0.5, 0.1, 0.01);
@@ -390,10 +1508,98 @@ mod tests {
         let image_width = 512;
         let image_height = 512;
         let camera = PinholeCamera::new(origin, look_at, field_of_view, image_width, image_height);
-        let ray_origin = Point3::new(0.49277762278284754, 0.040182486681127116, 0.0);
+        let ray_origin = Point3::new(0.49277762, 0.040182486, 0.0);
         let ray_direction = (origin - ray_origin).norm();
         let ray = Ray::new(ray_origin, ray_direction);
         let interaction = camera.intersect(ray);
         assert!(interaction.is_none());
     }
+
+    #[test]
+    fn test_exposure_factor() {
+        let exposure = |iso, shutter_time, f_number| ExposureConfig {
+            iso: Some(iso),
+            shutter_time: Some(shutter_time),
+            f_number: Some(f_number),
+        };
+        let base = exposure(100.0, 1.0, 1.0).factor().unwrap();
+        assert!((base - 1.0 / 1.2).abs() < 1e-9);
+        let factor = exposure(400.0, 0.25, 2.0).factor().unwrap();
+        assert!((factor - base / 4.0).abs() < 1e-9);
+        assert!(exposure(100.0, 0.0, 1.0).factor().is_err());
+    }
+
+    #[test]
+    fn test_bake_camera() {
+        let object: ObjectConfig = serde_yaml::from_str(
+            "
+type: geometric
+id: ball
+shape: { type: sphere, center: { x: 1.0, y: 2.0, z: 3.0 }, radius: 0.5 }
+material:
+  type: matte
+  texture: { type: constant, spectrum: { r: 0.5, g: 0.5, b: 0.5 } }
+",
+        )
+        .unwrap();
+        let objects = [object.configure(None, Placement::identity()).unwrap()];
+        let geometry = SceneGeometry {
+            lights: &[],
+            objects: &objects,
+            bounds: Bounds3::empty(),
+        };
+        let config = serde_yaml::from_str("{ object: missing }").unwrap();
+        assert!(BakeCamera::configure(config, 64, 32, &geometry).is_err());
+        let config = serde_yaml::from_str("{ object: ball }").unwrap();
+        let mut camera = BakeCamera::configure(config, 64, 32, &geometry).unwrap();
+
+        let mut sampler = MockSampler::new();
+        sampler.add(0.25);
+        sampler.add(0.6);
+        sampler.add(0.5);
+        sampler.add(0.5);
+        let Interaction::Camera(sampled) = camera.sample_interaction(&mut sampler) else {
+            panic!("expected camera interaction");
+        };
+        let geometry = sampled.geometry;
+        // The center of the disk maps to the normal.
+        assert!(geometry.direction.approx_eq(geometry.normal, loosen(1e-12)));
+        assert_eq!(
+            sampled.pixel_coordinates,
+            Point2::new(geometry.uv.x * 64.0, geometry.uv.y * 32.0)
+        );
+        let area = geometry.dpdu.cross(geometry.dpdv).len();
+        assert!(camera
+            .importance(&geometry, geometry.normal)
+            .approx_eq(Spectrum::fill(1.0 / area), loosen(1e-12)));
+        assert_eq!(
+            camera.importance(&geometry, -geometry.normal),
+            Spectrum::black()
+        );
+        let pdf = camera.positional_pdf(&geometry).value();
+        assert!((pdf - 1.0 / PI).abs() < loosen(1e-12));
+        let pdf = camera.directional_pdf(&geometry, geometry.normal).value();
+        assert!((pdf - 1.0 / PI).abs() < loosen(1e-12));
+
+        // The point is seen from outside the object, at the same pixel, but
+        // not from inside it.
+        let outside = geometry.point + geometry.normal * 2.0;
+        match camera.intersect(Ray::new(outside, geometry.point - outside)) {
+            Some(Interaction::Camera(hit)) => {
+                assert!(hit.geometry.point.approx_eq(geometry.point, 1e-9));
+                assert!(hit
+                    .pixel_coordinates
+                    .approx_eq(sampled.pixel_coordinates, 1e-9));
+            }
+            _ => panic!("expected camera interaction"),
+        }
+        let center = Point3::new(1.0, 2.0, 3.0);
+        assert!(camera
+            .intersect(Ray::new(center, geometry.point - center))
+            .is_none());
+
+        assert!(camera.is_visible(RayKind::Camera));
+        assert!(!camera.is_visible(RayKind::Indirect));
+        assert!(camera.lock_pixel(0, 0).is_err());
+    }
 }
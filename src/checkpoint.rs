@@ -0,0 +1,306 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::Error,
+    spectrum::Spectrum,
+    types::{widen, Float},
+};
+
+const MAGIC: &[u8; 8] = b"MMLTCKP2";
+
+// The accumulated, unnormalized state of an image together with the number
+// of samples behind it, from which the image can be normalized or combined
+// with other renders of the same scene. After an 8-byte magic number, the
+// width, height and layer count follow as u32s and the sample count as a
+// u64, then the number of random seeds of the renders behind it as a u32 and
+// the seeds as u64s, then the pixels of each layer in row-major order as f64
+// RGB triples, all little-endian.
+pub struct Checkpoint {
+    pub width: usize,
+    pub height: usize,
+    pub layers: usize,
+    pub sample_count: u64,
+    pub seeds: Vec<u64>,
+    pub pixels: Vec<Spectrum>,
+}
+
+impl Checkpoint {
+    pub fn read(path: &str) -> Result<Checkpoint, Error> {
+        let bytes = fs::read(path).map_err(Error::io(path))?;
+        Checkpoint::decode(&bytes).ok_or_else(|| Error::Checkpoint {
+            path: String::from(path),
+            message: String::from("not a valid checkpoint"),
+        })
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), Error> {
+        let m = Error::io(path);
+        let file = File::create(path).map_err(m)?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(MAGIC).map_err(m)?;
+        for value in [self.width, self.height, self.layers] {
+            writer.write_all(&(value as u32).to_le_bytes()).map_err(m)?;
+        }
+        writer
+            .write_all(&self.sample_count.to_le_bytes())
+            .map_err(m)?;
+        writer
+            .write_all(&(self.seeds.len() as u32).to_le_bytes())
+            .map_err(m)?;
+        for seed in &self.seeds {
+            writer.write_all(&seed.to_le_bytes()).map_err(m)?;
+        }
+        for pixel in &self.pixels {
+            for value in [pixel.r, pixel.g, pixel.b] {
+                writer.write_all(&widen(value).to_le_bytes()).map_err(m)?;
+            }
+        }
+        writer.flush().map_err(m)
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Checkpoint> {
+        let mut reader = Reader { bytes };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return None;
+        }
+        let width = reader.u32()? as usize;
+        let height = reader.u32()? as usize;
+        let layers = reader.u32()? as usize;
+        let sample_count = reader.u64()?;
+        let seed_count = reader.u32()?;
+        let seeds = (0..seed_count)
+            .map(|_| reader.u64())
+            .collect::<Option<Vec<u64>>>()?;
+        let pixel_count = layers.checked_mul(width)?.checked_mul(height)?;
+        if reader.bytes.len() != pixel_count.checked_mul(24)? {
+            return None;
+        }
+        let pixels = (0..pixel_count)
+            .map(|_| {
+                Some(Spectrum {
+                    r: reader.f64()? as Float,
+                    g: reader.f64()? as Float,
+                    b: reader.f64()? as Float,
+                })
+            })
+            .collect::<Option<Vec<Spectrum>>>()?;
+        Some(Checkpoint {
+            width,
+            height,
+            layers,
+            sample_count,
+            seeds,
+            pixels,
+        })
+    }
+}
+
+// What a render records in the header of an EXR image so that more samples
+// can be added to it later: the samples and seeds behind it, and the sum and
+// count of the scalar contributions behind the normalization estimate of
+// each path length. Values are stored as text attributes, with floats in
+// their shortest exact form.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderMetadata {
+    pub sample_count: u64,
+    pub seeds: Vec<u64>,
+    pub normalization: Vec<(Float, u64)>,
+}
+
+impl RenderMetadata {
+    pub fn attributes(&self) -> Vec<(&'static str, String)> {
+        let join = |values: Vec<String>| values.join(",");
+        vec![
+            ("mmlt.sample_count", self.sample_count.to_string()),
+            (
+                "mmlt.seeds",
+                join(self.seeds.iter().map(u64::to_string).collect()),
+            ),
+            (
+                "mmlt.normalization",
+                join(
+                    self.normalization
+                        .iter()
+                        .map(|(sum, count)| format!("{}/{}", sum, count))
+                        .collect(),
+                ),
+            ),
+        ]
+    }
+
+    pub fn parse(attribute: impl Fn(&str) -> Option<String>) -> Option<RenderMetadata> {
+        let split = |value: String| -> Vec<String> {
+            value
+                .split(',')
+                .filter(|v| !v.is_empty())
+                .map(String::from)
+                .collect()
+        };
+        let sample_count = attribute("mmlt.sample_count")?.parse().ok()?;
+        let seeds = split(attribute("mmlt.seeds")?)
+            .iter()
+            .map(|seed| seed.parse().ok())
+            .collect::<Option<Vec<u64>>>()?;
+        let normalization = split(attribute("mmlt.normalization")?)
+            .iter()
+            .map(|pair| {
+                let (sum, count) = pair.split_once('/')?;
+                Some((sum.parse().ok()?, count.parse().ok()?))
+            })
+            .collect::<Option<Vec<(Float, u64)>>>()?;
+        Some(RenderMetadata {
+            sample_count,
+            seeds,
+            normalization,
+        })
+    }
+}
+
+// The normalization estimate of each path length from a bootstrap, with the
+// probabilities of choosing the chain of each path length that follow from
+// it and the seeds of initial states for its chains, kept in a YAML file so
+// that later renders of the same scene can skip the bootstrap.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SavedNormalization {
+    pub sample_count: u64,
+    pub b: Vec<Float>,
+    pub pdf: Vec<Float>,
+    pub states: Vec<Vec<u64>>,
+}
+
+impl SavedNormalization {
+    pub fn read(path: &str) -> Result<SavedNormalization, Error> {
+        let source = fs::read_to_string(path).map_err(Error::io(path))?;
+        let error = |message: String| Error::Checkpoint {
+            path: String::from(path),
+            message,
+        };
+        let saved: SavedNormalization =
+            serde_yaml::from_str(&source).map_err(|e| error(e.to_string()))?;
+        let lengths = [saved.pdf.len(), saved.states.len()];
+        if lengths.iter().any(|l| *l != saved.b.len()) || saved.b.iter().all(|b_k| *b_k <= 0.0) {
+            return Err(error(String::from("not a valid normalization")));
+        }
+        Ok(saved)
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), Error> {
+        let source = serde_yaml::to_string(self).map_err(|e| Error::Checkpoint {
+            path: String::from(path),
+            message: e.to_string(),
+        })?;
+        fs::write(path, source).map_err(Error::io(path))
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Option<&[u8]> {
+        if self.bytes.len() < n {
+            return None;
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Some(head)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn f64(&mut self) -> Option<f64> {
+        Some(f64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::{Checkpoint, RenderMetadata, SavedNormalization};
+    use crate::spectrum::Spectrum;
+
+    #[test]
+    fn test_checkpoint_write() {
+        let path = env::temp_dir().join("mmlt-test-checkpoint.bin");
+        let path = path.to_str().unwrap();
+        let checkpoint = Checkpoint {
+            width: 2,
+            height: 1,
+            layers: 1,
+            sample_count: 7,
+            seeds: vec![42],
+            pixels: vec![Spectrum::fill(0.5), Spectrum::fill(2.0)],
+        };
+        checkpoint.write(path).unwrap();
+        let bytes = fs::read(path).unwrap();
+        let read = Checkpoint::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(bytes.len(), 8 + 12 + 8 + 4 + 8 + 2 * 24);
+        assert_eq!(&bytes[0..8], b"MMLTCKP2");
+        assert_eq!(&bytes[8..12], &2u32.to_le_bytes());
+        assert_eq!(&bytes[20..28], &7u64.to_le_bytes());
+        assert_eq!(&bytes[32..40], &42u64.to_le_bytes());
+        assert_eq!(&bytes[64..72], &2.0f64.to_le_bytes());
+
+        assert_eq!((read.width, read.height, read.layers), (2, 1, 1));
+        assert_eq!(read.sample_count, 7);
+        assert_eq!(read.seeds, vec![42]);
+        assert_eq!(read.pixels[1].g, 2.0);
+        assert!(Checkpoint::decode(&bytes[..bytes.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn test_render_metadata() {
+        let metadata = RenderMetadata {
+            sample_count: 1 << 40,
+            seeds: vec![3, u64::MAX],
+            normalization: vec![(0.0, 0), (0.1 + 0.2, 12)],
+        };
+        let attributes = metadata.attributes();
+        let attribute = |name: &str| {
+            attributes
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, value)| value.clone())
+        };
+        assert_eq!(RenderMetadata::parse(attribute), Some(metadata));
+        assert!(RenderMetadata::parse(|name: &str| (name != "mmlt.seeds")
+            .then(|| attribute(name))
+            .flatten())
+        .is_none());
+    }
+
+    #[test]
+    fn test_saved_normalization() {
+        let path = env::temp_dir().join("mmlt-test-normalization.yml");
+        let path = path.to_str().unwrap();
+        let saved = SavedNormalization {
+            sample_count: 100,
+            b: vec![0.0, 0.25, 0.75],
+            pdf: vec![0.0, 0.25, 0.75],
+            states: vec![Vec::new(), vec![1, 2], vec![u64::MAX]],
+        };
+        saved.write(path).unwrap();
+        assert_eq!(SavedNormalization::read(path).unwrap(), saved);
+        fs::write(
+            path,
+            "sample_count: 100\nb: [0.0, 1.0]\npdf: [1.0]\nstates: [[], []]\n",
+        )
+        .unwrap();
+        assert!(SavedNormalization::read(path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
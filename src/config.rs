@@ -1,21 +1,191 @@
+use std::env;
+
+use crate::error::Error;
+use crate::integrator::{DepthMode, DEFAULT_MAX_PATH_LENGTH};
+use crate::light::LightOverrides;
+use crate::rng::RngKind;
+use crate::settings;
+use crate::types::Float;
+
 pub struct Config {
-    pub scene_path: String,
-    pub image_path: String,
+    // Each scene is rendered into the image at the same position.
+    pub scene_paths: Vec<String>,
+    pub image_paths: Vec<String>,
+    pub job_manifest_path: Option<String>,
+    pub min_path_length: Option<usize>,
     pub max_path_length: Option<usize>,
     pub initial_sample_count: Option<u64>,
     pub average_samples_per_pixel: Option<u64>,
+    pub chain_count: Option<usize>,
+    // The mutations each chain makes, without splatting, before the render.
+    pub burn_in: Option<u64>,
+    // Whether to add an image of each path length, given like the other
+    // switches with an explicit value, as `--path-length-images true`.
+    pub path_length_images: bool,
+    pub trajectory_path: Option<String>,
+    // A JSON or OBJ file of a sample of the accepted paths, and about how
+    // many of them to export.
+    pub export_path: Option<String>,
+    pub export_path_count: Option<u64>,
+    // A YAML file of the primary sample values of the proposals whose
+    // scalar contribution is at least the threshold, and one of them to
+    // replay instead of rendering.
+    pub record_proposals_path: Option<String>,
+    pub record_threshold: Option<Float>,
+    pub replay_proposals_path: Option<String>,
+    pub bootstrap_image_path: Option<String>,
+    // Files to save the normalization estimates of the bootstrap to, and to
+    // load them from instead of running it.
+    pub write_normalization_path: Option<String>,
+    pub read_normalization_path: Option<String>,
+    pub langevin_probability: Option<Float>,
+    pub caustic_perturbation_probability: Option<Float>,
+    pub technique_mutation_probability: Option<Float>,
+    pub manifold_next_event_estimation: bool,
+    // The roughness of regularized specular surfaces.
+    pub regularization: Option<Float>,
+    // The most consecutive specular bounces a subpath may take, whatever
+    // its length.
+    pub max_specular_depth: Option<usize>,
+    pub sigma: Option<Float>,
+    pub large_step_probability: Option<Float>,
+    // Whether large steps stratify the film positions of camera subpaths.
+    pub stratify_film: Option<bool>,
+    // Whether large steps stratify the technique of each path.
+    pub stratify_techniques: Option<bool>,
+    pub camera: Option<String>,
+    // The values of the variables of the scene templates, in order.
+    pub definitions: Vec<(String, String)>,
+    // Scales of lights by ID, and the lights to solo.
+    pub light_scales: Vec<(String, Float)>,
+    pub solo_lights: Vec<String>,
+    pub seed: Option<u64>,
+    pub rng: Option<RngKind>,
+    pub write_checkpoint: bool,
+    // An EXR image of an earlier render of the scene, whose samples the
+    // render adds to.
+    pub merge_into: Option<String>,
+    // In megabytes.
+    pub texture_cache_size: Option<usize>,
+    // In megabytes, for the pages of packed meshes.
+    pub geometry_cache_size: Option<usize>,
+    pub profile: bool,
+    pub threads: Option<usize>,
+    // The samples per pixel of a direct lighting preview, which replaces
+    // the MMLT render.
+    pub preview: Option<u64>,
+    pub depth: Option<DepthMode>,
+    // The pixel, as x and y, to which a render is restricted, with a log of
+    // the paths through it.
+    pub debug_pixel: Option<(usize, usize)>,
+}
+
+// `mmlt merge` combines the checkpoints of independent renders of a scene,
+// such as those of the machines of a render farm, into a single image.
+pub struct MergeConfig {
+    pub scene_path: String,
+    pub image_path: String,
+    pub checkpoint_path: Option<String>,
+    pub input_paths: Vec<String>,
+    pub definitions: Vec<(String, String)>,
+}
+
+// `mmlt pack` packs a mesh into a file from which renders read only the
+// parts they need.
+pub struct PackConfig {
+    pub mesh_path: String,
+    pub output_path: String,
+}
+
+// `mmlt serve` renders the scenes submitted over HTTP, one at a time, into
+// the given directory. Its other flags apply to every render, under those of
+// each submission.
+pub struct ServeConfig {
+    pub address: String,
+    pub directory: String,
+    pub render_args: Vec<String>,
+    pub allow_origin: Option<String>,
+    // The directory under which submitted scenes may read meshes, textures
+    // and images, if any.
+    pub asset_directory: Option<String>,
 }
 
 impl Config {
-    pub fn parse(args: Vec<String>) -> Result<Config, String> {
-        let mut scene_path: Option<String> = None;
-        let mut image_path: Option<String> = None;
+    // Render settings may also come from a settings file and the
+    // environment, which are expanded into flags first.
+    pub fn parse(args: Vec<String>) -> Result<Config, Error> {
+        Config::parse_with_env(args, env::vars())
+    }
+
+    // Parses the arguments with the given variables in place of those of
+    // the environment.
+    pub fn parse_with_env(
+        args: Vec<String>,
+        variables: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Config, Error> {
+        let args = settings::expand(args, variables)?;
+        Config::parse_flags(args).map_err(Error::Cli)
+    }
+
+    pub fn light_overrides(&self) -> LightOverrides {
+        LightOverrides {
+            scales: self.light_scales.clone(),
+            solo: self.solo_lights.clone(),
+        }
+    }
+
+    fn parse_flags(args: Vec<String>) -> Result<Config, String> {
+        let mut scene_paths: Vec<String> = Vec::new();
+        let mut image_paths: Vec<String> = Vec::new();
+        let mut job_manifest_path: Option<String> = None;
+        let mut min_path_length: Option<usize> = None;
         let mut max_path_length: Option<usize> = None;
         let mut initial_sample_count: Option<u64> = None;
         let mut average_samples_per_pixel: Option<u64> = None;
+        let mut chain_count: Option<usize> = None;
+        let mut burn_in: Option<u64> = None;
+        let mut path_length_images = false;
+        let mut trajectory_path: Option<String> = None;
+        let mut export_path: Option<String> = None;
+        let mut export_path_count: Option<u64> = None;
+        let mut record_proposals_path: Option<String> = None;
+        let mut record_threshold: Option<Float> = None;
+        let mut replay_proposals_path: Option<String> = None;
+        let mut bootstrap_image_path: Option<String> = None;
+        let mut write_normalization_path: Option<String> = None;
+        let mut read_normalization_path: Option<String> = None;
+        let mut langevin_probability: Option<Float> = None;
+        let mut caustic_perturbation_probability: Option<Float> = None;
+        let mut technique_mutation_probability: Option<Float> = None;
+        let mut manifold_next_event_estimation = false;
+        let mut regularization: Option<Float> = None;
+        let mut max_specular_depth: Option<usize> = None;
+        let mut sigma: Option<Float> = None;
+        let mut large_step_probability: Option<Float> = None;
+        let mut stratify_film: Option<bool> = None;
+        let mut stratify_techniques: Option<bool> = None;
+        let mut camera: Option<String> = None;
+        let mut definitions: Vec<(String, String)> = Vec::new();
+        let mut light_scales: Vec<(String, Float)> = Vec::new();
+        let mut solo_lights: Vec<String> = Vec::new();
+        let mut seed: Option<u64> = None;
+        let mut rng: Option<RngKind> = None;
+        let mut write_checkpoint = false;
+        let mut merge_into: Option<String> = None;
+        let mut texture_cache_size: Option<usize> = None;
+        let mut geometry_cache_size: Option<usize> = None;
+        let mut profile = false;
+        let mut threads: Option<usize> = None;
+        let mut preview: Option<u64> = None;
+        let mut depth: Option<DepthMode> = None;
+        let mut debug_pixel: Option<(usize, usize)> = None;
 
-        for chunk in args[1..].chunks(2) {
+        // Flags take one argument, except --debug-pixel, which takes two.
+        let mut index = 1;
+        while index < args.len() {
+            let chunk = &args[index..usize::min(index + 2, args.len())];
             let flag = &chunk[0];
+            index += 2;
 
             match flag.as_str() {
                 "--scene" => {
@@ -23,14 +193,32 @@ impl Config {
                         return Err(String::from("no argument for --scene provided"));
                     }
                     let value = &chunk[1];
-                    scene_path.replace(value.clone());
+                    scene_paths.push(value.clone());
                 }
                 "--image" => {
                     if chunk.len() != 2 {
                         return Err(String::from("no argument for --image provided"));
                     }
                     let value = &chunk[1];
-                    image_path.replace(value.clone());
+                    image_paths.push(value.clone());
+                }
+                "--jobs" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --jobs provided"));
+                    }
+                    let value = &chunk[1];
+                    job_manifest_path.replace(value.clone());
+                }
+                "--min-path-length" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --min-path-length provided"));
+                    }
+                    let value = &chunk[1];
+                    min_path_length.replace(
+                        value
+                            .parse()
+                            .map_err(|_| "could not parse --min-path-length value")?,
+                    );
                 }
                 "--max-path-length" => {
                     if chunk.len() != 2 {
@@ -69,25 +257,692 @@ impl Config {
                             .map_err(|_| "could not parse --average-samples-per-pixel value")?,
                     );
                 }
+                "--chain-count" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --chain-count provided"));
+                    }
+                    let value = &chunk[1];
+                    chain_count.replace(
+                        value
+                            .parse()
+                            .map_err(|_| "could not parse --chain-count value")?,
+                    );
+                }
+                "--burn-in" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --burn-in provided"));
+                    }
+                    let value = &chunk[1];
+                    burn_in.replace(
+                        value
+                            .parse()
+                            .map_err(|_| "could not parse --burn-in value")?,
+                    );
+                }
+                "--path-length-images" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from(
+                            "no argument for --path-length-images provided",
+                        ));
+                    }
+                    let value = &chunk[1];
+                    path_length_images = value
+                        .parse()
+                        .map_err(|_| "could not parse --path-length-images value")?;
+                }
+                "--trajectory" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --trajectory provided"));
+                    }
+                    let value = &chunk[1];
+                    trajectory_path.replace(value.clone());
+                }
+                "--export-paths" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --export-paths provided"));
+                    }
+                    let value = &chunk[1];
+                    export_path.replace(value.clone());
+                }
+                "--export-path-count" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --export-path-count provided"));
+                    }
+                    let value = &chunk[1];
+                    let count: u64 = value
+                        .parse()
+                        .map_err(|_| "could not parse --export-path-count value")?;
+                    if count == 0 {
+                        return Err(String::from("--export-path-count must be positive"));
+                    }
+                    export_path_count.replace(count);
+                }
+                "--record-proposals" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --record-proposals provided"));
+                    }
+                    let value = &chunk[1];
+                    record_proposals_path.replace(value.clone());
+                }
+                "--record-threshold" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --record-threshold provided"));
+                    }
+                    let value = &chunk[1];
+                    record_threshold.replace(
+                        value
+                            .parse()
+                            .map_err(|_| "could not parse --record-threshold value")?,
+                    );
+                }
+                "--replay-proposals" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --replay-proposals provided"));
+                    }
+                    let value = &chunk[1];
+                    replay_proposals_path.replace(value.clone());
+                }
+                "--bootstrap-image" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --bootstrap-image provided"));
+                    }
+                    let value = &chunk[1];
+                    bootstrap_image_path.replace(value.clone());
+                }
+                "--write-normalization" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from(
+                            "no argument for --write-normalization provided",
+                        ));
+                    }
+                    let value = &chunk[1];
+                    write_normalization_path.replace(value.clone());
+                }
+                "--read-normalization" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from(
+                            "no argument for --read-normalization provided",
+                        ));
+                    }
+                    let value = &chunk[1];
+                    read_normalization_path.replace(value.clone());
+                }
+                "--langevin-probability" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from(
+                            "no argument for --langevin-probability provided",
+                        ));
+                    }
+                    let value = &chunk[1];
+                    langevin_probability.replace(
+                        value
+                            .parse()
+                            .map_err(|_| "could not parse --langevin-probability value")?,
+                    );
+                }
+                "--caustic-perturbation-probability" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from(
+                            "no argument for --caustic-perturbation-probability provided",
+                        ));
+                    }
+                    let value = &chunk[1];
+                    caustic_perturbation_probability.replace(
+                        value.parse().map_err(|_| {
+                            "could not parse --caustic-perturbation-probability value"
+                        })?,
+                    );
+                }
+                "--technique-mutation-probability" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from(
+                            "no argument for --technique-mutation-probability provided",
+                        ));
+                    }
+                    let value = &chunk[1];
+                    technique_mutation_probability.replace(
+                        value.parse().map_err(|_| {
+                            "could not parse --technique-mutation-probability value"
+                        })?,
+                    );
+                }
+                "--manifold-next-event-estimation" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from(
+                            "no argument for --manifold-next-event-estimation provided",
+                        ));
+                    }
+                    let value = &chunk[1];
+                    manifold_next_event_estimation = value
+                        .parse()
+                        .map_err(|_| "could not parse --manifold-next-event-estimation value")?;
+                }
+                "--regularization" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --regularization provided"));
+                    }
+                    let value = &chunk[1];
+                    regularization.replace(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|roughness| *roughness > 0.0 && *roughness <= 1.0)
+                            .ok_or("could not parse --regularization value")?,
+                    );
+                }
+                "--max-specular-depth" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from(
+                            "no argument for --max-specular-depth provided",
+                        ));
+                    }
+                    let value = &chunk[1];
+                    max_specular_depth.replace(
+                        value
+                            .parse()
+                            .map_err(|_| "could not parse --max-specular-depth value")?,
+                    );
+                }
+                "--sigma" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --sigma provided"));
+                    }
+                    let value = &chunk[1];
+                    sigma.replace(value.parse().map_err(|_| "could not parse --sigma value")?);
+                }
+                "--large-step-probability" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from(
+                            "no argument for --large-step-probability provided",
+                        ));
+                    }
+                    let value = &chunk[1];
+                    large_step_probability.replace(
+                        value
+                            .parse()
+                            .map_err(|_| "could not parse --large-step-probability value")?,
+                    );
+                }
+                "--stratify-film" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --stratify-film provided"));
+                    }
+                    let value = &chunk[1];
+                    stratify_film.replace(
+                        value
+                            .parse()
+                            .map_err(|_| "could not parse --stratify-film value")?,
+                    );
+                }
+                "--stratify-techniques" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from(
+                            "no argument for --stratify-techniques provided",
+                        ));
+                    }
+                    let value = &chunk[1];
+                    stratify_techniques.replace(
+                        value
+                            .parse()
+                            .map_err(|_| "could not parse --stratify-techniques value")?,
+                    );
+                }
+                "--camera" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --camera provided"));
+                    }
+                    let value = &chunk[1];
+                    camera.replace(value.clone());
+                }
+                "--define" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --define provided"));
+                    }
+                    definitions.push(parse_definition(&chunk[1])?);
+                }
+                "--light-scale" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --light-scale provided"));
+                    }
+                    let value = &chunk[1];
+                    let scale = value
+                        .split_once('=')
+                        .and_then(|(id, scale)| Some((String::from(id), scale.parse().ok()?)))
+                        .filter(|(_, scale)| *scale >= 0.0)
+                        .ok_or("could not parse --light-scale value")?;
+                    light_scales.push(scale);
+                }
+                "--solo-light" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --solo-light provided"));
+                    }
+                    solo_lights.push(chunk[1].clone());
+                }
+                "--seed" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --seed provided"));
+                    }
+                    let value = &chunk[1];
+                    seed.replace(value.parse().map_err(|_| "could not parse --seed value")?);
+                }
+                "--rng" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --rng provided"));
+                    }
+                    let kind = RngKind::parse(&chunk[1]).ok_or("could not parse --rng value")?;
+                    rng.replace(kind);
+                }
+                "--checkpoint" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --checkpoint provided"));
+                    }
+                    let value = &chunk[1];
+                    write_checkpoint = value
+                        .parse()
+                        .map_err(|_| "could not parse --checkpoint value")?;
+                }
+                "--merge-into" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --merge-into provided"));
+                    }
+                    let value = &chunk[1];
+                    merge_into.replace(value.clone());
+                }
+                "--texture-cache-size" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from(
+                            "no argument for --texture-cache-size provided",
+                        ));
+                    }
+                    let value = &chunk[1];
+                    texture_cache_size.replace(
+                        value
+                            .parse()
+                            .map_err(|_| "could not parse --texture-cache-size value")?,
+                    );
+                }
+                "--geometry-cache-size" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from(
+                            "no argument for --geometry-cache-size provided",
+                        ));
+                    }
+                    let value = &chunk[1];
+                    geometry_cache_size.replace(
+                        value
+                            .parse()
+                            .map_err(|_| "could not parse --geometry-cache-size value")?,
+                    );
+                }
+                "--profile" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --profile provided"));
+                    }
+                    let value = &chunk[1];
+                    profile = value
+                        .parse()
+                        .map_err(|_| "could not parse --profile value")?;
+                }
+                "--threads" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --threads provided"));
+                    }
+                    let value = &chunk[1];
+                    threads.replace(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|threads| *threads > 0)
+                            .ok_or("could not parse --threads value")?,
+                    );
+                }
+                "--preview" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --preview provided"));
+                    }
+                    let value = &chunk[1];
+                    preview.replace(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|samples| *samples > 0)
+                            .ok_or("could not parse --preview value")?,
+                    );
+                }
+                "--depth" => {
+                    if chunk.len() != 2 {
+                        return Err(String::from("no argument for --depth provided"));
+                    }
+                    let mode = match chunk[1].as_str() {
+                        "metric" => DepthMode::Metric,
+                        "normalized" => DepthMode::Normalized,
+                        _ => return Err(String::from("could not parse --depth value")),
+                    };
+                    depth.replace(mode);
+                }
+                "--debug-pixel" => {
+                    let (Some(x), Some(y)) = (chunk.get(1), args.get(index)) else {
+                        return Err(String::from("no arguments for --debug-pixel provided"));
+                    };
+                    index += 1;
+                    let parse = |value: &String| {
+                        value
+                            .parse()
+                            .map_err(|_| "could not parse --debug-pixel value")
+                    };
+                    debug_pixel.replace((parse(x)?, parse(y)?));
+                }
                 _ => return Err(format!("unknown flag: {}", flag)),
             };
         }
 
+        if job_manifest_path.is_none() {
+            if scene_paths.is_empty() {
+                return Err(String::from("--scene is required"));
+            }
+            if image_paths.is_empty() {
+                return Err(String::from("--image is required"));
+            }
+        }
+        if scene_paths.len() != image_paths.len() {
+            return Err(String::from("each --scene requires a matching --image"));
+        }
+        // Manifold next event estimation solves for exactly specular
+        // interfaces, which regularization removes.
+        if regularization.is_some() && manifold_next_event_estimation {
+            return Err(String::from(
+                "--regularization cannot be combined with --manifold-next-event-estimation",
+            ));
+        }
+
+        // The shortest path joins the camera to a light directly.
+        if max_path_length.is_some_and(|max_path_length| max_path_length < 2) {
+            return Err(String::from("--max-path-length must be at least 2"));
+        }
+        if let Some(min_path_length) = min_path_length {
+            let max_path_length = max_path_length.unwrap_or(DEFAULT_MAX_PATH_LENGTH);
+            if min_path_length < 2 || min_path_length > max_path_length {
+                return Err(format!(
+                    "--min-path-length must be between 2 and the maximum path length of {}",
+                    max_path_length
+                ));
+            }
+        }
+
+        // The perturbations take their shares of the small steps, and
+        // Langevin proposals a share of what is left, so only the shares of
+        // the perturbations add up.
+        let probabilities = [
+            ("--langevin-probability", langevin_probability),
+            (
+                "--caustic-perturbation-probability",
+                caustic_perturbation_probability,
+            ),
+            (
+                "--technique-mutation-probability",
+                technique_mutation_probability,
+            ),
+            ("--large-step-probability", large_step_probability),
+        ];
+        for (flag, probability) in probabilities {
+            if probability.is_some_and(|p| !(0.0..=1.0).contains(&p)) {
+                return Err(format!("{} must be between 0 and 1", flag));
+            }
+        }
+        if caustic_perturbation_probability.unwrap_or(0.0)
+            + technique_mutation_probability.unwrap_or(0.0)
+            > 1.0
+        {
+            return Err(String::from(
+                "--caustic-perturbation-probability and --technique-mutation-probability must add up to at most 1",
+            ));
+        }
+        if sigma.is_some_and(|sigma| sigma.is_nan() || sigma <= 0.0) {
+            return Err(String::from("--sigma must be greater than 0"));
+        }
+
+        if depth.is_some() && preview.is_some() {
+            return Err(String::from("--depth cannot be combined with --preview"));
+        }
+
+        // The bootstrap image is drawn by the bootstrap that a saved
+        // normalization skips.
+        if read_normalization_path.is_some() && bootstrap_image_path.is_some() {
+            return Err(String::from(
+                "--read-normalization cannot be combined with --bootstrap-image",
+            ));
+        }
+
+        // Proposals are made by the Markov chains of an MMLT render, which a
+        // replay replaces.
+        if record_proposals_path.is_some() || replay_proposals_path.is_some() {
+            if depth.is_some() || preview.is_some() {
+                return Err(String::from(
+                    "--record-proposals and --replay-proposals cannot be combined with --depth or --preview",
+                ));
+            }
+            if record_proposals_path.is_some() && replay_proposals_path.is_some() {
+                return Err(String::from(
+                    "--record-proposals cannot be combined with --replay-proposals",
+                ));
+            }
+        }
+        if record_threshold.is_some() && record_proposals_path.is_none() {
+            return Err(String::from(
+                "--record-threshold requires --record-proposals",
+            ));
+        }
+
+        if merge_into.is_some() {
+            if depth.is_some() || preview.is_some() {
+                return Err(String::from(
+                    "--merge-into cannot be combined with --depth or --preview",
+                ));
+            }
+            if scene_paths.len() != 1 || job_manifest_path.is_some() {
+                return Err(String::from("--merge-into requires a single scene"));
+            }
+        }
+
         let config = Config {
-            scene_path: scene_path.ok_or("--scene is required")?,
-            image_path: image_path.ok_or("--image is required")?,
+            scene_paths,
+            image_paths,
+            job_manifest_path,
+            min_path_length,
             max_path_length,
             initial_sample_count,
             average_samples_per_pixel,
+            chain_count,
+            burn_in,
+            path_length_images,
+            trajectory_path,
+            export_path,
+            export_path_count,
+            record_proposals_path,
+            record_threshold,
+            replay_proposals_path,
+            bootstrap_image_path,
+            write_normalization_path,
+            read_normalization_path,
+            langevin_probability,
+            caustic_perturbation_probability,
+            technique_mutation_probability,
+            manifold_next_event_estimation,
+            regularization,
+            max_specular_depth,
+            sigma,
+            large_step_probability,
+            stratify_film,
+            stratify_techniques,
+            camera,
+            definitions,
+            light_scales,
+            solo_lights,
+            seed,
+            rng,
+            write_checkpoint,
+            merge_into,
+            texture_cache_size,
+            geometry_cache_size,
+            profile,
+            threads,
+            preview,
+            depth,
+            debug_pixel,
         };
 
         Ok(config)
     }
 }
 
+impl MergeConfig {
+    // The arguments follow the `merge` subcommand; those that are not flags
+    // name the checkpoints to merge.
+    pub fn parse(args: Vec<String>) -> Result<MergeConfig, Error> {
+        MergeConfig::parse_flags(args).map_err(Error::Cli)
+    }
+
+    fn parse_flags(args: Vec<String>) -> Result<MergeConfig, String> {
+        let mut scene_path: Option<String> = None;
+        let mut image_path: Option<String> = None;
+        let mut checkpoint_path: Option<String> = None;
+        let mut input_paths: Vec<String> = Vec::new();
+        let mut definitions: Vec<(String, String)> = Vec::new();
+
+        let mut args = args.into_iter().skip(2);
+        while let Some(arg) = args.next() {
+            let target = match arg.as_str() {
+                "--scene" => &mut scene_path,
+                "--image" => &mut image_path,
+                "--checkpoint" => &mut checkpoint_path,
+                "--define" => {
+                    let value = args.next().ok_or("no argument for --define provided")?;
+                    definitions.push(parse_definition(&value)?);
+                    continue;
+                }
+                flag if flag.starts_with("--") => {
+                    return Err(format!("unknown flag: {}", flag));
+                }
+                _ => {
+                    input_paths.push(arg);
+                    continue;
+                }
+            };
+            let value = args
+                .next()
+                .ok_or_else(|| format!("no argument for {} provided", arg))?;
+            target.replace(value);
+        }
+
+        if input_paths.is_empty() {
+            return Err(String::from("no checkpoints to merge"));
+        }
+
+        Ok(MergeConfig {
+            scene_path: scene_path.ok_or("--scene is required")?,
+            image_path: image_path.ok_or("--image is required")?,
+            checkpoint_path,
+            input_paths,
+            definitions,
+        })
+    }
+}
+
+impl PackConfig {
+    pub fn parse(args: Vec<String>) -> Result<PackConfig, Error> {
+        PackConfig::parse_flags(args).map_err(Error::Cli)
+    }
+
+    fn parse_flags(args: Vec<String>) -> Result<PackConfig, String> {
+        let mut mesh_path: Option<String> = None;
+        let mut output_path: Option<String> = None;
+
+        let mut args = args.into_iter().skip(2);
+        while let Some(arg) = args.next() {
+            let target = match arg.as_str() {
+                "--mesh" => &mut mesh_path,
+                "--output" => &mut output_path,
+                _ => return Err(format!("unknown flag: {}", arg)),
+            };
+            let value = args
+                .next()
+                .ok_or_else(|| format!("no argument for {} provided", arg))?;
+            target.replace(value);
+        }
+
+        Ok(PackConfig {
+            mesh_path: mesh_path.ok_or("--mesh is required")?,
+            output_path: output_path.ok_or("--output is required")?,
+        })
+    }
+}
+
+impl ServeConfig {
+    pub fn parse(args: Vec<String>) -> Result<ServeConfig, Error> {
+        ServeConfig::parse_flags(args).map_err(Error::Cli)
+    }
+
+    fn parse_flags(args: Vec<String>) -> Result<ServeConfig, String> {
+        let mut address: Option<String> = None;
+        let mut directory: Option<String> = None;
+        let mut allow_origin: Option<String> = None;
+        let mut asset_directory: Option<String> = None;
+        let mut render_args: Vec<String> = Vec::new();
+
+        let mut args = args.into_iter().skip(2);
+        while let Some(arg) = args.next() {
+            let target = match arg.as_str() {
+                "--address" => &mut address,
+                "--directory" => &mut directory,
+                "--allow-origin" => &mut allow_origin,
+                "--asset-directory" => &mut asset_directory,
+                "--scene" | "--image" | "--jobs" => {
+                    return Err(format!("{} is given by each submission", arg));
+                }
+                _ => {
+                    render_args.push(arg);
+                    continue;
+                }
+            };
+            let value = args
+                .next()
+                .ok_or_else(|| format!("no argument for {} provided", arg))?;
+            target.replace(value);
+        }
+
+        Ok(ServeConfig {
+            address: address.unwrap_or_else(|| String::from("127.0.0.1:8080")),
+            directory: directory.ok_or("--directory is required")?,
+            render_args,
+            allow_origin,
+            asset_directory,
+        })
+    }
+}
+
+// A definition of a variable of the scene templates, as `name=value`.
+fn parse_definition(definition: &str) -> Result<(String, String), String> {
+    match definition.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((String::from(name), String::from(value))),
+        _ => Err(format!("could not parse --define value '{}'", definition)),
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Config;
+    use super::{Config, MergeConfig, PackConfig, ServeConfig};
+    use crate::integrator::DepthMode;
+    use crate::{error::Error, rng::RngKind};
+
+    // Parses the arguments of a render of a.yml into a.pfm followed by the
+    // given flags.
+    fn parse(flags: &[&str]) -> Result<Config, Error> {
+        let args = ["mmlt", "--scene", "a.yml", "--image", "a.pfm"];
+        Config::parse_with_env(
+            args.iter().chain(flags).map(|s| String::from(*s)).collect(),
+            [],
+        )
+    }
 
     #[test]
     fn test_parse() {
@@ -100,8 +955,283 @@ mod tests {
             String::from("--image"),
             String::from(image_path),
         ];
-        let config = Config::parse(args).unwrap();
-        assert_eq!(config.scene_path, String::from(scene_path));
-        assert_eq!(config.image_path, String::from(image_path));
+        let config = Config::parse_with_env(args, []).unwrap();
+        assert_eq!(config.scene_paths, vec![String::from(scene_path)]);
+        assert_eq!(config.image_paths, vec![String::from(image_path)]);
+        assert!(!config.path_length_images);
+        assert!(config.camera.is_none());
+    }
+
+    #[test]
+    fn test_parse_merge() {
+        let args = [
+            "mmlt",
+            "merge",
+            "a.checkpoint",
+            "--scene",
+            "s.yml",
+            "--image",
+            "m.exr",
+            "b.checkpoint",
+        ];
+        let args = args.iter().map(|s| String::from(*s)).collect();
+        let config = MergeConfig::parse(args).unwrap();
+        assert_eq!(config.input_paths, vec!["a.checkpoint", "b.checkpoint"]);
+        assert_eq!(config.image_path, "m.exr");
+        assert!(config.checkpoint_path.is_none());
+    }
+
+    #[test]
+    fn test_parse_serve() {
+        let args = [
+            "mmlt",
+            "serve",
+            "--directory",
+            "jobs",
+            "--max-path-length",
+            "8",
+        ];
+        let args = args.iter().map(|s| String::from(*s)).collect();
+        let config = ServeConfig::parse(args).unwrap();
+        assert_eq!(config.address, "127.0.0.1:8080");
+        assert_eq!(config.directory, "jobs");
+        assert_eq!(config.render_args, vec!["--max-path-length", "8"]);
+        assert!(config.allow_origin.is_none());
+        assert!(config.asset_directory.is_none());
+        let args = ["mmlt", "serve", "--directory", "jobs", "--scene", "a.yml"];
+        let args = args.iter().map(|s| String::from(*s)).collect();
+        assert!(ServeConfig::parse(args).is_err());
+    }
+
+    #[test]
+    fn test_parse_pack() {
+        let args = ["mmlt", "pack", "--mesh", "a.obj", "--output", "a.mesh"];
+        let args = args.iter().map(|s| String::from(*s)).collect();
+        let config = PackConfig::parse(args).unwrap();
+        assert_eq!(config.mesh_path, "a.obj");
+        assert_eq!(config.output_path, "a.mesh");
+        let args = ["mmlt", "pack", "--mesh", "a.obj", "--seed", "1"];
+        let args = args.iter().map(|s| String::from(*s)).collect();
+        assert!(PackConfig::parse(args).is_err());
+
+        let config = parse(&["--geometry-cache-size", "64"]).unwrap();
+        assert_eq!(config.geometry_cache_size, Some(64));
+        assert!(parse(&["--geometry-cache-size", "lots"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_unmatched_scene() {
+        assert!(parse(&["--scene", "b.yml"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_regularization() {
+        let config = parse(&["--regularization", "0.2"]).unwrap();
+        assert_eq!(config.regularization, Some(0.2));
+        assert!(parse(&[
+            "--regularization",
+            "0.2",
+            "--manifold-next-event-estimation",
+            "true"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_min_path_length() {
+        let config = parse(&["--min-path-length", "4"]).unwrap();
+        assert_eq!(config.min_path_length, Some(4));
+        assert!(parse(&["--min-path-length", "1"]).is_err());
+        assert!(parse(&["--min-path-length", "21"]).is_err());
+        assert!(parse(&["--min-path-length", "6", "--max-path-length", "5"]).is_err());
+        assert!(parse(&["--min-path-length", "5", "--max-path-length", "5"]).is_ok());
+        assert!(parse(&["--max-path-length", "1"]).is_err());
+        assert!(parse(&["--max-path-length", "0"]).is_err());
+        assert!(parse(&["--max-path-length", "2"]).is_ok());
+    }
+
+    #[test]
+    fn test_parse_mutation_probabilities() {
+        let config = parse(&[
+            "--caustic-perturbation-probability",
+            "0.25",
+            "--technique-mutation-probability",
+            "0.75",
+            "--langevin-probability",
+            "1",
+        ])
+        .unwrap();
+        assert_eq!(config.caustic_perturbation_probability, Some(0.25));
+        assert_eq!(config.technique_mutation_probability, Some(0.75));
+        assert_eq!(config.langevin_probability, Some(1.0));
+        assert!(parse(&["--langevin-probability", "-0.1"]).is_err());
+        assert!(parse(&["--caustic-perturbation-probability", "1.5"]).is_err());
+        assert!(parse(&["--technique-mutation-probability", "NaN"]).is_err());
+        assert!(parse(&["--large-step-probability", "2"]).is_err());
+        assert!(parse(&[
+            "--caustic-perturbation-probability",
+            "0.5",
+            "--technique-mutation-probability",
+            "0.6",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_sigma() {
+        assert_eq!(parse(&["--sigma", "0.05"]).unwrap().sigma, Some(0.05));
+        assert!(parse(&["--sigma", "0"]).is_err());
+        assert!(parse(&["--sigma", "-0.01"]).is_err());
+        assert!(parse(&["--sigma", "NaN"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_define() {
+        assert_eq!(
+            parse(&["--define", "radius=2.0", "--define", "name=a=b"])
+                .unwrap()
+                .definitions,
+            [
+                (String::from("radius"), String::from("2.0")),
+                (String::from("name"), String::from("a=b"))
+            ]
+        );
+        assert!(parse(&["--define", "radius"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_burn_in() {
+        assert_eq!(parse(&["--burn-in", "64"]).unwrap().burn_in, Some(64));
+    }
+
+    #[test]
+    fn test_parse_max_specular_depth() {
+        assert_eq!(parse(&[]).unwrap().max_specular_depth, None);
+        let config = parse(&["--max-specular-depth", "4"]).unwrap();
+        assert_eq!(config.max_specular_depth, Some(4));
+        assert!(parse(&["--max-specular-depth", "-1"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_preview() {
+        assert!(parse(&["--preview"]).is_err());
+        assert!(parse(&["--preview", "0"]).is_err());
+        assert_eq!(parse(&["--preview", "16"]).unwrap().preview, Some(16));
+    }
+
+    #[test]
+    fn test_parse_debug_pixel() {
+        let args = [
+            "mmlt",
+            "--debug-pixel",
+            "12",
+            "34",
+            "--scene",
+            "a.yml",
+            "--image",
+            "a.pfm",
+        ];
+        let args: Vec<String> = args.iter().map(|s| String::from(*s)).collect();
+        let config = Config::parse_with_env(args.clone(), []).unwrap();
+        assert_eq!(config.debug_pixel, Some((12, 34)));
+        assert_eq!(config.scene_paths, vec![String::from("a.yml")]);
+        let truncated = args[..3].to_vec();
+        assert!(Config::parse_with_env(truncated, []).is_err());
+    }
+
+    #[test]
+    fn test_parse_export_paths() {
+        let export = ["--export-paths", "paths.obj", "--export-path-count"];
+        let config = parse(&[&export[..], &["50"]].concat()).unwrap();
+        assert_eq!(config.export_path, Some(String::from("paths.obj")));
+        assert_eq!(config.export_path_count, Some(50));
+        assert!(parse(&[&export[..], &["0"]].concat()).is_err());
+    }
+
+    #[test]
+    fn test_parse_record_proposals() {
+        let record = ["--record-proposals", "p.yml", "--record-threshold", "2.5"];
+        let config = parse(&record).unwrap();
+        assert_eq!(config.record_proposals_path.as_deref(), Some("p.yml"));
+        assert_eq!(config.record_threshold, Some(2.5));
+        let replay = ["--replay-proposals", "p.yml"];
+        assert!(parse(&[&record[..], &replay].concat()).is_err());
+        let config = parse(&replay).unwrap();
+        assert_eq!(config.replay_proposals_path.as_deref(), Some("p.yml"));
+        assert!(parse(&[&replay[..], &["--preview", "4"]].concat()).is_err());
+        assert!(parse(&["--record-threshold", "1"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_rng() {
+        let config = parse(&["--rng", "xoshiro"]).unwrap();
+        assert_eq!(config.rng, Some(RngKind::Xoshiro));
+        assert!(parse(&["--rng", "mt19937"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_normalization() {
+        let config = parse(&["--write-normalization", "b.yml"]).unwrap();
+        assert_eq!(config.write_normalization_path.as_deref(), Some("b.yml"));
+        assert!(config.read_normalization_path.is_none());
+        let read = ["--read-normalization", "b.yml"];
+        let config = parse(&read).unwrap();
+        assert_eq!(config.read_normalization_path.as_deref(), Some("b.yml"));
+        assert!(parse(&[&read[..], &["--bootstrap-image", "b.pfm"]].concat()).is_err());
+    }
+
+    #[test]
+    fn test_parse_merge_into() {
+        let args = [
+            "mmlt",
+            "--scene",
+            "a.yml",
+            "--image",
+            "b.exr",
+            "--merge-into",
+            "a.exr",
+        ];
+        let args: Vec<String> = args.iter().map(|s| String::from(*s)).collect();
+        let config = Config::parse_with_env(args.clone(), []).unwrap();
+        assert_eq!(config.merge_into.as_deref(), Some("a.exr"));
+        let mut preview = args.clone();
+        preview.extend(["--preview", "4"].map(String::from));
+        assert!(Config::parse_with_env(preview, []).is_err());
+        let mut batch = args;
+        batch.extend(["--scene", "c.yml", "--image", "c.exr"].map(String::from));
+        assert!(Config::parse_with_env(batch, []).is_err());
+    }
+
+    #[test]
+    fn test_parse_stratify_film() {
+        assert_eq!(parse(&[]).unwrap().stratify_film, None);
+        let config = parse(&["--stratify-film", "true"]).unwrap();
+        assert_eq!(config.stratify_film, Some(true));
+        assert!(parse(&["--stratify-film", "yes"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_stratify_techniques() {
+        assert_eq!(parse(&[]).unwrap().stratify_techniques, None);
+        let config = parse(&["--stratify-techniques", "true"]).unwrap();
+        assert_eq!(config.stratify_techniques, Some(true));
+        assert!(parse(&["--stratify-techniques", "yes"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_depth() {
+        let config = parse(&["--depth", "metric"]).unwrap();
+        assert_eq!(config.depth, Some(DepthMode::Metric));
+        assert!(parse(&["--depth", "linear"]).is_err());
+        assert!(parse(&["--depth", "normalized", "--preview", "4"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_light_overrides() {
+        let config = parse(&["--light-scale", "key=2.5", "--solo-light", "key"]).unwrap();
+        let overrides = config.light_overrides();
+        assert_eq!(overrides.scales, [(String::from("key"), 2.5)]);
+        assert_eq!(overrides.solo, ["key"]);
+        assert!(parse(&["--light-scale", "key=-1"]).is_err());
     }
 }
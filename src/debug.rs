@@ -0,0 +1,79 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    sync::Mutex,
+};
+
+use crate::{error::Error, types::Float, vector::Point2};
+
+// Restricts a render to the paths through a single pixel and logs every path
+// generated through it, for tracking down where its value comes from. The
+// first error in writing the log ends it, and is reported when it finishes.
+pub struct PixelDebug {
+    x: usize,
+    y: usize,
+    pixel_count: usize,
+    path: String,
+    writer: Mutex<Result<BufWriter<File>, io::Error>>,
+}
+
+impl PixelDebug {
+    pub fn create(x: usize, y: usize, pixel_count: usize, path: &str) -> Result<PixelDebug, Error> {
+        let file = File::create(path).map_err(Error::io(path))?;
+        Ok(PixelDebug {
+            x,
+            y,
+            pixel_count,
+            path: String::from(path),
+            writer: Mutex::new(Ok(BufWriter::new(file))),
+        })
+    }
+
+    pub fn contains(&self, coordinates: Point2) -> bool {
+        coordinates.x.floor() == self.x as Float && coordinates.y.floor() == self.y as Float
+    }
+
+    // The camera samples only the pixel, so that the paths it starts are
+    // that many times denser than in a full render.
+    pub fn camera_density(&self) -> Float {
+        self.pixel_count as Float
+    }
+
+    pub fn log(&self, record: &str) {
+        let mut state = self.writer.lock().unwrap();
+        if let Ok(writer) = state.as_mut() {
+            if let Err(e) = writer.write_all(record.as_bytes()) {
+                *state = Err(e);
+            }
+        }
+    }
+
+    pub fn finish(self) -> Result<(), Error> {
+        let result = self.writer.into_inner().unwrap();
+        result
+            .and_then(|mut writer| writer.flush())
+            .map_err(Error::io(&self.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::PixelDebug;
+    use crate::vector::Point2;
+
+    #[test]
+    fn test_pixel_debug() {
+        let path = env::temp_dir().join("mmlt-test-pixel-debug.log");
+        let path = path.to_str().unwrap();
+        let debug = PixelDebug::create(3, 4, 12, path).unwrap();
+        assert!(debug.contains(Point2::new(3.5, 4.0)));
+        assert!(!debug.contains(Point2::new(4.0, 4.5)));
+        debug.log("a\n");
+        debug.log("b\n");
+        debug.finish().unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "a\nb\n");
+        fs::remove_file(path).unwrap();
+    }
+}
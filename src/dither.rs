@@ -0,0 +1,194 @@
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::Float;
+
+const BLUE_NOISE_SIZE: usize = 64;
+const BLUE_NOISE_SIGMA: Float = 1.5;
+// The fraction of the blue noise pattern seeded before it is relaxed.
+const INITIAL_DENSITY: Float = 0.1;
+
+// Noise added to display-encoded values before they are quantized to 8 or 16
+// bits, so that smooth gradients do not band. Triangular noise of two steps'
+// width removes banding at no cost in bias; blue noise does so with its noise
+// at high frequencies, where it is least visible.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Dither {
+    #[default]
+    None,
+    Triangular,
+    BlueNoise,
+}
+
+impl Dither {
+    // Quantizes a value in [0, 1] to an integer in [0, max], for a channel of
+    // a pixel. The noise depends only on its position, so writing an image
+    // twice gives the same file.
+    pub fn quantize(self, value: Float, max: Float, x: usize, y: usize, channel: usize) -> Float {
+        let offset = match self {
+            Dither::None => 0.5,
+            Dither::Triangular => {
+                let (u, v) = hash_uniforms(x, y, channel);
+                u + v - 0.5
+            }
+            // The channels read the pattern at distant offsets, so that their
+            // noise is not correlated into gray.
+            Dither::BlueNoise => blue_noise(x + 23 * channel, y + 41 * channel),
+        };
+        (value * max + offset).floor().clamp(0.0, max)
+    }
+}
+
+// Two uniform values in [0, 1) for a channel of a pixel.
+fn hash_uniforms(x: usize, y: usize, channel: usize) -> (Float, Float) {
+    let h = splitmix(((y as u64) << 34) ^ ((x as u64) << 2) ^ channel as u64);
+    let to_unit = |bits: u64| (bits >> 40) as Float / (1u64 << 24) as Float;
+    (to_unit(h), to_unit(splitmix(h)))
+}
+
+fn splitmix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+// A threshold in (0, 1) from a tiled blue noise pattern.
+fn blue_noise(x: usize, y: usize) -> Float {
+    static PATTERN: OnceLock<Vec<Float>> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE));
+    pattern[(y % BLUE_NOISE_SIZE) * BLUE_NOISE_SIZE + x % BLUE_NOISE_SIZE]
+}
+
+// Ulichney's void-and-cluster method: the pixels of a toroidal pattern are
+// ranked by adding each to the largest void of those before it, after
+// relaxing a sparse initial pattern until no point is in a tighter cluster
+// than the largest void. The ranks are the thresholds.
+fn void_and_cluster(size: usize) -> Vec<Float> {
+    let n = size * size;
+    // The energy each point adds at each toroidal offset.
+    let kernel: Vec<Float> = (0..n)
+        .map(|i| {
+            let wrap = |d: usize| usize::min(d, size - d) as Float;
+            let (dx, dy) = (wrap(i % size), wrap(i / size));
+            Float::exp(-(dx * dx + dy * dy) / (2.0 * BLUE_NOISE_SIGMA * BLUE_NOISE_SIGMA))
+        })
+        .collect();
+    let mut energy = vec![0.0; n];
+    let mut set = vec![false; n];
+    let update = |energy: &mut [Float], set: &mut [bool], i: usize, add: bool| {
+        set[i] = add;
+        let sign = if add { 1.0 } else { -1.0 };
+        let (x, y) = (i % size, i / size);
+        for (j, e) in energy.iter_mut().enumerate() {
+            let dx = (j % size + size - x) % size;
+            let dy = (j / size + size - y) % size;
+            *e += sign * kernel[dy * size + dx];
+        }
+    };
+    let tightest_cluster = |energy: &[Float], set: &[bool]| {
+        (0..n)
+            .filter(|i| set[*i])
+            .max_by(|a, b| energy[*a].total_cmp(&energy[*b]))
+            .unwrap()
+    };
+    let largest_void = |energy: &[Float], set: &[bool]| {
+        (0..n)
+            .filter(|i| !set[*i])
+            .min_by(|a, b| energy[*a].total_cmp(&energy[*b]))
+            .unwrap()
+    };
+
+    let initial_count = (INITIAL_DENSITY * n as Float) as usize;
+    let mut state = 1u64;
+    let mut count = 0;
+    while count < initial_count {
+        state = splitmix(state);
+        let i = (state % n as u64) as usize;
+        if !set[i] {
+            update(&mut energy, &mut set, i, true);
+            count += 1;
+        }
+    }
+    loop {
+        let cluster = tightest_cluster(&energy, &set);
+        update(&mut energy, &mut set, cluster, false);
+        let void = largest_void(&energy, &set);
+        update(&mut energy, &mut set, void, true);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; n];
+    let (initial_set, initial_energy) = (set.clone(), energy.clone());
+    for rank in (0..initial_count).rev() {
+        let cluster = tightest_cluster(&energy, &set);
+        update(&mut energy, &mut set, cluster, false);
+        ranks[cluster] = rank;
+    }
+    let (mut set, mut energy) = (initial_set, initial_energy);
+    for rank in initial_count..n {
+        let void = largest_void(&energy, &set);
+        update(&mut energy, &mut set, void, true);
+        ranks[void] = rank;
+    }
+    ranks
+        .into_iter()
+        .map(|rank| (rank as Float + 0.5) / n as Float)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{blue_noise, Dither, BLUE_NOISE_SIZE};
+    use crate::types::Float;
+
+    #[test]
+    fn test_quantize() {
+        assert_eq!(Dither::None.quantize(0.5, 255.0, 3, 4, 0), 128.0);
+        assert_eq!(Dither::None.quantize(1.0, 255.0, 3, 4, 0), 255.0);
+
+        // A value between two levels is dithered to either of them, in the
+        // proportion that preserves its mean.
+        let value = 100.3 / 255.0;
+        for dither in [Dither::Triangular, Dither::BlueNoise] {
+            let mut sum = 0.0;
+            for y in 0..BLUE_NOISE_SIZE {
+                for x in 0..BLUE_NOISE_SIZE {
+                    let q = dither.quantize(value, 255.0, x, y, 1);
+                    assert!((99.0..=101.0).contains(&q));
+                    sum += q;
+                }
+            }
+            let mean = sum / (BLUE_NOISE_SIZE * BLUE_NOISE_SIZE) as Float;
+            assert!((mean - 100.3).abs() < 0.05, "{:?} {}", dither, mean);
+        }
+        assert_eq!(Dither::Triangular.quantize(0.0, 255.0, 7, 9, 2), 0.0);
+        assert_eq!(Dither::BlueNoise.quantize(1.0, 65535.0, 7, 9, 2), 65535.0);
+    }
+
+    #[test]
+    fn test_blue_noise() {
+        // Every threshold appears once in a tile.
+        let n = BLUE_NOISE_SIZE * BLUE_NOISE_SIZE;
+        let mut ranks: Vec<usize> = (0..n)
+            .map(|i| (blue_noise(i % BLUE_NOISE_SIZE, i / BLUE_NOISE_SIZE) * n as Float) as usize)
+            .collect();
+        ranks.sort();
+        assert_eq!(ranks, (0..n).collect::<Vec<_>>());
+
+        // Neighboring thresholds differ by more than those of white noise,
+        // for which the mean absolute difference would be a third.
+        let difference: Float = (0..n)
+            .map(|i| {
+                let (x, y) = (i % BLUE_NOISE_SIZE, i / BLUE_NOISE_SIZE);
+                (blue_noise(x, y) - blue_noise(x + 1, y)).abs()
+            })
+            .sum::<Float>()
+            / n as Float;
+        assert!(difference > 0.4, "{}", difference);
+    }
+}
@@ -0,0 +1,591 @@
+use std::fmt;
+
+use crate::types::consts::PI;
+
+use exr::prelude::read_first_flat_layer_from_file;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bounds::Bounds3,
+    geometry::Geometry,
+    interaction::{Interaction, LightInteraction},
+    light::Light,
+    pdf::{Pdf, PdfValue},
+    ray::Ray,
+    sampler::Sampler,
+    shape::{Placement, RotationConfig, Shape, Sphere},
+    spectrum::Spectrum,
+    types::Float,
+    util,
+    vector::{Matrix4, Point3, Quaternion, Vector3},
+};
+
+// The radius of the sphere on which the environment lies, relative to that of
+// the sphere enclosing the scene and the camera, so that the environment is
+// seen in nearly the same direction from anywhere within the scene.
+const DISTANCE: Float = 100.0;
+
+// Light arriving from infinitely far away, from every direction, as given by
+// an image in latitude-longitude layout: its columns turn about the y axis of
+// the light, and its rows run down from +y at the top to -y at the bottom,
+// as the surface coordinates of a sphere do. The environment is placed on a
+// large sphere about the scene, facing inward, which rays that escape the
+// scene reach. Directions are sampled in proportion to the luminance of the
+// image, by the solid angle its pixels cover.
+pub struct EnvironmentLight {
+    id: String,
+    width: usize,
+    height: usize,
+    pixels: Vec<Spectrum>,
+    // The distribution of the rows, and that of the pixels of each row.
+    marginal: Pdf,
+    conditionals: Vec<Pdf>,
+    to_world: Matrix4,
+    to_local: Matrix4,
+    sphere: Sphere,
+    center: Point3,
+    radius: Float,
+    // One minus the cosine of the half angle of the cone in which light is
+    // emitted from each point, toward the sphere enclosing the scene.
+    one_minus_cos_cone: Float,
+    light_count: usize,
+}
+
+impl fmt::Debug for EnvironmentLight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnvironmentLight")
+            .field("id", &self.id)
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("center", &self.center)
+            .field("radius", &self.radius)
+            .finish()
+    }
+}
+
+impl Light for EnvironmentLight {
+    fn radiance(&self, _point: Point3, _normal: Vector3, direction: Vector3) -> Spectrum {
+        let local = self.to_local.transform_vector(-direction).norm();
+        let (x, y) = self.pixel(local);
+        self.pixels[y * self.width + x]
+    }
+
+    fn sampling_pdf(&self) -> Float {
+        1.0 / self.light_count as Float
+    }
+
+    fn positional_pdf(&self, point: Point3) -> PdfValue {
+        let pdf = self.direction_pdf(point - self.center) / (self.radius * self.radius);
+        PdfValue::Finite(pdf)
+    }
+
+    fn directional_pdf(&self, normal: Vector3, direction: Vector3) -> PdfValue {
+        if direction.norm().dot(normal) >= 1.0 - self.one_minus_cos_cone {
+            PdfValue::Finite(1.0 / (2.0 * PI * self.one_minus_cos_cone))
+        } else {
+            PdfValue::Finite(0.0)
+        }
+    }
+
+    // A point in the direction sampled from the center of the scene, and a
+    // direction uniformly within the cone toward the scene.
+    fn sample_interaction(&self, sampler: &mut dyn Sampler) -> Interaction<'_> {
+        let direction = self.sample_direction(sampler);
+        let mut geometry = self.sphere.geometry(self.center + direction * self.radius);
+        geometry.normal = -geometry.normal;
+
+        sampler.set_emission(true);
+        let u1 = sampler.sample(0.0..1.0);
+        let u2 = sampler.sample(0.0..1.0);
+        sampler.set_emission(false);
+        let one_minus_cos_theta = self.one_minus_cos_cone * u1;
+        let cos_theta = 1.0 - one_minus_cos_theta;
+        let sin_theta = util::safe_sqrt(one_minus_cos_theta * (2.0 - one_minus_cos_theta));
+        let phi = 2.0 * PI * u2;
+        let (x, y, z) = util::orthonormal_basis(geometry.normal);
+        let direction = x * (sin_theta * phi.cos()) + y * (sin_theta * phi.sin()) + z * cos_theta;
+
+        Interaction::Light(LightInteraction {
+            light: self,
+            geometry: Geometry {
+                direction,
+                ..geometry
+            },
+        })
+    }
+
+    // The point of the sphere in the direction sampled, seen from the
+    // reference point.
+    fn sample_point_from(&self, reference: Point3, sampler: &mut dyn Sampler) -> Point3 {
+        let direction = self.sample_direction(sampler);
+        let offset = reference - self.center;
+        let b = offset.dot(direction);
+        let c = offset.dot(offset) - self.radius * self.radius;
+        reference + direction * (util::safe_sqrt(b * b - c) - b)
+    }
+
+    fn positional_pdf_from(&self, reference: Point3, geometry: &Geometry) -> PdfValue {
+        let direction = geometry.point - reference;
+        let pdf =
+            self.direction_pdf(direction) * util::direction_to_area(direction, geometry.normal);
+        PdfValue::Finite(pdf)
+    }
+
+    fn intersect(&self, ray: Ray) -> Option<Interaction<'_>> {
+        let mut geometry = self.sphere.intersect(ray)?;
+        geometry.normal = -geometry.normal;
+        Some(Interaction::Light(LightInteraction {
+            light: self,
+            geometry,
+        }))
+    }
+
+    fn bounds(&self) -> Bounds3 {
+        self.sphere.bounds()
+    }
+
+    fn id(&self) -> &String {
+        &self.id
+    }
+
+    fn illuminates(&self, _object_id: &str) -> bool {
+        true
+    }
+
+    fn is_infinite(&self) -> bool {
+        true
+    }
+}
+
+impl EnvironmentLight {
+    // The environment about the scene with the given bounds, seen from the
+    // given camera position.
+    pub fn configure(
+        config: &EnvironmentLightConfig,
+        light_count: usize,
+        placement: Placement,
+        bounds: Bounds3,
+        camera: Point3,
+    ) -> Result<EnvironmentLight, String> {
+        let (width, height, pixels) = load(&config.path)?;
+        let scale = config.scale.unwrap_or(1.0);
+        let rotation = config
+            .rotation
+            .as_ref()
+            .map(RotationConfig::configure)
+            .unwrap_or(Quaternion::new(1.0, 0.0, 0.0, 0.0));
+        let (center, radius) = if bounds.is_empty() {
+            (camera, 0.0)
+        } else {
+            let center = bounds.center();
+            (center, Float::max(bounds.radius(), (camera - center).len()))
+        };
+        EnvironmentLight::new(
+            config.id.clone(),
+            width,
+            height,
+            pixels.into_iter().map(|p| p * scale).collect(),
+            placement.orient(rotation),
+            center,
+            if radius > 0.0 { radius } else { 1.0 },
+            light_count,
+        )
+        .map_err(|e| format!("environment light '{}': {}", config.id, e))
+    }
+
+    // The environment of the image, given row by row from the top, turned
+    // into the scene by the transform, about the sphere with the given
+    // center and radius that encloses the scene.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        id: String,
+        width: usize,
+        height: usize,
+        pixels: Vec<Spectrum>,
+        to_world: Matrix4,
+        center: Point3,
+        enclosing_radius: Float,
+        light_count: usize,
+    ) -> Result<EnvironmentLight, String> {
+        if width == 0 || height == 0 || pixels.len() != width * height {
+            return Err(String::from("the image is empty"));
+        }
+        let to_local = to_world
+            .inverse()
+            .ok_or("the transform of the environment cannot be inverted")?;
+        let mut conditionals = Vec::with_capacity(height);
+        let mut row_weights = Vec::with_capacity(height);
+        for y in 0..height {
+            let sin_theta = (PI * (y as Float + 0.5) / height as Float).sin();
+            let weights: Vec<Float> = pixels[y * width..(y + 1) * width]
+                .iter()
+                .map(|p| p.luminance().max(0.0) * sin_theta)
+                .collect();
+            let sum: Float = weights.iter().sum();
+            // A row that is black is never chosen, and any distribution
+            // serves for it.
+            conditionals.push(if sum > 0.0 {
+                Pdf::new(&weights)
+            } else {
+                Pdf::new(&vec![1.0; width])
+            });
+            row_weights.push(sum);
+        }
+        if row_weights.iter().sum::<Float>() <= 0.0 {
+            return Err(String::from("the image is black"));
+        }
+        let sin2_cone = 1.0 / (DISTANCE * DISTANCE);
+        let radius = enclosing_radius * DISTANCE;
+        Ok(EnvironmentLight {
+            id,
+            width,
+            height,
+            pixels,
+            marginal: Pdf::new(&row_weights),
+            conditionals,
+            to_world,
+            to_local,
+            sphere: Sphere::new(center, radius),
+            center,
+            radius,
+            one_minus_cos_cone: sin2_cone / (1.0 + (1.0 - sin2_cone).sqrt()),
+            light_count,
+        })
+    }
+
+    // A direction in the scene, in proportion to the radiance from it.
+    fn sample_direction(&self, sampler: &mut dyn Sampler) -> Vector3 {
+        let (y, v) = self.marginal.invert(sampler.sample(0.0..1.0));
+        let (x, u) = self.conditionals[y].invert(sampler.sample(0.0..1.0));
+        let phi = 2.0 * PI * ((x as Float + u) / self.width as Float - 0.5);
+        let theta = PI * (y as Float + v) / self.height as Float;
+        let local = Vector3::new(
+            theta.sin() * phi.cos(),
+            theta.cos(),
+            theta.sin() * phi.sin(),
+        );
+        self.to_world.transform_vector(local).norm()
+    }
+
+    // The density by solid angle of sampling the direction.
+    fn direction_pdf(&self, direction: Vector3) -> Float {
+        let local = self.to_local.transform_vector(direction).norm();
+        let sin_theta = util::safe_sqrt(1.0 - local.y * local.y);
+        if sin_theta == 0.0 {
+            return 0.0;
+        }
+        let (x, y) = self.pixel(local);
+        let pdf = self.marginal.value(y) * self.conditionals[y].value(x);
+        pdf * (self.width * self.height) as Float / (2.0 * PI * PI * sin_theta)
+    }
+
+    // The column and row of the pixel in the direction, given in the frame
+    // of the light.
+    fn pixel(&self, direction: Vector3) -> (usize, usize) {
+        let u = 0.5 + direction.z.atan2(direction.x) / (2.0 * PI);
+        let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+        let x = usize::min((u * self.width as Float) as usize, self.width - 1);
+        let y = usize::min((v * self.height as Float) as usize, self.height - 1);
+        (x, y)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EnvironmentLightConfig {
+    pub id: String,
+    // An OpenEXR or Radiance HDR image in latitude-longitude layout.
+    pub path: String,
+    // A rotation of the environment, about its center.
+    pub rotation: Option<RotationConfig>,
+    // A factor of the image, one by default.
+    pub scale: Option<Float>,
+    // Whether the light is part of the scene, as by default.
+    pub enabled: Option<bool>,
+}
+
+// The width, height and pixels of an image, row by row from the top.
+fn load(path: &str) -> Result<(usize, usize, Vec<Spectrum>), String> {
+    let extension = path.rsplit('.').next().unwrap_or_default();
+    match extension.to_ascii_lowercase().as_str() {
+        "exr" => load_exr(path),
+        "hdr" | "pic" => {
+            let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+            parse_hdr(&bytes).map_err(|e| format!("{}: {}", path, e))
+        }
+        _ => Err(format!(
+            "{}: an environment must be an EXR or HDR image",
+            path
+        )),
+    }
+}
+
+fn load_exr(path: &str) -> Result<(usize, usize, Vec<Spectrum>), String> {
+    let exr = read_first_flat_layer_from_file(path).map_err(|e| format!("{}: {}", path, e))?;
+    let layer = &exr.layer_data;
+    let [r, g, b] = ["R", "G", "B"].map(|name| {
+        layer
+            .channel_data
+            .list
+            .iter()
+            .find(|channel| channel.name == *name)
+            .map(|channel| channel.sample_data.values_as_f32().collect::<Vec<f32>>())
+            .ok_or_else(|| format!("{}: the image has no {} channel", path, name))
+    });
+    let pixels = r?
+        .into_iter()
+        .zip(g?)
+        .zip(b?)
+        .map(|((r, g), b)| Spectrum {
+            r: r as Float,
+            g: g as Float,
+            b: b as Float,
+        })
+        .collect();
+    Ok((layer.size.x(), layer.size.y(), pixels))
+}
+
+// A Radiance HDR image of RGBE pixels with the usual orientation, whose
+// scanlines are flat or run-length encoded by channel.
+fn parse_hdr(bytes: &[u8]) -> Result<(usize, usize, Vec<Spectrum>), String> {
+    let mut lines = bytes.split(|b| *b == b'\n');
+    let mut position = 0;
+    let mut next_line = || {
+        let line = lines.next().ok_or("the header is incomplete")?;
+        position += line.len() + 1;
+        Ok::<_, String>(String::from_utf8_lossy(line).into_owned())
+    };
+    if !next_line()?.starts_with("#?") {
+        return Err(String::from("not a Radiance HDR image"));
+    }
+    loop {
+        let line = next_line()?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(format!("the pixel format {} is not supported", format));
+            }
+        }
+    }
+    let resolution = next_line()?;
+    let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", height, "+X", width] => (height.parse::<usize>(), width.parse::<usize>()),
+        _ => return Err(format!("the orientation '{}' is not supported", resolution)),
+    };
+    let (height, width) = height
+        .ok()
+        .zip(width.ok())
+        .ok_or_else(|| format!("could not parse the resolution '{}'", resolution))?;
+
+    // A scanline takes at least four bytes, and a run-length encoded one at
+    // least two bytes of each channel for every 127 pixels, so a resolution
+    // larger than the data allows is refused before anything is allocated.
+    let data = bytes.get(position..).unwrap_or_default();
+    let count = width
+        .checked_mul(height)
+        .ok_or_else(|| format!("the resolution '{}' is too large", resolution))?;
+    if count == 0 {
+        return Err(String::from("the image is empty"));
+    }
+    if height > data.len() / 4 || count / 127 > data.len() / 8 {
+        return Err(String::from("the image is truncated"));
+    }
+    let mut data = data.iter().copied();
+    let mut next = || data.next().ok_or("the image is truncated");
+    let mut pixels = Vec::with_capacity(count);
+    let mut scanline = vec![[0u8; 4]; width];
+    for _ in 0..height {
+        let start = [next()?, next()?, next()?, next()?];
+        let encoded = (8..0x8000).contains(&width)
+            && start[..2] == [2, 2]
+            && ((start[2] as usize) << 8 | start[3] as usize) == width;
+        if encoded {
+            for channel in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = next()? as usize;
+                    let (count, run) = if count > 128 {
+                        (count - 128, Some(next()?))
+                    } else {
+                        (count, None)
+                    };
+                    if count == 0 || x + count > width {
+                        return Err(String::from("a scanline is malformed"));
+                    }
+                    for pixel in &mut scanline[x..x + count] {
+                        pixel[channel] = match run {
+                            Some(value) => value,
+                            None => next()?,
+                        };
+                    }
+                    x += count;
+                }
+            }
+        } else {
+            scanline[0] = start;
+            for pixel in scanline.iter_mut().skip(1) {
+                *pixel = [next()?, next()?, next()?, next()?];
+            }
+        }
+        pixels.extend(scanline.iter().map(|&[r, g, b, e]| {
+            if e == 0 {
+                Spectrum::black()
+            } else {
+                let f = (2.0 as Float).powi(e as i32 - 136);
+                Spectrum {
+                    r: (r as Float + 0.5) * f,
+                    g: (g as Float + 0.5) * f,
+                    b: (b as Float + 0.5) * f,
+                }
+            }
+        }));
+    }
+    Ok((width, height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_hdr, EnvironmentLight};
+    use crate::{
+        approx::loosen,
+        light::Light,
+        sampler::test::MockSampler,
+        spectrum::Spectrum,
+        types::{consts::PI, widen, Float},
+        vector::{Matrix4, Point3, Quaternion, Vector3},
+    };
+
+    // A dim environment but for a bright pixel, toward +x and above the
+    // horizon in the frame of the light.
+    fn light(to_world: Matrix4) -> EnvironmentLight {
+        let (width, height) = (16, 8);
+        let mut pixels = vec![Spectrum::fill(0.1); width * height];
+        pixels[2 * width + 8] = Spectrum::fill(50.0);
+        let center = Point3::new(1.0, 2.0, 3.0);
+        EnvironmentLight::new(
+            String::from("sky"),
+            width,
+            height,
+            pixels,
+            to_world,
+            center,
+            2.0,
+            1,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_environment_light_sampling() {
+        let light = light(Matrix4::identity());
+        assert_eq!(light.radius, 200.0);
+
+        // The density of directions integrates to one over the sphere.
+        let n = 256;
+        let mut total: f64 = 0.0;
+        for i in 0..n {
+            for j in 0..2 * n {
+                let theta = PI * (i as Float + 0.5) / n as Float;
+                let phi = PI * (j as Float + 0.5) / n as Float;
+                let d = Vector3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                total += widen(light.direction_pdf(d) * theta.sin() * (PI / n as Float).powi(2));
+            }
+        }
+        assert!((total - 1.0).abs() < 1e-3, "{}", total);
+
+        // About five in six directions sampled are toward the bright pixel,
+        // whose radiance is found there, and points sampled lie on the
+        // sphere, with densities that agree with those of their directions.
+        let mut sampler = MockSampler::new();
+        let reference = Point3::new(0.5, 1.0, 2.0);
+        let mut bright = 0;
+        for _ in 0..1000 {
+            let point = light.sample_point_from(reference, &mut sampler);
+            assert!(((point - light.center).len() / light.radius - 1.0).abs() < loosen(1e-9));
+            let direction = point - reference;
+            if light.radiance(point, -direction, -direction) == Spectrum::fill(50.0) {
+                bright += 1;
+            }
+            let mut geometry = light.sphere.geometry(point);
+            geometry.normal = -geometry.normal;
+            let normal = geometry.normal;
+            let expected = light.direction_pdf(direction) * normal.dot(direction.norm()).abs()
+                / direction.dot(direction);
+            let actual = light.positional_pdf_from(reference, &geometry).value();
+            assert!((actual / expected - 1.0).abs() < loosen(1e-9));
+        }
+        assert!(bright > 750, "{}", bright);
+
+        // Emitted light leaves inward, toward the scene.
+        let interaction = light.sample_interaction(&mut sampler);
+        let geometry = interaction.geometry();
+        let toward_center = (light.center - geometry.point).norm();
+        assert!(geometry.normal.dot(toward_center) > 0.9999);
+        assert!(
+            light
+                .directional_pdf(geometry.normal, geometry.direction)
+                .value()
+                > 0.0
+        );
+        assert_eq!(
+            light
+                .directional_pdf(geometry.normal, -geometry.normal)
+                .value(),
+            0.0
+        );
+        let expected = light.direction_pdf(-toward_center) / (light.radius * light.radius);
+        let actual = light.positional_pdf(geometry.point).value();
+        assert!((actual / expected - 1.0).abs() < loosen(1e-12));
+    }
+
+    #[test]
+    fn test_environment_light_rotation() {
+        // The bright pixel, toward +x, turned a quarter about y.
+        let rotation = Quaternion::from_axis_angle(Vector3::new(0.0, 1.0, 0.0), PI / 2.0);
+        let light = light(Matrix4::rotation(rotation));
+        let elevation: Float = PI * 2.5 / 8.0;
+        let local = Vector3::new(elevation.sin(), elevation.cos(), 0.0);
+        let world = Matrix4::rotation(rotation).transform_vector(local);
+        let point = Point3::new(0.0, 0.0, 0.0);
+        assert_eq!(light.radiance(point, world, -world), Spectrum::fill(50.0));
+        assert_eq!(light.radiance(point, local, -local), Spectrum::fill(0.1));
+        assert!(light.direction_pdf(world) > 100.0 * light.direction_pdf(local));
+    }
+
+    #[test]
+    fn test_parse_hdr() {
+        let mut bytes = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n".to_vec();
+        // A flat scanline, and then one run-length encoded by channel.
+        for x in 0..8 {
+            bytes.extend([128, 64, x * 16, 129]);
+        }
+        bytes.extend([2, 2, 0, 8]);
+        bytes.extend([136, 128, 136, 0, 4, 1, 2, 3, 4, 132, 255, 136, 130]);
+        let (width, height, pixels) = parse_hdr(&bytes).unwrap();
+        assert_eq!((width, height), (8, 2));
+        assert_eq!(pixels[0].r, 128.5 / 128.0);
+        assert_eq!(pixels[0].g, 64.5 / 128.0);
+        assert_eq!(pixels[3].b, 48.5 / 128.0);
+        assert_eq!(pixels[8].r, 128.5 / 64.0);
+        assert_eq!(pixels[8].g, 0.5 / 64.0);
+        assert_eq!(pixels[9].b, 2.5 / 64.0);
+        assert_eq!(pixels[13].b, 255.5 / 64.0);
+
+        assert!(parse_hdr(b"#?RADIANCE\n\n+Y 2 +X 8\n").is_err());
+        assert!(parse_hdr(&bytes[..bytes.len() - 1]).is_err());
+        // Resolutions that overflow, or that the data cannot hold, are
+        // refused before anything is allocated.
+        let huge = format!("#?RADIANCE\n\n-Y {} +X 2\n", usize::MAX);
+        assert!(parse_hdr(huge.as_bytes())
+            .unwrap_err()
+            .contains("too large"));
+        let header = b"#?RADIANCE\n\n-Y 100000 +X 100000\n\x02\x02\x00\x08";
+        assert_eq!(parse_hdr(header).unwrap_err(), "the image is truncated");
+        assert!(parse_hdr(b"#?RADIANCE\n\n-Y 0 +X 8\n").is_err());
+    }
+}
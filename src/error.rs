@@ -0,0 +1,137 @@
+use std::{fmt, io};
+
+#[derive(Debug)]
+pub enum Error {
+    // An invalid command line.
+    Cli(String),
+    // A file that could not be read or written.
+    Io {
+        path: String,
+        source: io::Error,
+    },
+    // A scene file that could not be parsed, with the location of the
+    // problem when it is known.
+    Scene {
+        path: String,
+        location: Option<Location>,
+        message: String,
+    },
+    // An image that could not be encoded as requested.
+    Image {
+        path: String,
+        message: String,
+    },
+    // A checkpoint that could not be read or combined with others.
+    Checkpoint {
+        path: String,
+        message: String,
+    },
+    // Some of the jobs of a batch that failed, each reported as it failed.
+    Batch {
+        failed: usize,
+        total: usize,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Error {
+    pub fn io(path: &str) -> impl Fn(io::Error) -> Error + Copy + '_ {
+        move |source| Error::Io {
+            path: String::from(path),
+            source,
+        }
+    }
+
+    pub fn image<E: fmt::Display>(path: &str) -> impl Fn(E) -> Error + Copy + '_ {
+        move |e| Error::Image {
+            path: String::from(path),
+            message: e.to_string(),
+        }
+    }
+
+    pub fn scene(path: &str, e: serde_yaml::Error) -> Error {
+        let location = e.location().map(|l| Location {
+            line: l.line(),
+            column: l.column(),
+        });
+        // The location is reported separately, so it is removed from the
+        // message, which would otherwise end with it.
+        let message = e.to_string();
+        let message = match message.rsplit_once(" at line ") {
+            Some((message, _)) if location.is_some() => String::from(message),
+            _ => message,
+        };
+        Error::Scene {
+            path: String::from(path),
+            location,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Cli(message) => write!(f, "{}", message),
+            Error::Io { path, source } => write!(f, "{}: {}", path, source),
+            Error::Scene {
+                path,
+                location: Some(location),
+                message,
+            } => write!(
+                f,
+                "{}:{}:{}: {}",
+                path, location.line, location.column, message
+            ),
+            Error::Scene {
+                path,
+                location: None,
+                message,
+            } => write!(f, "{}: {}", path, message),
+            Error::Image { path, message } => write!(f, "{}: {}", path, message),
+            Error::Checkpoint { path, message } => write!(f, "{}: {}", path, message),
+            Error::Batch { failed, total } => write!(f, "{} of {} jobs failed", failed, total),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, Location};
+    use crate::scene::SceneConfig;
+
+    #[test]
+    fn test_scene_error_location() {
+        let e = serde_yaml::from_str::<SceneConfig>("image:\n  width: wide\n").unwrap_err();
+        let error = Error::scene("scene.yml", e);
+        match &error {
+            Error::Scene {
+                location: Some(location),
+                ..
+            } => assert_eq!(
+                *location,
+                Location {
+                    line: 2,
+                    column: 10
+                }
+            ),
+            _ => panic!("expected a located scene error"),
+        }
+        assert!(error.to_string().starts_with("scene.yml:2:10: "));
+        assert!(!error.to_string().contains(" at line "));
+    }
+}
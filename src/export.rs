@@ -0,0 +1,201 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use crate::{
+    bsdf::Scattering,
+    error::Error,
+    lpe::{Event, Label},
+    path::PathTrace,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Format {
+    Json,
+    Obj,
+}
+
+// Exported paths, for visualizing the light transport: a JSON array with the
+// length, contribution and vertices of each path, or OBJ polylines, with the
+// contribution and vertex types of each path in a comment.
+pub struct PathExporter {
+    path: String,
+    format: Format,
+    writer: BufWriter<File>,
+    count: usize,
+    vertex_count: usize,
+}
+
+impl PathExporter {
+    pub fn create(path: &str) -> Result<PathExporter, Error> {
+        let format = if path.ends_with(".json") {
+            Format::Json
+        } else if path.ends_with(".obj") {
+            Format::Obj
+        } else {
+            return Err(Error::Cli(format!(
+                "unknown path export type for {}; use .json or .obj",
+                path
+            )));
+        };
+        let file = File::create(path).map_err(Error::io(path))?;
+        let mut writer = BufWriter::new(file);
+        if format == Format::Json {
+            writer.write_all(b"[").map_err(Error::io(path))?;
+        }
+        Ok(PathExporter {
+            path: String::from(path),
+            format,
+            writer,
+            count: 0,
+            vertex_count: 0,
+        })
+    }
+
+    pub fn record(&mut self, trace: &PathTrace) -> Result<(), Error> {
+        let spectrum = trace.contribution.spectrum;
+        let record = match self.format {
+            Format::Json => {
+                let vertices: Vec<String> = trace
+                    .vertices
+                    .iter()
+                    .map(|(point, label)| {
+                        format!(
+                            "{{\"type\":\"{}\",\"position\":[{},{},{}]}}",
+                            label_name(*label),
+                            point.x,
+                            point.y,
+                            point.z
+                        )
+                    })
+                    .collect();
+                format!(
+                    "{}\n{{\"length\":{},\"contribution\":[{},{},{}],\"vertices\":[{}]}}",
+                    if self.count == 0 { "" } else { "," },
+                    trace.contribution.path_length,
+                    spectrum.r,
+                    spectrum.g,
+                    spectrum.b,
+                    vertices.join(",")
+                )
+            }
+            Format::Obj => {
+                let types: Vec<&str> = trace
+                    .vertices
+                    .iter()
+                    .map(|(_, label)| label_name(*label))
+                    .collect();
+                let mut record = format!(
+                    "o path_{}\n# length {} contribution {} {} {} vertices {}\n",
+                    self.count,
+                    trace.contribution.path_length,
+                    spectrum.r,
+                    spectrum.g,
+                    spectrum.b,
+                    types.join(" ")
+                );
+                for (point, _) in &trace.vertices {
+                    record.push_str(&format!("v {} {} {}\n", point.x, point.y, point.z));
+                }
+                // OBJ indices are one-based and count the vertices of the
+                // whole file.
+                let indices: Vec<String> = (1..=trace.vertices.len())
+                    .map(|i| (self.vertex_count + i).to_string())
+                    .collect();
+                record.push_str(&format!("l {}\n", indices.join(" ")));
+                record
+            }
+        };
+        self.count += 1;
+        self.vertex_count += trace.vertices.len();
+        self.writer
+            .write_all(record.as_bytes())
+            .map_err(Error::io(&self.path))
+    }
+
+    pub fn finish(mut self) -> Result<(), Error> {
+        if self.format == Format::Json {
+            self.writer
+                .write_all(b"\n]\n")
+                .map_err(Error::io(&self.path))?;
+        }
+        self.writer.flush().map_err(Error::io(&self.path))
+    }
+}
+
+fn label_name(label: Label) -> &'static str {
+    match label {
+        Label::Camera => "camera",
+        Label::Light => "light",
+        Label::Surface(Event::Reflection, Scattering::Diffuse) => "diffuse_reflection",
+        Label::Surface(Event::Reflection, Scattering::Specular) => "specular_reflection",
+        Label::Surface(Event::Transmission, Scattering::Diffuse) => "diffuse_transmission",
+        Label::Surface(Event::Transmission, Scattering::Specular) => "specular_transmission",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::PathExporter;
+    use crate::{
+        bsdf::Scattering,
+        lpe::{Event, Label},
+        path::{Contribution, PathTrace},
+        spectrum::Spectrum,
+        vector::{Point2, Point3},
+    };
+
+    fn trace() -> PathTrace {
+        PathTrace {
+            contribution: Contribution::new(Spectrum::fill(0.5), Point2::new(1.5, 2.5), 3),
+            vertices: vec![
+                (Point3::new(0.0, 0.0, 0.0), Label::Camera),
+                (
+                    Point3::new(1.0, 0.0, 0.0),
+                    Label::Surface(Event::Reflection, Scattering::Diffuse),
+                ),
+                (Point3::new(1.0, 1.0, 0.0), Label::Light),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_path_exporter() {
+        let path = env::temp_dir().join("mmlt-test-paths.json");
+        let path = path.to_str().unwrap();
+        let mut exporter = PathExporter::create(path).unwrap();
+        exporter.record(&trace()).unwrap();
+        exporter.record(&trace()).unwrap();
+        exporter.finish().unwrap();
+        let json = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).unwrap();
+        let record = "{\"length\":3,\"contribution\":[0.5,0.5,0.5],\"vertices\":[\
+            {\"type\":\"camera\",\"position\":[0,0,0]},\
+            {\"type\":\"diffuse_reflection\",\"position\":[1,0,0]},\
+            {\"type\":\"light\",\"position\":[1,1,0]}]}";
+        assert_eq!(json, format!("[\n{},\n{}\n]\n", record, record));
+
+        let path = env::temp_dir().join("mmlt-test-paths.obj");
+        let path = path.to_str().unwrap();
+        let mut exporter = PathExporter::create(path).unwrap();
+        exporter.record(&trace()).unwrap();
+        exporter.record(&trace()).unwrap();
+        exporter.finish().unwrap();
+        let obj = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).unwrap();
+        let lines: Vec<&str> = obj.lines().collect();
+        assert_eq!(lines.len(), 12);
+        assert_eq!(lines[0], "o path_0");
+        assert_eq!(
+            lines[1],
+            "# length 3 contribution 0.5 0.5 0.5 vertices camera diffuse_reflection light"
+        );
+        assert_eq!(lines[5], "l 1 2 3");
+        assert_eq!(lines[11], "l 4 5 6");
+
+        assert!(PathExporter::create("paths.txt").is_err());
+    }
+}
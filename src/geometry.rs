@@ -1,6 +1,8 @@
 use crate::{
     approx::ApproxEq,
-    vector::{Point3, Vector3},
+    types::Float,
+    util,
+    vector::{Point2, Point3, Vector3},
 };
 
 #[derive(Copy, Clone, Debug)]
@@ -8,6 +10,43 @@ pub struct Geometry {
     pub point: Point3,
     pub normal: Vector3,
     pub direction: Vector3,
+    // The distance along the ray that found the point, or zero for a point
+    // that was sampled.
+    pub t: Float,
+    // The surface coordinates of the point, each in [0, 1].
+    pub uv: Point2,
+    // The partial derivatives of the point with respect to u and v, which
+    // orient anisotropic BSDFs, normal maps and ray differentials. They may
+    // vanish, as at the poles of a sphere.
+    pub dpdu: Vector3,
+    pub dpdv: Vector3,
+    // The surface the point lies on, for rays leaving it to exclude it, or
+    // none for a point that lies on no surface, such as a pinhole.
+    pub surface: Option<SurfaceId>,
+}
+
+// Identifies a surface by the address of its shape, which stays put while a
+// scene is rendered, and the index of the surface among those of the shape,
+// such as the triangles of a mesh, which may not be in memory. It names the
+// surface rather than describing the point, so geometries compare equal
+// without it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SurfaceId(usize, usize);
+
+impl SurfaceId {
+    pub fn of<T>(shape: &T) -> SurfaceId {
+        SurfaceId::element(shape, 0)
+    }
+
+    pub fn element<T>(shape: &T, index: usize) -> SurfaceId {
+        SurfaceId(shape as *const T as usize, index)
+    }
+
+    // The index of the surface among those of the shape, if it is one of
+    // them.
+    pub fn index_within<T>(self, shape: &T) -> Option<usize> {
+        (self.0 == shape as *const T as usize).then_some(self.1)
+    }
 }
 
 impl Geometry {
@@ -21,14 +60,22 @@ impl PartialEq for Geometry {
         self.point == other.point
             && self.normal == other.normal
             && self.direction == other.direction
+            && self.t == other.t
+            && self.uv == other.uv
+            && self.dpdu == other.dpdu
+            && self.dpdv == other.dpdv
     }
 }
 
 impl ApproxEq for Geometry {
-    fn approx_eq(&self, other: Self, tolerance: f64) -> bool {
+    fn approx_eq(&self, other: Self, tolerance: Float) -> bool {
         self.point.approx_eq(other.point, tolerance)
             && self.normal.approx_eq(other.normal, tolerance)
             && self.direction.approx_eq(other.direction, tolerance)
+            && util::equals(self.t, other.t, tolerance)
+            && self.uv.approx_eq(other.uv, tolerance)
+            && self.dpdu.approx_eq(other.dpdu, tolerance)
+            && self.dpdv.approx_eq(other.dpdv, tolerance)
     }
 }
 
@@ -37,7 +84,7 @@ mod tests {
     use super::Geometry;
     use crate::{
         approx::ApproxEq,
-        vector::{Point3, Vector3},
+        vector::{Point2, Point3, Vector3},
     };
 
     #[test]
@@ -46,6 +93,11 @@ mod tests {
             point: Point3::new(1.0, 1.0, 1.0),
             normal: Vector3::new(1.0, 0.0, 0.0),
             direction: Vector3::new(1.0, 1.0, 1.0),
+            t: 1.0,
+            uv: Point2::new(0.5, 0.5),
+            dpdu: Vector3::new(0.0, 1.0, 0.0),
+            dpdv: Vector3::new(0.0, 0.0, 1.0),
+            surface: None,
         };
 
         assert_eq!(g1, g1);
@@ -57,12 +109,22 @@ mod tests {
             point: Point3::new(1.0, 1.0, 1.0),
             normal: Vector3::new(1.0, 0.0, 0.0),
             direction: Vector3::new(1.0, 1.0, 1.0),
+            t: 1.0,
+            uv: Point2::new(0.5, 0.5),
+            dpdu: Vector3::new(0.0, 1.0, 0.0),
+            dpdv: Vector3::new(0.0, 0.0, 1.0),
+            surface: None,
         };
 
         let g2 = Geometry {
             point: g1.point + Point3::new(1e-9, 1e-9, 1e-9),
             normal: g1.normal + Vector3::new(1e-9, 1e-9, 1e-9),
             direction: g1.direction + Vector3::new(1e-9, 1e-9, 1e-9),
+            t: g1.t + 1e-9,
+            uv: Point2::new(0.5 + 1e-9, 0.5),
+            dpdu: g1.dpdu + Vector3::new(1e-9, 0.0, 0.0),
+            dpdv: g1.dpdv,
+            surface: None,
         };
 
         assert!(g1.approx_eq(g2, 1e-8));
@@ -1,56 +1,178 @@
 use std::{
     fs::File,
-    io::{self, LineWriter, Write},
+    io::{BufWriter, Write},
+    sync::atomic::{AtomicU64, Ordering},
 };
 
-use exr::image::write::write_rgb_file;
+use exr::prelude::{
+    f16, read_first_flat_layer_from_file, AnyChannel, AnyChannels, AttributeValue, FlatSamples,
+    Text, WritableImage,
+};
 use serde::{Deserialize, Serialize};
+use tiff::encoder::{colortype, TiffEncoder};
 
 use crate::{
+    aov::{Aov, AovConfig},
+    atomic::AtomicF64,
+    checkpoint::{Checkpoint, RenderMetadata},
+    dither::Dither,
+    error::Error,
+    path::Contribution,
+    post::{AutoExposure, AutoExposureConfig, Bloom, BloomConfig, DisplayTransform},
+    profile::{self, Subsystem},
     spectrum::Spectrum,
+    types::{narrow, widen, Float},
     util,
     vector::{Point2, Vector2, Vector2Config},
 };
 
+const TILE_SIZE: usize = 32;
+
 pub struct Image {
-    pixels: Vec<Spectrum>,
+    tiles: Vec<Tile>,
+    tile_columns: usize,
     width: usize,
     height: usize,
     filter: Box<dyn Filter>,
-    sample_clamp: Option<f64>,
-    clamp: Option<f64>,
+    sample_clamp: Option<Float>,
+    indirect_clamp: Option<Float>,
+    clamp: Option<Float>,
+    scale: Float,
+    bloom: Option<Bloom>,
+    display: DisplayTransform,
+    auto_exposure: Option<AutoExposure>,
+    encoding: Encoding,
+    aovs: Vec<Aov>,
+    // The alpha of each pixel, row by row, if computed.
+    alpha: Option<Vec<Float>>,
+    // Written to the header of EXR images, so a render can be continued.
+    metadata: Option<RenderMetadata>,
 }
 
 impl Image {
     pub fn configure(config: &ImageConfig) -> Image {
-        Image::new(
+        let mut image = Image::new(
             config.width,
             config.height,
-            config.filter.configure(),
+            config
+                .filter
+                .as_ref()
+                .map(FilterConfig::configure)
+                .unwrap_or_else(|| Box::new(BoxFilter::new())),
             config.sample_clamp,
             config.clamp,
-        )
+        );
+        image.indirect_clamp = config.indirect_clamp;
+        image.bloom = config.bloom.as_ref().map(Bloom::configure);
+        image.auto_exposure = config.auto_exposure.as_ref().map(AutoExposure::configure);
+        image.encoding = config
+            .encoding
+            .as_ref()
+            .map(Encoding::configure)
+            .unwrap_or_default();
+        if let Some(aovs) = &config.aovs {
+            image.add_aovs(aovs.iter().map(Aov::configure));
+        }
+        image.display = DisplayTransform::new(
+            config.exposure.unwrap_or(0.0),
+            config.white_balance,
+            config.gamma.unwrap_or(2.2),
+        );
+        image
     }
 
     pub fn new(
         width: usize,
         height: usize,
         filter: Box<dyn Filter>,
-        sample_clamp: Option<f64>,
-        clamp: Option<f64>,
+        sample_clamp: Option<Float>,
+        clamp: Option<Float>,
     ) -> Image {
+        let tile_columns = width.div_ceil(TILE_SIZE);
+        let tile_rows = height.div_ceil(TILE_SIZE);
+        let mut tiles = Vec::with_capacity(tile_columns * tile_rows);
+        for ty in 0..tile_rows {
+            for tx in 0..tile_columns {
+                let x = tx * TILE_SIZE;
+                let y = ty * TILE_SIZE;
+                let tile_width = usize::min(TILE_SIZE, width - x);
+                let tile_height = usize::min(TILE_SIZE, height - y);
+                tiles.push(Tile::new(x, y, tile_width, tile_height, 1));
+            }
+        }
         Image {
-            pixels: vec![Spectrum::black(); width * height],
+            tiles,
+            tile_columns,
             width,
             height,
             filter,
             sample_clamp,
+            indirect_clamp: None,
             clamp,
+            scale: 1.0,
+            bloom: None,
+            display: DisplayTransform::new(0.0, None, 2.2),
+            auto_exposure: None,
+            encoding: Encoding::default(),
+            aovs: Vec::new(),
+            alpha: None,
+            metadata: None,
+        }
+    }
+
+    pub fn set_display(&mut self, display: DisplayTransform) {
+        self.display = display;
+    }
+
+    pub fn set_alpha(&mut self, alpha: Vec<Float>) {
+        self.alpha = Some(alpha);
+    }
+
+    pub fn set_metadata(&mut self, metadata: RenderMetadata) {
+        self.metadata = Some(metadata);
+    }
+
+    // Reallocates the tiles, so this must happen before any contributions.
+    pub fn add_aovs(&mut self, aovs: impl IntoIterator<Item = Aov>) {
+        self.aovs.extend(aovs);
+        let layers = self.layer_count();
+        for tile in self.tiles.iter_mut() {
+            *tile = Tile::new(tile.x, tile.y, tile.width, tile.height, layers);
         }
     }
 
-    pub fn contribute(&mut self, spectrum: Spectrum, coordinates: Point2) {
+    pub fn local(&self) -> LocalImage<'_> {
+        LocalImage {
+            image: self,
+            tiles: (0..self.tiles.len()).map(|_| None).collect(),
+        }
+    }
+
+    // Indirect contributions are further clamped by a limit that grows as
+    // the first surface seen from the camera gets smoother, so that fireflies
+    // are suppressed on rough surfaces while sharp reflections and direct
+    // light are left alone. A perfectly specular surface is not clamped.
+    fn sample_limit(&self, contribution: &Contribution) -> Option<Float> {
+        let indirect = self
+            .indirect_clamp
+            .filter(|_| contribution.path_length > 3 && contribution.roughness > 0.0)
+            .map(|limit| limit / contribution.roughness.min(1.0));
+        match (self.sample_clamp, indirect) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn splat(
+        &self,
+        spectrum: Spectrum,
+        coordinates: Point2,
+        limit: Option<Float>,
+        mut add: impl FnMut(usize, usize, Spectrum),
+    ) {
+        let _timer = profile::time(Subsystem::Splatting);
         if !spectrum.has_nans() {
+            let sample = spectrum.try_clamp(limit).to_rgb();
             let radius = self.filter.radius();
             let min_x = usize::max(0, (coordinates.x - radius.x) as usize);
             let max_x = usize::min(self.width - 1, (coordinates.x + radius.x) as usize);
@@ -58,12 +180,10 @@ impl Image {
             let max_y = usize::min(self.height - 1, (coordinates.y + radius.y) as usize);
             for y in min_y..=max_y {
                 for x in min_x..=max_x {
-                    let i = y * self.width + x;
-                    let p = Point2::new(x as f64, y as f64);
+                    let p = Point2::new(x as Float, y as Float);
                     let weight = self.filter.evaluate(coordinates - p);
-                    self.pixels[i] =
-                        self.pixels[i] + weight * spectrum.try_clamp(self.sample_clamp);
-                    self.pixels[i] = self.pixels[i].try_clamp(self.clamp);
+                    let (tile, offset) = self.locate(x, y);
+                    add(tile, offset, weight * sample);
                 }
             }
         } else {
@@ -71,80 +191,744 @@ impl Image {
         }
     }
 
-    pub fn write(&self, path: String) -> Result<(), String> {
+    fn locate(&self, x: usize, y: usize) -> (usize, usize) {
+        let index = (y / TILE_SIZE) * self.tile_columns + x / TILE_SIZE;
+        let tile = &self.tiles[index];
+        (index, (y - tile.y) * tile.width + (x - tile.x))
+    }
+
+    fn tile_at(&self, coordinates: Point2) -> Option<usize> {
+        if coordinates.x < 0.0 || coordinates.y < 0.0 {
+            return None;
+        }
+        let x = coordinates.x as usize;
+        let y = coordinates.y as usize;
+        if x < self.width && y < self.height {
+            Some(self.locate(x, y).0)
+        } else {
+            None
+        }
+    }
+
+    // EXR files hold every layer. Other formats hold a single layer, so each
+    // AOV is written to a sibling file, e.g. `image.direct.pfm`.
+    pub fn write(&self, path: String) -> Result<(), Error> {
+        self.write_scaled(path, 1.0)
+    }
+
+    // Writes the image scaled by a further factor, as a snapshot of a render
+    // in progress is by the samples taken so far.
+    pub fn write_scaled(&self, path: String, scale: Float) -> Result<(), Error> {
+        if path.ends_with(".exr") {
+            return self.write_exr(&path, scale);
+        }
+        if self.alpha.is_some() {
+            eprintln!("warning: the alpha channel is written only to EXR images");
+        }
+        for (index, aov) in self.aovs.iter().enumerate() {
+            let aov_path = match path.rfind('.') {
+                Some(i) => format!("{}.{}{}", &path[..i], aov.name(), &path[i..]),
+                None => format!("{}.{}", path, aov.name()),
+            };
+            self.write_layer(index + 1, &aov_path, scale)?;
+        }
+        self.write_layer(0, &path, scale)
+    }
+
+    fn write_layer(&self, layer: usize, path: &str, scale: Float) -> Result<(), Error> {
         if path.ends_with(".pfm") {
-            self.write_pfm(path)
-        } else if path.ends_with(".exr") {
-            self.write_exr(path)
+            self.write_pfm(layer, path, scale)
         } else if path.ends_with("ppm") {
-            self.write_ppm(path)
+            self.write_ppm(layer, path, scale)
+        } else if path.ends_with(".png") {
+            self.write_png(layer, path, scale)
+        } else if path.ends_with(".tif") || path.ends_with(".tiff") {
+            self.write_tiff(layer, path, scale)
         } else {
-            Err(String::from("unknown image type"))
+            Err(Error::image(path)("unknown image type"))
         }
     }
 
-    fn write_pfm(&self, path: String) -> Result<(), String> {
-        let m = |e: io::Error| e.to_string();
+    fn write_pfm(&self, layer: usize, path: &str, scale: Float) -> Result<(), Error> {
+        self.encoding
+            .bit_depth(32, &[32])
+            .map_err(Error::image(path))?;
+        let m = Error::io(path);
         let file = File::create(path).map_err(m)?;
-        let mut writer = LineWriter::new(file);
-        writeln!(writer, "PF").map_err(m)?;
+        let mut writer = BufWriter::new(file);
+        let magic = match self.encoding.channels {
+            ChannelLayout::Rgb => "PF",
+            ChannelLayout::Gray => "Pf",
+        };
+        // The sign of the scale factor encodes the byte order.
+        let (byte_order, to_bytes): (i32, fn(f32) -> [u8; 4]) = match self.encoding.endianness {
+            Endianness::Little => (-1, f32::to_le_bytes),
+            Endianness::Big => (1, f32::to_be_bytes),
+        };
+        writeln!(writer, "{}", magic).map_err(m)?;
         writeln!(writer, "{} {}", self.width, self.height).map_err(m)?;
-        writeln!(writer, "-1").map_err(m)?;
+        writeln!(writer, "{}", byte_order).map_err(m)?;
         for y in (0..self.height).rev() {
             for x in 0..self.width {
-                let i = (y * self.width + x) as usize;
-                let pixel = self.pixels[i];
-                let rgb = pixel.to_rgb();
-                writer.write(&(rgb.r as f32).to_le_bytes()).map_err(m)?;
-                writer.write(&(rgb.g as f32).to_le_bytes()).map_err(m)?;
-                writer.write(&(rgb.b as f32).to_le_bytes()).map_err(m)?;
+                for value in self
+                    .encoding
+                    .channels
+                    .samples(self.layer_pixel(layer, x, y, scale))
+                {
+                    writer.write_all(&to_bytes(narrow(value))).map_err(m)?;
+                }
             }
         }
         writer.flush().map_err(m)?;
         Ok(())
     }
 
-    fn write_ppm(&self, path: String) -> Result<(), String> {
-        let m = |e: io::Error| e.to_string();
+    fn write_ppm(&self, layer: usize, path: &str, scale: Float) -> Result<(), Error> {
+        let bit_depth = self
+            .encoding
+            .bit_depth(8, &[8, 16])
+            .map_err(Error::image(path))?;
+        let m = Error::io(path);
         let file = File::create(path).map_err(m)?;
-        let mut writer = LineWriter::new(file);
-        writeln!(writer, "P6").map_err(m)?;
+        let mut writer = BufWriter::new(file);
+        let magic = match self.encoding.channels {
+            ChannelLayout::Rgb => "P6",
+            ChannelLayout::Gray => "P5",
+        };
+        writeln!(writer, "{}", magic).map_err(m)?;
         writeln!(writer, "{} {}", self.width, self.height).map_err(m)?;
-        writeln!(writer, "255").map_err(m)?;
-        let correct = |value: f64| -> [u8; 1] {
-            let tone_mapped_value = 1.0 - f64::exp(-value);
-            let gamma_corrected_value = f64::powf(tone_mapped_value, 1.0 / 2.2);
-            let scaled_value = gamma_corrected_value * 255.0;
-            let byte_value = (scaled_value + 0.5) as u8;
-            byte_value.to_be_bytes()
+        if bit_depth == 8 {
+            writeln!(writer, "255").map_err(m)?;
+            writer
+                .write_all(&self.ldr_samples8(layer, scale))
+                .map_err(m)?;
+        } else {
+            // 16-bit samples are always big-endian in PPM.
+            writeln!(writer, "65535").map_err(m)?;
+            for value in self.ldr_samples16(layer, scale) {
+                writer.write_all(&value.to_be_bytes()).map_err(m)?;
+            }
+        }
+        writer.flush().map_err(m)?;
+        Ok(())
+    }
+
+    fn write_png(&self, layer: usize, path: &str, scale: Float) -> Result<(), Error> {
+        let bit_depth = self
+            .encoding
+            .bit_depth(16, &[8, 16])
+            .map_err(Error::image(path))?;
+        let file = File::create(path).map_err(Error::io(path))?;
+        let mut encoder =
+            png::Encoder::new(BufWriter::new(file), self.width as u32, self.height as u32);
+        encoder.set_color(match self.encoding.channels {
+            ChannelLayout::Rgb => png::ColorType::Rgb,
+            ChannelLayout::Gray => png::ColorType::Grayscale,
+        });
+        let data: Vec<u8> = if bit_depth == 8 {
+            encoder.set_depth(png::BitDepth::Eight);
+            self.ldr_samples8(layer, scale)
+        } else {
+            encoder.set_depth(png::BitDepth::Sixteen);
+            self.ldr_samples16(layer, scale)
+                .iter()
+                .flat_map(|value| value.to_be_bytes())
+                .collect()
+        };
+        let mut writer = encoder.write_header().map_err(Error::image(path))?;
+        writer.write_image_data(&data).map_err(Error::image(path))
+    }
+
+    fn write_tiff(&self, layer: usize, path: &str, scale: Float) -> Result<(), Error> {
+        let bit_depth = self
+            .encoding
+            .bit_depth(16, &[8, 16])
+            .map_err(Error::image(path))?;
+        let file = File::create(path).map_err(Error::io(path))?;
+        let mut encoder = TiffEncoder::new(BufWriter::new(file)).map_err(Error::image(path))?;
+        let (width, height) = (self.width as u32, self.height as u32);
+        match (self.encoding.channels, bit_depth) {
+            (ChannelLayout::Rgb, 8) => encoder.write_image::<colortype::RGB8>(
+                width,
+                height,
+                &self.ldr_samples8(layer, scale),
+            ),
+            (ChannelLayout::Rgb, _) => encoder.write_image::<colortype::RGB16>(
+                width,
+                height,
+                &self.ldr_samples16(layer, scale),
+            ),
+            (ChannelLayout::Gray, 8) => encoder.write_image::<colortype::Gray8>(
+                width,
+                height,
+                &self.ldr_samples8(layer, scale),
+            ),
+            (ChannelLayout::Gray, _) => encoder.write_image::<colortype::Gray16>(
+                width,
+                height,
+                &self.ldr_samples16(layer, scale),
+            ),
+        }
+        .map_err(Error::image(path))
+    }
+
+    fn ldr_samples8(&self, layer: usize, scale: Float) -> Vec<u8> {
+        self.quantized_samples(layer, scale, u8::MAX as Float)
+            .map(|value| value as u8)
+            .collect()
+    }
+
+    fn ldr_samples16(&self, layer: usize, scale: Float) -> Vec<u16> {
+        self.quantized_samples(layer, scale, u16::MAX as Float)
+            .map(|value| value as u16)
+            .collect()
+    }
+
+    // The beauty layer as display-encoded 8-bit RGB, for viewers of a render
+    // in progress.
+    pub fn preview(&self, scale: Float) -> Preview {
+        let pixels = self
+            .ldr_pixels(0, scale)
+            .into_iter()
+            .flat_map(|rgb| [rgb.r, rgb.g, rgb.b])
+            .map(|value| (value.clamp(0.0, 1.0) * u8::MAX as Float).round() as u8)
+            .collect();
+        Preview {
+            width: self.width,
+            height: self.height,
+            pixels,
+        }
+    }
+
+    // The samples as integers in [0, max], dithered as configured.
+    fn quantized_samples(
+        &self,
+        layer: usize,
+        scale: Float,
+        max: Float,
+    ) -> impl Iterator<Item = Float> + '_ {
+        let channel_count = self.encoding.channels.channel_count();
+        self.ldr_samples(layer, scale)
+            .enumerate()
+            .map(move |(i, value)| {
+                let pixel = i / channel_count;
+                self.encoding.dither.quantize(
+                    value,
+                    max,
+                    pixel % self.width,
+                    pixel / self.width,
+                    i % channel_count,
+                )
+            })
+    }
+
+    // Interleaved display-encoded samples in the configured channel layout.
+    fn ldr_samples(&self, layer: usize, scale: Float) -> impl Iterator<Item = Float> + '_ {
+        self.ldr_pixels(layer, scale)
+            .into_iter()
+            .flat_map(|rgb| self.encoding.channels.samples(rgb))
+    }
+
+    // Additional layers are written as prefixed channels (e.g. `direct.R`)
+    // alongside the beauty channels, which compositors read as EXR layers.
+    fn write_exr(&self, path: &str, scale: Float) -> Result<(), Error> {
+        let bit_depth = self
+            .encoding
+            .bit_depth(32, &[16, 32])
+            .map_err(Error::image(path))?;
+        let names = match self.encoding.channels {
+            ChannelLayout::Rgb => vec!["R", "G", "B"],
+            ChannelLayout::Gray => vec!["Y"],
+        };
+        let mut channels = Vec::new();
+        for layer in 0..self.layer_count() {
+            let mut samples = vec![Vec::with_capacity(self.width * self.height); names.len()];
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    let values = self
+                        .encoding
+                        .channels
+                        .samples(self.layer_pixel(layer, x, y, scale));
+                    for (channel, value) in samples.iter_mut().zip(values) {
+                        channel.push(value);
+                    }
+                }
+            }
+            for (name, samples) in names.iter().zip(samples) {
+                let name = self.channel_name(layer, name);
+                let samples = if bit_depth == 16 {
+                    FlatSamples::F16(
+                        samples
+                            .into_iter()
+                            .map(|v| f16::from_f64(widen(v)))
+                            .collect(),
+                    )
+                } else {
+                    FlatSamples::F32(samples.into_iter().map(narrow).collect())
+                };
+                channels.push(AnyChannel::new(name.as_str(), samples));
+            }
+        }
+        if let Some(alpha) = &self.alpha {
+            let samples = if bit_depth == 16 {
+                FlatSamples::F16(alpha.iter().map(|v| f16::from_f64(widen(*v))).collect())
+            } else {
+                FlatSamples::F32(alpha.iter().map(|v| narrow(*v)).collect())
+            };
+            channels.push(AnyChannel::new("A", samples));
+        }
+        let channels = AnyChannels::sort(channels.into());
+        let mut image = exr::image::Image::from_channels((self.width, self.height), channels);
+        if let Some(metadata) = &self.metadata {
+            for (name, value) in metadata.attributes() {
+                image
+                    .layer_data
+                    .attributes
+                    .other
+                    .insert(Text::from(name), AttributeValue::Text(Text::from(&*value)));
+            }
+        }
+        image.write().to_file(path).map_err(Error::image(path))
+    }
+
+    fn channel_name(&self, layer: usize, name: &str) -> String {
+        match layer {
+            0 => name.to_string(),
+            _ => format!("{}.{}", self.aovs[layer - 1].name(), name),
+        }
+    }
+
+    // Adds the values of an EXR image of the same size and layers, written
+    // by an earlier render, as they were accumulated over the samples its
+    // header records, so that a render can carry on where that one stopped.
+    // Statistics cannot be recovered from the values they were resolved to,
+    // and start again.
+    pub fn restore(&self, path: &str) -> Result<RenderMetadata, Error> {
+        let error = |message: String| Error::Checkpoint {
+            path: String::from(path),
+            message,
+        };
+        if self.clamp.is_some() {
+            return Err(error(String::from(
+                "the values of a clamped image cannot be restored",
+            )));
+        }
+        if self.encoding.channels != ChannelLayout::Rgb {
+            return Err(error(String::from(
+                "the values of a grayscale image cannot be restored",
+            )));
+        }
+        let exr = read_first_flat_layer_from_file(path).map_err(Error::image(path))?;
+        let layer = &exr.layer_data;
+        let metadata =
+            RenderMetadata::parse(|name| match layer.attributes.other.get(&Text::from(name)) {
+                Some(AttributeValue::Text(value)) => Some(value.to_string()),
+                _ => None,
+            })
+            .ok_or_else(|| error(String::from("the image header holds no render metadata")))?;
+        let pixel_count = self.width * self.height;
+        let factor = metadata.sample_count as Float / pixel_count as Float;
+        let mut pixels = Vec::with_capacity(self.layer_count() * pixel_count);
+        for index in 0..self.layer_count() {
+            if self.statistic(index).is_some() {
+                pixels.extend((0..pixel_count).map(|_| Spectrum::black()));
+                continue;
+            }
+            let [r, g, b] = ["R", "G", "B"].map(|name| {
+                let name = self.channel_name(index, name);
+                layer
+                    .channel_data
+                    .list
+                    .iter()
+                    .find(|channel| channel.name == *name.as_str())
+                    .map(|channel| channel.sample_data.values_as_f32().collect::<Vec<f32>>())
+                    .ok_or_else(|| error(format!("the image has no {} channel", name)))
+            });
+            for ((r, g), b) in r?.into_iter().zip(g?).zip(b?) {
+                pixels.push(Spectrum {
+                    r: r as Float * factor,
+                    g: g as Float * factor,
+                    b: b as Float * factor,
+                });
+            }
+        }
+        let checkpoint = Checkpoint {
+            width: layer.size.x(),
+            height: layer.size.y(),
+            layers: self.layer_count(),
+            sample_count: metadata.sample_count,
+            seeds: metadata.seeds.clone(),
+            pixels,
         };
+        self.accumulate(&checkpoint).map_err(error)?;
+        Ok(metadata)
+    }
+
+    // Scaling is deferred to read time, so the image can be written at any
+    // point during rendering without disturbing the accumulated values.
+    pub fn scale(&mut self, s: Float) {
+        self.scale *= s;
+    }
+
+    // The accumulated values, before clamping and scaling.
+    pub fn checkpoint(&self, sample_count: u64, seeds: Vec<u64>) -> Checkpoint {
+        let mut pixels = Vec::with_capacity(self.layer_count() * self.width * self.height);
+        for layer in 0..self.layer_count() {
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    pixels.push(self.accumulated_pixel(layer, x, y));
+                }
+            }
+        }
+        Checkpoint {
+            width: self.width,
+            height: self.height,
+            layers: self.layer_count(),
+            sample_count,
+            seeds,
+            pixels,
+        }
+    }
+
+    // Adds the accumulated values of a checkpoint of an image of the same
+    // size and layers.
+    pub fn accumulate(&self, checkpoint: &Checkpoint) -> Result<(), String> {
+        let size = (self.width, self.height, self.layer_count());
+        if (checkpoint.width, checkpoint.height, checkpoint.layers) != size {
+            return Err(format!(
+                "checkpoint is {}x{} with {} layers, but the image is {}x{} with {}",
+                checkpoint.width, checkpoint.height, checkpoint.layers, size.0, size.1, size.2
+            ));
+        }
+        let mut pixels = checkpoint.pixels.iter().copied();
+        for layer in 0..self.layer_count() {
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    let (tile, offset) = self.locate(x, y);
+                    let tile = &self.tiles[tile];
+                    if let Some(value) = pixels.next() {
+                        tile.pixels[layer * tile.width * tile.height + offset].add(value);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn accumulated_pixel(&self, layer: usize, x: usize, y: usize) -> Spectrum {
+        let (tile, offset) = self.locate(x, y);
+        let tile = &self.tiles[tile];
+        tile.pixels[layer * tile.width * tile.height + offset].get()
+    }
+
+    // Layer 0 is the beauty image; layer i + 1 holds the i-th AOV.
+    fn layer_pixel(&self, layer: usize, x: usize, y: usize, scale: Float) -> Spectrum {
+        let value = self.accumulated_pixel(layer, x, y);
+        match self.statistic(layer) {
+            Some(aov) => aov.resolve(value),
+            None => value.try_clamp(self.clamp) * self.scale * scale,
+        }
+    }
+
+    fn statistic(&self, layer: usize) -> Option<&Aov> {
+        let aov = self.aovs.get(layer.checked_sub(1)?)?;
+        aov.is_statistic().then_some(aov)
+    }
+
+    fn layer_count(&self) -> usize {
+        1 + self.aovs.len()
+    }
+
+    // The single post-processing stage shared by all LDR writers, producing
+    // display-encoded values in [0, 1]. HDR formats are left untouched.
+    fn ldr_pixels(&self, layer: usize, scale: Float) -> Vec<Spectrum> {
+        let mut pixels = Vec::with_capacity(self.width * self.height);
+        if self.statistic(layer).is_some() {
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    let value = self.layer_pixel(layer, x, y, scale);
+                    pixels.push(match self.encoding.channels {
+                        ChannelLayout::Rgb => value,
+                        ChannelLayout::Gray => Spectrum::fill(value.luminance()),
+                    });
+                }
+            }
+            return pixels;
+        }
         for y in 0..self.height {
             for x in 0..self.width {
-                let i = (y * self.width + x) as usize;
-                let pixel = self.pixels[i];
-                let rgb = pixel.to_rgb();
-                writer.write(&correct(rgb.r)).map_err(m)?;
-                writer.write(&correct(rgb.g)).map_err(m)?;
-                writer.write(&correct(rgb.b)).map_err(m)?;
+                pixels.push(self.display.expose(self.layer_pixel(layer, x, y, scale)));
             }
         }
-        writer.flush().map_err(m)?;
-        Ok(())
+        // With auto exposure, the configured exposure acts as compensation
+        // on top of the metered value.
+        if let Some(auto_exposure) = &self.auto_exposure {
+            let scale = auto_exposure.scale(&pixels) * self.display.exposure();
+            pixels.iter_mut().for_each(|p| *p = *p * scale);
+        }
+        if let Some(bloom) = &self.bloom {
+            bloom.apply(&mut pixels, self.width, self.height);
+        }
+        if self.encoding.channels == ChannelLayout::Gray {
+            pixels
+                .iter_mut()
+                .for_each(|p| *p = Spectrum::fill(p.luminance()));
+        }
+        pixels.iter().map(|p| self.display.encode(*p)).collect()
+    }
+
+    pub fn tile_statistics(&self) -> Vec<TileStatistics> {
+        self.tiles
+            .iter()
+            .map(|tile| TileStatistics {
+                x: tile.x,
+                y: tile.y,
+                width: tile.width,
+                height: tile.height,
+                splat_count: tile.splat_count.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+// Pixels are RGB triples, row by row.
+#[derive(Debug, PartialEq)]
+pub struct Preview {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Preview {
+    // The pixels of a rectangle, row by row.
+    pub fn region(&self, x: usize, y: usize, width: usize, height: usize) -> Vec<u8> {
+        (y..y + height)
+            .flat_map(|row| {
+                let start = 3 * (row * self.width + x);
+                &self.pixels[start..start + 3 * width]
+            })
+            .copied()
+            .collect()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TileStatistics {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub splat_count: u64,
+}
+
+struct Tile {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    pixels: Vec<Pixel>,
+    splat_count: AtomicU64,
+}
+
+impl Tile {
+    fn new(x: usize, y: usize, width: usize, height: usize, layers: usize) -> Tile {
+        Tile {
+            x,
+            y,
+            width,
+            height,
+            pixels: (0..layers * width * height).map(|_| Pixel::new()).collect(),
+            splat_count: AtomicU64::new(0),
+        }
+    }
+}
+
+// Pixels are accumulated with atomic adds so that any number of threads can
+// merge into the same image concurrently. The accumulated-value clamp is
+// therefore applied when pixels are read rather than per splat.
+#[derive(Debug)]
+struct Pixel {
+    r: AtomicF64,
+    g: AtomicF64,
+    b: AtomicF64,
+}
+
+impl Pixel {
+    fn new() -> Pixel {
+        Pixel {
+            r: AtomicF64::new(0.0),
+            g: AtomicF64::new(0.0),
+            b: AtomicF64::new(0.0),
+        }
+    }
+
+    fn add(&self, rgb: Spectrum) {
+        self.r.add(widen(rgb.r));
+        self.g.add(widen(rgb.g));
+        self.b.add(widen(rgb.b));
+    }
+
+    fn get(&self) -> Spectrum {
+        Spectrum {
+            r: self.r.load() as Float,
+            g: self.g.load() as Float,
+            b: self.b.load() as Float,
+        }
+    }
+}
+
+// A chain-local view of an image. Splats are accumulated into lazily
+// allocated tiles without any synchronization and only reach the shared
+// image when merged.
+pub struct LocalImage<'a> {
+    image: &'a Image,
+    tiles: Vec<Option<LocalTile>>,
+}
+
+struct LocalTile {
+    pixels: Vec<Spectrum>,
+    splat_count: u64,
+}
+
+impl<'a> LocalImage<'a> {
+    pub fn contribute(&mut self, contribution: &Contribution, weight: Float) {
+        let image = self.image;
+        let spectrum = contribution.spectrum * weight;
+        let coordinates = contribution.pixel_coordinates;
+        let limit = image.sample_limit(contribution);
+        image.splat(spectrum, coordinates, limit, |tile, offset, value| {
+            let local = self.tile(tile);
+            let size = image.tiles[tile].width * image.tiles[tile].height;
+            local.pixels[offset] = local.pixels[offset] + value;
+            for (index, aov) in image.aovs.iter().enumerate() {
+                if aov.accepts(contribution) {
+                    let offset = (index + 1) * size + offset;
+                    local.pixels[offset] = local.pixels[offset] + value;
+                }
+            }
+        });
+        if let Some(tile) = image.tile_at(coordinates) {
+            self.tile(tile).splat_count += 1;
+        }
+    }
+
+    // Counts accepted and total mutations at the pixel of the chain's state.
+    pub fn record_acceptance(&mut self, coordinates: Point2, accepted: bool) {
+        let value = Spectrum {
+            r: if accepted { 1.0 } else { 0.0 },
+            g: 1.0,
+            b: 0.0,
+        };
+        self.record(coordinates, value, |aov| matches!(aov, Aov::Acceptance));
     }
 
-    fn write_exr(&self, path: String) -> Result<(), String> {
-        write_rgb_file(path, self.width, self.height, |x, y| {
-            let i = y * self.width + x;
-            let pixel = self.pixels[i];
-            let rgb = pixel.to_rgb();
-            (rgb.r as f32, rgb.g as f32, rgb.b as f32)
+    // Counts the states a chain takes at each pixel.
+    pub fn record_state(&mut self, coordinates: Point2) {
+        let value = Spectrum::fill(1.0);
+        self.record(coordinates, value, |aov| matches!(aov, Aov::SampleCount));
+    }
+
+    fn record(&mut self, coordinates: Point2, value: Spectrum, records: impl Fn(&Aov) -> bool) {
+        let image = self.image;
+        let Some(tile_index) = image.tile_at(coordinates) else {
+            return;
+        };
+        let tile = &image.tiles[tile_index];
+        let size = tile.width * tile.height;
+        let x = coordinates.x as usize - tile.x;
+        let y = coordinates.y as usize - tile.y;
+        for (index, aov) in image.aovs.iter().enumerate() {
+            if records(aov) {
+                let local = self.tile(tile_index);
+                let offset = (index + 1) * size + y * tile.width + x;
+                local.pixels[offset] = local.pixels[offset] + value;
+            }
+        }
+    }
+
+    fn tile(&mut self, index: usize) -> &mut LocalTile {
+        let size = self.image.tiles[index].pixels.len();
+        self.tiles[index].get_or_insert_with(|| LocalTile {
+            pixels: vec![Spectrum::black(); size],
+            splat_count: 0,
         })
-        .map_err(|e| e.to_string())
     }
 
-    pub fn scale(&mut self, s: f64) {
-        for i in 0..self.pixels.len() {
-            self.pixels[i] = self.pixels[i] * s;
+    pub fn merge(&mut self) {
+        for (index, local) in self.tiles.iter_mut().enumerate() {
+            if let Some(local) = local.take() {
+                let tile = &self.image.tiles[index];
+                for (pixel, value) in tile.pixels.iter().zip(local.pixels) {
+                    pixel.add(value);
+                }
+                tile.splat_count
+                    .fetch_add(local.splat_count, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+// How the writers lay out and store samples. The bit depth defaults to the
+// natural depth of each format and is validated when the image is written.
+#[derive(Debug, Default)]
+struct Encoding {
+    bit_depth: Option<u8>,
+    endianness: Endianness,
+    channels: ChannelLayout,
+    dither: Dither,
+}
+
+impl Encoding {
+    fn configure(config: &EncodingConfig) -> Encoding {
+        Encoding {
+            bit_depth: config.bit_depth,
+            endianness: config.endianness.unwrap_or_default(),
+            channels: config.channels.unwrap_or_default(),
+            dither: config.dither.unwrap_or_default(),
+        }
+    }
+
+    fn bit_depth(&self, default: u8, supported: &[u8]) -> Result<u8, String> {
+        let bit_depth = self.bit_depth.unwrap_or(default);
+        if supported.contains(&bit_depth) {
+            Ok(bit_depth)
+        } else {
+            Err(format!(
+                "unsupported bit depth {} for image type",
+                bit_depth
+            ))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelLayout {
+    #[default]
+    Rgb,
+    Gray,
+}
+
+impl ChannelLayout {
+    fn samples(&self, rgb: Spectrum) -> Vec<Float> {
+        match self {
+            ChannelLayout::Rgb => vec![rgb.r, rgb.g, rgb.b],
+            ChannelLayout::Gray => vec![rgb.luminance()],
+        }
+    }
+
+    fn channel_count(&self) -> usize {
+        match self {
+            ChannelLayout::Rgb => 3,
+            ChannelLayout::Gray => 1,
         }
     }
 }
@@ -153,9 +937,28 @@ impl Image {
 pub struct ImageConfig {
     pub width: usize,
     pub height: usize,
-    pub filter: FilterConfig,
-    pub sample_clamp: Option<f64>,
-    pub clamp: Option<f64>,
+    // The default filter of the scene if omitted, and otherwise a box.
+    pub filter: Option<FilterConfig>,
+    pub sample_clamp: Option<Float>,
+    // The per-sample limit of indirect light off a fully rough surface.
+    pub indirect_clamp: Option<Float>,
+    pub clamp: Option<Float>,
+    pub bloom: Option<BloomConfig>,
+    pub exposure: Option<Float>,
+    pub auto_exposure: Option<AutoExposureConfig>,
+    pub white_balance: Option<Float>,
+    pub gamma: Option<Float>,
+    pub encoding: Option<EncodingConfig>,
+    pub aovs: Option<Vec<AovConfig>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EncodingConfig {
+    pub bit_depth: Option<u8>,
+    pub endianness: Option<Endianness>,
+    pub channels: Option<ChannelLayout>,
+    // For 8- and 16-bit samples; none by default.
+    pub dither: Option<Dither>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -178,19 +981,19 @@ impl FilterConfig {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GaussianFilterConfig {
     radius: Vector2Config,
-    sigma: f64,
+    sigma: Float,
 }
 
-pub trait Filter: Sync {
+pub trait Filter: Send + Sync {
     fn radius(&self) -> Vector2;
-    fn evaluate(&self, point: Point2) -> f64;
+    fn evaluate(&self, point: Point2) -> Float;
 }
 
 pub struct GaussianFilter {
-    sigma: f64,
+    sigma: Float,
     radius: Vector2,
-    exp_x: f64,
-    exp_y: f64,
+    exp_x: Float,
+    exp_y: Float,
 }
 
 impl GaussianFilter {
@@ -211,9 +1014,9 @@ impl Filter for GaussianFilter {
         self.radius
     }
 
-    fn evaluate(&self, p: Point2) -> f64 {
-        f64::max(0.0, util::gaussian(p.x, self.sigma) - self.exp_x)
-            * f64::max(0.0, util::gaussian(p.y, self.sigma) - self.exp_y)
+    fn evaluate(&self, p: Point2) -> Float {
+        Float::max(0.0, util::gaussian(p.x, self.sigma) - self.exp_x)
+            * Float::max(0.0, util::gaussian(p.y, self.sigma) - self.exp_y)
     }
 }
 
@@ -230,7 +1033,262 @@ impl Filter for BoxFilter {
         Point2::new(0.0, 0.0)
     }
 
-    fn evaluate(&self, _point: Point2) -> f64 {
+    fn evaluate(&self, _point: Point2) -> Float {
         1.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, thread};
+
+    use super::{BoxFilter, ChannelLayout, Encoding, Image, TileStatistics};
+    use crate::{
+        aov::Aov, checkpoint::RenderMetadata, path::Contribution, post, spectrum::Spectrum,
+        vector::Point2,
+    };
+
+    #[test]
+    fn test_image_tiles() {
+        let image = Image::new(40, 70, Box::new(BoxFilter::new()), None, None);
+        let statistics = image.tile_statistics();
+        assert_eq!(statistics.len(), 6);
+        assert_eq!(
+            statistics[5],
+            TileStatistics {
+                x: 32,
+                y: 64,
+                width: 8,
+                height: 6,
+                splat_count: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_local_image_merge() {
+        let image = Image::new(40, 40, Box::new(BoxFilter::new()), None, None);
+        let mut local = image.local();
+        local.contribute(
+            &Contribution::new(Spectrum::fill(1.0), Point2::new(33.5, 2.5), 2),
+            1.0,
+        );
+        assert_eq!(image.layer_pixel(0, 33, 2, 1.0), Spectrum::black());
+        local.merge();
+        assert_eq!(image.layer_pixel(0, 33, 2, 1.0), Spectrum::fill(1.0));
+        assert_eq!(image.layer_pixel(0, 2, 33, 1.0), Spectrum::black());
+        assert_eq!(image.tile_statistics()[1].splat_count, 1);
+        local.merge();
+        assert_eq!(image.layer_pixel(0, 33, 2, 1.0), Spectrum::fill(1.0));
+    }
+
+    #[test]
+    fn test_indirect_clamp() {
+        let mut image = Image::new(1, 1, Box::new(BoxFilter::new()), Some(8.0), None);
+        image.indirect_clamp = Some(1.0);
+        let contribution = |path_length, roughness| Contribution {
+            roughness,
+            ..Contribution::new(Spectrum::fill(10.0), Point2::new(0.5, 0.5), path_length)
+        };
+        assert_eq!(image.sample_limit(&contribution(3, 1.0)), Some(8.0));
+        assert_eq!(image.sample_limit(&contribution(4, 1.0)), Some(1.0));
+        assert_eq!(image.sample_limit(&contribution(4, 0.25)), Some(4.0));
+        assert_eq!(image.sample_limit(&contribution(4, 0.0)), Some(8.0));
+        image.sample_clamp = None;
+        assert_eq!(image.sample_limit(&contribution(4, 0.0)), None);
+    }
+
+    #[test]
+    fn test_local_image_merge_concurrent() {
+        let image = Image::new(4, 4, Box::new(BoxFilter::new()), None, None);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let mut local = image.local();
+                    for _ in 0..100 {
+                        local.contribute(
+                            &Contribution::new(Spectrum::fill(0.25), Point2::new(3.5, 0.5), 2),
+                            1.0,
+                        );
+                    }
+                    local.merge();
+                });
+            }
+        });
+        assert_eq!(image.layer_pixel(0, 3, 0, 1.0), Spectrum::fill(100.0));
+        assert_eq!(image.tile_statistics()[0].splat_count, 400);
+    }
+
+    #[test]
+    fn test_ldr_samples16() {
+        let image = Image::new(2, 1, Box::new(BoxFilter::new()), None, None);
+        let mut local = image.local();
+        local.contribute(
+            &Contribution::new(Spectrum::fill(1e6), Point2::new(1.5, 0.5), 2),
+            1.0,
+        );
+        local.merge();
+        let samples = image.ldr_samples16(0, 1.0);
+        assert_eq!(samples, vec![0, 0, 0, 65535, 65535, 65535]);
+    }
+
+    #[test]
+    fn test_preview() {
+        let image = Image::new(2, 2, Box::new(BoxFilter::new()), None, None);
+        let mut local = image.local();
+        local.contribute(
+            &Contribution::new(Spectrum::fill(1e6), Point2::new(1.5, 1.5), 2),
+            1.0,
+        );
+        local.merge();
+        let preview = image.preview(1.0);
+        assert_eq!(preview.pixels.len(), 12);
+        assert_eq!(preview.region(1, 0, 1, 2), vec![0, 0, 0, 255, 255, 255]);
+        assert_eq!(image.preview(0.0).region(1, 1, 1, 1), vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn test_encoding() {
+        let mut image = Image::new(2, 1, Box::new(BoxFilter::new()), None, None);
+        image.encoding = Encoding {
+            bit_depth: Some(8),
+            channels: ChannelLayout::Gray,
+            ..Encoding::default()
+        };
+        let mut local = image.local();
+        local.contribute(
+            &Contribution::new(Spectrum::fill(1e6), Point2::new(1.5, 0.5), 2),
+            1.0,
+        );
+        local.merge();
+        assert_eq!(image.ldr_samples8(0, 1.0), vec![0, 255]);
+        assert_eq!(image.encoding.bit_depth(16, &[8, 16]), Ok(8));
+        assert!(image.encoding.bit_depth(32, &[32]).is_err());
+    }
+
+    #[test]
+    fn test_aov_layers() {
+        let mut image = Image::new(2, 1, Box::new(BoxFilter::new()), None, None);
+        image.add_aovs([Aov::Direct, Aov::Indirect]);
+        let mut local = image.local();
+        local.contribute(
+            &Contribution::new(Spectrum::fill(1.0), Point2::new(0.5, 0.5), 3),
+            2.0,
+        );
+        local.contribute(
+            &Contribution::new(Spectrum::fill(1.0), Point2::new(0.5, 0.5), 4),
+            1.0,
+        );
+        local.merge();
+        assert_eq!(image.layer_pixel(0, 0, 0, 1.0), Spectrum::fill(3.0));
+        assert_eq!(image.layer_pixel(1, 0, 0, 1.0), Spectrum::fill(2.0));
+        assert_eq!(image.layer_pixel(2, 0, 0, 1.0), Spectrum::fill(1.0));
+        assert_eq!(image.layer_pixel(2, 1, 0, 1.0), Spectrum::black());
+    }
+
+    #[test]
+    fn test_record_acceptance() {
+        let mut image = Image::new(2, 1, Box::new(BoxFilter::new()), None, None);
+        image.add_aovs([Aov::Acceptance]);
+        let mut local = image.local();
+        local.record_acceptance(Point2::new(1.5, 0.5), true);
+        local.record_acceptance(Point2::new(1.5, 0.5), false);
+        local.merge();
+        assert_eq!(image.layer_pixel(1, 1, 0, 1.0), post::heat_map(0.5));
+        assert_eq!(image.layer_pixel(1, 0, 0, 1.0), Spectrum::black());
+        assert_eq!(image.layer_pixel(0, 1, 0, 1.0), Spectrum::black());
+    }
+
+    #[test]
+    fn test_record_state() {
+        let mut image = Image::new(2, 1, Box::new(BoxFilter::new()), None, None);
+        image.add_aovs([Aov::SampleCount]);
+        let mut local = image.local();
+        local.record_state(Point2::new(1.5, 0.5));
+        local.record_state(Point2::new(1.5, 0.5));
+        local.record_state(Point2::new(2.5, 0.5));
+        local.merge();
+        image.scale(0.5);
+        assert_eq!(image.layer_pixel(1, 1, 0, 1.0), Spectrum::fill(2.0));
+        assert_eq!(image.layer_pixel(1, 0, 0, 1.0), Spectrum::black());
+    }
+
+    #[test]
+    fn test_image_scale() {
+        let mut image = Image::new(2, 2, Box::new(BoxFilter::new()), Some(2.0), Some(3.0));
+        let mut local = image.local();
+        for _ in 0..4 {
+            local.contribute(
+                &Contribution::new(Spectrum::fill(4.0), Point2::new(0.5, 0.5), 2),
+                1.0,
+            );
+        }
+        local.contribute(
+            &Contribution::new(Spectrum::fill(1.0), Point2::new(1.5, 1.5), 2),
+            1.0,
+        );
+        local.merge();
+        image.scale(0.5);
+        assert_eq!(image.layer_pixel(0, 0, 0, 1.0), Spectrum::fill(1.5));
+        assert_eq!(image.layer_pixel(0, 1, 1, 1.0), Spectrum::fill(0.5));
+    }
+
+    #[test]
+    fn test_image_accumulate() {
+        let image = Image::new(40, 3, Box::new(BoxFilter::new()), None, None);
+        let mut local = image.local();
+        local.contribute(
+            &Contribution::new(Spectrum::fill(2.0), Point2::new(35.5, 1.5), 2),
+            1.0,
+        );
+        local.merge();
+        let checkpoint = image.checkpoint(10, vec![1]);
+        let merged = Image::new(40, 3, Box::new(BoxFilter::new()), None, None);
+        merged.accumulate(&checkpoint).unwrap();
+        merged.accumulate(&checkpoint).unwrap();
+        assert_eq!(merged.layer_pixel(0, 35, 1, 1.0), Spectrum::fill(4.0));
+        assert_eq!(merged.layer_pixel(0, 1, 2, 1.0), Spectrum::black());
+
+        let other = Image::new(3, 40, Box::new(BoxFilter::new()), None, None);
+        assert!(other.accumulate(&checkpoint).is_err());
+    }
+
+    #[test]
+    fn test_image_restore() {
+        let path = env::temp_dir().join("mmlt-test-image-restore.exr");
+        let path = path.to_str().unwrap();
+        let image = |aovs| {
+            let mut image = Image::new(40, 3, Box::new(BoxFilter::new()), None, None);
+            image.add_aovs(aovs);
+            image
+        };
+        let mut rendered = image(vec![Aov::Direct, Aov::SampleCount]);
+        let mut local = rendered.local();
+        local.contribute(
+            &Contribution::new(Spectrum::fill(2.0), Point2::new(35.5, 1.5), 2),
+            1.0,
+        );
+        local.record_state(Point2::new(35.5, 1.5));
+        local.merge();
+        // Written without metadata, the image cannot be restored.
+        rendered.write(String::from(path)).unwrap();
+        let restored = image(vec![Aov::Direct, Aov::SampleCount]);
+        assert!(restored.restore(path).is_err());
+
+        let metadata = RenderMetadata {
+            sample_count: 240,
+            seeds: vec![7],
+            normalization: vec![(1.5, 10)],
+        };
+        rendered.set_metadata(metadata.clone());
+        rendered.scale(120.0 / 240.0);
+        rendered.write(String::from(path)).unwrap();
+        assert_eq!(restored.restore(path).unwrap(), metadata);
+        assert!(image(vec![Aov::Indirect]).restore(path).is_err());
+        fs::remove_file(path).unwrap();
+        assert_eq!(restored.accumulated_pixel(0, 35, 1), Spectrum::fill(2.0));
+        assert_eq!(restored.accumulated_pixel(1, 35, 1), Spectrum::fill(2.0));
+        // Statistics start again.
+        assert_eq!(restored.accumulated_pixel(2, 35, 1), Spectrum::black());
+    }
+}
@@ -1,121 +1,1403 @@
-use std::time::Instant;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Barrier, Mutex,
+    },
+    time::Instant,
+};
 
 use rand::{distributions::Distribution, thread_rng, Rng};
 
 use crate::{
+    aov::Aov,
+    cache,
+    checkpoint::{RenderMetadata, SavedNormalization},
     config::Config,
-    image::Image,
-    path::{Contribution, Path},
+    error::Error,
+    export::PathExporter,
+    image::{BoxFilter, Image, LocalImage, TileStatistics},
+    interrupt::Cancellation,
+    langevin::Langevin,
+    memory, parallel,
+    path::{Contribution, Path, PathArena, PathTrace, Technique, TechniqueStatistics},
     pdf::Pdf,
-    progress::{report, report_progress},
-    sampler::{MmltSampler, MutationType},
+    post::DisplayTransform,
+    profile,
+    progress::{Phase, ProgressSink},
+    ray::{Ray, RayKind},
+    record::{ProposalRecorder, RecordedProposal},
+    rng::{Generator, RngKind},
+    sampler::{MmltSampler, MutationType, ReplaySampler},
     scene::Scene,
+    spectrum::Spectrum,
+    trajectory::TrajectoryWriter,
+    types::Float,
+    vector::Point2,
 };
 
+// The samples each group of chains takes between merges into the image.
+const EPOCH_LENGTH: u64 = 1 << 14;
+const DEFAULT_CHAIN_COUNT: usize = 64;
+pub const DEFAULT_MAX_PATH_LENGTH: usize = 20;
+const BOOTSTRAP_IMAGE_DOWNSCALE: usize = 4;
+// The initial states of each path length kept with a saved normalization.
+const SAVED_STATE_COUNT: usize = 256;
+
 pub trait Integrator {
-    fn integrate(&self, scene: &Scene) -> Image;
+    fn integrate(
+        &self,
+        scene: &Scene,
+        progress: &dyn ProgressSink,
+        cancellation: &Cancellation,
+    ) -> Result<Image, Error>;
+}
+
+// A Markov chain for each path length, with a random number generator of
+// its own, the normalization estimates of the current epoch, and the samples
+// it has splatted, accepted and taken by large steps since the last merge.
+// Every `export_stride` samples of the render, counted from `first_sample`
+// for the group, the next accepted path is traced for export.
+struct ChainGroup<'a> {
+    rng: Generator,
+    samplers: Vec<MmltSampler>,
+    contributions: Vec<Contribution>,
+    arena: PathArena<'a>,
+    local_image: LocalImage<'a>,
+    b: Vec<Float>,
+    large_steps: Normalization,
+    accepted: Vec<Contribution>,
+    recorded: Vec<RecordedProposal>,
+    traces: Vec<PathTrace>,
+    export_stride: u64,
+    export_due: bool,
+    first_sample: u64,
+    sample_count: u64,
+    share: u64,
+}
+
+// The running estimate of the normalization constant of each path length,
+// the average scalar contribution of its paths. Large steps are independent
+// samples just like those of the bootstrap, so the estimate keeps improving
+// during the render instead of being frozen after it.
+struct Normalization {
+    sums: Vec<Float>,
+    counts: Vec<u64>,
+}
+
+impl Normalization {
+    fn new(path_length_count: usize) -> Normalization {
+        Normalization {
+            sums: vec![0.0; path_length_count],
+            counts: vec![0; path_length_count],
+        }
+    }
+
+    fn bootstrap(b: &[Float], sample_count: u64) -> Normalization {
+        Normalization {
+            sums: b.iter().map(|b_k| b_k * sample_count as Float).collect(),
+            counts: vec![sample_count; b.len()],
+        }
+    }
+
+    fn record(&mut self, k: usize, scalar: Float) {
+        self.sums[k] += scalar;
+        self.counts[k] += 1;
+    }
+
+    fn add(&mut self, other: &Normalization) {
+        for k in 0..self.sums.len() {
+            self.sums[k] += other.sums[k];
+            self.counts[k] += other.counts[k];
+        }
+    }
+
+    // The sums and counts, which add up across renders.
+    fn totals(&self) -> Vec<(Float, u64)> {
+        self.sums.iter().copied().zip(self.counts.clone()).collect()
+    }
+
+    fn restore(&mut self, totals: &[(Float, u64)]) {
+        for (k, (sum, count)) in totals.iter().enumerate() {
+            self.sums[k] += sum;
+            self.counts[k] += count;
+        }
+    }
+
+    fn estimates(&self) -> Vec<Float> {
+        self.sums
+            .iter()
+            .zip(&self.counts)
+            .map(|(sum, count)| {
+                if *count > 0 {
+                    sum / *count as Float
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    }
 }
 
 pub struct MmltIntegrator {
+    // Shorter path lengths are left out of the render.
+    min_path_length: usize,
     max_path_length: usize,
     initial_sample_count: u64,
     average_samples_per_pixel: u64,
+    chain_count: usize,
+    // The mutations of each chain before the render, none by default.
+    burn_in: u64,
+    path_length_images: bool,
+    trajectory_path: Option<String>,
+    export_path: Option<String>,
+    export_path_count: u64,
+    record_proposals_path: Option<String>,
+    record_threshold: Float,
+    bootstrap_image_path: Option<String>,
+    write_normalization_path: Option<String>,
+    read_normalization_path: Option<String>,
+    checkpoint_path: String,
+    write_checkpoint: bool,
+    merge_into: Option<String>,
+    seed: Option<u64>,
+    rng: RngKind,
+    langevin: Option<Langevin>,
+    caustic_perturbation_probability: Float,
+    technique_mutation_probability: Float,
 }
 
 impl MmltIntegrator {
-    pub fn new(config: &Config) -> MmltIntegrator {
+    pub fn new(config: &Config, image_path: &str) -> MmltIntegrator {
         MmltIntegrator {
-            max_path_length: config.max_path_length.unwrap_or(20),
+            min_path_length: config.min_path_length.unwrap_or(2),
+            max_path_length: config.max_path_length.unwrap_or(DEFAULT_MAX_PATH_LENGTH),
             initial_sample_count: config.initial_sample_count.unwrap_or(100_000),
             average_samples_per_pixel: config.average_samples_per_pixel.unwrap_or(4096),
+            chain_count: usize::max(1, config.chain_count.unwrap_or(DEFAULT_CHAIN_COUNT)),
+            burn_in: config.burn_in.unwrap_or(0),
+            path_length_images: config.path_length_images,
+            trajectory_path: config.trajectory_path.clone(),
+            export_path: config.export_path.clone(),
+            export_path_count: config.export_path_count.unwrap_or(1000),
+            record_proposals_path: config.record_proposals_path.clone(),
+            record_threshold: config.record_threshold.unwrap_or(0.0),
+            bootstrap_image_path: config.bootstrap_image_path.clone(),
+            write_normalization_path: config.write_normalization_path.clone(),
+            read_normalization_path: config.read_normalization_path.clone(),
+            checkpoint_path: format!("{}.checkpoint", image_path),
+            write_checkpoint: config.write_checkpoint,
+            merge_into: config.merge_into.clone(),
+            seed: config.seed,
+            rng: config.rng.unwrap_or_default(),
+            langevin: config
+                .langevin_probability
+                .filter(|p| *p > 0.0)
+                .map(Langevin::new),
+            caustic_perturbation_probability: config
+                .caustic_perturbation_probability
+                .unwrap_or(0.0),
+            technique_mutation_probability: config.technique_mutation_probability.unwrap_or(0.0),
+        }
+    }
+
+    // Mutates the chain of the given index, giving the type of the mutation,
+    // the contribution of the proposal and the correction of its acceptance.
+    fn propose<'a>(
+        &self,
+        scene: &'a Scene,
+        group: &mut ChainGroup<'a>,
+        k: usize,
+    ) -> (MutationType, Contribution, Float) {
+        let rng = &mut group.rng;
+        let sampler = &mut group.samplers[k];
+        let arena = &mut group.arena;
+        let current_contribution = group.contributions[k];
+        let mut mutation_type = sampler.mutate();
+        if mutation_type == MutationType::SmallStep {
+            let r = rng.gen_range(0.0..1.0);
+            if r < self.caustic_perturbation_probability {
+                mutation_type = Path::perturb_emission(sampler);
+            } else if r < self.caustic_perturbation_probability
+                + self.technique_mutation_probability
+            {
+                mutation_type = Technique::mutate(k + 2, sampler, rng);
+            }
+        }
+        // A fraction of the small steps are replaced by Langevin
+        // proposals, whose asymmetry corrects the acceptance probability.
+        let (proposal_contribution, correction) = match &self.langevin {
+            Some(langevin)
+                if mutation_type == MutationType::SmallStep
+                    && rng.gen_range(0.0..1.0) < langevin.probability() =>
+            {
+                langevin.propose(scene, sampler, current_contribution, k + 2, rng, arena)
+            }
+            _ => (Path::contribute(scene, sampler, k + 2, arena), 1.0),
+        };
+        (mutation_type, proposal_contribution, correction)
+    }
+
+    // Mutates every chain of the group for the burn-in, with the usual
+    // acceptance but without splatting, so that the states the chains start
+    // the render from are less tied to their initial ones. Chains of path
+    // lengths without contribution are never chosen, and are left alone.
+    fn burn_in_chains<'a>(&self, scene: &'a Scene, group: &mut ChainGroup<'a>) {
+        for k in 0..group.samplers.len() {
+            if group.b[k] == 0.0 {
+                continue;
+            }
+            for _ in 0..self.burn_in {
+                let (_, proposal_contribution, correction) = self.propose(scene, group, k);
+                let a = Contribution::acceptance(
+                    group.contributions[k],
+                    proposal_contribution,
+                    correction,
+                );
+                if group.rng.gen_range(0.0..1.0) <= a {
+                    group.samplers[k].accept();
+                    group.contributions[k] = proposal_contribution;
+                } else {
+                    group.samplers[k].reject();
+                }
+            }
+        }
+    }
+
+    // Takes a sample with a chain of the group picked at random.
+    fn step<'a>(&self, scene: &'a Scene, group: &mut ChainGroup<'a>, pdf: &Pdf) {
+        group.sample_count += 1;
+        let k = pdf.sample(&mut group.rng);
+        let current_contribution = group.contributions[k];
+        let (mutation_type, proposal_contribution, correction) = self.propose(scene, group, k);
+        let rng = &mut group.rng;
+        let sampler = &mut group.samplers[k];
+        let arena = &mut group.arena;
+        let a = Contribution::acceptance(current_contribution, proposal_contribution, correction);
+        let step_factor = match mutation_type {
+            MutationType::LargeStep => 1.0,
+            MutationType::SmallStep | MutationType::Proposal | MutationType::Perturbation => 0.0,
+        };
+        if mutation_type == MutationType::LargeStep {
+            group.large_steps.record(k, proposal_contribution.scalar);
+        }
+        let b = &group.b;
+
+        if self.record_proposals_path.is_some()
+            && !proposal_contribution.is_empty()
+            && proposal_contribution.scalar >= self.record_threshold
+        {
+            let pixel = proposal_contribution.pixel_coordinates;
+            group.recorded.push(RecordedProposal {
+                path_length: k + 2,
+                pixel: [pixel.x, pixel.y],
+                contribution: proposal_contribution.scalar,
+                values: sampler.current_values(),
+            });
+        }
+
+        // A path of k + 2 vertices is sampled by one of its k + 3
+        // techniques, chosen uniformly, so each sample stands for all of
+        // them.
+        let technique_count = k as Float + 3.0;
+        if !proposal_contribution.is_empty() {
+            let weight = ((technique_count / pdf.value(k)) * (a + step_factor))
+                / ((proposal_contribution.scalar / b[k]) + sampler.large_step_probability);
+            group.local_image.contribute(&proposal_contribution, weight);
+        }
+
+        if !current_contribution.is_empty() {
+            let weight = ((technique_count / pdf.value(k)) * (1.0 - a))
+                / ((current_contribution.scalar / b[k]) + sampler.large_step_probability);
+            group.local_image.contribute(&current_contribution, weight);
+        }
+
+        let accepted = rng.gen_range(0.0..1.0) <= a;
+        let state = if current_contribution.is_empty() {
+            proposal_contribution
+        } else {
+            current_contribution
+        };
+        if !state.is_empty() {
+            group
+                .local_image
+                .record_acceptance(state.pixel_coordinates, accepted);
+        }
+
+        if accepted {
+            sampler.accept();
+            group.contributions[k] = proposal_contribution;
+            if self.trajectory_path.is_some() && !proposal_contribution.is_empty() {
+                group.accepted.push(proposal_contribution);
+            }
+        } else {
+            sampler.reject();
+        }
+        let state = group.contributions[k];
+        if !state.is_empty() {
+            group.local_image.record_state(state.pixel_coordinates);
+        }
+
+        // The exported paths are traced again from the accepted state, which
+        // leaves the random numbers of the render alone.
+        if self.export_path.is_some() {
+            if (group.first_sample + group.sample_count).is_multiple_of(group.export_stride) {
+                group.export_due = true;
+            }
+            if group.export_due && accepted && !proposal_contribution.is_empty() {
+                let mut replay = Path::replay_sampler(sampler.current_values());
+                group
+                    .traces
+                    .extend(Path::trace_paths(scene, &mut replay, k + 2, arena));
+                group.export_due = false;
+            }
         }
     }
 }
 
 impl Integrator for MmltIntegrator {
-    fn integrate(&self, scene: &Scene) -> Image {
-        report("Initializing MMLT integrator...");
+    fn integrate(
+        &self,
+        scene: &Scene,
+        progress: &dyn ProgressSink,
+        cancellation: &Cancellation,
+    ) -> Result<Image, Error> {
+        progress.report("Initializing MMLT integrator...");
+        let (min, max) = (scene.bounds.min, scene.bounds.max);
+        progress.report(&format!(
+            "scene bounds: ({:.3}, {:.3}, {:.3}) to ({:.3}, {:.3}, {:.3})",
+            min.x, min.y, min.z, max.x, max.y, max.z
+        ));
         let start = Instant::now();
+        profile::reset();
+
+        let trajectory = match &self.trajectory_path {
+            Some(path) => Some(Mutex::new(TrajectoryWriter::create(path)?)),
+            None => None,
+        };
+        let exporter = match &self.export_path {
+            Some(path) => Some(Mutex::new(PathExporter::create(path)?)),
+            None => None,
+        };
+        let recorder = match &self.record_proposals_path {
+            Some(path) => Some(Mutex::new(ProposalRecorder::create(path)?)),
+            None => None,
+        };
 
         let mut b = vec![0.0; self.max_path_length - 1];
-        let mut rng = thread_rng();
+        // Every random decision follows from the seed, so a render can be
+        // reproduced, and renders with different seeds can be merged.
+        let seed = self.seed.unwrap_or_else(|| thread_rng().gen());
+        let threads = parallel::threads();
+        progress.report(&format!("seed: {}", seed));
+        progress.report(&format!("threads: {}", threads));
+        progress.report(&format!("chains: {}", self.chain_count));
+        let mut rng = self.rng.seeded(seed);
+
+        let mut image = Image::configure(&scene.image_config);
+        if self.path_length_images {
+            image.add_aovs((self.min_path_length..=self.max_path_length).map(Aov::PathLength));
+        }
+        // A render merged into an earlier one carries on from its image, its
+        // samples and its normalization estimates, with a seed of its own.
+        let previous = match &self.merge_into {
+            Some(path) => {
+                let previous = image.restore(path)?;
+                let error = |message| Error::Checkpoint {
+                    path: path.clone(),
+                    message,
+                };
+                if previous.normalization.len() != b.len() {
+                    return Err(error(format!(
+                        "the image was rendered with a maximum path length of {}, not {}",
+                        previous.normalization.len() + 1,
+                        self.max_path_length
+                    )));
+                }
+                if previous.seeds.contains(&seed) {
+                    return Err(error(format!("seed {} was already merged", seed)));
+                }
+                progress.report(&format!(
+                    "merging into {} with {} samples",
+                    path, previous.sample_count
+                ));
+                Some(previous)
+            }
+            None => None,
+        };
+        let previous_sample_count = previous.as_ref().map_or(0, |p| p.sample_count);
+
+        // An optional low-resolution image of the bootstrap samples, for
+        // checking that the normalization estimate saw the important features.
+        let bootstrap_image = self.bootstrap_image_path.as_ref().map(|_| {
+            Image::new(
+                scene.image_config.width.div_ceil(BOOTSTRAP_IMAGE_DOWNSCALE),
+                scene
+                    .image_config
+                    .height
+                    .div_ceil(BOOTSTRAP_IMAGE_DOWNSCALE),
+                Box::new(BoxFilter::new()),
+                None,
+                None,
+            )
+        });
 
-        for k in 0..self.max_path_length - 1 {
-            for _ in 0..self.initial_sample_count {
-                let mut sampler = Path::sampler();
-                let contribution = Path::contribute(scene, &mut sampler, k + 2);
-                b[k] = b[k] + contribution.scalar;
+        // A saved normalization replaces the bootstrap.
+        let saved = match &self.read_normalization_path {
+            Some(path) => {
+                let mut saved = SavedNormalization::read(path)?;
+                let error = |message| Error::Checkpoint {
+                    path: path.clone(),
+                    message,
+                };
+                if saved.b.len() != b.len() {
+                    return Err(error(format!(
+                        "the normalization is for a maximum path length of {}, not {}",
+                        saved.b.len() + 1,
+                        self.max_path_length
+                    )));
+                }
+                // Path lengths below the minimum are left out as the
+                // bootstrap would have left them, and the chains are chosen
+                // among the rest.
+                let excluded = self.min_path_length.saturating_sub(2).min(b.len());
+                for k in 0..excluded {
+                    saved.b[k] = 0.0;
+                    saved.pdf[k] = 0.0;
+                    saved.states[k].clear();
+                }
+                if saved.b.iter().all(|b_k| *b_k <= 0.0) || saved.pdf.iter().all(|p| *p <= 0.0) {
+                    return Err(error(format!(
+                        "the normalization has no path length of at least {}",
+                        self.min_path_length
+                    )));
+                }
+                progress.report(&format!("normalization: {}", path));
+                Some(saved)
             }
-            b[k] = b[k] / self.initial_sample_count as f64;
-            report_progress((k + 1) as f64 / (self.max_path_length - 1) as f64);
+            None => None,
+        };
+
+        // Each bootstrap sample is kept as the seed of its sampler, so that
+        // the initial chain states can be resampled from them afterwards.
+        let n = match saved {
+            Some(_) => 0,
+            None => self.initial_sample_count as usize,
+        };
+        let seeds: Vec<Vec<u64>> = (0..b.len())
+            .map(|_| (0..n).map(|_| rng.gen()).collect())
+            .collect();
+
+        let mut allocations = Vec::new();
+        let allocation_count = memory::allocation_count();
+        if saved.is_none() {
+            progress.start(Phase::Bootstrap, (b.len() * n) as u64);
+        }
+        let scalars = parallel::run(
+            parallel::split(b.len() * n, threads),
+            progress,
+            |_, range, counter| {
+                let mut local_image = bootstrap_image.as_ref().map(Image::local);
+                let mut arena = PathArena::default();
+                let scalars: Vec<Float> = range
+                    .map(|i| {
+                        let k = i / n;
+                        // An excluded path length keeps a normalization of
+                        // zero, so that its chain is never chosen.
+                        if k + 2 < self.min_path_length {
+                            counter.fetch_add(1, Ordering::Relaxed);
+                            return 0.0;
+                        }
+                        let mut sampler = Path::seeded_sampler(
+                            &scene.mutation_parameters,
+                            self.rng,
+                            seeds[k][i % n],
+                        );
+                        let contribution = Path::contribute(scene, &mut sampler, k + 2, &mut arena);
+                        counter.fetch_add(1, Ordering::Relaxed);
+                        if let Some(local_image) = &mut local_image {
+                            if !contribution.is_empty() {
+                                let coordinates = contribution.pixel_coordinates;
+                                // Weighted by the k + 3 techniques, as in
+                                // the main loop, so the image matches the
+                                // final render.
+                                let downscaled = Contribution::new(
+                                    Spectrum::fill(contribution.scalar * (k + 3) as Float),
+                                    Point2::new(
+                                        coordinates.x / BOOTSTRAP_IMAGE_DOWNSCALE as Float,
+                                        coordinates.y / BOOTSTRAP_IMAGE_DOWNSCALE as Float,
+                                    ),
+                                    contribution.path_length,
+                                );
+                                local_image.contribute(&downscaled, 1.0);
+                            }
+                        }
+                        contribution.scalar
+                    })
+                    .collect();
+                (scalars, local_image)
+            },
+        );
+        // The local images are merged in order, so that the sums do not
+        // depend on which thread finished first.
+        let scalars: Vec<Float> = scalars
+            .into_iter()
+            .flat_map(|(scalars, local_image)| {
+                if let Some(mut local_image) = local_image {
+                    local_image.merge();
+                }
+                scalars
+            })
+            .collect();
+        let scalars: Vec<&[Float]> = scalars.chunks(n.max(1)).collect();
+        for (b_k, scalars_k) in b.iter_mut().zip(&scalars) {
+            *b_k = scalars_k.iter().sum::<Float>() / n as Float;
+        }
+        if saved.is_none() {
+            progress.finish();
+            allocations.push((
+                Phase::Bootstrap,
+                memory::allocation_count() - allocation_count,
+            ));
+        }
+
+        if let (Some(mut image), Some(path)) = (bootstrap_image, &self.bootstrap_image_path) {
+            let pixel_count = scene.image_config.width * scene.image_config.height;
+            let downscale = BOOTSTRAP_IMAGE_DOWNSCALE * BOOTSTRAP_IMAGE_DOWNSCALE;
+            image.scale(
+                pixel_count as Float / (downscale as Float * self.initial_sample_count as Float),
+            );
+            image.write(path.clone())?;
+        }
+
+        if let Some(saved) = &saved {
+            b.clone_from(&saved.b);
+        }
+        let pdf = Pdf::new(saved.as_ref().map_or(&b, |saved| &saved.pdf));
+        // The chains keep being chosen by the bootstrap estimate, while their
+        // samples are weighted by the refined one.
+        let bootstrap_sample_count = saved.as_ref().map_or(n as u64, |saved| saved.sample_count);
+        let mut normalization = Normalization::bootstrap(&b, bootstrap_sample_count);
+        if let Some(previous) = &previous {
+            normalization.restore(&previous.normalization);
+        }
+        let normalization = Mutex::new(normalization);
+
+        // Initial states are drawn in proportion to their contribution, so the
+        // chains start in high-contribution regions without needing a burn-in,
+        // although one may still be asked for. Every
+        // chain of a path length produces an unbiased estimate of its image,
+        // so the chains are averaged. Each group of chains has a generator of
+        // its own and a fixed share of the samples, and groups are merged
+        // into the image in order, so the image depends on the seed and the
+        // number of chains but not on the number of threads.
+        let distributions: Vec<Option<Pdf>> = b
+            .iter()
+            .enumerate()
+            .map(|(k, b_k)| (*b_k > 0.0 && n > 0).then(|| Pdf::new(scalars[k])))
+            .collect();
+        // A saved normalization keeps initial states drawn from the bootstrap
+        // in the same way, though evenly, so as to leave the generator alone.
+        // Those that no longer contribute, as after a change to the sampler,
+        // are dropped, and the rest are drawn from uniformly.
+        if let (None, Some(path)) = (&saved, &self.write_normalization_path) {
+            progress.report(&format!("writing normalization to {}", path));
+            let states = distributions
+                .iter()
+                .enumerate()
+                .map(|(k, distribution)| match distribution {
+                    Some(distribution) => (0..SAVED_STATE_COUNT)
+                        .map(|i| {
+                            let r = (i as Float + 0.5) / SAVED_STATE_COUNT as Float;
+                            seeds[k][distribution.invert(r).0]
+                        })
+                        .collect(),
+                    None => Vec::new(),
+                })
+                .collect();
+            SavedNormalization {
+                sample_count: n as u64,
+                b: b.clone(),
+                pdf: (0..b.len()).map(|k| pdf.value(k)).collect(),
+                states,
+            }
+            .write(path)?;
+        }
+        let saved_states: Vec<Vec<u64>> = match &saved {
+            Some(saved) => {
+                let mut arena = PathArena::default();
+                let mut contributes = |k: usize, seed: u64| {
+                    let mut sampler =
+                        Path::seeded_sampler(&scene.mutation_parameters, self.rng, seed);
+                    !Path::contribute(scene, &mut sampler, k + 2, &mut arena).is_empty()
+                };
+                saved
+                    .states
+                    .iter()
+                    .enumerate()
+                    .map(|(k, states)| {
+                        let states = states.iter().copied();
+                        states.filter(|seed| contributes(k, *seed)).collect()
+                    })
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        let group_seeds: Vec<u64> = (0..self.chain_count).map(|_| rng.gen()).collect();
+        let threads = threads.min(self.chain_count);
+        let allocation_count = memory::allocation_count();
+        progress.start(
+            Phase::ChainInitialization,
+            (b.len() * self.chain_count) as u64,
+        );
+        let initial_states = parallel::run(
+            parallel::split(self.chain_count, threads),
+            progress,
+            |_, range, counter| {
+                let mut arena = PathArena::default();
+                range
+                    .map(|group| {
+                        let mut rng = self.rng.seeded(group_seeds[group]);
+                        let mut samplers = Vec::with_capacity(b.len());
+                        let mut contributions = Vec::with_capacity(b.len());
+                        for (k, distribution) in distributions.iter().enumerate() {
+                            let seed = match distribution {
+                                Some(distribution) => Some(seeds[k][distribution.sample(&mut rng)]),
+                                None => match saved_states.get(k) {
+                                    Some(states) if !states.is_empty() => {
+                                        Some(states[rng.gen_range(0..states.len())])
+                                    }
+                                    _ => None,
+                                },
+                            };
+                            let mut sampler = match seed {
+                                Some(seed) => {
+                                    Path::seeded_sampler(&scene.mutation_parameters, self.rng, seed)
+                                }
+                                None => Path::sampler(&scene.mutation_parameters, self.rng),
+                            };
+                            let contribution =
+                                Path::contribute(scene, &mut sampler, k + 2, &mut arena);
+                            // Groups may draw the same initial state, whose
+                            // seed regenerates it, but each mutates it with
+                            // numbers of its own.
+                            sampler.reseed(self.rng.seeded(rng.gen()));
+                            contributions.push(contribution);
+                            samplers.push(sampler);
+                            counter.fetch_add(1, Ordering::Relaxed);
+                        }
+                        (rng, samplers, contributions)
+                    })
+                    .collect::<Vec<_>>()
+            },
+        )
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+        progress.finish();
+        allocations.push((
+            Phase::ChainInitialization,
+            memory::allocation_count() - allocation_count,
+        ));
+        drop(seeds);
+        drop(scalars);
+
+        let pixel_count = (scene.image_config.width * scene.image_config.height) as u64;
+        let total_sample_count = self.average_samples_per_pixel * pixel_count;
+        let shares = parallel::split(total_sample_count as usize, self.chain_count);
+        let export_stride = u64::max(1, total_sample_count / self.export_path_count);
+        let groups: Vec<Mutex<ChainGroup>> = initial_states
+            .into_iter()
+            .zip(shares)
+            .map(|((rng, samplers, contributions), share)| {
+                Mutex::new(ChainGroup {
+                    rng,
+                    samplers,
+                    contributions,
+                    arena: PathArena::default(),
+                    local_image: image.local(),
+                    b: b.clone(),
+                    large_steps: Normalization::new(b.len()),
+                    accepted: Vec::new(),
+                    recorded: Vec::new(),
+                    traces: Vec::new(),
+                    export_stride,
+                    export_due: false,
+                    first_sample: share.start as u64,
+                    sample_count: 0,
+                    share: share.len() as u64,
+                })
+            })
+            .collect();
+        if self.burn_in > 0 {
+            let allocation_count = memory::allocation_count();
+            let chain_count = groups.len() * b.iter().filter(|b_k| **b_k > 0.0).count();
+            progress.start(Phase::BurnIn, self.burn_in * chain_count as u64);
+            parallel::run(
+                parallel::split(groups.len(), threads),
+                progress,
+                |_, range, counter| {
+                    for group in &groups[range] {
+                        let mut group = group.lock().unwrap();
+                        self.burn_in_chains(scene, &mut group);
+                        let chain_count = group.b.iter().filter(|b_k| **b_k > 0.0).count();
+                        counter.fetch_add(self.burn_in * chain_count as u64, Ordering::Relaxed);
+                    }
+                },
+            );
+            progress.finish();
+            allocations.push((Phase::BurnIn, memory::allocation_count() - allocation_count));
         }
 
-        let pdf = Pdf::new(&b);
-        let mut samplers: Vec<MmltSampler> = Vec::new();
-        let mut contributions: Vec<Contribution> = Vec::new();
+        let barrier = Barrier::new(threads);
+        let done = AtomicBool::new(false);
+        let allocation_count = memory::allocation_count();
+        progress.start(Phase::Integration, total_sample_count);
 
-        for k in 0..self.max_path_length - 1 {
-            let mut sampler = Path::sampler();
-            let contribution = Path::contribute(scene, &mut sampler, k + 2);
-            contributions.push(contribution);
-            samplers.push(sampler);
+        // The threads run their groups for an epoch, after which one of them
+        // merges every group into the image, in order.
+        let results = parallel::run(
+            (0..threads).collect(),
+            progress,
+            |thread, _, counter| loop {
+                for group in groups.iter().skip(thread).step_by(threads) {
+                    let mut group = group.lock().unwrap();
+                    let end = u64::min(group.sample_count + EPOCH_LENGTH, group.share);
+                    while group.sample_count < end && !cancellation.is_cancelled() {
+                        self.step(scene, &mut group, &pdf);
+                        counter.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                let mut result = Ok(());
+                if barrier.wait().is_leader() {
+                    result = merge_groups(
+                        &groups,
+                        &normalization,
+                        trajectory.as_ref(),
+                        exporter.as_ref(),
+                        recorder.as_ref(),
+                    );
+                    let finished = groups.iter().all(|group| {
+                        let group = group.lock().unwrap();
+                        group.sample_count == group.share
+                    });
+                    let sample_count: u64 = previous_sample_count
+                        + groups
+                            .iter()
+                            .map(|group| group.lock().unwrap().sample_count)
+                            .sum::<u64>();
+                    let stop = result.is_err() || finished || cancellation.is_cancelled();
+                    let scale = pixel_count as Float / sample_count.max(1) as Float;
+                    progress.snapshot(&image, scale, stop);
+                    done.store(stop, Ordering::SeqCst);
+                }
+                barrier.wait();
+                result?;
+                if done.load(Ordering::SeqCst) {
+                    return Ok::<(), Error>(());
+                }
+            },
+        );
+        progress.finish();
+        allocations.push((
+            Phase::Integration,
+            memory::allocation_count() - allocation_count,
+        ));
+        results.into_iter().collect::<Result<(), Error>>()?;
+        let sample_count: u64 = groups
+            .iter()
+            .map(|group| group.lock().unwrap().sample_count)
+            .sum();
+        let mut techniques = TechniqueStatistics::default();
+        for group in &groups {
+            techniques.add(group.lock().unwrap().arena.techniques());
         }
+        drop(groups);
+        let mut seeds = previous.map_or_else(Vec::new, |p| p.seeds);
+        seeds.push(seed);
+        let accumulated_sample_count = previous_sample_count + sample_count;
+
+        if let Some(trajectory) = trajectory {
+            trajectory.into_inner().unwrap().finish()?;
+        }
+        if let Some(exporter) = exporter {
+            exporter.into_inner().unwrap().finish()?;
+        }
+        if let Some(recorder) = recorder {
+            let count = recorder.into_inner().unwrap().finish()?;
+            progress.report(&format!("recorded {} proposals", count));
+        }
+        report_tile_statistics(progress, &image.tile_statistics());
+        report_technique_statistics(progress, &techniques);
+        report_memory_statistics(progress, scene, &allocations);
+        if profile::enabled() {
+            report_profile(progress, start.elapsed().as_secs_f64());
+        }
+
+        // An interrupted render is normalized by the samples actually taken
+        // and its accumulated state is kept, so the work is not lost.
+        if cancellation.is_cancelled() || self.write_checkpoint {
+            if cancellation.is_cancelled() {
+                progress.report(&format!(
+                    "interrupted at {:.2} samples per pixel",
+                    sample_count as Float / pixel_count as Float
+                ));
+            }
+            progress.report(&format!("writing checkpoint to {}", self.checkpoint_path));
+            image
+                .checkpoint(accumulated_sample_count, seeds.clone())
+                .write(&self.checkpoint_path)?;
+        }
+        image.set_metadata(RenderMetadata {
+            sample_count: accumulated_sample_count,
+            seeds,
+            normalization: normalization.into_inner().unwrap().totals(),
+        });
+        image.scale(pixel_count as Float / accumulated_sample_count.max(1) as Float);
+
+        progress.report("MMLT integration complete");
+
+        let elapsed = start.elapsed();
+        progress.report(&format!("elapsed time: {} seconds", elapsed.as_secs()));
+
+        Ok(image)
+    }
+}
+
+// Independent samples of direct lighting, at a fraction of the cost of an
+// MMLT render, for checking the framing and materials of a scene. Each sample
+// is one of the bidirectional techniques for a path of two or three vertices,
+// so emitters seen directly and lighting after a single bounce are unbiased,
+// while anything seen through a specular surface is missing.
+pub struct PreviewIntegrator {
+    samples_per_pixel: u64,
+    seed: Option<u64>,
+    rng: RngKind,
+}
+
+impl PreviewIntegrator {
+    pub fn new(config: &Config, samples_per_pixel: u64) -> PreviewIntegrator {
+        PreviewIntegrator {
+            samples_per_pixel,
+            seed: config.seed,
+            rng: config.rng.unwrap_or_default(),
+        }
+    }
+}
+
+impl Integrator for PreviewIntegrator {
+    fn integrate(
+        &self,
+        scene: &Scene,
+        progress: &dyn ProgressSink,
+        cancellation: &Cancellation,
+    ) -> Result<Image, Error> {
+        progress.report("Rendering preview...");
+        let start = Instant::now();
+        let seed = self.seed.unwrap_or_else(|| thread_rng().gen());
+        let threads = parallel::threads();
+        progress.report(&format!("seed: {}", seed));
 
-        let mut sample_count: u64 = 0;
         let mut image = Image::configure(&scene.image_config);
         let pixel_count = (scene.image_config.width * scene.image_config.height) as u64;
-        let mut spp = 0;
-        let mut last_reported_spp = 0;
-
-        report("Integrating...");
-
-        while spp < self.average_samples_per_pixel {
-            spp = sample_count / pixel_count;
-            if last_reported_spp < spp {
-                report_progress(spp as f64 / self.average_samples_per_pixel as f64);
-                last_reported_spp = spp;
-            }
-            sample_count = sample_count + 1;
-            let k = pdf.sample(&mut rng);
-            let sampler = &mut samplers[k];
-            let mutation_type = sampler.mutate();
-            let current_contribution = contributions[k];
-            let proposal_contribution = Path::contribute(scene, sampler, k + 2);
-            let a = Contribution::acceptance(current_contribution, proposal_contribution);
-            let step_factor = match mutation_type {
-                MutationType::LargeStep => 1.0,
-                MutationType::SmallStep => 0.0,
-            };
+        let n = self.samples_per_pixel * pixel_count;
+        let path_lengths = [2, 3];
+        let total_sample_count = n as usize * path_lengths.len();
+        // The samples are taken in blocks with generators of their own, so
+        // that the image does not depend on the number of threads. The path
+        // lengths alternate, so that the blocks taken before a cancellation
+        // have as many samples of each.
+        let block_size = EPOCH_LENGTH as usize;
+        let mut rng = self.rng.seeded(seed);
+        let block_seeds: Vec<u64> = (0..total_sample_count.div_ceil(block_size))
+            .map(|_| rng.gen())
+            .collect();
+        progress.start(Phase::Preview, total_sample_count as u64);
+        let local_images = parallel::run(
+            parallel::split(block_seeds.len(), threads),
+            progress,
+            |_, blocks, counter| {
+                let mut local_image = image.local();
+                let mut arena = PathArena::default();
+                let mut sample_count = 0;
+                for block in blocks {
+                    if cancellation.is_cancelled() {
+                        break;
+                    }
+                    let mut rng = self.rng.seeded(block_seeds[block]);
+                    let end = usize::min((block + 1) * block_size, total_sample_count);
+                    sample_count += end - block * block_size;
+                    for i in block * block_size..end {
+                        let path_length = path_lengths[i % path_lengths.len()];
+                        let mut sampler =
+                            Path::seeded_sampler(&scene.mutation_parameters, self.rng, rng.gen());
+                        let contribution =
+                            Path::contribute(scene, &mut sampler, path_length, &mut arena);
+                        if !contribution.is_empty() {
+                            // Weighted by the number of techniques the
+                            // sample was chosen from.
+                            local_image.contribute(&contribution, (path_length + 1) as Float);
+                        }
+                        counter.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                (local_image, sample_count)
+            },
+        );
+        let mut sample_count = 0;
+        for (mut local_image, count) in local_images {
+            local_image.merge();
+            sample_count += count;
+        }
+        progress.finish();
+        if cancellation.is_cancelled() {
+            progress.report(&format!(
+                "interrupted at {:.2} samples per pixel",
+                sample_count as Float / (path_lengths.len() as u64 * pixel_count) as Float
+            ));
+        }
+        let samples_per_length = sample_count / path_lengths.len();
+        image.scale(pixel_count as Float / samples_per_length.max(1) as Float);
+
+        progress.report(&format!(
+            "elapsed time: {} seconds",
+            start.elapsed().as_secs()
+        ));
+        Ok(image)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DepthMode {
+    // The distance along the view direction, in scene units.
+    Metric,
+    // The metric depth mapped from the nearest to the farthest point seen
+    // onto [0, 1].
+    Normalized,
+}
+
+// The depth of the first surface seen through the center of each pixel, for
+// compositing and for checking the setup of a camera. Pixels that see
+// nothing are infinitely far, or 1 when normalized.
+pub struct DepthIntegrator {
+    mode: DepthMode,
+}
+
+impl DepthIntegrator {
+    pub fn new(mode: DepthMode) -> DepthIntegrator {
+        DepthIntegrator { mode }
+    }
+}
 
-            if !proposal_contribution.is_empty() {
-                let weight = (((k as f64 + 2.0) / pdf.value(k)) * (a + step_factor))
-                    / ((proposal_contribution.scalar / b[k]) + sampler.large_step_probability);
-                let spectrum = proposal_contribution.spectrum * weight;
-                image.contribute(spectrum, proposal_contribution.pixel_coordinates);
+impl Integrator for DepthIntegrator {
+    fn integrate(
+        &self,
+        scene: &Scene,
+        progress: &dyn ProgressSink,
+        cancellation: &Cancellation,
+    ) -> Result<Image, Error> {
+        progress.report("Rendering depth...");
+        let (width, height) = (scene.image_config.width, scene.image_config.height);
+        // The rows are rendered whole, and those not yet begun when the render
+        // is cancelled are left out.
+        let rows = parallel::map(height, |y| {
+            if cancellation.is_cancelled() {
+                return None;
             }
+            let depths = (0..width).map(|x| {
+                let (x, y) = (x as Float + 0.5, y as Float + 0.5);
+                let values = vec![x / width as Float, y / height as Float, 0.5, 0.5];
+                let camera = scene
+                    .camera
+                    .sample_interaction(&mut ReplaySampler::new(1, values));
+                let geometry = camera.geometry();
+                let ray = Ray::new(geometry.point, geometry.direction);
+                // The environment is infinitely far, like the rays that escape
+                // a scene without one.
+                let interaction = scene
+                    .intersect(ray, RayKind::Camera)
+                    .filter(|interaction| !interaction.is_environment())?;
+                Some((interaction.geometry().point - geometry.point).dot(geometry.normal))
+            });
+            Some(depths.collect::<Vec<_>>())
+        });
 
-            if !current_contribution.is_empty() {
-                let weight = (((k as f64 + 2.0) / pdf.value(k)) * (1.0 - a))
-                    / ((current_contribution.scalar / b[k]) + sampler.large_step_probability);
-                let spectrum = current_contribution.spectrum * weight;
-                image.contribute(spectrum, current_contribution.pixel_coordinates);
+        // The range is that of the surfaces seen, if any are.
+        let range = rows.iter().flatten().flatten().flatten().fold(
+            None,
+            |range: Option<(Float, Float)>, &depth| match range {
+                Some((near, far)) => Some((near.min(depth), far.max(depth))),
+                None => Some((depth, depth)),
+            },
+        );
+        let mut image = Image::new(width, height, Box::new(BoxFilter::new()), None, None);
+        image.set_display(DisplayTransform::linear());
+        let mut local_image = image.local();
+        let mut rendered = 0;
+        for (y, row) in rows.into_iter().enumerate() {
+            let Some(row) = row else {
+                continue;
+            };
+            rendered += 1;
+            for (x, depth) in row.into_iter().enumerate() {
+                let value = match (self.mode, depth, range) {
+                    (DepthMode::Metric, Some(depth), _) => depth,
+                    (DepthMode::Metric, None, _) => Float::INFINITY,
+                    (DepthMode::Normalized, Some(depth), Some((near, far))) if far > near => {
+                        (depth - near) / (far - near)
+                    }
+                    (DepthMode::Normalized, Some(_), _) => 0.0,
+                    (DepthMode::Normalized, None, _) => 1.0,
+                };
+                let coordinates = Point2::new(x as Float, y as Float);
+                local_image.contribute(
+                    &Contribution::new(Spectrum::fill(value), coordinates, 0),
+                    1.0,
+                );
+            }
+        }
+        local_image.merge();
+        drop(local_image);
+        if rendered < height {
+            progress.report(&format!("interrupted at {} of {} rows", rendered, height));
+        }
+        match range {
+            Some((near, far)) => {
+                progress.report(&format!("depth range: {:.3} to {:.3}", near, far))
             }
+            None => progress.report("depth range: no surface is seen"),
+        }
+        Ok(image)
+    }
+}
 
-            if rng.gen_range(0.0..1.0) <= a {
-                sampler.accept();
-                contributions[k] = proposal_contribution;
-            } else {
-                sampler.reject();
+// Merges the groups into the image and their accepted states into the
+// trajectory and the exported paths, and their recorded proposals, in the
+// order of the groups, so that none of them depends on the order in which the
+// groups ran.
+// The normalization estimates are refined in order too, and the groups run
+// the next epoch with them, so they do not depend on the number of threads.
+fn merge_groups(
+    groups: &[Mutex<ChainGroup>],
+    normalization: &Mutex<Normalization>,
+    trajectory: Option<&Mutex<TrajectoryWriter>>,
+    exporter: Option<&Mutex<PathExporter>>,
+    recorder: Option<&Mutex<ProposalRecorder>>,
+) -> Result<(), Error> {
+    let mut normalization = normalization.lock().unwrap();
+    for group in groups {
+        let mut group = group.lock().unwrap();
+        let large_steps = Normalization::new(group.b.len());
+        normalization.add(&std::mem::replace(&mut group.large_steps, large_steps));
+    }
+    let b = normalization.estimates();
+    for (index, group) in groups.iter().enumerate() {
+        let mut group = group.lock().unwrap();
+        group.b.clone_from(&b);
+        group.local_image.merge();
+        let accepted = std::mem::take(&mut group.accepted);
+        if let Some(trajectory) = trajectory {
+            let mut trajectory = trajectory.lock().unwrap();
+            for contribution in &accepted {
+                trajectory.record(contribution, index)?;
             }
         }
+        let traces = std::mem::take(&mut group.traces);
+        if let Some(exporter) = exporter {
+            let mut exporter = exporter.lock().unwrap();
+            for trace in &traces {
+                exporter.record(trace)?;
+            }
+        }
+        let recorded = std::mem::take(&mut group.recorded);
+        if let Some(recorder) = recorder {
+            let mut recorder = recorder.lock().unwrap();
+            for proposal in &recorded {
+                recorder.record(proposal)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn report_memory_statistics(
+    progress: &dyn ProgressSink,
+    scene: &Scene,
+    allocations: &[(Phase, u64)],
+) {
+    if memory::counting() {
+        progress.report(&format!(
+            "memory: peak {}, geometry {}",
+            memory::format_bytes(memory::peak().unwrap_or(0)),
+            memory::format_bytes(scene.geometry_bytes)
+        ));
+        let counts: Vec<String> = allocations
+            .iter()
+            .map(|(phase, count)| format!("{} {}", phase.name().to_lowercase(), count))
+            .collect();
+        progress.report(&format!("allocations: {}", counts.join(", ")));
+    } else if let Some(peak) = memory::peak() {
+        progress.report(&format!(
+            "memory: peak resident {}",
+            memory::format_bytes(peak)
+        ));
+    }
+    let caches = [
+        ("texture", cache::textures().statistics()),
+        ("geometry", cache::geometry().statistics()),
+    ];
+    for (name, statistics) in caches {
+        if statistics.files > 0 {
+            progress.report(&format!(
+                "{} cache: {} hits, {} misses, {} evictions, peak {}",
+                name,
+                statistics.hits,
+                statistics.misses,
+                statistics.evictions,
+                memory::format_bytes(statistics.peak_bytes)
+            ));
+        }
+    }
+}
 
-        image.scale(1.0 / self.average_samples_per_pixel as f64);
+fn report_profile(progress: &dyn ProgressSink, elapsed: f64) {
+    progress.report("profile (inclusive times):");
+    for timing in profile::timings() {
+        progress.report(&format!(
+            "  {:<16} {:>12} calls {:>9.3} s {:>5.1}%  {:>8.1} ns/call",
+            timing.subsystem.name(),
+            timing.calls,
+            timing.seconds,
+            100.0 * timing.seconds / elapsed.max(f64::MIN_POSITIVE),
+            1e9 * timing.seconds / timing.calls.max(1) as f64
+        ));
+    }
+}
 
-        report("MMLT integration complete");
+// The share of its samples with which each technique contributed, so that
+// connection strategies that are sampled but seldom find light stand out.
+fn report_technique_statistics(progress: &dyn ProgressSink, statistics: &TechniqueStatistics) {
+    progress.report("contributing techniques, by camera + light vertices:");
+    for (path_length, counts) in statistics.by_path_length() {
+        let techniques: Vec<String> = counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| count.sampled > 0)
+            .map(|(camera, count)| {
+                format!(
+                    "{}+{} {:.1}% of {}",
+                    camera,
+                    path_length - camera,
+                    100.0 * count.contributed as f64 / count.sampled as f64,
+                    count.sampled
+                )
+            })
+            .collect();
+        progress.report(&format!(
+            "  length {}: {}",
+            path_length,
+            techniques.join(", ")
+        ));
+    }
+}
 
-        let elapsed = start.elapsed();
-        report(&format!("elapsed time: {} seconds", elapsed.as_secs()));
+fn report_tile_statistics(progress: &dyn ProgressSink, statistics: &[TileStatistics]) {
+    let density = |s: &TileStatistics| s.splat_count as Float / (s.width * s.height) as Float;
+    let min = statistics
+        .iter()
+        .min_by(|a, b| density(a).total_cmp(&density(b)));
+    let max = statistics
+        .iter()
+        .max_by(|a, b| density(a).total_cmp(&density(b)));
+    if let (Some(min), Some(max)) = (min, max) {
+        progress.report(&format!(
+            "splats per pixel by tile: min {:.2} at ({}, {}), max {:.2} at ({}, {})",
+            density(min),
+            min.x,
+            min.y,
+            density(max),
+            max.x,
+            max.y
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, sync::Mutex};
+
+    use super::{DepthIntegrator, DepthMode, Integrator, MmltIntegrator, PreviewIntegrator};
+    use crate::{
+        checkpoint::SavedNormalization,
+        config::Config,
+        image::Image,
+        interrupt::Cancellation,
+        light::LightOverrides,
+        progress::{Phase, ProgressSink},
+        scene::Scene,
+    };
+
+    #[derive(Default)]
+    struct Messages(Mutex<Vec<String>>);
+
+    impl ProgressSink for Messages {
+        fn report(&self, message: &str) {
+            self.0.lock().unwrap().push(String::from(message));
+        }
+        fn start(&self, _phase: Phase, _total: u64) {}
+        fn update(&self, _count: u64) {}
+        fn finish(&self) {}
+    }
+
+    fn scene(name: &str, lights: &str, objects: &str) -> Scene {
+        let source = format!(
+            "image: {{ width: 4, height: 2 }}
+camera:
+  type: pinhole
+  origin: {{ x: 0.0, y: 0.0, z: 10.0 }}
+  look_at: {{ x: 0.0, y: 0.0, z: 0.0 }}
+  field_of_view: {{ value: 40.0, unit: degrees }}
+lights: {}
+objects: {}
+",
+            lights, objects
+        );
+        let path = env::temp_dir().join(name);
+        fs::write(&path, source).unwrap();
+        let path = path.to_string_lossy().into_owned();
+        let scene = Scene::load(path.clone(), None, &[], &LightOverrides::default()).unwrap();
+        fs::remove_file(path).unwrap();
+        scene
+    }
+
+    #[test]
+    fn test_depth_empty_scene() {
+        let scene = scene("mmlt-test-depth-empty.yml", "[]", "[]");
+        let messages = Messages::default();
+        let integrator = DepthIntegrator::new(DepthMode::Normalized);
+        integrator
+            .integrate(&scene, &messages, &Cancellation::new())
+            .unwrap();
+        let messages = messages.0.into_inner().unwrap();
+        assert!(messages.contains(&String::from("depth range: no surface is seen")));
+    }
+
+    #[test]
+    fn test_depth_cancelled() {
+        let objects = "
+  - id: ball
+    type: geometric
+    material: { type: matte, texture: { type: constant, spectrum: { r: 0.5, g: 0.5, b: 0.5 } } }
+    shape: { type: sphere, center: { x: 0.0, y: 0.0, z: 0.0 }, radius: 1.0 }";
+        let scene = scene("mmlt-test-depth-cancelled.yml", "[]", objects);
+        let messages = Messages::default();
+        let cancellation = Cancellation::new();
+        cancellation.cancel();
+        let integrator = DepthIntegrator::new(DepthMode::Metric);
+        integrator
+            .integrate(&scene, &messages, &cancellation)
+            .unwrap();
+        let messages = messages.0.into_inner().unwrap();
+        assert!(messages.contains(&String::from("interrupted at 0 of 2 rows")));
+    }
+
+    // A sphere of unit radiance that fills the view, so that every pixel
+    // sees it and nothing else.
+    const EMITTER: &str = "
+  - id: emitter
+    type: diffuse_area
+    shape: { type: sphere, center: { x: 0.0, y: 0.0, z: 0.0 }, radius: 8.0 }
+    spectrum: { r: 1.0, g: 1.0, b: 1.0 }";
+
+    fn config(flags: &[&str]) -> Config {
+        let args = ["mmlt", "--scene", "a.yml", "--image", "a.pfm"];
+        Config::parse_with_env(
+            args.iter().chain(flags).map(|s| String::from(*s)).collect(),
+            [],
+        )
+        .unwrap()
+    }
+
+    // The mean of the values of an image, read back from a PFM file, whose
+    // values are scaled.
+    fn mean_value(image: &Image, name: &str) -> f32 {
+        let path = env::temp_dir().join(name);
+        let path = path.to_string_lossy().into_owned();
+        image.write(path.clone()).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(path).unwrap();
+        let header = b"PF\n4 2\n-1\n".len();
+        let values: Vec<f32> = bytes[header..]
+            .chunks(4)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        values.iter().sum::<f32>() / values.len() as f32
+    }
+
+    #[test]
+    fn test_mmlt_saved_normalization_min_path_length() {
+        // Only paths of length 2 reach the emitter, which the saved
+        // normalization would choose half of the time.
+        let scene = scene("mmlt-test-saved-min-path-length.yml", EMITTER, "[]");
+        let path = env::temp_dir().join("mmlt-test-saved-min-path-length.normalization");
+        let path = path.to_str().unwrap();
+        SavedNormalization {
+            sample_count: 100,
+            b: vec![1.0, 1.0],
+            pdf: vec![0.5, 0.5],
+            states: vec![Vec::new(), Vec::new()],
+        }
+        .write(path)
+        .unwrap();
+        let config = config(&[
+            "--min-path-length",
+            "3",
+            "--max-path-length",
+            "3",
+            "--average-samples-per-pixel",
+            "64",
+            "--chain-count",
+            "1",
+            "--seed",
+            "1",
+            "--read-normalization",
+            path,
+        ]);
+        let integrator = MmltIntegrator::new(&config, "a.pfm");
+        let image = integrator.integrate(&scene, &Messages::default(), &Cancellation::new());
+        fs::remove_file(path).unwrap();
+        let pixels = image.unwrap().checkpoint(0, Vec::new()).pixels;
+        assert!(pixels.iter().all(|pixel| pixel.is_black()));
+    }
+
+    #[test]
+    fn test_preview_emitter() {
+        let scene = scene("mmlt-test-preview-emitter.yml", EMITTER, "[]");
+        let integrator = PreviewIntegrator::new(&config(&["--seed", "1"]), 256);
+        let image = integrator
+            .integrate(&scene, &Messages::default(), &Cancellation::new())
+            .unwrap();
+        let mean = mean_value(&image, "mmlt-test-preview-emitter.pfm");
+        assert!((mean - 1.0).abs() < 0.05, "{}", mean);
+    }
 
-        image
+    #[test]
+    fn test_mmlt_emitter() {
+        let scene = scene("mmlt-test-mmlt-emitter.yml", EMITTER, "[]");
+        let config = config(&[
+            "--max-path-length",
+            "2",
+            "--initial-sample-count",
+            "100000",
+            "--average-samples-per-pixel",
+            "256",
+            "--seed",
+            "1",
+        ]);
+        let integrator = MmltIntegrator::new(&config, "a.pfm");
+        let image = integrator
+            .integrate(&scene, &Messages::default(), &Cancellation::new())
+            .unwrap();
+        let mean = mean_value(&image, "mmlt-test-mmlt-emitter.pfm");
+        assert!((mean - 1.0).abs() < 0.05, "{}", mean);
     }
 }
@@ -1,15 +1,18 @@
-use std::cell::OnceCell;
+use std::sync::OnceLock;
 
 use crate::{
-    bsdf::{Bsdf, EvaluationContext},
+    bsdf::{Bsdf, BsdfSample, EvaluationContext},
     camera::Camera,
     geometry::Geometry,
     light::Light,
+    lpe::{Event, Label},
     object::Object,
-    ray::Ray,
+    pdf::PdfValue,
+    ray::{Ray, RayKind},
     sampler::Sampler,
     spectrum::Spectrum,
-    types::PathType,
+    types::{Float, PathType},
+    util,
     vector::{Point2, Vector3},
 };
 
@@ -30,7 +33,11 @@ pub struct LightInteraction<'a> {
 pub struct ObjectInteraction<'a> {
     pub object: &'a (dyn Object + 'a),
     pub geometry: Geometry,
-    pub bsdf: OnceCell<Bsdf>,
+    pub bsdf: OnceLock<Bsdf>,
+    // The roughness to which the BSDF is regularized, if any.
+    pub regularization: Option<Float>,
+    // The direction in which the path left the point, once sampled.
+    pub sample: Option<BsdfSample>,
 }
 
 #[derive(Debug)]
@@ -42,34 +49,57 @@ pub enum Interaction<'a> {
 
 impl<'a> ObjectInteraction<'a> {
     pub fn get_bsdf(&self) -> &Bsdf {
-        self.bsdf
-            .get_or_init(|| self.object.compute_bsdf(self.geometry))
+        self.bsdf.get_or_init(|| {
+            let bsdf = self.object.compute_bsdf(self.geometry);
+            match self.regularization {
+                Some(roughness) => bsdf.regularize(util::sqr(roughness)),
+                None => bsdf,
+            }
+        })
     }
 
-    pub fn generate_ray(&self, path_type: PathType, sampler: &mut dyn Sampler) -> Option<Ray> {
+    pub fn generate_ray(&mut self, path_type: PathType, sampler: &mut dyn Sampler) -> Option<Ray> {
         let wx = self.geometry.direction * -1.0;
-        let direction = self
+        let sample = self
             .get_bsdf()
-            .sample_direction(wx, path_type, sampler)?
-            .norm();
-        let ray = Ray {
-            origin: self.geometry.point,
-            direction,
-        };
-        Some(ray)
+            .sample(wx, path_type, sampler)
+            .filter(|sample| !sample.is_empty())?;
+        self.sample = Some(sample);
+        Some(Ray::leaving(&self.geometry, sample.wi.norm()))
     }
 
-    pub fn sampling_pdf(&self, wo: Vector3, wi: Vector3, path_type: PathType) -> Option<f64> {
+    pub fn sampling_pdf(&self, wo: Vector3, wi: Vector3, path_type: PathType) -> Option<Float> {
         self.get_bsdf().sampling_pdf(wo, wi, path_type)
     }
 
-    pub fn pdf(&self, wo: Vector3, wi: Vector3, path_type: PathType) -> Option<f64> {
+    pub fn pdf(&self, wo: Vector3, wi: Vector3, path_type: PathType) -> PdfValue {
         self.get_bsdf().pdf(wo, wi, path_type)
     }
 
     pub fn reflectance(&self, wo: Vector3, wi: Vector3, context: EvaluationContext) -> Spectrum {
         self.get_bsdf().evaluate(wo, wi, context)
     }
+
+    // The fraction of light that survives the segment leaving the point in
+    // the given direction, which is attenuated by the Beer-Lambert law when
+    // it enters an absorbing object.
+    pub fn transmittance(&self, wi: Vector3) -> Spectrum {
+        match self.object.absorption() {
+            Some(absorption) if wi.dot(self.geometry.normal) < 0.0 => {
+                (absorption * -wi.len()).exp()
+            }
+            _ => Spectrum::fill(1.0),
+        }
+    }
+
+    pub fn label(&self, wo: Vector3, wi: Vector3, context: EvaluationContext) -> Label {
+        let event = if util::same_hemisphere(self.geometry.normal, wo, wi) {
+            Event::Reflection
+        } else {
+            Event::Transmission
+        };
+        Label::Surface(event, self.get_bsdf().scattering(wo, wi, context))
+    }
 }
 
 impl<'a> Interaction<'a> {
@@ -80,14 +110,14 @@ impl<'a> Interaction<'a> {
                 Some(ray)
             }
             Interaction::Light(i) => {
-                let ray = Ray::new(i.geometry.point, i.geometry.direction);
+                let ray = Ray::leaving(&i.geometry, i.geometry.direction);
                 Some(ray)
             }
             _ => None,
         }
     }
 
-    pub fn generate_ray(&self, path_type: PathType, sampler: &mut dyn Sampler) -> Option<Ray> {
+    pub fn generate_ray(&mut self, path_type: PathType, sampler: &mut dyn Sampler) -> Option<Ray> {
         match self {
             Interaction::Camera(_) => None,
             Interaction::Light(_) => None,
@@ -97,6 +127,12 @@ impl<'a> Interaction<'a> {
         }
     }
 
+    pub fn regularize(&mut self, roughness: Float) {
+        if let Interaction::Object(object_interaction) = self {
+            object_interaction.regularization = Some(roughness);
+        }
+    }
+
     pub fn id(&self) -> &String {
         match self {
             Interaction::Camera(i) => i.camera.id(),
@@ -113,31 +149,59 @@ impl<'a> Interaction<'a> {
         }
     }
 
-    pub fn distance(&self) -> f64 {
+    pub fn distance(&self) -> Float {
         match self {
-            Interaction::Camera(i) => i.geometry.direction.len(),
-            Interaction::Light(i) => i.geometry.direction.len(),
-            Interaction::Object(i) => i.geometry.direction.len(),
+            Interaction::Camera(i) => i.geometry.t,
+            Interaction::Light(i) => i.geometry.t,
+            Interaction::Object(i) => i.geometry.t,
         }
     }
 
     pub fn is_camera(&self) -> bool {
+        matches!(self, Interaction::Camera(_))
+    }
+
+    pub fn is_light(&self) -> bool {
+        matches!(self, Interaction::Light(_))
+    }
+
+    // Whether the interaction is with the environment, which rays that escape
+    // the scene reach.
+    pub fn is_environment(&self) -> bool {
+        matches!(self, Interaction::Light(i) if i.light.is_infinite())
+    }
+
+    pub fn is_object(&self) -> bool {
+        matches!(self, Interaction::Object(_))
+    }
+
+    pub fn is_shadow_catcher(&self) -> bool {
         match self {
-            Interaction::Camera(_) => true,
+            Interaction::Object(i) => i.object.is_shadow_catcher(),
             _ => false,
         }
     }
 
-    pub fn is_light(&self) -> bool {
+    pub fn is_holdout(&self) -> bool {
         match self {
-            Interaction::Light(_) => true,
+            Interaction::Object(i) => i.object.is_holdout(),
             _ => false,
         }
     }
 
-    pub fn is_object(&self) -> bool {
+    // Whether rays of the given kind may hit the point; the camera and
+    // lights are seen by all.
+    pub fn is_visible(&self, kind: RayKind) -> bool {
         match self {
-            Interaction::Object(_) => true,
+            Interaction::Object(i) => i.object.is_visible(kind),
+            _ => true,
+        }
+    }
+
+    // Whether the path left the point by sampling a specular lobe.
+    pub fn is_specular(&self) -> bool {
+        match self {
+            Interaction::Object(i) => i.sample.is_some_and(|sample| sample.is_specular),
             _ => false,
         }
     }
@@ -146,3 +210,18 @@ impl<'a> Interaction<'a> {
         self.geometry().set_direction(direction);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Interaction, ObjectInteraction};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_interaction_is_send_sync() {
+        // Interactions, with their lazily computed BSDFs, may be shared
+        // between the threads of the integrator.
+        assert_send_sync::<ObjectInteraction>();
+        assert_send_sync::<Interaction>();
+    }
+}
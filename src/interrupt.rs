@@ -0,0 +1,74 @@
+use std::{
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+};
+
+use crate::progress::report;
+
+// A request to stop a render, shared by whoever may cancel it and the
+// integrator, which stops at the next sample so that the partial image can
+// still be written.
+#[derive(Clone, Debug, Default)]
+pub struct Cancellation {
+    requested: Arc<AtomicBool>,
+}
+
+impl Cancellation {
+    pub fn new() -> Cancellation {
+        Cancellation::default()
+    }
+
+    pub fn cancel(&self) {
+        self.requested.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.requested.load(Ordering::Relaxed)
+    }
+}
+
+static CTRL_C: OnceLock<Cancellation> = OnceLock::new();
+
+// The cancellation requested by the first Ctrl-C. A second one exits
+// immediately. The handler is installed once, however many images are
+// rendered.
+pub fn ctrl_c() -> Cancellation {
+    CTRL_C.get_or_init(install_handler).clone()
+}
+
+fn install_handler() -> Cancellation {
+    let cancellation = Cancellation::new();
+    let handled = cancellation.clone();
+    let result = ctrlc::set_handler(move || {
+        if handled.is_cancelled() {
+            process::exit(130);
+        }
+        handled.cancel();
+        eprintln!();
+        report("interrupted; finishing the current sample (press Ctrl-C again to abort)");
+    });
+    if let Err(e) = result {
+        report(&format!(
+            "warning: could not install interrupt handler: {}",
+            e
+        ));
+    }
+    cancellation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cancellation;
+
+    #[test]
+    fn test_cancellation() {
+        let cancellation = Cancellation::new();
+        let shared = cancellation.clone();
+        assert!(!shared.is_cancelled());
+        cancellation.cancel();
+        assert!(shared.is_cancelled());
+    }
+}
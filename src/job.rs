@@ -0,0 +1,95 @@
+use std::{fs::File, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, error::Error};
+
+// A scene to render into an image, with the camera to render it from.
+#[derive(Debug, PartialEq)]
+pub struct Job {
+    pub scene_path: String,
+    pub image_path: String,
+    pub camera: Option<String>,
+}
+
+// An entry of a job manifest, whose relative paths are relative to the
+// directory of the manifest.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JobConfig {
+    scene: String,
+    image: String,
+    camera: Option<String>,
+}
+
+impl Job {
+    pub fn configure(config: JobConfig, directory: &Path) -> Job {
+        let resolve = |path: String| directory.join(path).to_string_lossy().into_owned();
+        Job {
+            scene_path: resolve(config.scene),
+            image_path: resolve(config.image),
+            camera: config.camera,
+        }
+    }
+}
+
+// The jobs given by `--scene` and `--image` pairs, in order, followed by those
+// of the job manifest. A camera given on the command line applies to the jobs
+// that do not name their own.
+pub fn load(config: &Config) -> Result<Vec<Job>, Error> {
+    let mut jobs: Vec<Job> = config
+        .scene_paths
+        .iter()
+        .zip(&config.image_paths)
+        .map(|(scene_path, image_path)| Job {
+            scene_path: scene_path.clone(),
+            image_path: image_path.clone(),
+            camera: None,
+        })
+        .collect();
+    if let Some(path) = &config.job_manifest_path {
+        let file = File::open(path).map_err(Error::io(path))?;
+        let configs: Vec<JobConfig> =
+            serde_yaml::from_reader(file).map_err(|e| Error::scene(path, e))?;
+        let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+        jobs.extend(configs.into_iter().map(|c| Job::configure(c, directory)));
+    }
+    for job in &mut jobs {
+        if job.camera.is_none() {
+            job.camera = config.camera.clone();
+        }
+    }
+    Ok(jobs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Job, JobConfig};
+    use std::path::Path;
+
+    #[test]
+    fn test_job_configure() {
+        let source = "
+- scene: scenes/a.yml
+  image: /tmp/a.pfm
+  camera: side
+- scene: b.yml
+  image: b.pfm
+";
+        let configs: Vec<JobConfig> = serde_yaml::from_str(source).unwrap();
+        let jobs: Vec<Job> = configs
+            .into_iter()
+            .map(|c| Job::configure(c, Path::new("shots")))
+            .collect();
+        assert_eq!(
+            jobs[0],
+            Job {
+                scene_path: String::from("shots/scenes/a.yml"),
+                image_path: String::from("/tmp/a.pfm"),
+                camera: Some(String::from("side")),
+            }
+        );
+        assert_eq!(jobs[1].scene_path, "shots/b.yml");
+        assert_eq!(jobs[1].image_path, "shots/b.pfm");
+        assert!(jobs[1].camera.is_none());
+    }
+}
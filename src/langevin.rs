@@ -0,0 +1,197 @@
+use rand::Rng;
+
+use crate::{
+    path::{Contribution, Path, PathArena},
+    sampler::MmltSampler,
+    scene::Scene,
+    types::Float,
+    util,
+};
+
+const STEP_SIZE: Float = 0.01;
+const FINITE_DIFFERENCE: Float = 1e-4;
+// Bounds each component of the drift, since the contribution is only
+// piecewise smooth and finite differences across an edge are meaningless.
+const MAX_DRIFT: Float = 0.05;
+
+// Metropolis-adjusted Langevin mutations in primary sample space. The
+// gradient of the log contribution is estimated by finite differences, at
+// the cost of one path evaluation per primary sample at each end of the
+// proposal.
+pub struct Langevin {
+    probability: Float,
+}
+
+impl Langevin {
+    pub fn new(probability: Float) -> Langevin {
+        Langevin { probability }
+    }
+
+    pub fn probability(&self) -> Float {
+        self.probability
+    }
+
+    // Proposes a new state for a sampler on which `mutate` has just been
+    // called, returning the proposal and the ratio of the reverse and forward
+    // proposal densities.
+    pub fn propose<'a>(
+        &self,
+        scene: &'a Scene,
+        sampler: &mut MmltSampler,
+        current: Contribution,
+        path_length: usize,
+        rng: &mut impl Rng,
+        arena: &mut PathArena<'a>,
+    ) -> (Contribution, Float) {
+        if current.is_empty() {
+            return (Path::contribute(scene, sampler, path_length, arena), 1.0);
+        }
+
+        let x = sampler.values();
+        let drift_x = drift(scene, &x, x.len(), current.scalar, path_length, arena);
+        let y: Vec<Float> = x
+            .iter()
+            .zip(&drift_x)
+            .map(|(x, d)| wrap(x + d + STEP_SIZE * normal(rng)))
+            .collect();
+
+        sampler.propose(&y);
+        let proposal = Path::contribute(scene, sampler, path_length, arena);
+        if proposal.is_empty() {
+            return (proposal, 1.0);
+        }
+
+        // The proposal may use more primary samples than the current state,
+        // which the sampler draws as independent uniforms, as it does for a
+        // small step. Their density is the same both ways, so the ratio
+        // covers only the samples of the current state.
+        let y = sampler.values();
+        let n = x.len();
+        let drift_y = drift(scene, &y, n, proposal.scalar, path_length, arena);
+        let forward = log_density(&x, &drift_x, &y[..n]);
+        let reverse = log_density(&y[..n], &drift_y, &x);
+        (proposal, (reverse - forward).exp())
+    }
+}
+
+// Half the squared step size times the gradient of the log contribution,
+// which is the gradient of the contribution divided by the contribution,
+// along the first `dimensions` samples.
+fn drift<'a>(
+    scene: &'a Scene,
+    values: &[Float],
+    dimensions: usize,
+    scalar: Float,
+    path_length: usize,
+    arena: &mut PathArena<'a>,
+) -> Vec<Float> {
+    (0..dimensions)
+        .map(|i| {
+            let mut shifted = values.to_vec();
+            shifted[i] = wrap(values[i] + FINITE_DIFFERENCE);
+            let mut sampler = Path::replay_sampler(shifted);
+            let shifted_scalar = Path::contribute(scene, &mut sampler, path_length, arena).scalar;
+            let gradient = (shifted_scalar - scalar) / (FINITE_DIFFERENCE * scalar);
+            (0.5 * STEP_SIZE * STEP_SIZE * gradient).clamp(-MAX_DRIFT, MAX_DRIFT)
+        })
+        .collect()
+}
+
+fn normal(rng: &mut impl Rng) -> Float {
+    Float::sqrt(2.0) * util::erf_inv(2.0 * rng.gen_range(0.0..1.0) - 1.0)
+}
+
+// The log density, up to a constant, of moving from `from` to `to` under a
+// Gaussian step about `from + drift`, measured on the unit torus.
+fn log_density(from: &[Float], drift: &[Float], to: &[Float]) -> Float {
+    from.iter()
+        .zip(drift)
+        .zip(to)
+        .map(|((from, drift), to)| {
+            let mut delta = to - from - drift;
+            delta -= delta.round();
+            -delta * delta / (2.0 * STEP_SIZE * STEP_SIZE)
+        })
+        .sum()
+}
+
+fn wrap(value: Float) -> Float {
+    value - value.floor()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{log_density, Langevin};
+    use crate::{
+        light::LightOverrides,
+        path::{Path, PathArena},
+        rng::RngKind,
+        scene::SceneConfig,
+    };
+
+    const SCENE: &str = "
+image: { width: 4, height: 4 }
+camera:
+  type: pinhole
+  origin: { x: 0.0, y: 0.0, z: 10.0 }
+  look_at: { x: 0.0, y: 0.0, z: 0.0 }
+  field_of_view: { value: 30.0, unit: degrees }
+lights:
+  - id: light
+    type: diffuse_area
+    shape: { type: sphere, center: { x: 6.0, y: 0.0, z: 6.0 }, radius: 1.0 }
+    spectrum: { r: 1.0, g: 1.0, b: 1.0 }
+objects:
+  - id: ground
+    type: geometric
+    shape: { type: sphere, center: { x: 0.0, y: 0.0, z: 0.0 }, radius: 3.0 }
+    material:
+      type: matte
+      texture: { type: constant, spectrum: { r: 0.5, g: 0.5, b: 0.5 } }
+";
+
+    #[test]
+    fn test_log_density_wraps() {
+        let inside = log_density(&[0.5], &[0.0], &[0.51]);
+        let across = log_density(&[0.995], &[0.0], &[0.005]);
+        assert!((inside - across).abs() < 1e-9);
+        assert!(log_density(&[0.5], &[0.01], &[0.51]).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_propose_more_samples() {
+        let scene = serde_yaml::from_str::<SceneConfig>(SCENE)
+            .unwrap()
+            .configure(None, &LightOverrides::default())
+            .unwrap();
+        let parameters = &scene.mutation_parameters;
+        let mut arena = PathArena::default();
+        let mut rng = StdRng::seed_from_u64(7);
+        let langevin = Langevin::new(1.0);
+        let mut proposed = 0;
+        for seed in 0..64 {
+            let mut sampler = Path::seeded_sampler(parameters, RngKind::default(), seed);
+            let current = Path::contribute(&scene, &mut sampler, 3, &mut arena);
+            if current.is_empty() {
+                continue;
+            }
+            // A state whose sampler has none of the samples of its path, so
+            // that every sample of the proposal is drawn beyond it.
+            let mut sampler = Path::seeded_sampler(parameters, RngKind::default(), seed + 64);
+            sampler.mutate();
+            let (proposal, correction) =
+                langevin.propose(&scene, &mut sampler, current, 3, &mut rng, &mut arena);
+            if proposal.is_empty() {
+                continue;
+            }
+            assert!(!sampler.values().is_empty());
+            // Every sample of the proposal is a new, uniform one, which the
+            // ratio leaves out.
+            assert_eq!(correction, 1.0);
+            proposed += 1;
+        }
+        assert!(proposed > 0);
+    }
+}
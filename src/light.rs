@@ -1,34 +1,56 @@
-use std::{f64::consts::PI, fmt};
+use std::fmt;
+
+use crate::types::consts::PI;
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    bounds::Bounds3,
+    environment::EnvironmentLightConfig,
     geometry::Geometry,
     interaction::{Interaction, LightInteraction},
+    pdf::PdfValue,
     ray::Ray,
     sampler::Sampler,
-    shape::{Shape, ShapeConfig},
+    shape::{AnyShape, Placement, Shape, ShapeConfig},
     spectrum::{Spectrum, SpectrumConfig},
+    types::Float,
     util,
     vector::{Point3, Vector3},
 };
 
-pub trait Light: fmt::Debug {
+pub trait Light: fmt::Debug + Send + Sync {
     fn radiance(&self, point: Point3, normal: Vector3, direction: Vector3) -> Spectrum;
-    fn sampling_pdf(&self) -> Option<f64>;
-    fn positional_pdf(&self, point: Point3) -> Option<f64>;
-    fn directional_pdf(&self, normal: Vector3, direction: Vector3) -> Option<f64>;
-    fn sample_interaction(&self, sampler: &mut dyn Sampler) -> Interaction;
-    fn intersect(&self, ray: Ray) -> Option<Interaction>;
+    // The probability of choosing this light among all the lights.
+    fn sampling_pdf(&self) -> Float;
+    fn positional_pdf(&self, point: Point3) -> PdfValue;
+    fn directional_pdf(&self, normal: Vector3, direction: Vector3) -> PdfValue;
+    fn sample_interaction(&self, sampler: &mut dyn Sampler) -> Interaction<'_>;
+    // A point on the light sampled for lighting the given reference point,
+    // and the density by area of doing so.
+    fn sample_point_from(&self, reference: Point3, sampler: &mut dyn Sampler) -> Point3;
+    fn positional_pdf_from(&self, reference: Point3, geometry: &Geometry) -> PdfValue;
+    fn intersect(&self, ray: Ray) -> Option<Interaction<'_>>;
+    fn bounds(&self) -> Bounds3;
     fn id(&self) -> &String;
+    // Whether the light lights the object with the given ID.
+    fn illuminates(&self, object_id: &str) -> bool;
+    // Whether the light surrounds the scene, reached by the rays that escape
+    // it, rather than lying within it.
+    fn is_infinite(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug)]
 pub struct DiffuseAreaLight {
     id: String,
-    shape: Box<dyn Shape>,
+    shape: AnyShape,
     radiance: Spectrum,
     light_count: usize,
+    // The objects lit, all but the excluded ones if not given.
+    include: Option<Vec<String>>,
+    exclude: Vec<String>,
 }
 
 impl Light for DiffuseAreaLight {
@@ -40,62 +62,96 @@ impl Light for DiffuseAreaLight {
         }
     }
 
-    fn sampling_pdf(&self) -> Option<f64> {
-        Some(1.0 / self.light_count as f64)
+    fn sampling_pdf(&self) -> Float {
+        1.0 / self.light_count as Float
     }
 
-    fn positional_pdf(&self, _: Point3) -> Option<f64> {
-        Some(1.0 / self.shape.area())
+    fn positional_pdf(&self, _: Point3) -> PdfValue {
+        PdfValue::Finite(1.0 / self.shape.area())
     }
 
-    fn directional_pdf(&self, normal: Vector3, direction: Vector3) -> Option<f64> {
-        Some(direction.norm().dot(normal).abs() / PI)
+    fn directional_pdf(&self, normal: Vector3, direction: Vector3) -> PdfValue {
+        PdfValue::Finite(direction.norm().dot(normal).abs() / PI)
     }
 
-    fn sample_interaction(&self, sampler: &mut dyn Sampler) -> Interaction {
+    fn sample_interaction(&self, sampler: &mut dyn Sampler) -> Interaction<'_> {
         let geometry = self.shape.sample_geometry(sampler);
 
+        sampler.set_emission(true);
         let direction = util::cosine_sample_hemisphere(geometry.normal, sampler);
+        sampler.set_emission(false);
 
         let light_interaction = LightInteraction {
             light: self,
             geometry: Geometry {
-                point: geometry.point,
                 direction,
-                normal: geometry.normal,
+                ..geometry
             },
         };
 
         Interaction::Light(light_interaction)
     }
 
-    fn intersect(&self, ray: Ray) -> Option<Interaction> {
+    fn sample_point_from(&self, reference: Point3, sampler: &mut dyn Sampler) -> Point3 {
+        self.shape.sample_from(reference, sampler).point
+    }
+
+    fn positional_pdf_from(&self, reference: Point3, geometry: &Geometry) -> PdfValue {
+        PdfValue::Finite(self.shape.pdf_from(reference, geometry))
+    }
+
+    fn intersect(&self, ray: Ray) -> Option<Interaction<'_>> {
         let geometry = self.shape.intersect(ray)?;
         let light_interaction = LightInteraction {
             light: self,
-            geometry: Geometry {
-                point: geometry.point,
-                direction: geometry.direction,
-                normal: geometry.normal,
-            },
+            geometry,
         };
         let interaction = Interaction::Light(light_interaction);
         Some(interaction)
     }
 
+    fn bounds(&self) -> Bounds3 {
+        self.shape.bounds()
+    }
+
     fn id(&self) -> &String {
         &self.id
     }
+
+    fn illuminates(&self, object_id: &str) -> bool {
+        let included = self
+            .include
+            .as_ref()
+            .is_none_or(|ids| ids.iter().any(|id| id == object_id));
+        included && !self.exclude.iter().any(|id| id == object_id)
+    }
 }
 
 impl DiffuseAreaLight {
-    pub fn configure(config: &DiffuseAreaLightConfig, light_count: usize) -> DiffuseAreaLight {
-        DiffuseAreaLight {
+    pub fn configure(
+        config: &DiffuseAreaLightConfig,
+        light_count: usize,
+        default_spectrum: Option<&SpectrumConfig>,
+        placement: Placement,
+    ) -> Result<DiffuseAreaLight, String> {
+        let spectrum = config
+            .spectrum
+            .as_ref()
+            .or(default_spectrum)
+            .ok_or_else(|| {
+                format!(
+                    "light '{}' has no spectrum and there is no default",
+                    config.id
+                )
+            })?;
+        Ok(DiffuseAreaLight {
             id: config.id.clone(),
-            shape: config.shape.configure(),
-            radiance: Spectrum::configure(&config.spectrum),
+            shape: config.shape.configure(placement)?,
+            radiance: Spectrum::configure(spectrum) * config.scale.unwrap_or(1.0),
             light_count,
-        }
+            include: config.include.clone(),
+            exclude: config.exclude.clone().unwrap_or_default(),
+        })
     }
 }
 
@@ -104,14 +160,72 @@ impl DiffuseAreaLight {
 #[serde(rename_all = "snake_case")]
 pub enum LightConfig {
     DiffuseArea(DiffuseAreaLightConfig),
+    Environment(EnvironmentLightConfig),
 }
 
 impl LightConfig {
-    pub fn configure(&self, light_count: usize) -> Box<dyn Light> {
+    pub fn id(&self) -> &str {
+        match self {
+            LightConfig::DiffuseArea(config) => &config.id,
+            LightConfig::Environment(config) => &config.id,
+        }
+    }
+
+    // The IDs of the objects the light is linked to.
+    pub fn linked_ids(&self) -> impl Iterator<Item = &String> {
+        let (include, exclude) = match self {
+            LightConfig::DiffuseArea(config) => (&config.include, &config.exclude),
+            LightConfig::Environment(_) => (&None, &None),
+        };
+        include.iter().chain(exclude).flatten()
+    }
+
+    pub fn is_enabled(&self) -> bool {
         match self {
-            LightConfig::DiffuseArea(config) => {
-                Box::new(DiffuseAreaLight::configure(config, light_count))
-            }
+            LightConfig::DiffuseArea(config) => config.enabled.unwrap_or(true),
+            LightConfig::Environment(config) => config.enabled.unwrap_or(true),
+        }
+    }
+
+    // Soloing lights disables every other light, whether or not the soloed
+    // ones were enabled, and scales multiply that of the configuration.
+    pub fn apply(&mut self, overrides: &LightOverrides) {
+        let id = String::from(self.id());
+        let scale: Float = overrides
+            .scales
+            .iter()
+            .filter(|(scaled, _)| *scaled == id)
+            .map(|(_, scale)| scale)
+            .product();
+        let (enabled, scaled) = match self {
+            LightConfig::DiffuseArea(config) => (&mut config.enabled, &mut config.scale),
+            LightConfig::Environment(config) => (&mut config.enabled, &mut config.scale),
+        };
+        if !overrides.solo.is_empty() {
+            *enabled = Some(overrides.solo.contains(&id));
+        }
+        *scaled = Some(scaled.unwrap_or(1.0) * scale);
+    }
+
+    // A light within the scene. The environment, which surrounds the scene,
+    // is configured with its bounds instead.
+    pub fn configure(
+        &self,
+        light_count: usize,
+        default_spectrum: Option<&SpectrumConfig>,
+        placement: Placement,
+    ) -> Result<Box<dyn Light>, String> {
+        match self {
+            LightConfig::DiffuseArea(config) => Ok(Box::new(DiffuseAreaLight::configure(
+                config,
+                light_count,
+                default_spectrum,
+                placement,
+            )?)),
+            LightConfig::Environment(config) => Err(format!(
+                "environment light '{}' must be configured with the bounds of the scene",
+                config.id
+            )),
         }
     }
 }
@@ -120,17 +234,40 @@ impl LightConfig {
 pub struct DiffuseAreaLightConfig {
     pub id: String,
     pub shape: ShapeConfig,
-    pub spectrum: SpectrumConfig,
+    // The default spectrum of the scene if omitted.
+    pub spectrum: Option<SpectrumConfig>,
+    // A factor of the spectrum, one by default.
+    pub scale: Option<Float>,
+    // Whether the light is part of the scene, as by default.
+    pub enabled: Option<bool>,
+    // The IDs of the only objects the light lights, and of objects it does
+    // not light.
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+}
+
+// Changes to the lights of a scene given on the command line, for tweaking
+// the lighting without editing the scene: a scale for each of some lights
+// by ID, and the lights to solo, if any.
+#[derive(Default, Debug)]
+pub struct LightOverrides {
+    pub scales: Vec<(String, Float)>,
+    pub solo: Vec<String>,
+}
+
+impl LightOverrides {
+    pub fn ids(&self) -> impl Iterator<Item = &String> {
+        self.scales.iter().map(|(id, _)| id).chain(&self.solo)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::f64::consts::PI;
-
     use crate::{
         light::Light,
-        shape::{Shape, Sphere},
+        shape::{AnyShape, Shape, Sphere},
         spectrum::{RgbSpectrum, Spectrum},
+        types::{consts::PI, Float},
         vector::{Point3, Vector3},
     };
 
@@ -142,9 +279,11 @@ mod tests {
         let radiance = RgbSpectrum::fill(10.0);
         let light = DiffuseAreaLight {
             id: String::from("light-1"),
-            shape: Box::new(shape),
+            shape: AnyShape::Sphere(shape),
             radiance,
             light_count: 1,
+            include: None,
+            exclude: Vec::new(),
         };
         let point = Point3::new(0.0, 2.0, 0.0);
         let normal = Vector3::new(0.0, 1.0, 0.0);
@@ -162,24 +301,41 @@ mod tests {
         let radiance = RgbSpectrum::fill(10.0);
         let light = DiffuseAreaLight {
             id: String::from("light-1"),
-            shape: Box::new(shape),
+            shape: AnyShape::Sphere(shape),
             radiance,
             light_count,
+            include: None,
+            exclude: Vec::new(),
         };
         let point = Point3::new(0.0, 2.0, 0.0);
         let normal = Vector3::new(0.0, 1.0, 0.0);
         let direction = Vector3::new(1.0, 1.0, 0.0);
-        let p_light = 1.0 / light_count as f64;
+        let p_light = 1.0 / light_count as Float;
         let p_point = 1.0 / area;
         let p_direction = normal.dot(direction.norm()) / PI;
         let p_total = p_light * p_point * p_direction;
-        let p_actual = || -> Option<f64> {
-            Some(
-                light.sampling_pdf()?
-                    * light.positional_pdf(point)?
-                    * light.directional_pdf(normal, direction)?,
-            )
+        let p_actual = light.sampling_pdf()
+            * light.positional_pdf(point).value()
+            * light.directional_pdf(normal, direction).value();
+        assert_eq!(p_actual, p_total);
+    }
+
+    #[test]
+    fn test_light_linking() {
+        let shape = Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0);
+        let mut light = DiffuseAreaLight {
+            id: String::from("light-1"),
+            shape: AnyShape::Sphere(shape),
+            radiance: RgbSpectrum::fill(1.0),
+            light_count: 1,
+            include: None,
+            exclude: vec![String::from("wall")],
         };
-        assert_eq!(p_actual(), Some(p_total));
+        assert!(light.illuminates("floor"));
+        assert!(!light.illuminates("wall"));
+        light.include = Some(vec![String::from("floor"), String::from("wall")]);
+        assert!(light.illuminates("floor"));
+        assert!(!light.illuminates("wall"));
+        assert!(!light.illuminates("ceiling"));
     }
 }
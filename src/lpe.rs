@@ -0,0 +1,360 @@
+use serde::{Deserialize, Serialize};
+
+use crate::bsdf::Scattering;
+
+// Paths longer than this are never matched by a light path expression.
+const MAX_LABELS: usize = 32;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Event {
+    Reflection,
+    Transmission,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Label {
+    Camera,
+    Light,
+    Surface(Event, Scattering),
+}
+
+// The vertex labels of a path, ordered from the camera to the light.
+#[derive(Copy, Clone, Debug)]
+pub struct PathLabels {
+    labels: [Label; MAX_LABELS],
+    length: usize,
+}
+
+impl PathLabels {
+    pub fn new() -> PathLabels {
+        PathLabels {
+            labels: [Label::Camera; MAX_LABELS],
+            length: 0,
+        }
+    }
+
+    pub fn push(&mut self, label: Label) {
+        if self.length < MAX_LABELS {
+            self.labels[self.length] = label;
+        }
+        self.length += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn get(&self, index: usize) -> Option<Label> {
+        (index < self.length.min(MAX_LABELS)).then(|| self.labels[index])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Label> + '_ {
+        self.labels[..self.length.min(MAX_LABELS)].iter().copied()
+    }
+
+    fn as_slice(&self) -> Option<&[Label]> {
+        if self.length <= MAX_LABELS {
+            Some(&self.labels[..self.length])
+        } else {
+            None
+        }
+    }
+}
+
+impl FromIterator<Label> for PathLabels {
+    fn from_iter<I: IntoIterator<Item = Label>>(iter: I) -> PathLabels {
+        let mut labels = PathLabels::new();
+        for label in iter {
+            labels.push(label);
+        }
+        labels
+    }
+}
+
+// A small subset of OSL light path expressions. Vertices are written from
+// the camera to the light: `C` and `L` match the camera and light vertices,
+// `R` and `T` match surface reflection and transmission events, `D` and `S`
+// match diffuse and specular scattering, `<RD>` matches both an event and a
+// scattering type, and `.` matches any surface vertex. Atoms may be grouped
+// with parentheses, combined with `|`, and repeated with `*`, `+` and `?`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(try_from = "String", into = "String")]
+pub struct LightPathExpression {
+    source: String,
+    expression: Expression,
+}
+
+impl LightPathExpression {
+    pub fn parse(source: &str) -> Result<LightPathExpression, String> {
+        let mut parser = Parser {
+            chars: source.chars().filter(|c| !c.is_whitespace()).collect(),
+            position: 0,
+        };
+        let expression = parser.alternation()?;
+        if let Some(c) = parser.peek() {
+            return Err(format!("unexpected '{}' in light path expression", c));
+        }
+        Ok(LightPathExpression {
+            source: String::from(source),
+            expression,
+        })
+    }
+
+    pub fn matches(&self, labels: &PathLabels) -> bool {
+        match labels.as_slice() {
+            Some(labels) => self.expression.advance(labels, 1) & (1 << labels.len()) != 0,
+            None => false,
+        }
+    }
+}
+
+impl TryFrom<String> for LightPathExpression {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<LightPathExpression, String> {
+        LightPathExpression::parse(&source)
+    }
+}
+
+impl From<LightPathExpression> for String {
+    fn from(expression: LightPathExpression) -> String {
+        expression.source
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Expression {
+    Atom(Atom),
+    Sequence(Vec<Expression>),
+    Alternation(Vec<Expression>),
+    Optional(Box<Expression>),
+    Star(Box<Expression>),
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Atom {
+    Camera,
+    Light,
+    Surface(Option<Event>, Option<Scattering>),
+}
+
+impl Atom {
+    fn matches(&self, label: Label) -> bool {
+        match (self, label) {
+            (Atom::Camera, Label::Camera) => true,
+            (Atom::Light, Label::Light) => true,
+            (Atom::Surface(event, scattering), Label::Surface(e, s)) => {
+                event.is_none_or(|event| event == e)
+                    && scattering.is_none_or(|scattering| scattering == s)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Expression {
+    // Matching is a simulation over sets of positions, represented as bit
+    // masks: given the positions at which the expression may start, returns
+    // the positions at which it may end.
+    fn advance(&self, labels: &[Label], starts: u64) -> u64 {
+        match self {
+            Expression::Atom(atom) => {
+                let mut ends = 0;
+                for (i, label) in labels.iter().enumerate() {
+                    if starts & (1 << i) != 0 && atom.matches(*label) {
+                        ends |= 1 << (i + 1);
+                    }
+                }
+                ends
+            }
+            Expression::Sequence(expressions) => expressions
+                .iter()
+                .fold(starts, |positions, e| e.advance(labels, positions)),
+            Expression::Alternation(expressions) => expressions
+                .iter()
+                .fold(0, |ends, e| ends | e.advance(labels, starts)),
+            Expression::Optional(expression) => starts | expression.advance(labels, starts),
+            Expression::Star(expression) => {
+                let mut ends = starts;
+                loop {
+                    let next = ends | expression.advance(labels, ends);
+                    if next == ends {
+                        return ends;
+                    }
+                    ends = next;
+                }
+            }
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.position += 1;
+        c
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!(
+                "expected '{}' but found '{}' in light path expression",
+                expected, c
+            )),
+            None => Err(format!(
+                "expected '{}' at end of light path expression",
+                expected
+            )),
+        }
+    }
+
+    fn alternation(&mut self) -> Result<Expression, String> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.peek() == Some('|') {
+            self.next();
+            alternatives.push(self.sequence()?);
+        }
+        if alternatives.len() == 1 {
+            Ok(alternatives.remove(0))
+        } else {
+            Ok(Expression::Alternation(alternatives))
+        }
+    }
+
+    fn sequence(&mut self) -> Result<Expression, String> {
+        let mut expressions = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            expressions.push(self.repetition()?);
+        }
+        if expressions.is_empty() {
+            return Err(String::from("empty light path expression"));
+        }
+        Ok(Expression::Sequence(expressions))
+    }
+
+    fn repetition(&mut self) -> Result<Expression, String> {
+        let mut expression = self.primary()?;
+        loop {
+            expression = match self.peek() {
+                Some('*') => Expression::Star(Box::new(expression)),
+                Some('?') => Expression::Optional(Box::new(expression)),
+                Some('+') => Expression::Sequence(vec![
+                    expression.clone(),
+                    Expression::Star(Box::new(expression)),
+                ]),
+                _ => return Ok(expression),
+            };
+            self.next();
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expression, String> {
+        match self.next() {
+            Some('(') => {
+                let expression = self.alternation()?;
+                self.expect(')')?;
+                Ok(expression)
+            }
+            Some('<') => {
+                let event = match self.next() {
+                    Some('R') => Some(Event::Reflection),
+                    Some('T') => Some(Event::Transmission),
+                    Some('.') => None,
+                    c => return Err(unexpected(c)),
+                };
+                let scattering = match self.next() {
+                    Some('D') => Some(Scattering::Diffuse),
+                    Some('S') => Some(Scattering::Specular),
+                    Some('.') => None,
+                    c => return Err(unexpected(c)),
+                };
+                self.expect('>')?;
+                Ok(Expression::Atom(Atom::Surface(event, scattering)))
+            }
+            Some('C') => Ok(Expression::Atom(Atom::Camera)),
+            Some('L') => Ok(Expression::Atom(Atom::Light)),
+            Some('R') => Ok(Expression::Atom(Atom::Surface(
+                Some(Event::Reflection),
+                None,
+            ))),
+            Some('T') => Ok(Expression::Atom(Atom::Surface(
+                Some(Event::Transmission),
+                None,
+            ))),
+            Some('D') => Ok(Expression::Atom(Atom::Surface(
+                None,
+                Some(Scattering::Diffuse),
+            ))),
+            Some('S') => Ok(Expression::Atom(Atom::Surface(
+                None,
+                Some(Scattering::Specular),
+            ))),
+            Some('.') => Ok(Expression::Atom(Atom::Surface(None, None))),
+            c => Err(unexpected(c)),
+        }
+    }
+}
+
+fn unexpected(c: Option<char>) -> String {
+    match c {
+        Some(c) => format!("unexpected '{}' in light path expression", c),
+        None => String::from("unexpected end of light path expression"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, Label, LightPathExpression, PathLabels};
+    use crate::bsdf::Scattering;
+
+    const DIFFUSE: Label = Label::Surface(Event::Reflection, Scattering::Diffuse);
+    const MIRROR: Label = Label::Surface(Event::Reflection, Scattering::Specular);
+    const GLASS: Label = Label::Surface(Event::Transmission, Scattering::Specular);
+
+    fn matches(source: &str, labels: &[Label]) -> bool {
+        let expression = LightPathExpression::parse(source).unwrap();
+        expression.matches(&labels.iter().copied().collect())
+    }
+
+    #[test]
+    fn test_lpe_matches() {
+        use Label::{Camera as C, Light as L};
+        assert!(matches("CL", &[C, L]));
+        assert!(matches("C<RD>*L", &[C, L]));
+        assert!(matches("C<RD>*L", &[C, DIFFUSE, DIFFUSE, L]));
+        assert!(!matches("C<RD>*L", &[C, DIFFUSE, MIRROR, L]));
+        assert!(matches("CS+L", &[C, MIRROR, GLASS, L]));
+        assert!(!matches("CS+L", &[C, L]));
+        assert!(matches("C(D|S)?L", &[C, MIRROR, L]));
+        assert!(matches("C.*<T.>L", &[C, DIFFUSE, GLASS, L]));
+        assert!(!matches("CD", &[C, DIFFUSE, L]));
+    }
+
+    #[test]
+    fn test_lpe_parse_errors() {
+        assert!(LightPathExpression::parse("C<RD").is_err());
+        assert!(LightPathExpression::parse("C(DL").is_err());
+        assert!(LightPathExpression::parse("CXL").is_err());
+        assert!(LightPathExpression::parse("C|").is_err());
+    }
+
+    #[test]
+    fn test_path_labels_overflow() {
+        let labels: PathLabels = std::iter::repeat_n(DIFFUSE, 40).collect();
+        assert_eq!(labels.len(), 40);
+        assert!(!LightPathExpression::parse(".*").unwrap().matches(&labels));
+    }
+}
@@ -1,46 +1,224 @@
-use std::env;
+use std::{env, process, time::Instant};
 
 use crate::{
-    config::Config,
-    integrator::{Integrator, MmltIntegrator},
+    config::{Config, MergeConfig, PackConfig, ServeConfig},
+    debug::PixelDebug,
+    error::Error,
+    integrator::{DepthIntegrator, Integrator, MmltIntegrator, PreviewIntegrator},
+    interrupt::Cancellation,
+    job::Job,
+    path::Path,
+    progress::{report, ProgressSink, TerminalProgress},
     scene::Scene,
 };
 
+mod aov;
+mod aperture;
 mod approx;
+mod atomic;
+mod bounds;
 mod bsdf;
+mod bvh;
+mod cache;
 mod camera;
+mod checkpoint;
 mod config;
+mod debug;
+mod dither;
+mod environment;
+mod error;
+mod export;
 mod geometry;
 mod image;
 mod integrator;
 mod interaction;
+mod interrupt;
+mod job;
+mod langevin;
 mod light;
+mod lpe;
 mod material;
+mod matte;
+mod memory;
+mod merge;
+mod mnee;
+mod obj;
 mod object;
+mod packed;
+mod parallel;
 mod path;
 mod pdf;
+mod polarization;
+mod post;
+mod profile;
 mod progress;
 mod ray;
+mod record;
+mod rng;
 mod sampler;
 mod scene;
+mod service;
+mod settings;
 mod shape;
 mod spectrum;
+mod template;
 mod texture;
+mod trajectory;
 mod types;
 mod util;
 mod vector;
+mod visibility;
+mod websocket;
 
 fn main() {
     if let Err(e) = execute() {
         eprintln!("An error occurred: {e}");
+        process::exit(1);
     }
 }
 
-fn execute() -> Result<(), String> {
+fn execute() -> Result<(), Error> {
     let args: Vec<String> = env::args().collect();
+    if args.get(1).is_some_and(|arg| arg == "merge") {
+        return merge::merge(&MergeConfig::parse(args)?);
+    }
+    if args.get(1).is_some_and(|arg| arg == "serve") {
+        return service::serve(&ServeConfig::parse(args)?);
+    }
+    if args.get(1).is_some_and(|arg| arg == "pack") {
+        return packed::pack(&PackConfig::parse(args)?);
+    }
     let config = Config::parse(args)?;
-    let integrator = MmltIntegrator::new(&config);
-    let scene = Scene::load(String::from(config.scene_path))?;
-    let image = integrator.integrate(&scene);
-    image.write(config.image_path)
+    if config.profile {
+        profile::enable();
+    }
+    if let Some(size) = config.texture_cache_size {
+        cache::textures().set_budget(size << 20);
+    }
+    if let Some(size) = config.geometry_cache_size {
+        cache::geometry().set_budget(size << 20);
+    }
+    let jobs = job::load(&config)?;
+    let cancellation = interrupt::ctrl_c();
+    let progress = TerminalProgress::default();
+    if let [job] = jobs.as_slice() {
+        return render(&config, job, &progress, &cancellation);
+    }
+
+    // A failed job is reported and the batch carries on with the next one.
+    let mut results = Vec::new();
+    for (i, job) in jobs.iter().enumerate() {
+        if cancellation.is_cancelled() {
+            break;
+        }
+        report(&format!(
+            "Job {}/{}: {} -> {}",
+            i + 1,
+            jobs.len(),
+            job.scene_path,
+            job.image_path
+        ));
+        let start = Instant::now();
+        let result = render(&config, job, &progress, &cancellation);
+        if let Err(e) = &result {
+            report(&format!("job failed: {e}"));
+        }
+        results.push((job, start.elapsed(), result.is_ok()));
+    }
+
+    report("Batch summary:");
+    for (job, elapsed, succeeded) in &results {
+        report(&format!(
+            "  {:<6} {:>8.1}s  {}",
+            if *succeeded { "ok" } else { "failed" },
+            elapsed.as_secs_f64(),
+            job.image_path
+        ));
+    }
+    let skipped = jobs.len() - results.len();
+    if skipped > 0 {
+        report(&format!("  {} jobs skipped after interruption", skipped));
+    }
+    let failed = results.iter().filter(|(_, _, ok)| !ok).count();
+    if failed > 0 {
+        return Err(Error::Batch {
+            failed,
+            total: jobs.len(),
+        });
+    }
+    Ok(())
+}
+
+fn render(
+    config: &Config,
+    job: &Job,
+    progress: &dyn ProgressSink,
+    cancellation: &Cancellation,
+) -> Result<(), Error> {
+    // Set for each render, as the jobs of the service each give their own.
+    parallel::set_threads(config.threads.unwrap_or(0));
+    let integrator: Box<dyn Integrator> = match (config.depth, config.preview) {
+        (Some(mode), _) => Box::new(DepthIntegrator::new(mode)),
+        (None, Some(samples_per_pixel)) => {
+            Box::new(PreviewIntegrator::new(config, samples_per_pixel))
+        }
+        (None, None) => Box::new(MmltIntegrator::new(config, &job.image_path)),
+    };
+    let mut scene = Scene::load(
+        job.scene_path.clone(),
+        job.camera.as_deref(),
+        &config.definitions,
+        &config.light_overrides(),
+    )?;
+    scene.manifold_next_event_estimation = config.manifold_next_event_estimation;
+    scene.regularization = config.regularization;
+    scene.max_specular_depth = config.max_specular_depth;
+    if let Some((x, y)) = config.debug_pixel {
+        let (width, height) = (scene.image_config.width, scene.image_config.height);
+        if x >= width || y >= height {
+            return Err(Error::Cli(format!(
+                "--debug-pixel {} {} is outside the {}x{} image",
+                x, y, width, height
+            )));
+        }
+        let log_path = format!("{}.paths.log", job.image_path);
+        progress.report(&format!(
+            "logging the paths through the pixel to {}",
+            log_path
+        ));
+        scene.debug_pixel = Some(PixelDebug::create(x, y, width * height, &log_path)?);
+        scene.camera.lock_pixel(x, y).map_err(Error::Cli)?;
+    }
+    // Mutation parameters given on the command line override the scene's.
+    if let Some(sigma) = config.sigma {
+        scene.mutation_parameters.sigma = sigma;
+    }
+    if let Some(large_step_probability) = config.large_step_probability {
+        scene.mutation_parameters.large_step_probability = large_step_probability;
+    }
+    if let Some(stratify_film) = config.stratify_film {
+        Path::stratify_film(&mut scene.mutation_parameters, stratify_film);
+    }
+    if let Some(stratify_techniques) = config.stratify_techniques {
+        Path::stratify_techniques(&mut scene.mutation_parameters, stratify_techniques);
+    }
+    if let Some(path) = &config.replay_proposals_path {
+        let log_path = format!("{}.replay.log", job.image_path);
+        return record::replay(&scene, path, &log_path, progress);
+    }
+    let mut image = integrator.integrate(&scene, progress, cancellation)?;
+    // EXR images always have an alpha channel, and other images warn of
+    // the one they cannot hold.
+    let composited = scene
+        .objects
+        .iter()
+        .any(|o| o.is_shadow_catcher() || o.is_holdout());
+    if config.depth.is_none() && (composited || job.image_path.ends_with(".exr")) {
+        progress.report("Computing the alpha...");
+        image.set_alpha(matte::alpha(&scene));
+    }
+    if let Some(debug) = scene.debug_pixel.take() {
+        debug.finish()?;
+    }
+    image.write(job.image_path.clone())
 }
@@ -3,75 +3,126 @@ use std::fmt;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    bsdf::{Bsdf, DielectricBxdf, DiffuseBrdf, SpecularBrdf},
+    bsdf::{
+        AnyBxdf, Bsdf, ConductorBrdf, DielectricBxdf, DiffuseBrdf, MicrofacetBrdf, SheenBrdf,
+        SpecularBrdf,
+    },
     geometry::Geometry,
-    texture::{Texture, TextureConfig},
+    spectrum::{Spectrum, SpectrumConfig},
+    texture::{AnyTexture, Texture, TextureConfig},
+    types::Float,
+    util,
 };
 
-pub trait Material: fmt::Debug {
+pub trait Material: fmt::Debug + Send + Sync {
     fn compute_bsdf(&self, geometry: Geometry) -> Bsdf;
+    // The absorption coefficient of the interior, per unit distance, for
+    // materials that enclose an absorbing medium.
+    fn absorption(&self) -> Option<Spectrum>;
+    // Whether the surface stands in for one of a photograph, receiving
+    // shadows to composite over it rather than being rendered.
+    fn is_shadow_catcher(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug)]
 pub struct MatteMaterial {
-    texture: Box<dyn Texture>,
+    texture: AnyTexture,
 }
 
 impl MatteMaterial {
-    pub fn configure(config: &MatteMaterialConfig) -> MatteMaterial {
-        MatteMaterial {
-            texture: config.texture.configure(),
-        }
+    pub fn configure(config: &MatteMaterialConfig) -> Result<MatteMaterial, String> {
+        Ok(MatteMaterial {
+            texture: config.texture.configure()?,
+        })
     }
 }
 
 impl Material for MatteMaterial {
     fn compute_bsdf(&self, geometry: Geometry) -> Bsdf {
         Bsdf {
-            bxdfs: vec![Box::new(DiffuseBrdf::new(
+            bxdfs: vec![AnyBxdf::Diffuse(DiffuseBrdf::new(
                 geometry.normal,
                 self.texture.evaluate(geometry),
             ))],
         }
     }
+
+    fn absorption(&self) -> Option<Spectrum> {
+        None
+    }
+}
+
+// A diffuse surface left out of the render, which scatters light onto the
+// other objects as the surface of the photograph would.
+#[derive(Debug)]
+pub struct ShadowCatcherMaterial {
+    matte: MatteMaterial,
+}
+
+impl ShadowCatcherMaterial {
+    pub fn configure(config: &MatteMaterialConfig) -> Result<ShadowCatcherMaterial, String> {
+        Ok(ShadowCatcherMaterial {
+            matte: MatteMaterial::configure(config)?,
+        })
+    }
+}
+
+impl Material for ShadowCatcherMaterial {
+    fn compute_bsdf(&self, geometry: Geometry) -> Bsdf {
+        self.matte.compute_bsdf(geometry)
+    }
+
+    fn absorption(&self) -> Option<Spectrum> {
+        None
+    }
+
+    fn is_shadow_catcher(&self) -> bool {
+        true
+    }
 }
 
 #[derive(Debug)]
 pub struct MirrorMaterial {
-    texture: Box<dyn Texture>,
+    texture: AnyTexture,
 }
 
 impl MirrorMaterial {
-    pub fn configure(config: &MirrorMaterialConfig) -> MirrorMaterial {
-        MirrorMaterial {
-            texture: config.texture.configure(),
-        }
+    pub fn configure(config: &MirrorMaterialConfig) -> Result<MirrorMaterial, String> {
+        Ok(MirrorMaterial {
+            texture: config.texture.configure()?,
+        })
     }
 }
 
 impl Material for MirrorMaterial {
     fn compute_bsdf(&self, geometry: Geometry) -> Bsdf {
         Bsdf {
-            bxdfs: vec![Box::new(SpecularBrdf::new(
+            bxdfs: vec![AnyBxdf::Specular(SpecularBrdf::new(
                 geometry.normal,
                 self.texture.evaluate(geometry),
             ))],
         }
     }
+
+    fn absorption(&self) -> Option<Spectrum> {
+        None
+    }
 }
 
 #[derive(Debug)]
 pub struct GlossyMaterial {
-    diffuse_texture: Box<dyn Texture>,
-    specular_texture: Box<dyn Texture>,
+    diffuse_texture: AnyTexture,
+    specular_texture: AnyTexture,
 }
 
 impl GlossyMaterial {
-    pub fn configure(config: &GlossyMaterialConfig) -> GlossyMaterial {
-        GlossyMaterial {
-            diffuse_texture: config.diffuse_texture.configure(),
-            specular_texture: config.specular_texture.configure(),
-        }
+    pub fn configure(config: &GlossyMaterialConfig) -> Result<GlossyMaterial, String> {
+        Ok(GlossyMaterial {
+            diffuse_texture: config.diffuse_texture.configure()?,
+            specular_texture: config.specular_texture.configure()?,
+        })
     }
 }
 
@@ -79,44 +130,246 @@ impl Material for GlossyMaterial {
     fn compute_bsdf(&self, geometry: Geometry) -> Bsdf {
         Bsdf {
             bxdfs: vec![
-                Box::new(DiffuseBrdf::new(
+                AnyBxdf::Diffuse(DiffuseBrdf::new(
                     geometry.normal,
                     self.diffuse_texture.evaluate(geometry),
                 )),
-                Box::new(SpecularBrdf::new(
+                AnyBxdf::Specular(SpecularBrdf::new(
                     geometry.normal,
                     self.specular_texture.evaluate(geometry),
                 )),
             ],
         }
     }
+
+    fn absorption(&self) -> Option<Spectrum> {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub struct ConductorMaterial {
+    eta: Spectrum,
+    k: Spectrum,
+}
+
+impl ConductorMaterial {
+    pub fn configure(config: &ConductorMaterialConfig) -> Result<ConductorMaterial, String> {
+        let (eta, k) = match config {
+            ConductorMaterialConfig {
+                metal: Some(metal),
+                eta: None,
+                k: None,
+                reflectance: None,
+                edge_tint: None,
+            } => metal.ior(),
+            ConductorMaterialConfig {
+                metal: None,
+                eta: Some(eta),
+                k: Some(k),
+                reflectance: None,
+                edge_tint: None,
+            } => (Spectrum::configure(eta), Spectrum::configure(k)),
+            ConductorMaterialConfig {
+                metal: None,
+                eta: None,
+                k: None,
+                reflectance: Some(reflectance),
+                edge_tint,
+            } => artistic_ior(
+                Spectrum::configure(reflectance),
+                edge_tint
+                    .as_ref()
+                    .map(Spectrum::configure)
+                    .unwrap_or(Spectrum::fill(1.0)),
+            ),
+            _ => {
+                return Err(String::from(
+                    "a conductor needs exactly one of a metal, eta and k, or a reflectance",
+                ))
+            }
+        };
+        Ok(ConductorMaterial { eta, k })
+    }
+}
+
+impl Material for ConductorMaterial {
+    fn compute_bsdf(&self, geometry: Geometry) -> Bsdf {
+        Bsdf {
+            bxdfs: vec![AnyBxdf::Conductor(ConductorBrdf::new(
+                geometry.normal,
+                self.eta,
+                self.k,
+            ))],
+        }
+    }
+
+    fn absorption(&self) -> Option<Spectrum> {
+        None
+    }
+}
+
+// The roughest of smooth surfaces, below which the microfacet distribution
+// is too narrow to sample reliably.
+const MIN_ALPHA: Float = 1e-3;
+// The sheen distribution becomes a spike along the surface as its
+// roughness vanishes.
+const MIN_SHEEN_ROUGHNESS: Float = 0.07;
+
+// A material in the manner of the Disney principled BSDF, built from the
+// diffuse, microfacet and dielectric lobes. Metals reflect their base color,
+// dielectrics reflect in proportion to `specular`, a fraction of which is
+// transmitted through a smooth interface, and a clear coat adds a second,
+// colorless specular layer. Fabrics take a sheen, whose color is given.
+#[derive(Debug)]
+pub struct PrincipledMaterial {
+    base_color: AnyTexture,
+    metallic: Float,
+    roughness: Float,
+    specular: Float,
+    transmission: Float,
+    clearcoat: Float,
+    clearcoat_roughness: Float,
+    sheen_color: Option<Spectrum>,
+    sheen_roughness: Float,
+}
+
+impl PrincipledMaterial {
+    pub fn configure(config: &PrincipledMaterialConfig) -> Result<PrincipledMaterial, String> {
+        let parameter = |name: &str, value: Option<Float>, default: Float| {
+            let value = value.unwrap_or(default);
+            if (0.0..=1.0).contains(&value) {
+                Ok(value)
+            } else {
+                Err(format!(
+                    "the {} of a principled material must be in [0, 1]",
+                    name
+                ))
+            }
+        };
+        Ok(PrincipledMaterial {
+            base_color: config.base_color.configure()?,
+            metallic: parameter("metallic", config.metallic, 0.0)?,
+            roughness: parameter("roughness", config.roughness, 0.5)?,
+            specular: parameter("specular", config.specular, 0.5)?,
+            transmission: parameter("transmission", config.transmission, 0.0)?,
+            clearcoat: parameter("clearcoat", config.clearcoat, 0.0)?,
+            clearcoat_roughness: parameter(
+                "clearcoat roughness",
+                config.clearcoat_roughness,
+                0.03,
+            )?,
+            sheen_color: config.sheen_color.as_ref().map(Spectrum::configure),
+            sheen_roughness: parameter("sheen roughness", config.sheen_roughness, 0.5)?
+                .max(MIN_SHEEN_ROUGHNESS),
+        })
+    }
+}
+
+impl Material for PrincipledMaterial {
+    fn compute_bsdf(&self, geometry: Geometry) -> Bsdf {
+        let base_color = self.base_color.evaluate(geometry);
+        let normal = geometry.normal;
+        let dielectric = 1.0 - self.metallic;
+        let mut bxdfs: Vec<AnyBxdf> = Vec::new();
+
+        let diffuse = dielectric * (1.0 - self.transmission);
+        if diffuse > 0.0 {
+            bxdfs.push(AnyBxdf::Diffuse(DiffuseBrdf::new(
+                normal,
+                base_color * diffuse,
+            )));
+        }
+
+        // The reflectance at normal incidence is 0.08 * specular for
+        // dielectrics, which gives 4% for the default, and the base color
+        // for metals. Transmitted light is reflected by the interface.
+        let f0 = Spectrum::fill(0.08 * self.specular) * dielectric + base_color * self.metallic;
+        let reflection = 1.0 - dielectric * self.transmission;
+        if reflection > 0.0 {
+            let alpha = util::sqr(self.roughness).max(MIN_ALPHA);
+            bxdfs.push(AnyBxdf::Microfacet(MicrofacetBrdf::new(
+                normal, alpha, f0, reflection,
+            )));
+        }
+
+        let transmission = dielectric * self.transmission;
+        if transmission > 0.0 {
+            let f0 = (0.08 * self.specular).sqrt();
+            let eta = (1.0 + f0) / (1.0 - f0);
+            bxdfs.push(AnyBxdf::Dielectric(DielectricBxdf::new(
+                normal,
+                base_color * transmission,
+                eta,
+            )));
+        }
+
+        if let Some(sheen_color) = self.sheen_color.filter(|_| dielectric > 0.0) {
+            bxdfs.push(AnyBxdf::Sheen(SheenBrdf::new(
+                normal,
+                sheen_color * dielectric,
+                self.sheen_roughness,
+            )));
+        }
+
+        if self.clearcoat > 0.0 {
+            let alpha = util::sqr(self.clearcoat_roughness).max(MIN_ALPHA);
+            bxdfs.push(AnyBxdf::Microfacet(MicrofacetBrdf::new(
+                normal,
+                alpha,
+                Spectrum::fill(0.04),
+                0.25 * self.clearcoat,
+            )));
+        }
+
+        Bsdf { bxdfs }
+    }
+
+    fn absorption(&self) -> Option<Spectrum> {
+        None
+    }
 }
 
 #[derive(Debug)]
 pub struct DielectricMaterial {
-    texture: Box<dyn Texture>,
-    eta: f64,
+    texture: AnyTexture,
+    eta: Spectrum,
+    absorption: Option<Spectrum>,
 }
 
 impl DielectricMaterial {
-    pub fn configure(config: &DielectricMaterialConfig) -> DielectricMaterial {
-        DielectricMaterial {
-            texture: config.texture.configure(),
-            eta: config.eta,
-        }
+    pub fn configure(config: &DielectricMaterialConfig) -> Result<DielectricMaterial, String> {
+        let eta = match (config.eta, &config.dispersion) {
+            (Some(eta), None) => Spectrum::fill(eta),
+            (None, Some(dispersion)) => dispersion.eta()?,
+            _ => {
+                return Err(String::from(
+                    "a dielectric needs exactly one of eta and dispersion",
+                ))
+            }
+        };
+        Ok(DielectricMaterial {
+            texture: config.texture.configure()?,
+            eta,
+            absorption: config.absorption.as_ref().map(Spectrum::configure),
+        })
     }
 }
 
 impl Material for DielectricMaterial {
     fn compute_bsdf(&self, geometry: Geometry) -> Bsdf {
         Bsdf {
-            bxdfs: vec![Box::new(DielectricBxdf::new(
+            bxdfs: vec![AnyBxdf::Dielectric(DielectricBxdf::dispersive(
                 geometry.normal,
                 self.texture.evaluate(geometry),
                 self.eta,
             ))],
         }
     }
+
+    fn absorption(&self) -> Option<Spectrum> {
+        self.absorption
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -126,7 +379,10 @@ pub enum MaterialConfig {
     Matte(MatteMaterialConfig),
     Glossy(GlossyMaterialConfig),
     Mirror(MirrorMaterialConfig),
+    Conductor(ConductorMaterialConfig),
     Dielectric(DielectricMaterialConfig),
+    Principled(PrincipledMaterialConfig),
+    ShadowCatcher(MatteMaterialConfig),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -140,13 +396,16 @@ pub struct MirrorMaterialConfig {
 }
 
 impl MaterialConfig {
-    pub fn configure(&self) -> Box<dyn Material> {
-        match self {
-            MaterialConfig::Matte(c) => Box::new(MatteMaterial::configure(&c)),
-            MaterialConfig::Glossy(c) => Box::new(GlossyMaterial::configure(&c)),
-            MaterialConfig::Mirror(c) => Box::new(MirrorMaterial::configure(&c)),
-            MaterialConfig::Dielectric(c) => Box::new(DielectricMaterial::configure(&c)),
-        }
+    pub fn configure(&self) -> Result<Box<dyn Material>, String> {
+        Ok(match self {
+            MaterialConfig::Matte(c) => Box::new(MatteMaterial::configure(c)?),
+            MaterialConfig::Glossy(c) => Box::new(GlossyMaterial::configure(c)?),
+            MaterialConfig::Mirror(c) => Box::new(MirrorMaterial::configure(c)?),
+            MaterialConfig::Conductor(c) => Box::new(ConductorMaterial::configure(c)?),
+            MaterialConfig::Dielectric(c) => Box::new(DielectricMaterial::configure(c)?),
+            MaterialConfig::Principled(c) => Box::new(PrincipledMaterial::configure(c)?),
+            MaterialConfig::ShadowCatcher(c) => Box::new(ShadowCatcherMaterial::configure(c)?),
+        })
     }
 }
 
@@ -156,8 +415,207 @@ pub struct GlossyMaterialConfig {
     specular_texture: TextureConfig,
 }
 
+// A conductor is given by a measured metal, by its complex index of
+// refraction, eta + ik, in each channel, or by its reflectance at normal
+// incidence and the tint of its edges, which default to white.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ConductorMaterialConfig {
+    metal: Option<MetalConfig>,
+    eta: Option<SpectrumConfig>,
+    k: Option<SpectrumConfig>,
+    reflectance: Option<SpectrumConfig>,
+    edge_tint: Option<SpectrumConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PrincipledMaterialConfig {
+    base_color: TextureConfig,
+    metallic: Option<Float>,
+    roughness: Option<Float>,
+    specular: Option<Float>,
+    transmission: Option<Float>,
+    clearcoat: Option<Float>,
+    clearcoat_roughness: Option<Float>,
+    sheen_color: Option<SpectrumConfig>,
+    sheen_roughness: Option<Float>,
+}
+
+// The complex index of refraction with the given reflectance at normal
+// incidence, whose reflectance towards grazing angles follows the edge tint,
+// after Gulbrandsen, "Artist Friendly Metallic Fresnel" (2014).
+fn artistic_ior(reflectance: Spectrum, edge_tint: Spectrum) -> (Spectrum, Spectrum) {
+    let channel = |r: Float, g: Float| {
+        // A reflectance of one would need an infinite index.
+        let r = r.clamp(0.0, 0.999);
+        let g = g.clamp(0.0, 1.0);
+        let n_min = (1.0 - r) / (1.0 + r);
+        let n_max = (1.0 + r.sqrt()) / (1.0 - r.sqrt());
+        let n = g * n_min + (1.0 - g) * n_max;
+        let k2 = (r * (n + 1.0) * (n + 1.0) - (n - 1.0) * (n - 1.0)) / (1.0 - r);
+        (n, k2.max(0.0).sqrt())
+    };
+    let (r, g, b) = (
+        channel(reflectance.r, edge_tint.r),
+        channel(reflectance.g, edge_tint.g),
+        channel(reflectance.b, edge_tint.b),
+    );
+    (
+        Spectrum {
+            r: r.0,
+            g: g.0,
+            b: b.0,
+        },
+        Spectrum {
+            r: r.1,
+            g: g.1,
+            b: b.1,
+        },
+    )
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum MetalConfig {
+    Aluminum,
+    Copper,
+    Gold,
+    Silver,
+}
+
+impl MetalConfig {
+    // Measured indices of refraction at the dominant wavelengths of the
+    // sRGB primaries, 630, 532 and 465 nm.
+    fn ior(&self) -> (Spectrum, Spectrum) {
+        let (eta, k) = match self {
+            MetalConfig::Aluminum => ([1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+            MetalConfig::Copper => ([0.200, 0.924, 1.102], [3.912, 2.452, 2.142]),
+            MetalConfig::Gold => ([0.143, 0.374, 1.442], [3.983, 2.386, 1.603]),
+            MetalConfig::Silver => ([0.155, 0.117, 0.138], [4.828, 3.122, 2.147]),
+        };
+        let spectrum = |[r, g, b]: [Float; 3]| Spectrum { r, g, b };
+        (spectrum(eta), spectrum(k))
+    }
+}
+
+// A dielectric is given by a constant index of refraction or by one that
+// depends on the wavelength.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DielectricMaterialConfig {
     texture: TextureConfig,
-    eta: f64,
+    eta: Option<Float>,
+    dispersion: Option<DispersionConfig>,
+    absorption: Option<SpectrumConfig>,
+}
+
+// The dominant wavelengths of the sRGB primaries, in micrometers.
+const WAVELENGTHS: [Float; 3] = [0.630, 0.532, 0.465];
+
+// The index of refraction as a function of the wavelength in micrometers,
+// either by Cauchy's equation, n = a + b / λ², or by the Sellmeier
+// equation, n² = 1 + Σ bᵢ λ² / (λ² - cᵢ).
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum DispersionConfig {
+    Cauchy(CauchyConfig),
+    Sellmeier(SellmeierConfig),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CauchyConfig {
+    a: Float,
+    b: Float,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SellmeierConfig {
+    b: Vec<Float>,
+    c: Vec<Float>,
+}
+
+impl DispersionConfig {
+    // The index of refraction in each channel, at its dominant wavelength.
+    fn eta(&self) -> Result<Spectrum, String> {
+        if let DispersionConfig::Sellmeier(config) = self {
+            if config.b.len() != config.c.len() {
+                return Err(String::from(
+                    "the Sellmeier coefficients b and c must have the same length",
+                ));
+            }
+        }
+        let eta = |wavelength: Float| {
+            let l2 = util::sqr(wavelength);
+            match self {
+                DispersionConfig::Cauchy(config) => config.a + config.b / l2,
+                DispersionConfig::Sellmeier(config) => {
+                    let terms = config.b.iter().zip(&config.c);
+                    (1.0 + terms.map(|(b, c)| b * l2 / (l2 - c)).sum::<Float>()).sqrt()
+                }
+            }
+        };
+        let [r, g, b] = WAVELENGTHS.map(eta);
+        if [r, g, b].iter().any(|eta| !eta.is_finite() || *eta <= 0.0) {
+            return Err(String::from(
+                "the dispersion gives no valid index of refraction in the visible range",
+            ));
+        }
+        Ok(Spectrum { r, g, b })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{artistic_ior, DispersionConfig};
+    use crate::{approx::loosen, spectrum::Spectrum, util};
+
+    #[test]
+    fn test_artistic_ior() {
+        let reflectance = Spectrum {
+            r: 0.95,
+            g: 0.64,
+            b: 0.54,
+        };
+        let edge_tint = Spectrum {
+            r: 1.0,
+            g: 0.5,
+            b: 0.0,
+        };
+        let (eta, k) = artistic_ior(reflectance, edge_tint);
+        // The reflectance at normal incidence is reproduced exactly.
+        let r = util::fresnel_conductor(1.0, eta.r, k.r);
+        let g = util::fresnel_conductor(1.0, eta.g, k.g);
+        let b = util::fresnel_conductor(1.0, eta.b, k.b);
+        assert!((r - 0.95).abs() < loosen(1e-9));
+        assert!((g - 0.64).abs() < loosen(1e-9));
+        assert!((b - 0.54).abs() < loosen(1e-9));
+        // A white edge tint gives the smallest index with that reflectance.
+        assert!((eta.r - 0.05 / 1.95).abs() < loosen(1e-9));
+    }
+
+    #[test]
+    fn test_dispersion_eta() {
+        // Schott N-BK7.
+        let config: DispersionConfig = serde_yaml::from_str(
+            "
+            type: sellmeier
+            b: [1.03961212, 0.231792344, 1.01046945]
+            c: [0.00600069867, 0.0200179144, 103.560653]
+            ",
+        )
+        .unwrap();
+        let eta = config.eta().unwrap();
+        assert!((eta.r - 1.51519).abs() < 1e-5);
+        assert!((eta.g - 1.51947).abs() < 1e-5);
+        assert!((eta.b - 1.52401).abs() < 1e-5);
+
+        let config: DispersionConfig =
+            serde_yaml::from_str("{ type: cauchy, a: 1.5, b: 0.01 }").unwrap();
+        let eta = config.eta().unwrap();
+        assert!((eta.g - (1.5 + 0.01 / util::sqr(0.532))).abs() < 1e-9);
+        assert!(eta.r < eta.g && eta.g < eta.b);
+
+        let config: DispersionConfig =
+            serde_yaml::from_str("{ type: sellmeier, b: [1.0], c: [] }").unwrap();
+        assert!(config.eta().is_err());
+    }
 }
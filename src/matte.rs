@@ -0,0 +1,148 @@
+use crate::{
+    interaction::Interaction,
+    parallel,
+    path::Path,
+    ray::{Ray, RayKind},
+    rng::RngKind,
+    sampler::{ReplaySampler, Sampler},
+    scene::Scene,
+    types::Float,
+    util,
+    visibility::VisibilityTester,
+};
+
+// The samples per side of the grid of each pixel, and the light samples per
+// point on a shadow catcher.
+const PIXEL_SAMPLES: usize = 4;
+const LIGHT_SAMPLES: usize = 16;
+
+// The alpha of each pixel, for compositing the render over a photograph:
+// one where an object is seen, none where nothing, the environment or a
+// holdout is, and on a shadow catcher the fraction of the light of the scene
+// that objects keep from it, as the catcher itself is left out of the render.
+pub fn alpha(scene: &Scene) -> Vec<Float> {
+    let (width, height) = (scene.image_config.width, scene.image_config.height);
+    parallel::map(width * height, |i| {
+        let mut sum = 0.0;
+        for j in 0..PIXEL_SAMPLES * PIXEL_SAMPLES {
+            let offset_x = ((j % PIXEL_SAMPLES) as Float + 0.5) / PIXEL_SAMPLES as Float;
+            let offset_y = ((j / PIXEL_SAMPLES) as Float + 0.5) / PIXEL_SAMPLES as Float;
+            let x = (i % width) as Float + offset_x;
+            let y = (i / width) as Float + offset_y;
+            let values = vec![x / width as Float, y / height as Float, 0.5, 0.5];
+            let camera = scene
+                .camera
+                .sample_interaction(&mut ReplaySampler::new(1, values));
+            let geometry = camera.geometry();
+            let ray = Ray::new(geometry.point, geometry.direction);
+            sum += match scene.intersect(ray, RayKind::Camera) {
+                Some(interaction) if interaction.is_holdout() => 0.0,
+                Some(interaction) if interaction.is_environment() => 0.0,
+                Some(interaction) if interaction.is_shadow_catcher() => {
+                    let seed = (i * PIXEL_SAMPLES * PIXEL_SAMPLES + j) as u64;
+                    shadow(scene, &interaction, seed)
+                }
+                Some(_) => 1.0,
+                None => 0.0,
+            };
+        }
+        sum / (PIXEL_SAMPLES * PIXEL_SAMPLES) as Float
+    })
+}
+
+// The fraction of the direct light reaching the point that is occluded,
+// estimated with the same light samples with and without occlusion.
+fn shadow(scene: &Scene, interaction: &Interaction, seed: u64) -> Float {
+    let geometry = interaction.geometry();
+    let mut sampler = Path::seeded_sampler(&scene.mutation_parameters, RngKind::default(), seed);
+    sampler.start_stream(0);
+    let (mut total, mut visible) = (0.0, 0.0);
+    for _ in 0..LIGHT_SAMPLES {
+        let light = scene.sample_light(&mut sampler);
+        if !light.illuminates(interaction.id()) {
+            continue;
+        }
+        let light_geometry = light.sample_interaction(&mut sampler).geometry();
+        let direction = light_geometry.point - geometry.point;
+        // Only light arriving on the side of the surface that is seen.
+        if direction.dot(geometry.normal) * geometry.direction.dot(geometry.normal) >= 0.0 {
+            continue;
+        }
+        let radiance = light.radiance(light_geometry.point, light_geometry.normal, -direction);
+        let pdf = light.sampling_pdf() * light.positional_pdf(light_geometry.point).value();
+        let irradiance = radiance.luminance()
+            * util::geometry_term(direction, geometry.normal, light_geometry.normal)
+            / pdf;
+        total += irradiance;
+        let tester = VisibilityTester::new(scene, geometry, light_geometry.point, RayKind::Shadow);
+        visible += irradiance * tester.transmittance(light.id()).luminance();
+    }
+    if total > 0.0 {
+        1.0 - visible / total
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::alpha;
+    use crate::{approx::loosen, light::LightOverrides, scene::SceneConfig, types::Float};
+
+    const SCENE: &str = "
+image: { width: 2, height: 2 }
+camera:
+  type: pinhole
+  origin: { x: 0.0, y: 0.0, z: 10.0 }
+  look_at: { x: 0.0, y: 0.0, z: 0.0 }
+  field_of_view: { value: 10.0, unit: degrees }
+lights:
+  - id: light
+    type: diffuse_area
+    shape: { type: sphere, center: { x: 0.0, y: 0.0, z: 20.0 }, radius: 1.0 }
+    spectrum: { r: 1.0, g: 1.0, b: 1.0 }
+objects:
+  - id: ground
+    type: geometric
+    shape: { type: sphere, center: { x: 0.0, y: 0.0, z: 0.0 }, radius: 3.0 }
+    material:
+      type: shadow_catcher
+      texture: { type: constant, spectrum: { r: 0.5, g: 0.5, b: 0.5 } }
+";
+
+    const OCCLUDER: &str = "
+  - id: occluder
+    type: geometric
+    visible_to_camera: false
+    shape: { type: sphere, center: { x: 0.0, y: 0.0, z: 6.0 }, radius: 2.0 }
+    material:
+      type: matte
+      texture: { type: constant, spectrum: { r: 0.5, g: 0.5, b: 0.5 } }
+";
+
+    fn render(source: &str) -> Vec<Float> {
+        let scene = serde_yaml::from_str::<SceneConfig>(source)
+            .unwrap()
+            .configure(None, &LightOverrides::default())
+            .unwrap();
+        alpha(&scene)
+    }
+
+    #[test]
+    fn test_alpha() {
+        assert!(render(SCENE).iter().all(|a| *a < loosen(1e-6)));
+        let occluded = format!("{}{}", SCENE, OCCLUDER);
+        assert!(render(&occluded)
+            .iter()
+            .all(|a| (a - 1.0).abs() < loosen(1e-6)));
+        let matte = SCENE.replace("shadow_catcher", "matte");
+        assert!(render(&matte)
+            .iter()
+            .all(|a| (a - 1.0).abs() < loosen(1e-6)));
+        let holdout = matte.replace(
+            "    type: geometric\n",
+            "    type: geometric\n    holdout: true\n",
+        );
+        assert!(render(&holdout).iter().all(|a| *a == 0.0));
+    }
+}
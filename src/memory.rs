@@ -0,0 +1,158 @@
+#[cfg(feature = "allocation-statistics")]
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+// Wraps the system allocator to keep track of the heap in use, its peak and
+// the number of allocations. This costs a few atomic operations on every
+// allocation, so it is only compiled in with the `allocation-statistics`
+// feature; without it, only the peak resident memory is known.
+#[cfg(feature = "allocation-statistics")]
+struct CountingAllocator;
+
+#[cfg(feature = "allocation-statistics")]
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "allocation-statistics")]
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "allocation-statistics")]
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(feature = "allocation-statistics")]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[cfg(feature = "allocation-statistics")]
+impl CountingAllocator {
+    fn record_allocation(size: usize) {
+        let allocated = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK.fetch_max(allocated, Ordering::Relaxed);
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(feature = "allocation-statistics")]
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let pointer = System.alloc(layout);
+        if !pointer.is_null() {
+            CountingAllocator::record_allocation(layout.size());
+        }
+        pointer
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let pointer = System.alloc_zeroed(layout);
+        if !pointer.is_null() {
+            CountingAllocator::record_allocation(layout.size());
+        }
+        pointer
+    }
+
+    unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+        System.dealloc(pointer, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    // A reallocation counts as a new allocation of the new size.
+    unsafe fn realloc(&self, pointer: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+        let new_pointer = System.realloc(pointer, layout, size);
+        if !new_pointer.is_null() {
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+            CountingAllocator::record_allocation(size);
+        }
+        new_pointer
+    }
+}
+
+// Whether the heap is counted, without which allocated() and
+// allocation_count() are always zero.
+pub fn counting() -> bool {
+    cfg!(feature = "allocation-statistics")
+}
+
+// The number of bytes currently allocated on the heap.
+pub fn allocated() -> usize {
+    #[cfg(feature = "allocation-statistics")]
+    return ALLOCATED.load(Ordering::Relaxed);
+    #[cfg(not(feature = "allocation-statistics"))]
+    return 0;
+}
+
+pub fn allocation_count() -> u64 {
+    #[cfg(feature = "allocation-statistics")]
+    return ALLOCATIONS.load(Ordering::Relaxed);
+    #[cfg(not(feature = "allocation-statistics"))]
+    return 0;
+}
+
+// The peak of the heap if it is counted, or else the peak resident memory of
+// the process where the system reports it.
+pub fn peak() -> Option<usize> {
+    #[cfg(feature = "allocation-statistics")]
+    return Some(PEAK.load(Ordering::Relaxed));
+    #[cfg(not(feature = "allocation-statistics"))]
+    return std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| status_kibibytes(&status, "VmHWM"))
+        .map(|kibibytes| kibibytes * 1024);
+}
+
+// Reads a field such as `VmHWM:     1234 kB` of /proc/self/status.
+#[cfg_attr(feature = "allocation-statistics", allow(dead_code))]
+fn status_kibibytes(status: &str, key: &str) -> Option<usize> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix(':'))
+        .and_then(|value| value.trim().strip_suffix("kB"))
+        .and_then(|value| value.trim().parse().ok())
+}
+
+pub fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{allocation_count, format_bytes, peak, status_kibibytes};
+
+    #[cfg(feature = "allocation-statistics")]
+    #[test]
+    fn test_allocation_tracking() {
+        let count = allocation_count();
+        let buffer = vec![0u8; 1 << 20];
+        assert!(allocation_count() > count);
+        assert!(peak().unwrap() >= buffer.len());
+    }
+
+    #[cfg(all(target_os = "linux", not(feature = "allocation-statistics")))]
+    #[test]
+    fn test_peak_resident_memory() {
+        assert_eq!(allocation_count(), 0);
+        assert!(peak().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_status_kibibytes() {
+        let status = "Name:\tmmlt\nVmPeak:\t  20480 kB\nVmHWM:\t    1234 kB\n";
+        assert_eq!(status_kibibytes(status, "VmHWM"), Some(1234));
+        assert_eq!(status_kibibytes(status, "VmRSS"), None);
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 << 30), "3.0 GiB");
+    }
+}
@@ -0,0 +1,40 @@
+use crate::{
+    checkpoint::Checkpoint, config::MergeConfig, error::Error, image::Image, progress::report,
+    scene::SceneConfig, types::Float,
+};
+
+// Each checkpoint holds the sum of its render's weighted contributions, an
+// estimate of the image times its sample count, so the merged image is the
+// sum of the checkpoints normalized by the total sample count. Renders that
+// share a seed took the same samples and would be counted twice.
+pub fn merge(config: &MergeConfig) -> Result<(), Error> {
+    let scene_config = SceneConfig::load(&config.scene_path, &config.definitions)?;
+    let mut image = Image::configure(&scene_config.image);
+    let mut sample_count: u64 = 0;
+    let mut seeds: Vec<u64> = Vec::new();
+    for path in &config.input_paths {
+        let checkpoint = Checkpoint::read(path)?;
+        let error = |message| Error::Checkpoint {
+            path: path.clone(),
+            message,
+        };
+        if let Some(seed) = checkpoint.seeds.iter().find(|s| seeds.contains(s)) {
+            return Err(error(format!("seed {} was already merged", seed)));
+        }
+        image.accumulate(&checkpoint).map_err(error)?;
+        report(&format!("{}: {} samples", path, checkpoint.sample_count));
+        sample_count += checkpoint.sample_count;
+        seeds.extend(checkpoint.seeds);
+    }
+    report(&format!(
+        "merged {} checkpoints with {} samples",
+        config.input_paths.len(),
+        sample_count
+    ));
+    if let Some(path) = &config.checkpoint_path {
+        image.checkpoint(sample_count, seeds).write(path)?;
+    }
+    let pixel_count = (scene_config.image.width * scene_config.image.height) as u64;
+    image.scale(pixel_count as Float / sample_count.max(1) as Float);
+    image.write(config.image_path.clone())
+}
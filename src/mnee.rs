@@ -0,0 +1,217 @@
+use crate::{
+    geometry::Geometry,
+    interaction::Interaction,
+    ray::{Ray, RayKind},
+    scene::Scene,
+    types::Float,
+    util,
+    vector::{Point3, Vector3},
+};
+
+const MAX_ITERATIONS: usize = 32;
+// The tolerances and the finite difference step follow the precision.
+#[cfg(not(feature = "f32"))]
+const TOLERANCE: Float = 1e-10;
+#[cfg(feature = "f32")]
+const TOLERANCE: Float = 1e-5;
+#[cfg(not(feature = "f32"))]
+const STEP: Float = 1e-7;
+#[cfg(feature = "f32")]
+const STEP: Float = 1e-3;
+// Relative to the length of the final segment.
+#[cfg(not(feature = "f32"))]
+const LIGHT_TOLERANCE: Float = 1e-6;
+#[cfg(feature = "f32")]
+const LIGHT_TOLERANCE: Float = 1e-3;
+
+// A connection from a shading point to a point on a light through a single
+// refractive interface, as in manifold next event estimation.
+pub struct Manifold<'a> {
+    pub interface: Interaction<'a>,
+    pub light: Interaction<'a>,
+    // The solid angle leaving the shading point per unit area on the light,
+    // which replaces the geometry term of the connection.
+    pub solid_angle_density: Float,
+}
+
+// Finds the point on a specular transmitter through which the point on the
+// light refracts towards the shading point, by Newton iteration over the
+// direction leaving the shading point, starting from the direction of the
+// light. The iteration finds at most one of the possible solutions.
+pub fn connect<'a>(scene: &'a Scene, point: Point3, light: Geometry) -> Option<Manifold<'a>> {
+    // The difference between the transmitted direction and the direction
+    // to the light, which vanishes at a solution, with the latter.
+    let residual = |direction: Vector3| {
+        let (interface, transmitted) = refract(scene, point, direction)?;
+        let target = (light.point - interface.geometry().point).norm();
+        Some((transmitted - target, target))
+    };
+
+    let mut direction = (light.point - point).norm();
+    let mut converged = false;
+    for _ in 0..MAX_ITERATIONS {
+        let (r, target) = residual(direction)?;
+        if r.len() < TOLERANCE {
+            converged = true;
+            break;
+        }
+        let (t1, t2, _) = util::orthonormal_basis(direction);
+        let (a1, a2, _) = util::orthonormal_basis(target);
+        let du = (residual((direction + t1 * STEP).norm())?.0 - r) / STEP;
+        let dv = (residual((direction + t2 * STEP).norm())?.0 - r) / STEP;
+        let (r1, r2) = (r.dot(a1), r.dot(a2));
+        let (j11, j21) = (du.dot(a1), du.dot(a2));
+        let (j12, j22) = (dv.dot(a1), dv.dot(a2));
+        let determinant = j11 * j22 - j12 * j21;
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let u = -(j22 * r1 - j12 * r2) / determinant;
+        let v = -(j11 * r2 - j21 * r1) / determinant;
+        direction = (direction + t1 * u + t2 * v).norm();
+    }
+    if !converged {
+        return None;
+    }
+
+    let (interface, transmitted) = refract(scene, point, direction)?;
+    let origin = interface.geometry().point;
+    let light_interaction = scene
+        .intersect(
+            Ray::leaving(&interface.geometry(), transmitted),
+            RayKind::Shadow,
+        )
+        .filter(Interaction::is_light)?;
+    let distance = (light.point - origin).len();
+    if (light_interaction.geometry().point - light.point).len() > LIGHT_TOLERANCE * distance {
+        return None;
+    }
+
+    // The area swept on the plane of the light by a small patch of
+    // directions leaving the shading point.
+    let (t1, t2, _) = util::orthonormal_basis(direction);
+    let project = |direction: Vector3| {
+        let (interface, transmitted) = refract(scene, point, direction)?;
+        let origin = interface.geometry().point;
+        let cosine = transmitted.dot(light.normal);
+        if cosine.abs() < 1e-12 {
+            return None;
+        }
+        Some(origin + transmitted * ((light.point - origin).dot(light.normal) / cosine))
+    };
+    let center = project(direction)?;
+    let du = (project((direction + t1 * STEP).norm())? - center) / STEP;
+    let dv = (project((direction + t2 * STEP).norm())? - center) / STEP;
+    let area_density = du.cross(dv).len();
+    if area_density == 0.0 {
+        return None;
+    }
+
+    Some(Manifold {
+        interface,
+        light: light_interaction,
+        solid_angle_density: 1.0 / area_density,
+    })
+}
+
+fn refract(scene: &Scene, point: Point3, direction: Vector3) -> Option<(Interaction<'_>, Vector3)> {
+    let interaction = scene.intersect(Ray::new(point, direction), RayKind::Shadow)?;
+    let transmitted = match &interaction {
+        Interaction::Object(object) => object.get_bsdf().transmit(-direction)?,
+        _ => return None,
+    };
+    Some((interaction, transmitted.norm()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::connect;
+    use crate::{
+        approx::loosen,
+        geometry::Geometry,
+        light::LightOverrides,
+        scene::{Scene, SceneConfig},
+        types::Float,
+        util,
+        vector::{Point2, Vector3},
+    };
+
+    fn scene(eta: Float) -> Scene {
+        let source = format!(
+            "
+image: {{ width: 4, height: 4, filter: {{ type: box }} }}
+camera:
+  type: pinhole
+  origin: {{ x: 0.0, y: 1.0, z: 10.0 }}
+  look_at: {{ x: 0.0, y: 0.0, z: 0.0 }}
+  field_of_view: {{ value: 40.0, unit: degrees }}
+lights:
+  - id: light
+    type: diffuse_area
+    shape: {{ type: sphere, center: {{ x: 0.0, y: 4.0, z: 0.0 }}, radius: 0.5 }}
+    spectrum: {{ r: 1.0, g: 1.0, b: 1.0 }}
+objects:
+  - id: glass
+    type: geometric
+    shape: {{ type: sphere, center: {{ x: 0.0, y: 4.0, z: 0.0 }}, radius: 2.0 }}
+    material:
+      type: dielectric
+      eta: {eta}
+      texture: {{ type: constant, spectrum: {{ r: 1.0, g: 1.0, b: 1.0 }} }}
+"
+        );
+        serde_yaml::from_str::<SceneConfig>(&source)
+            .unwrap()
+            .configure(None, &LightOverrides::default())
+            .unwrap()
+    }
+
+    fn light(direction: Vector3) -> Geometry {
+        let normal = direction.norm();
+        Geometry {
+            point: Vector3::new(0.0, 4.0, 0.0) + normal * 0.5,
+            normal,
+            direction: normal,
+            t: 0.0,
+            uv: Point2::new(0.0, 0.0),
+            dpdu: Vector3::new(0.0, 0.0, 0.0),
+            dpdv: Vector3::new(0.0, 0.0, 0.0),
+            surface: None,
+        }
+    }
+
+    #[test]
+    fn test_connect_index_matched() {
+        // Through an interface that does not bend light, the connection
+        // reduces to the geometry term between the two end points.
+        let scene = scene(1.0);
+        let point = Vector3::new(1.0, 0.0, 1.0);
+        let light = light(Vector3::new(0.3, -1.0, 0.2));
+        let manifold = connect(&scene, point, light).unwrap();
+        let interface = manifold.interface.geometry();
+        let to_point = point - interface.point;
+        let up = Vector3::new(0.0, 1.0, 0.0);
+        let factor = manifold.solid_angle_density * to_point.dot(to_point)
+            / util::abs_cos_theta(interface.normal, to_point);
+        let expected = util::geometry_term(light.point - point, up, light.normal);
+        let actual = factor * util::geometry_term(interface.point - point, up, interface.normal);
+        assert!((actual - expected).abs() < loosen(1e-6) * expected);
+    }
+
+    #[test]
+    fn test_connect_refracts() {
+        let scene = scene(1.5);
+        let point = Vector3::new(3.0, 0.0, 1.0);
+        let target = light(Vector3::new(0.6, -1.0, 0.2));
+        let manifold = connect(&scene, point, target).unwrap();
+        let interface = manifold.interface.geometry();
+        let incoming = (point - interface.point).norm();
+        let transmitted = util::refract(incoming, interface.normal, 1.5).unwrap();
+        let actual = manifold.light.geometry().point - interface.point;
+        assert!((transmitted.norm() - actual.norm()).len() < loosen(1e-8));
+        assert!((manifold.light.geometry().point - target.point).len() < loosen(1e-6));
+
+        // The far side of the light is hidden by the light itself.
+        assert!(connect(&scene, point, light(Vector3::new(0.0, 1.0, 0.0))).is_none());
+    }
+}
@@ -0,0 +1,149 @@
+use std::fs;
+
+use crate::{
+    types::Float,
+    vector::{Point2, Point3},
+};
+
+// The triangles of a Wavefront OBJ file, with the positions and texture
+// coordinates their corners index. Polygons are split into fans of
+// triangles about their first corner, and normals, groups and materials are
+// ignored.
+#[derive(Debug, Default, PartialEq)]
+pub struct ObjMesh {
+    pub positions: Vec<Point3>,
+    pub uvs: Vec<Point2>,
+    pub triangles: Vec<[ObjCorner; 3]>,
+}
+
+// The indices of the position and texture coordinates of a corner of a face.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ObjCorner {
+    pub position: usize,
+    pub uv: Option<usize>,
+}
+
+impl ObjMesh {
+    pub fn load(path: &str) -> Result<ObjMesh, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        ObjMesh::parse(&source).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn parse(source: &str) -> Result<ObjMesh, String> {
+        let mut mesh = ObjMesh::default();
+        for (index, line) in source.lines().enumerate() {
+            let error = |message: &str| format!("line {}: {}", index + 1, message);
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("v") => {
+                    let [x, y, z] = parse_floats(&mut tokens).map_err(|e| error(&e))?;
+                    mesh.positions.push(Point3::new(x, y, z));
+                }
+                Some("vt") => {
+                    let [u, v] = parse_floats(&mut tokens).map_err(|e| error(&e))?;
+                    mesh.uvs.push(Point2::new(u, v));
+                }
+                Some("f") => {
+                    let corners = tokens
+                        .map(|token| mesh.parse_corner(token))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|e| error(&e))?;
+                    if corners.len() < 3 {
+                        return Err(error("a face needs at least three corners"));
+                    }
+                    let textured = corners[0].uv.is_some();
+                    if corners.iter().any(|c| c.uv.is_some() != textured) {
+                        return Err(error("texture coordinates are given for only some corners"));
+                    }
+                    for i in 1..corners.len() - 1 {
+                        mesh.triangles
+                            .push([corners[0], corners[i], corners[i + 1]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(mesh)
+    }
+
+    // A corner, `v`, `v/vt`, `v//vn` or `v/vt/vn`, whose indices count from
+    // one, or back from the last element read when negative.
+    fn parse_corner(&self, token: &str) -> Result<ObjCorner, String> {
+        let mut indices = token.split('/');
+        let position = indices.next().unwrap_or_default();
+        let position = resolve(position, self.positions.len())?;
+        let uv = match indices.next() {
+            Some(uv) if !uv.is_empty() => Some(resolve(uv, self.uvs.len())?),
+            _ => None,
+        };
+        Ok(ObjCorner { position, uv })
+    }
+}
+
+fn parse_floats<'a, const N: usize>(
+    tokens: &mut impl Iterator<Item = &'a str>,
+) -> Result<[Float; N], String> {
+    let mut values = [0.0; N];
+    for value in values.iter_mut() {
+        let token = tokens.next().ok_or("too few coordinates")?;
+        *value = token
+            .parse()
+            .map_err(|_| format!("could not parse coordinate '{}'", token))?;
+    }
+    Ok(values)
+}
+
+fn resolve(token: &str, count: usize) -> Result<usize, String> {
+    let index: i64 = token
+        .parse()
+        .map_err(|_| format!("could not parse index '{}'", token))?;
+    let resolved = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+    if (0..count as i64).contains(&resolved) {
+        Ok(resolved as usize)
+    } else {
+        Err(format!("index {} is out of range", index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ObjCorner, ObjMesh};
+    use crate::vector::{Point2, Point3};
+
+    #[test]
+    fn test_parse() {
+        let source = "
+# a unit square
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 1
+vn 0 0 1
+f 1/1/1 2/1/1 3/2/1 4/2/1
+f -4 -3 -2
+";
+        let mesh = ObjMesh::parse(source).unwrap();
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.positions[2], Point3::new(1.0, 1.0, 0.0));
+        assert_eq!(mesh.uvs[1], Point2::new(1.0, 1.0));
+        let corner = |position, uv| ObjCorner { position, uv };
+        assert_eq!(
+            mesh.triangles,
+            vec![
+                [corner(0, Some(0)), corner(1, Some(0)), corner(2, Some(1))],
+                [corner(0, Some(0)), corner(2, Some(1)), corner(3, Some(1))],
+                [corner(0, None), corner(1, None), corner(2, None)],
+            ]
+        );
+
+        assert!(ObjMesh::parse("v 0 0 0\nf 1 2 3\n").is_err());
+        assert!(ObjMesh::parse("v 0 0\n").is_err());
+        assert!(ObjMesh::parse("v 0 0 0\nv 1 0 0\nf 1 2\n").is_err());
+    }
+}
@@ -1,36 +1,52 @@
-use std::{cell::OnceCell, fmt};
+use std::{fmt, sync::OnceLock};
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    bounds::Bounds3,
     bsdf::Bsdf,
     geometry::Geometry,
     interaction::{Interaction, ObjectInteraction},
     material::{Material, MaterialConfig},
-    ray::Ray,
-    shape::{Shape, ShapeConfig},
+    ray::{Ray, RayKind},
+    shape::{AnyShape, Placement, Shape, ShapeConfig},
+    spectrum::Spectrum,
 };
 
-pub trait Object: fmt::Debug {
-    fn intersect(&self, ray: Ray) -> Option<Interaction>;
+pub trait Object: fmt::Debug + Send + Sync {
+    fn intersect(&self, ray: Ray) -> Option<Interaction<'_>>;
     fn compute_bsdf(&self, geometry: Geometry) -> Bsdf;
+    fn absorption(&self) -> Option<Spectrum>;
+    fn bounds(&self) -> Bounds3;
     fn id(&self) -> &String;
+    // Whether rays of the given kind may hit the object.
+    fn is_visible(&self, kind: RayKind) -> bool;
+    fn is_shadow_catcher(&self) -> bool;
+    // Whether the object cuts a hole in the render where the camera sees it.
+    fn is_holdout(&self) -> bool;
+    fn shape(&self) -> &AnyShape;
 }
 
 #[derive(Debug)]
 pub struct GeometricObject {
     id: String,
-    shape: Box<dyn Shape>,
+    shape: AnyShape,
     material: Box<dyn Material>,
+    visible_to_camera: bool,
+    casts_shadow: bool,
+    visible_to_indirect: bool,
+    holdout: bool,
 }
 
 impl Object for GeometricObject {
-    fn intersect(&self, ray: Ray) -> Option<Interaction> {
+    fn intersect(&self, ray: Ray) -> Option<Interaction<'_>> {
         let geometry = self.shape.intersect(ray)?;
         let interaction = ObjectInteraction {
             object: self,
             geometry,
-            bsdf: OnceCell::new(),
+            bsdf: OnceLock::new(),
+            regularization: None,
+            sample: None,
         };
         Some(Interaction::Object(interaction))
     }
@@ -39,18 +55,64 @@ impl Object for GeometricObject {
         self.material.compute_bsdf(geometry)
     }
 
+    fn absorption(&self) -> Option<Spectrum> {
+        self.material.absorption()
+    }
+
+    fn bounds(&self) -> Bounds3 {
+        self.shape.bounds()
+    }
+
     fn id(&self) -> &String {
         &self.id
     }
+
+    fn is_visible(&self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Camera => self.visible_to_camera,
+            RayKind::Shadow => self.casts_shadow,
+            RayKind::Indirect => self.visible_to_indirect,
+        }
+    }
+
+    fn is_shadow_catcher(&self) -> bool {
+        self.material.is_shadow_catcher()
+    }
+
+    fn is_holdout(&self) -> bool {
+        self.holdout
+    }
+
+    fn shape(&self) -> &AnyShape {
+        &self.shape
+    }
 }
 
 impl GeometricObject {
-    pub fn configure(config: &GeometricObjectConfig) -> GeometricObject {
-        GeometricObject {
+    pub fn configure(
+        config: &GeometricObjectConfig,
+        default_material: Option<&MaterialConfig>,
+        placement: Placement,
+    ) -> Result<GeometricObject, String> {
+        let material = config
+            .material
+            .as_ref()
+            .or(default_material)
+            .ok_or_else(|| {
+                format!(
+                    "object '{}' has no material and there is no default",
+                    config.id
+                )
+            })?;
+        Ok(GeometricObject {
             id: config.id.clone(),
-            shape: config.shape.configure(),
-            material: config.material.configure(),
-        }
+            shape: config.shape.configure(placement)?,
+            material: material.configure()?,
+            visible_to_camera: config.visible_to_camera.unwrap_or(true),
+            casts_shadow: config.casts_shadow.unwrap_or(true),
+            visible_to_indirect: config.visible_to_indirect.unwrap_or(true),
+            holdout: config.holdout.unwrap_or(false),
+        })
     }
 }
 
@@ -62,9 +124,17 @@ pub enum ObjectConfig {
 }
 
 impl ObjectConfig {
-    pub fn configure(&self) -> Box<dyn Object> {
+    pub fn configure(
+        &self,
+        default_material: Option<&MaterialConfig>,
+        placement: Placement,
+    ) -> Result<Box<dyn Object>, String> {
         match self {
-            ObjectConfig::Geometric(config) => Box::new(GeometricObject::configure(config)),
+            ObjectConfig::Geometric(config) => Ok(Box::new(GeometricObject::configure(
+                config,
+                default_material,
+                placement,
+            )?)),
         }
     }
 }
@@ -73,5 +143,14 @@ impl ObjectConfig {
 pub struct GeometricObjectConfig {
     id: String,
     shape: ShapeConfig,
-    material: MaterialConfig,
+    // The default material of the scene if omitted.
+    material: Option<MaterialConfig>,
+    // Whether the object is seen by the camera, blocks the light of the
+    // lights, and is seen by the bounces between, all by default.
+    visible_to_camera: Option<bool>,
+    casts_shadow: Option<bool>,
+    visible_to_indirect: Option<bool>,
+    // Whether the object stands in for one of a photograph that hides the
+    // render behind it, while still shadowing and reflecting it.
+    holdout: Option<bool>,
 }
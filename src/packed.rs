@@ -0,0 +1,420 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+};
+
+use crate::{
+    bounds::Bounds3,
+    bvh::{BvhNode, BvhNodes, MAX_DEPTH},
+    cache,
+    config::PackConfig,
+    error::Error,
+    obj::ObjMesh,
+    progress::report,
+    shape::{Triangle, TriangleMesh},
+    types::{widen, Float},
+    vector::{Point2, Point3},
+};
+
+const MAGIC: &[u8; 8] = b"MMLTMSH1";
+
+// The sections of a packed mesh, in the order of the file, and the number of
+// 8-byte words of each of their records.
+const POSITIONS: usize = 0;
+const UVS: usize = 1;
+const TRIANGLES: usize = 2;
+const AREAS: usize = 3;
+const NODES: usize = 4;
+const INDICES: usize = 5;
+const RECORD_WORDS: [u64; 6] = [3, 2, 6, 1, 9, 1];
+const HEADER_SIZE: u64 = 8 + 8 * RECORD_WORDS.len() as u64;
+
+// A triangle without surface coordinates.
+const NO_UV: u64 = u64::MAX;
+
+// A triangle mesh packed into a file, with the hierarchy over its triangles,
+// from which a render reads only the pages its rays and samples visit, through
+// the geometry cache, so that meshes larger than memory can be rendered. After
+// an 8-byte magic number, the number of records of each section follows as a
+// u64, then the sections: the positions as f64 triples, the surface
+// coordinates as f64 pairs, the triangles as the indices of the positions and
+// then of the surface coordinates of their corners, or u64::MAX for each
+// without them, the cumulative areas of the triangles as f64s, the nodes of
+// the hierarchy as the minimum and maximum of their bounds as f64 triples and
+// their offset, count and axis, and the triangles in the order of the leaves,
+// all little-endian.
+#[derive(Clone, Debug)]
+pub struct PackedMesh {
+    file: usize,
+    counts: [usize; 6],
+    starts: [u64; 6],
+}
+
+// `mmlt pack` packs a mesh ahead of rendering, which needs the whole mesh in
+// memory once, so that renders then need only what they visit.
+pub fn pack(config: &PackConfig) -> Result<(), Error> {
+    let error = |message: String| Error::Scene {
+        path: config.mesh_path.clone(),
+        location: None,
+        message,
+    };
+    let source = fs::read_to_string(&config.mesh_path).map_err(Error::io(&config.mesh_path))?;
+    let obj = ObjMesh::parse(&source).map_err(error)?;
+    let mesh = TriangleMesh::from_obj(obj).map_err(error)?;
+    mesh.pack(&config.output_path)?;
+    report(&format!(
+        "packed {} triangles into {}",
+        mesh.triangle_count(),
+        config.output_path
+    ));
+    Ok(())
+}
+
+impl PackedMesh {
+    // Whether the file starts as a packed mesh, rather than as an OBJ file.
+    pub fn is_packed(path: &str) -> bool {
+        let mut magic = [0; 8];
+        File::open(path)
+            .and_then(|mut file| file.read_exact(&mut magic))
+            .is_ok_and(|()| &magic == MAGIC)
+    }
+
+    // Checks the header and the length of the file, and that every record
+    // refers only to records that exist, reading the triangles and the
+    // hierarchy once through, so that a render never reads past a section.
+    // The file is registered with the geometry cache only once it is valid.
+    pub fn open(path: &str) -> Result<PackedMesh, String> {
+        let m = |e: io::Error| format!("{}: {}", path, e);
+        let invalid = || format!("{}: not a valid packed mesh", path);
+        let mut file = File::open(path).map_err(m)?;
+        let mut header = [0; HEADER_SIZE as usize];
+        file.read_exact(&mut header).map_err(m)?;
+        if &header[..8] != MAGIC {
+            return Err(format!("{}: not a packed mesh", path));
+        }
+        let length = file.metadata().map_err(m)?.len();
+        let mut counts = [0; 6];
+        let mut starts = [0; 6];
+        let mut start = HEADER_SIZE;
+        for (section, words) in header[8..].chunks_exact(8).enumerate() {
+            let count = u64::from_le_bytes(words.try_into().unwrap());
+            counts[section] = usize::try_from(count).map_err(|_| invalid())?;
+            starts[section] = start;
+            start = count
+                .checked_mul(8 * RECORD_WORDS[section])
+                .and_then(|size| start.checked_add(size))
+                .ok_or_else(invalid)?;
+        }
+        if start != length
+            || counts[TRIANGLES] == 0
+            || counts[NODES] == 0
+            || counts[AREAS] != counts[TRIANGLES]
+            || counts[INDICES] != counts[TRIANGLES]
+        {
+            return Err(invalid());
+        }
+        let mut reader = BufReader::new(file);
+        match check_references(&mut reader, &counts, &starts) {
+            Ok(true) => {}
+            Ok(false) => return Err(invalid()),
+            Err(e) => return Err(m(e)),
+        }
+        let (file, registered_length) = cache::geometry().register(path)?;
+        if registered_length != length {
+            return Err(format!("{}: changed while it was opened", path));
+        }
+        Ok(PackedMesh {
+            file,
+            counts,
+            starts,
+        })
+    }
+
+    pub fn write(
+        path: &str,
+        positions: &[Point3],
+        uvs: &[Point2],
+        triangles: &[Triangle],
+        cumulative_areas: &[Float],
+        bvh: &impl BvhNodes,
+    ) -> Result<(), Error> {
+        let m = Error::io(path);
+        let file = File::create(path).map_err(m)?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(MAGIC).map_err(m)?;
+        let mut put = |words: &[u64]| {
+            words
+                .iter()
+                .try_for_each(|word| writer.write_all(&word.to_le_bytes()))
+                .map_err(m)
+        };
+        let counts = [
+            positions.len(),
+            uvs.len(),
+            triangles.len(),
+            cumulative_areas.len(),
+            bvh.node_count(),
+            triangles.len(),
+        ];
+        put(&counts.map(|count| count as u64))?;
+        for p in positions {
+            put(&[p.x, p.y, p.z].map(float_word))?;
+        }
+        for uv in uvs {
+            put(&[uv.x, uv.y].map(float_word))?;
+        }
+        for triangle in triangles {
+            put(&triangle.positions.map(|i| i as u64))?;
+            put(&match triangle.uvs {
+                Some(uvs) => uvs.map(|i| i as u64),
+                None => [NO_UV; 3],
+            })?;
+        }
+        for &area in cumulative_areas {
+            put(&[float_word(area)])?;
+        }
+        for i in 0..bvh.node_count() {
+            let BvhNode {
+                bounds,
+                offset,
+                count,
+                axis,
+            } = bvh.node(i);
+            let (min, max) = (bounds.min, bounds.max);
+            put(&[min.x, min.y, min.z, max.x, max.y, max.z].map(float_word))?;
+            put(&[offset, count, axis].map(|n| n as u64))?;
+        }
+        for i in 0..triangles.len() {
+            put(&[bvh.primitive(i) as u64])?;
+        }
+        writer.flush().map_err(m)
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.counts[TRIANGLES]
+    }
+
+    pub fn position(&self, index: usize) -> Point3 {
+        let [x, y, z] = self.record(POSITIONS, index).map(word_float);
+        Point3::new(x, y, z)
+    }
+
+    pub fn uv(&self, index: usize) -> Point2 {
+        let [u, v] = self.record(UVS, index).map(word_float);
+        Point2::new(u, v)
+    }
+
+    pub fn triangle(&self, index: usize) -> Triangle {
+        let [p0, p1, p2, uv0, uv1, uv2] = self.record(TRIANGLES, index);
+        Triangle {
+            positions: [p0, p1, p2].map(|i| i as usize),
+            uvs: (uv0 != NO_UV).then_some([uv0, uv1, uv2].map(|i| i as usize)),
+        }
+    }
+
+    pub fn cumulative_area(&self, index: usize) -> Float {
+        let [area] = self.record(AREAS, index);
+        word_float(area)
+    }
+
+    // Reads the words of a record, whose index `open` has checked.
+    fn record<const N: usize>(&self, section: usize, index: usize) -> [u64; N] {
+        let mut bytes = [[0; 8]; N];
+        let offset = self.starts[section] + (index * 8 * N) as u64;
+        cache::geometry().read(self.file, offset, bytes.as_flattened_mut());
+        bytes.map(u64::from_le_bytes)
+    }
+}
+
+impl BvhNodes for PackedMesh {
+    fn node_count(&self) -> usize {
+        self.counts[NODES]
+    }
+
+    fn node(&self, index: usize) -> BvhNode {
+        let words: [u64; 9] = self.record(NODES, index);
+        let [x0, y0, z0, x1, y1, z1] = [0, 1, 2, 3, 4, 5].map(|i| word_float(words[i]));
+        BvhNode {
+            bounds: Bounds3::new(Point3::new(x0, y0, z0), Point3::new(x1, y1, z1)),
+            offset: words[6] as usize,
+            count: words[7] as usize,
+            axis: words[8] as usize,
+        }
+    }
+
+    fn primitive(&self, position: usize) -> usize {
+        let [index] = self.record(INDICES, position);
+        index as usize
+    }
+}
+
+// Whether the triangles refer to positions and surface coordinates that
+// exist, the leaves of the hierarchy to triangles that exist, and each
+// interior node to children that follow it, no deeper than a traversal can
+// go.
+fn check_references(
+    reader: &mut (impl Read + Seek),
+    counts: &[usize; 6],
+    starts: &[u64; 6],
+) -> io::Result<bool> {
+    reader.seek(SeekFrom::Start(starts[TRIANGLES]))?;
+    for _ in 0..counts[TRIANGLES] {
+        let [p0, p1, p2, uv0, uv1, uv2] = read_words(reader)?;
+        let exists = |indices: [u64; 3], count: usize| indices.iter().all(|i| *i < count as u64);
+        if !exists([p0, p1, p2], counts[POSITIONS])
+            || (uv0 != NO_UV && !exists([uv0, uv1, uv2], counts[UVS]))
+        {
+            return Ok(false);
+        }
+    }
+    reader.seek(SeekFrom::Start(starts[NODES]))?;
+    let node_count = counts[NODES] as u64;
+    let mut depths = vec![0u8; counts[NODES]];
+    for index in 0..counts[NODES] {
+        let words: [u64; 9] = read_words(reader)?;
+        let [offset, count, axis] = [words[6], words[7], words[8]];
+        let depth = depths[index];
+        if count > 0 {
+            if offset
+                .checked_add(count)
+                .is_none_or(|end| end > counts[INDICES] as u64)
+            {
+                return Ok(false);
+            }
+        } else {
+            let first = index as u64 + 1;
+            if depth as usize >= MAX_DEPTH || axis > 2 || first >= offset || offset >= node_count {
+                return Ok(false);
+            }
+            for child in [first, offset] {
+                let child = &mut depths[child as usize];
+                *child = u8::max(*child, depth + 1);
+            }
+        }
+    }
+    for _ in 0..counts[INDICES] {
+        let [index] = read_words(reader)?;
+        if index >= counts[TRIANGLES] as u64 {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn read_words<const N: usize>(reader: &mut impl Read) -> io::Result<[u64; N]> {
+    let mut bytes = [[0; 8]; N];
+    reader.read_exact(bytes.as_flattened_mut())?;
+    Ok(bytes.map(u64::from_le_bytes))
+}
+
+fn float_word(x: Float) -> u64 {
+    widen(x).to_bits()
+}
+
+fn word_float(word: u64) -> Float {
+    f64::from_bits(word) as Float
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::{PackedMesh, HEADER_SIZE, INDICES, NODES, POSITIONS, RECORD_WORDS, TRIANGLES, UVS};
+    use crate::{
+        cache,
+        obj::ObjMesh,
+        ray::Ray,
+        shape::{Shape, TriangleMesh},
+        types::Float,
+        vector::Point3,
+    };
+
+    #[test]
+    fn test_packed_mesh() {
+        // A bumpy grid, with surface coordinates on half of its squares.
+        let n = 8;
+        let mut source = String::from("vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n");
+        for y in 0..=n {
+            for x in 0..=n {
+                let z = ((x * 7 + y * 3) % 5) as Float * 0.1;
+                source += &format!("v {} {} {}\n", x, y, z);
+            }
+        }
+        for y in 0..n {
+            for x in 0..n {
+                let i = y * (n + 1) + x + 1;
+                let (a, b, c, d) = (i, i + 1, i + n + 2, i + n + 1);
+                source += &if (x + y) % 2 == 0 {
+                    format!("f {a}/1 {b}/2 {c}/3 {d}/4\n")
+                } else {
+                    format!("f {a} {b} {c} {d}\n")
+                };
+            }
+        }
+        let mesh = TriangleMesh::from_obj(ObjMesh::parse(&source).unwrap()).unwrap();
+        let path = env::temp_dir().join("mmlt-test-packed-mesh.bin");
+        let path = path.to_str().unwrap();
+        mesh.pack(path).unwrap();
+        assert!(PackedMesh::is_packed(path));
+        let packed = TriangleMesh::open(path).unwrap();
+        assert_eq!(packed.triangle_count(), mesh.triangle_count());
+        assert_eq!(packed.area(), mesh.area());
+        assert_eq!(packed.bounds(), mesh.bounds());
+        assert!(packed.pack(path).is_err());
+
+        // The packed mesh finds what the mesh in memory does.
+        for i in 0..100 {
+            let x = (i as Float * 0.618034).fract() * (n - 2) as Float + 1.0;
+            let y = (i as Float * 0.414214).fract() * (n - 2) as Float + 1.0;
+            let origin = Point3::new(4.0, 4.0, 5.0);
+            let ray = Ray::new(origin, Point3::new(x, y, 0.2) - origin);
+            let expected = mesh.intersect(ray).unwrap();
+            let geometry = packed.intersect(ray).unwrap();
+            assert_eq!(geometry, expected);
+            let leaving = Ray::leaving(&geometry, ray.direction);
+            assert_eq!(
+                packed.intersect(leaving).map(|g| g.point),
+                mesh.intersect(Ray::leaving(&expected, ray.direction))
+                    .map(|g| g.point)
+            );
+        }
+
+        // Files that are not whole packed meshes are refused, and left
+        // unregistered.
+        let files = cache::geometry().statistics().files;
+        let bytes = fs::read(path).unwrap();
+        fs::write(path, &bytes[..bytes.len() - 8]).unwrap();
+        assert!(PackedMesh::open(path).is_err());
+        // So are those whose records refer to records that do not exist.
+        let word =
+            |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        let count = |section: usize| word(8 + 8 * section);
+        let offset = |section: usize, index: u64, i: u64| {
+            let start: u64 = (0..section).map(|s| count(s) * 8 * RECORD_WORDS[s]).sum();
+            (HEADER_SIZE + start + 8 * (index * RECORD_WORDS[section] + i)) as usize
+        };
+        let leaf = (0..count(NODES))
+            .find(|node| word(offset(NODES, *node, 7)) > 0)
+            .unwrap();
+        let corruptions = [
+            (offset(TRIANGLES, 3, 1), count(POSITIONS)),
+            (offset(TRIANGLES, 0, 3), count(UVS)),
+            (offset(NODES, 0, 6), count(NODES)),
+            (offset(NODES, 0, 6), 0),
+            (offset(NODES, 0, 8), 3),
+            (offset(NODES, leaf, 6), count(INDICES)),
+            (offset(INDICES, 5, 0), count(TRIANGLES)),
+        ];
+        for (offset, value) in corruptions {
+            let mut corrupt = bytes.clone();
+            corrupt[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+            fs::write(path, &corrupt).unwrap();
+            assert!(PackedMesh::open(path).is_err());
+        }
+        assert_eq!(cache::geometry().statistics().files, files);
+        fs::write(path, &source).unwrap();
+        assert!(!PackedMesh::is_packed(path));
+        assert!(PackedMesh::open(path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    num::NonZeroUsize,
+    panic,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    thread,
+    time::Duration,
+};
+
+use crate::progress::ProgressSink;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// Zero stands for one thread per logical core.
+static THREADS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_threads(threads: usize) {
+    THREADS.store(threads, Ordering::Relaxed);
+}
+
+// The number of threads used by every parallel phase of a render.
+pub fn threads() -> usize {
+    match THREADS.load(Ordering::Relaxed) {
+        0 => thread::available_parallelism().map_or(1, NonZeroUsize::get),
+        threads => threads,
+    }
+}
+
+// Runs the work on one thread per input, passing each its index, its input
+// and a counter of the work done by all of them, which is reported to the
+// progress while they run. The results are in the order of the inputs.
+pub fn run<I: Send, T: Send>(
+    inputs: Vec<I>,
+    progress: &dyn ProgressSink,
+    work: impl Fn(usize, I, &AtomicU64) -> T + Sync,
+) -> Vec<T> {
+    let counter = AtomicU64::new(0);
+    thread::scope(|s| {
+        let handles: Vec<_> = inputs
+            .into_iter()
+            .enumerate()
+            .map(|(index, input)| {
+                let (work, counter) = (&work, &counter);
+                s.spawn(move || work(index, input, counter))
+            })
+            .collect();
+        while !handles.iter().all(|h| h.is_finished()) {
+            progress.update(counter.load(Ordering::Relaxed));
+            thread::sleep(POLL_INTERVAL);
+        }
+        progress.update(counter.load(Ordering::Relaxed));
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+            .collect()
+    })
+}
+
+// Evaluates the function at each index in parallel, in contiguous ranges.
+pub fn map<T: Send>(count: usize, f: impl Fn(usize) -> T + Sync) -> Vec<T> {
+    let threads = threads().min(count).max(1);
+    thread::scope(|s| {
+        let handles: Vec<_> = split(count, threads)
+            .into_iter()
+            .map(|range| {
+                let f = &f;
+                s.spawn(move || range.map(f).collect::<Vec<T>>())
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+            .collect()
+    })
+}
+
+// Splits 0..count into the given number of contiguous ranges, whose sizes
+// differ by at most one.
+pub fn split(count: usize, parts: usize) -> Vec<std::ops::Range<usize>> {
+    (0..parts)
+        .map(|i| (i * count / parts)..((i + 1) * count / parts))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{map, split};
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split(10, 3), vec![0..3, 3..6, 6..10]);
+        assert_eq!(split(2, 4), vec![0..0, 0..1, 1..1, 1..2]);
+    }
+
+    #[test]
+    fn test_map() {
+        let squares = map(1000, |i| i * i);
+        assert_eq!(squares.len(), 1000);
+        assert!(squares.iter().enumerate().all(|(i, s)| *s == i * i));
+    }
+}
@@ -1,43 +1,179 @@
 use std::collections::VecDeque;
 
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
 use crate::{
-    bsdf::EvaluationContext,
+    bsdf::{EvaluationContext, Scattering},
     geometry::Geometry,
     interaction::Interaction,
-    ray::Ray,
-    sampler::{MmltSampler, Sampler},
+    lpe::{Event, Label, PathLabels},
+    mnee,
+    pdf::PdfValue,
+    polarization::{Polarization, PolarizedLight},
+    profile::{self, Subsystem},
+    ray::RayKind,
+    rng::RngKind,
+    sampler::{MmltSampler, MutationParameters, MutationType, ReplaySampler, Sampler},
     scene::Scene,
     spectrum::Spectrum,
-    types::PathType,
+    types::{Float, PathType},
     util,
-    vector::Point2,
+    vector::{Point2, Point3},
+    visibility::VisibilityTester,
 };
 
 #[derive(Debug)]
 pub struct Path {
     vertices: Vec<Vertex>,
+    labels: PathLabels,
     technique: Technique,
     pixel_coordinates: Point2,
+    // For a path found by manifold next event estimation, the ratio of the
+    // density of its specular connection to the geometry term it was
+    // evaluated with.
+    manifold_factor: Option<Float>,
+    // Whether the path is of the kind that manifold next event estimation
+    // finds, whichever technique sampled it.
+    manifold_connection: bool,
+    // The ratio of the density of the light vertex as sampled for lighting
+    // the vertex before it, by the technique with one light vertex and at
+    // least two camera vertices, to its density as sampled on its own, by
+    // the others.
+    connection_ratio: Float,
+    // The roughness of the first surface seen from the camera, or one if
+    // the path has none.
+    roughness: Float,
+}
+
+// The storage of the paths of a chain, which is kept from one proposal to
+// the next rather than allocated anew for each.
+#[derive(Default)]
+pub struct PathArena<'a> {
+    camera: VecDeque<Interaction<'a>>,
+    light: VecDeque<Interaction<'a>>,
+    vertices: Vec<Vec<Vertex>>,
+    paths: Vec<Path>,
+    techniques: TechniqueStatistics,
+}
+
+impl PathArena<'_> {
+    pub fn techniques(&self) -> &TechniqueStatistics {
+        &self.techniques
+    }
+}
+
+// How often each technique was sampled, and how often it contributed, by path
+// length and then by the number of camera vertices, for finding connection
+// strategies that are sampled but never find light.
+#[derive(Clone, Debug, Default)]
+pub struct TechniqueStatistics {
+    counts: Vec<Vec<TechniqueCount>>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TechniqueCount {
+    pub sampled: u64,
+    pub contributed: u64,
+}
+
+impl TechniqueStatistics {
+    fn record(&mut self, technique: Technique, contributed: bool) {
+        let path_length = technique.camera + technique.light;
+        if self.counts.len() <= path_length {
+            self.counts.resize_with(path_length + 1, Vec::new);
+        }
+        let counts = &mut self.counts[path_length];
+        if counts.len() <= path_length {
+            counts.resize(path_length + 1, TechniqueCount::default());
+        }
+        let count = &mut counts[technique.camera];
+        count.sampled += 1;
+        count.contributed += contributed as u64;
+    }
+
+    pub fn add(&mut self, other: &TechniqueStatistics) {
+        if self.counts.len() < other.counts.len() {
+            self.counts.resize_with(other.counts.len(), Vec::new);
+        }
+        for (counts, other) in self.counts.iter_mut().zip(&other.counts) {
+            if counts.len() < other.len() {
+                counts.resize(other.len(), TechniqueCount::default());
+            }
+            for (count, other) in counts.iter_mut().zip(other) {
+                count.sampled += other.sampled;
+                count.contributed += other.contributed;
+            }
+        }
+    }
+
+    // The counts of each sampled path length, indexed by the number of
+    // camera vertices, the rest of the vertices being on the light subpath.
+    pub fn by_path_length(&self) -> impl Iterator<Item = (usize, &[TechniqueCount])> {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, counts)| counts.iter().any(|count| count.sampled > 0))
+            .map(|(path_length, counts)| (path_length, counts.as_slice()))
+    }
 }
 
 #[derive(Debug)]
 pub struct Vertex {
+    point: Point3,
     throughput: Spectrum,
-    forward_pdf: Option<f64>,
-    reverse_pdf: Option<f64>,
+    forward_pdf: Density,
+    reverse_pdf: Density,
+    // Whether the vertex scatters by a delta distribution or, at either end
+    // of the path, has a delta position. No strategy can connect at such a
+    // vertex, nor hit such an end point.
+    delta: bool,
 }
 
 impl Vertex {
-    fn weight(&self) -> Option<f64> {
-        if self.forward_pdf.is_none() && self.reverse_pdf.is_none() {
-            return None;
+    fn weight(&self) -> Float {
+        let forward = self.forward_pdf.ratio_value();
+        if forward == 0.0 {
+            return 0.0;
         }
-        let fwd = self.forward_pdf.unwrap_or(1.0);
-        let rev = self.reverse_pdf.unwrap_or(1.0);
-        if fwd == 0.0 {
-            return None;
+        self.reverse_pdf.ratio_value() / forward
+    }
+}
+
+// The density of a vertex, split into the density of its continuous part
+// and the probability of the discrete choices, of a light or of a lobe,
+// made in sampling it.
+#[derive(Copy, Clone, Debug)]
+struct Density {
+    pdf: PdfValue,
+    probability: Float,
+}
+
+impl Density {
+    fn new(pdf: PdfValue, probability: Option<Float>) -> Density {
+        Density {
+            pdf,
+            probability: probability.unwrap_or(1.0),
+        }
+    }
+
+    // Filled in once the next vertex of the path is known.
+    fn unknown() -> Density {
+        Density::new(PdfValue::Finite(0.0), None)
+    }
+
+    fn value(self) -> Float {
+        self.pdf.value() * self.probability
+    }
+
+    // The deltas along a path come in pairs, one for each direction, which
+    // cancel in the ratio of the densities of two strategies, so that a
+    // delta counts as one.
+    fn ratio_value(self) -> Float {
+        match self.pdf {
+            PdfValue::Delta => 1.0,
+            PdfValue::Finite(p) => p * self.probability,
         }
-        Some(rev / fwd)
     }
 }
 
@@ -49,13 +185,35 @@ pub struct Technique {
 
 impl Technique {
     pub fn sample(path_length: usize, sampler: &mut impl Sampler) -> Technique {
-        let end = path_length as f64 + 1.0;
+        let end = path_length as Float + 1.0;
         let r = sampler.sample(0.0..end);
         let camera = r.floor() as usize;
         let light = path_length - camera;
         Technique::new(camera, light)
     }
 
+    // Moves the technique sample of the current state into a different,
+    // uniformly chosen technique, keeping its offset within the technique
+    // and every other primary sample. The proposal is symmetric, and gives
+    // chains a way to switch strategies without disturbing the path.
+    pub fn mutate(
+        path_length: usize,
+        sampler: &mut MmltSampler,
+        rng: &mut impl Rng,
+    ) -> MutationType {
+        let mut values = sampler.values();
+        if values.is_empty() {
+            return MutationType::SmallStep;
+        }
+        let technique_count = path_length + 1;
+        let r = values[TECHNIQUE_STREAM] * technique_count as Float;
+        let technique = r.floor() as usize;
+        let offset = r - r.floor();
+        let technique = (technique + rng.gen_range(1..technique_count)) % technique_count;
+        values[TECHNIQUE_STREAM] = (technique as Float + offset) / technique_count as Float;
+        sampler.propose(&values)
+    }
+
     pub fn new(camera: usize, light: usize) -> Technique {
         Technique { camera, light }
     }
@@ -69,19 +227,43 @@ impl Technique {
     }
 }
 
+// A path with the position and label of each of its vertices, from the
+// camera to the light.
+pub struct PathTrace {
+    pub contribution: Contribution,
+    pub vertices: Vec<(Point3, Label)>,
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct Contribution {
-    pub scalar: f64,
+    pub scalar: Float,
     pub spectrum: Spectrum,
     pub pixel_coordinates: Point2,
+    pub path_length: usize,
+    pub labels: PathLabels,
+    pub roughness: Float,
 }
 
 impl Contribution {
+    pub fn new(spectrum: Spectrum, pixel_coordinates: Point2, path_length: usize) -> Contribution {
+        Contribution {
+            scalar: spectrum.luminance(),
+            spectrum,
+            pixel_coordinates,
+            path_length,
+            labels: PathLabels::new(),
+            roughness: 1.0,
+        }
+    }
+
     pub fn empty() -> Contribution {
         Contribution {
             scalar: 0.0,
             spectrum: Spectrum::black(),
             pixel_coordinates: Point2::new(0.0, 0.0),
+            path_length: 0,
+            labels: PathLabels::new(),
+            roughness: 1.0,
         }
     }
 
@@ -89,18 +271,49 @@ impl Contribution {
         self.scalar == 0.0
     }
 
+    pub fn scale(self, factor: Float) -> Contribution {
+        Contribution {
+            labels: self.labels,
+            roughness: self.roughness,
+            ..Contribution::new(
+                self.spectrum * factor,
+                self.pixel_coordinates,
+                self.path_length,
+            )
+        }
+    }
+
+    // Sums two contributions to the same pixel, keeping the path length,
+    // labels and roughness of the larger.
+    pub fn combine(a: Contribution, b: Contribution) -> Contribution {
+        if a.is_empty() {
+            return b;
+        }
+        if b.is_empty() {
+            return a;
+        }
+        let larger = if a.scalar >= b.scalar { a } else { b };
+        Contribution {
+            labels: larger.labels,
+            roughness: larger.roughness,
+            ..Contribution::new(
+                a.spectrum + b.spectrum,
+                a.pixel_coordinates,
+                larger.path_length,
+            )
+        }
+    }
+
+    // The correction is the ratio of the reverse and forward proposal
+    // densities, which is one for symmetric mutations.
     pub fn acceptance(
         current_contribution: Contribution,
         proposal_contribution: Contribution,
-    ) -> f64 {
+        correction: Float,
+    ) -> Float {
         if current_contribution.scalar > 0.0 {
-            f64::max(
-                f64::min(
-                    1.0,
-                    proposal_contribution.scalar / current_contribution.scalar,
-                ),
-                0.0,
-            )
+            (correction * proposal_contribution.scalar / current_contribution.scalar)
+                .clamp(0.0, 1.0)
         } else {
             1.0
         }
@@ -112,223 +325,629 @@ const LIGHT_STREAM: usize = 1;
 const CAMERA_STREAM: usize = 2;
 const STREAM_COUNT: usize = 3;
 
+// The mutation parameters of a scene, with the stream scales named after
+// the samples they apply to.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MutationConfig {
+    pub sigma: Option<Float>,
+    pub large_step_probability: Option<Float>,
+    pub stream_scales: Option<StreamScalesConfig>,
+    pub stratify_film: Option<bool>,
+    pub stratify_techniques: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StreamScalesConfig {
+    pub technique: Option<Float>,
+    pub light: Option<Float>,
+    pub camera: Option<Float>,
+}
+
+impl MutationConfig {
+    pub fn configure(&self) -> MutationParameters {
+        let defaults = MutationParameters::default();
+        let mut stream_scales = vec![1.0; STREAM_COUNT];
+        if let Some(scales) = &self.stream_scales {
+            for (index, scale) in [
+                (TECHNIQUE_STREAM, scales.technique),
+                (LIGHT_STREAM, scales.light),
+                (CAMERA_STREAM, scales.camera),
+            ] {
+                stream_scales[index] = scale.unwrap_or(1.0);
+            }
+        }
+        let mut parameters = MutationParameters {
+            large_step_probability: self
+                .large_step_probability
+                .unwrap_or(defaults.large_step_probability),
+            sigma: self.sigma.unwrap_or(defaults.sigma),
+            stream_scales,
+            ..defaults
+        };
+        Path::stratify_film(&mut parameters, self.stratify_film.unwrap_or(false));
+        Path::stratify_techniques(&mut parameters, self.stratify_techniques.unwrap_or(false));
+        parameters
+    }
+}
+
 impl<'a> Path {
-    pub fn sampler() -> MmltSampler {
-        MmltSampler::new(STREAM_COUNT)
+    pub fn sampler(parameters: &MutationParameters, kind: RngKind) -> MmltSampler {
+        MmltSampler::new(STREAM_COUNT, parameters, kind)
+    }
+
+    // Large steps cover the film more evenly early in a render when the film
+    // positions of camera subpaths, the first two samples of the camera
+    // stream, are stratified.
+    pub fn stratify_film(parameters: &mut MutationParameters, stratify: bool) {
+        parameters.stratified_stream = stratify.then_some(CAMERA_STREAM);
+    }
+
+    // Large steps likewise exercise every technique evenly, rather than some
+    // more than others by chance, when the technique sample is stratified.
+    pub fn stratify_techniques(parameters: &mut MutationParameters, stratify: bool) {
+        parameters.stratified_sample = stratify.then_some(TECHNIQUE_STREAM);
+    }
+
+    pub fn seeded_sampler(
+        parameters: &MutationParameters,
+        kind: RngKind,
+        seed: u64,
+    ) -> MmltSampler {
+        MmltSampler::seeded(STREAM_COUNT, parameters, kind, seed)
+    }
+
+    // A caustic perturbation moves only the direction in which the light
+    // emits, leaving the light, the point on it, every bounce, the camera
+    // subpath and the technique in place, so that paths seen through
+    // specular surfaces can be explored without losing their structure.
+    pub fn perturb_emission(sampler: &mut MmltSampler) -> MutationType {
+        sampler.perturb()
+    }
+
+    pub fn replay_sampler(values: Vec<Float>) -> ReplaySampler {
+        ReplaySampler::new(STREAM_COUNT, values)
     }
 
     pub fn contribute(
-        scene: &Scene,
+        scene: &'a Scene,
         sampler: &mut impl Sampler,
         path_length: usize,
+        arena: &mut PathArena<'a>,
     ) -> Contribution {
-        if let Some(path) = Path::generate(scene, sampler, path_length) {
-            path.contribution()
-        } else {
-            Contribution::empty()
+        let technique = Path::generate(scene, sampler, path_length, arena);
+        let mut contribution = Contribution::empty();
+        for path in arena.paths.drain(..) {
+            // With manifold next event estimation, the paths it can find are
+            // left to it alone rather than weighted against the other
+            // techniques.
+            let counted = !scene.manifold_next_event_estimation
+                || path.manifold_factor.is_some() == path.manifold_connection;
+            // A debugged pixel keeps only the paths through it, and those
+            // started from the camera, which samples only the pixel, are
+            // scaled to match the density of a full render.
+            let scale = match &scene.debug_pixel {
+                Some(debug) if debug.contains(path.pixel_coordinates) => {
+                    debug.log(&path.describe(counted));
+                    if path.technique.camera >= 2 {
+                        Some(1.0 / debug.camera_density())
+                    } else {
+                        Some(1.0)
+                    }
+                }
+                Some(_) => None,
+                None => Some(1.0),
+            };
+            if let Some(scale) = scale.filter(|_| counted) {
+                let mut path_contribution = path.contribution();
+                if scale != 1.0 {
+                    path_contribution = path_contribution.scale(scale);
+                }
+                contribution = Contribution::combine(contribution, path_contribution);
+            }
+            let mut vertices = path.vertices;
+            vertices.clear();
+            arena.vertices.push(vertices);
         }
+        arena.techniques.record(technique, !contribution.is_empty());
+        contribution
     }
 
-    pub fn generate(scene: &Scene, sampler: &mut impl Sampler, path_length: usize) -> Option<Path> {
+    // The counted paths that a state generates, with their vertices, for
+    // exporting them.
+    pub fn trace_paths(
+        scene: &'a Scene,
+        sampler: &mut impl Sampler,
+        path_length: usize,
+        arena: &mut PathArena<'a>,
+    ) -> Vec<PathTrace> {
+        Path::generate(scene, sampler, path_length, arena);
+        let mut traces = Vec::new();
+        for path in arena.paths.drain(..) {
+            let counted = !scene.manifold_next_event_estimation
+                || path.manifold_factor.is_some() == path.manifold_connection;
+            let contribution = path.contribution();
+            if counted && !contribution.is_empty() {
+                traces.push(PathTrace {
+                    contribution,
+                    vertices: path
+                        .vertices
+                        .iter()
+                        .zip(path.labels.iter())
+                        .map(|(vertex, label)| (vertex.point, label))
+                        .collect(),
+                });
+            }
+            let mut vertices = path.vertices;
+            vertices.clear();
+            arena.vertices.push(vertices);
+        }
+        traces
+    }
+
+    // The contribution of the state given by its primary sample values, with
+    // a description of each path it generates, for reproducing a recorded
+    // proposal.
+    pub fn replay(
+        scene: &'a Scene,
+        values: &[Float],
+        path_length: usize,
+        arena: &mut PathArena<'a>,
+    ) -> (Contribution, String) {
+        let mut sampler = Path::replay_sampler(values.to_vec());
+        let contribution = Path::contribute(scene, &mut sampler, path_length, arena);
+        let mut sampler = Path::replay_sampler(values.to_vec());
+        Path::generate(scene, &mut sampler, path_length, arena);
+        let mut record = String::new();
+        for path in arena.paths.drain(..) {
+            let counted = !scene.manifold_next_event_estimation
+                || path.manifold_factor.is_some() == path.manifold_connection;
+            record.push_str(&path.describe(counted));
+            let mut vertices = path.vertices;
+            vertices.clear();
+            arena.vertices.push(vertices);
+        }
+        (contribution, record)
+    }
+
+    // The paths sampled by a technique, into the arena: at most one, except
+    // that manifold next event estimation may add a second. Gives the
+    // technique.
+    pub fn generate(
+        scene: &'a Scene,
+        sampler: &mut impl Sampler,
+        path_length: usize,
+        arena: &mut PathArena<'a>,
+    ) -> Technique {
         sampler.start_stream(TECHNIQUE_STREAM);
         let technique = Technique::sample(path_length, sampler);
-        if technique.camera == 0 {
-            Path::connect_full_light_path(scene, sampler, technique)
+        arena.camera.clear();
+        arena.light.clear();
+        if scene.manifold_next_event_estimation && technique.camera >= 2 && technique.light == 2 {
+            Path::connect_camera_subpath_to_light_subpath_with_manifold(
+                scene, sampler, technique, arena,
+            );
+            return technique;
+        }
+        let path = if technique.camera == 0 {
+            Path::connect_full_light_path(scene, sampler, technique, arena)
         } else if technique.camera == 1 {
             if technique.light == 1 {
-                Path::connect_camera_to_light(scene, sampler, technique)
+                Path::connect_camera_to_light(scene, sampler, technique, arena)
             } else {
-                Path::connect_camera_to_light_subpath(scene, sampler, technique)
+                Path::connect_camera_to_light_subpath(scene, sampler, technique, arena)
             }
         } else {
             if technique.light == 0 {
-                Path::connect_full_camera_path(scene, sampler, technique)
+                Path::connect_full_camera_path(scene, sampler, technique, arena)
             } else if technique.light == 1 {
-                Path::connect_camera_subpath_to_light(scene, sampler, technique)
+                Path::connect_camera_subpath_to_light(scene, sampler, technique, arena)
             } else {
-                Path::connect_camera_subpath_to_light_subpath(scene, sampler, technique)
+                Path::connect_camera_subpath_to_light_subpath(scene, sampler, technique, arena)
             }
-        }
+        };
+        arena.paths.extend(path);
+        technique
     }
 
     fn connect_camera_to_light(
-        scene: &Scene,
+        scene: &'a Scene,
         sampler: &mut impl Sampler,
         technique: Technique,
+        arena: &mut PathArena<'a>,
     ) -> Option<Path> {
-        sampler.start_stream(CAMERA_STREAM);
-        let sampled_camera_interaction = scene.camera.sample_interaction(sampler);
         sampler.start_stream(LIGHT_STREAM);
         let light = scene.sample_light(sampler);
         let mut light_interaction = light.sample_interaction(sampler);
-        let ray_direction =
-            sampled_camera_interaction.geometry().point - light_interaction.geometry().point;
-        let ray = Ray::new(light_interaction.geometry().point, ray_direction);
-        let camera_interaction = scene.intersect(ray).filter(|i| i.is_camera())?;
+        let light_point = light_interaction.geometry().point;
+        sampler.start_stream(CAMERA_STREAM);
+        let camera_point = scene.camera.sample_point(light_point, sampler);
+        let camera_interaction = VisibilityTester::new(
+            scene,
+            light_interaction.geometry(),
+            camera_point,
+            RayKind::Camera,
+        )
+        .test(scene.camera.id())?;
         light_interaction.set_direction(-camera_interaction.geometry().direction);
-        let mut interactions: VecDeque<Interaction> = VecDeque::new();
-        interactions.push_back(camera_interaction);
-        interactions.push_back(light_interaction);
-        Path::connect(&mut interactions, technique)
+        arena.camera.push_back(camera_interaction);
+        arena.camera.push_back(light_interaction);
+        Path::connect(&arena.camera, technique, &mut arena.vertices)
     }
 
     fn connect_full_light_path(
-        scene: &Scene,
+        scene: &'a Scene,
         sampler: &mut impl Sampler,
         technique: Technique,
+        arena: &mut PathArena<'a>,
     ) -> Option<Path> {
         sampler.start_stream(LIGHT_STREAM);
         let light = scene.sample_light(sampler);
         let light_interaction = light.sample_interaction(sampler);
-        let mut interactions = Path::trace(
+        Path::trace(
             scene,
             sampler,
             light_interaction,
-            technique.light,
+            technique,
             PathType::Light,
+            &mut arena.light,
         )?;
-        interactions.front().filter(|i| i.is_camera())?;
-        Path::connect(&mut interactions, technique)
+        arena.light.front().filter(|i| i.is_camera())?;
+        arena
+            .light
+            .get(1)
+            .filter(|i| i.is_visible(RayKind::Camera))?;
+        Path::connect(&arena.light, technique, &mut arena.vertices)
     }
 
     fn connect_full_camera_path(
-        scene: &Scene,
+        scene: &'a Scene,
         sampler: &mut impl Sampler,
         technique: Technique,
+        arena: &mut PathArena<'a>,
     ) -> Option<Path> {
         sampler.start_stream(CAMERA_STREAM);
         let camera_interaction = scene.camera.sample_interaction(sampler);
-        let mut interactions = Path::trace(
+        Path::trace(
             scene,
             sampler,
             camera_interaction,
-            technique.camera,
+            technique,
             PathType::Camera,
+            &mut arena.camera,
         )?;
-        interactions.back().filter(|i| i.is_light())?;
-        Path::connect(&mut interactions, technique)
+        arena.camera.back().filter(|i| i.is_light())?;
+        Path::connect(&arena.camera, technique, &mut arena.vertices)
     }
 
     fn connect_camera_to_light_subpath(
-        scene: &Scene,
+        scene: &'a Scene,
         sampler: &mut impl Sampler,
         technique: Technique,
+        arena: &mut PathArena<'a>,
     ) -> Option<Path> {
         sampler.start_stream(LIGHT_STREAM);
         let light = scene.sample_light(sampler);
         let light_interaction = light.sample_interaction(sampler);
-        let mut interactions = Path::trace(
+        Path::trace(
             scene,
             sampler,
             light_interaction,
-            technique.light,
+            technique,
             PathType::Light,
+            &mut arena.light,
         )?;
-        let last = interactions.front().filter(|i| i.is_object())?;
+        let last = arena
+            .light
+            .front()
+            .filter(|i| i.is_object() && i.is_visible(RayKind::Camera))?;
         sampler.start_stream(CAMERA_STREAM);
-        let sampled_camera_interaction = scene.camera.sample_interaction(sampler);
-        let ray = Ray::new(
-            last.geometry().point,
-            sampled_camera_interaction.geometry().point - last.geometry().point,
-        );
-        let camera_interaction = scene.intersect(ray).filter(|i| i.is_camera())?;
-        interactions.push_front(camera_interaction);
-        Path::connect(&mut interactions, technique)
+        let last_point = last.geometry().point;
+        let camera_point = scene.camera.sample_point(last_point, sampler);
+        let camera_interaction =
+            VisibilityTester::new(scene, last.geometry(), camera_point, RayKind::Camera)
+                .test(scene.camera.id())?;
+        arena.light.push_front(camera_interaction);
+        Path::connect(&arena.light, technique, &mut arena.vertices)
     }
 
     fn connect_camera_subpath_to_light(
-        scene: &Scene,
+        scene: &'a Scene,
         sampler: &mut impl Sampler,
         technique: Technique,
+        arena: &mut PathArena<'a>,
     ) -> Option<Path> {
         sampler.start_stream(CAMERA_STREAM);
         let camera_interaction = scene.camera.sample_interaction(sampler);
-        let mut interactions = Path::trace(
+        Path::trace(
             scene,
             sampler,
             camera_interaction,
-            technique.camera,
+            technique,
             PathType::Camera,
+            &mut arena.camera,
         )?;
-        let last = interactions.back().filter(|i| i.is_object())?;
+        let last = arena.camera.back().filter(|i| i.is_object())?;
         sampler.start_stream(LIGHT_STREAM);
         let light = scene.sample_light(sampler);
-        let sampled_light_interaction = light.sample_interaction(sampler);
-        let ray = Ray::new(
-            last.geometry().point,
-            sampled_light_interaction.geometry().point - last.geometry().point,
-        );
-        let light_interaction = scene.intersect(ray).filter(|i| i.is_light())?;
-        interactions.push_back(light_interaction);
-        Path::connect(&mut interactions, technique)
+        let light_point = light.sample_point_from(last.geometry().point, sampler);
+        let light_interaction =
+            VisibilityTester::new(scene, last.geometry(), light_point, RayKind::Shadow)
+                .test(light.id())?;
+        arena.camera.push_back(light_interaction);
+        Path::connect(&arena.camera, technique, &mut arena.vertices)
     }
 
     fn connect_camera_subpath_to_light_subpath(
-        scene: &Scene,
+        scene: &'a Scene,
         sampler: &mut impl Sampler,
         technique: Technique,
+        arena: &mut PathArena<'a>,
     ) -> Option<Path> {
         sampler.start_stream(CAMERA_STREAM);
         let camera_interaction = scene.camera.sample_interaction(sampler);
-        let camera_interactions = Path::trace(
+        Path::trace(
             scene,
             sampler,
             camera_interaction,
-            technique.camera,
+            technique,
             PathType::Camera,
+            &mut arena.camera,
         )?;
         sampler.start_stream(LIGHT_STREAM);
         let light = scene.sample_light(sampler);
         let light_interaction = light.sample_interaction(sampler);
-        let light_interactions = Path::trace(
+        Path::trace(
             scene,
             sampler,
             light_interaction,
-            technique.light,
+            technique,
             PathType::Light,
+            &mut arena.light,
         )?;
-        let camera_last = camera_interactions.back().filter(|i| i.is_object())?;
-        let light_last = light_interactions.front().filter(|i| i.is_object())?;
-        let ray = Ray::new(
-            camera_last.geometry().point,
-            light_last.geometry().point - camera_last.geometry().point,
-        );
-        scene.intersect(ray).filter(|i| i.id() == light_last.id())?;
-        let mut interactions = camera_interactions;
-        interactions.extend(light_interactions);
-        Path::connect(&mut interactions, technique)
+        let camera_last = arena.camera.back().filter(|i| i.is_object())?;
+        let light_last = arena.light.front().filter(|i| i.is_object())?;
+        VisibilityTester::new(
+            scene,
+            camera_last.geometry(),
+            light_last.geometry().point,
+            RayKind::Indirect,
+        )
+        .test(light_last.id())?;
+        arena.camera.extend(arena.light.drain(..));
+        Path::connect(&arena.camera, technique, &mut arena.vertices)
     }
 
+    // Traces a subpath into the given stack, in the order of the full path.
     fn trace(
         scene: &'a Scene,
         sampler: &mut impl Sampler,
         interaction: Interaction<'a>,
-        length: usize,
+        technique: Technique,
         path_type: PathType,
-    ) -> Option<VecDeque<Interaction<'a>>> {
-        let mut stack: VecDeque<Interaction<'a>> = VecDeque::new();
+        stack: &mut VecDeque<Interaction<'a>>,
+    ) -> Option<()> {
+        let length = match path_type {
+            PathType::Camera => technique.camera,
+            PathType::Light => technique.light,
+        };
         let mut ray = interaction.initial_ray()?;
         match path_type {
             PathType::Camera => stack.push_back(interaction),
             PathType::Light => stack.push_front(interaction),
         };
-        for _ in 1..length {
-            let interaction = scene.intersect(ray)?;
-            ray = interaction.generate_ray(path_type, sampler)?;
+        // A run of specular bounces longer than the limit, as between two
+        // facing mirrors, ends the subpath rather than the path budget.
+        let mut specular_depth = 0;
+        for index in 1..length {
+            // Surfaces seen directly by the camera keep their sharpness.
+            let path_index = match path_type {
+                PathType::Camera => index,
+                PathType::Light => technique.camera + technique.light - 1 - index,
+            };
+            // The rays leaving or reaching the camera are camera rays, and
+            // all others traced are bounces, even those that reach a light.
+            let kind = match (path_type, path_index) {
+                (PathType::Camera, 1) | (PathType::Light, 0) => RayKind::Camera,
+                _ => RayKind::Indirect,
+            };
+            // A ray that escapes the scene ends the subpath, unless it
+            // reaches the environment.
+            let mut interaction = scene.intersect(ray, kind)?;
+            if let Some(roughness) = scene.regularization.filter(|_| path_index >= 2) {
+                interaction.regularize(roughness);
+            }
+            // The last vertex scatters nowhere, and may well be a light hit
+            // by the camera subpath, which has no BSDF to sample.
+            if index + 1 < length {
+                ray = interaction.generate_ray(path_type, sampler)?;
+                if interaction.is_specular() {
+                    specular_depth += 1;
+                    if scene
+                        .max_specular_depth
+                        .is_some_and(|max| specular_depth > max)
+                    {
+                        return None;
+                    }
+                } else {
+                    specular_depth = 0;
+                }
+            }
             match path_type {
                 PathType::Camera => stack.push_back(interaction),
                 PathType::Light => stack.push_front(interaction),
             };
         }
-        Some(stack)
+        Some(())
     }
 
-    fn connect(interactions: &mut VecDeque<Interaction>, technique: Technique) -> Option<Path> {
-        let mut vertices: Vec<Vertex> = Vec::new();
+    // Samples the technique as usual and, from the same camera subpath and
+    // point on the light, connects through a refractive interface.
+    fn connect_camera_subpath_to_light_subpath_with_manifold(
+        scene: &'a Scene,
+        sampler: &mut impl Sampler,
+        technique: Technique,
+        arena: &mut PathArena<'a>,
+    ) {
+        sampler.start_stream(CAMERA_STREAM);
+        let camera_interaction = scene.camera.sample_interaction(sampler);
+        if Path::trace(
+            scene,
+            sampler,
+            camera_interaction,
+            technique,
+            PathType::Camera,
+            &mut arena.camera,
+        )
+        .is_none()
+        {
+            return;
+        }
+        sampler.start_stream(LIGHT_STREAM);
+        let light = scene.sample_light(sampler);
+        let light_interaction = light.sample_interaction(sampler);
+        let light_geometry = light_interaction.geometry();
+        let traced = Path::trace(
+            scene,
+            sampler,
+            light_interaction,
+            technique,
+            PathType::Light,
+            &mut arena.light,
+        );
+
+        let camera_last = arena.camera.back().filter(|i| i.is_object());
+        let Some(geometry) = camera_last.map(|i| i.geometry()) else {
+            return;
+        };
+        let point = geometry.point;
+
+        if traced.is_some() {
+            let light_last = arena.light.front().filter(|i| i.is_object());
+            let visible = light_last.is_some_and(|light_last| {
+                let light_point = light_last.geometry().point;
+                VisibilityTester::new(scene, geometry, light_point, RayKind::Indirect)
+                    .test(light_last.id())
+                    .is_some()
+            });
+            if visible {
+                arena.camera.extend(arena.light.drain(..));
+                let path = Path::connect(&arena.camera, technique, &mut arena.vertices);
+                arena.paths.extend(path);
+                arena.camera.truncate(technique.camera);
+            }
+        }
+
+        if let Some(manifold) = mnee::connect(scene, point, light_geometry) {
+            let interface = manifold.interface.geometry();
+            let to_point = point - interface.point;
+            let cosine = util::abs_cos_theta(interface.normal, to_point);
+            arena.camera.push_back(manifold.interface);
+            arena.camera.push_back(manifold.light);
+            // The interface joins the camera subpath, so that it scatters
+            // radiance, as it would when found by the camera.
+            let connection = Technique::new(technique.camera + 1, 1);
+            if let Some(mut path) = Path::connect(&arena.camera, connection, &mut arena.vertices) {
+                // The interface was solved for rather than sampled, and the
+                // geometry term of its connection is replaced by the density
+                // of the whole specular connection.
+                path.vertices[technique.camera].forward_pdf = Density::new(PdfValue::Delta, None);
+                path.manifold_factor =
+                    Some(manifold.solid_angle_density * to_point.dot(to_point) / cosine);
+                // The light was sampled on its own, before the interface
+                // that it lights was found.
+                path.connection_ratio = 1.0;
+                arena.paths.push(path);
+            }
+        }
+    }
+
+    // Whether the path ends in a connection through a single refractive
+    // interface from a non-specular surface, as manifold next event
+    // estimation finds. A dispersive interface, which it cannot solve for,
+    // is left to the other techniques.
+    fn is_manifold_connection(labels: &PathLabels, interactions: &VecDeque<Interaction>) -> bool {
+        let n = labels.len();
+        n >= 4
+            && matches!(labels.get(n - 1), Some(Label::Light))
+            && matches!(
+                labels.get(n - 2),
+                Some(Label::Surface(Event::Transmission, Scattering::Specular))
+            )
+            && matches!(
+                labels.get(n - 3),
+                Some(Label::Surface(_, Scattering::Diffuse))
+            )
+            && match &interactions[n - 2] {
+                Interaction::Object(interface) => {
+                    let wo = interactions[n - 3].geometry().point - interface.geometry.point;
+                    interface.get_bsdf().transmit(wo).is_some()
+                }
+                _ => false,
+            }
+    }
+
+    // The factor by which polarization changes the throughput of the path
+    // in each channel. Light leaves the light unpolarized, and only the
+    // specular vertices keep its polarization as it travels to the camera.
+    fn polarization_factor(
+        interactions: &VecDeque<Interaction>,
+        vertices: &[Vertex],
+        polarization: &Polarization,
+    ) -> Spectrum {
+        let mut light = PolarizedLight::unpolarized();
+        for index in (1..interactions.len() - 1).rev() {
+            let Interaction::Object(object_interaction) = &interactions[index] else {
+                light.depolarize();
+                continue;
+            };
+            let point = object_interaction.geometry.point;
+            let wo = interactions[index - 1].geometry().point - point;
+            let wi = interactions[index + 1].geometry().point - point;
+            let mueller = object_interaction.get_bsdf().mueller(wo, wi);
+            match mueller.filter(|_| vertices[index].delta) {
+                Some(mueller) => light.scatter(-wi, object_interaction.geometry.normal, &mueller),
+                None => light.depolarize(),
+            }
+        }
+        let direction = interactions[0].geometry().point - interactions[1].geometry().point;
+        light.measure(direction, polarization.polarizer)
+    }
+
+    // Connects the interactions into a path, whose vertices are stored in
+    // one of the given free vectors, if any.
+    fn connect(
+        interactions: &VecDeque<Interaction>,
+        technique: Technique,
+        free_vertices: &mut Vec<Vec<Vertex>>,
+    ) -> Option<Path> {
+        let _timer = profile::time(Subsystem::PathConnection);
+        // A light lights only the objects linked to it, whichever technique
+        // the path is sampled with.
+        let lit = interactions.len().checked_sub(2).map(|i| &interactions[i]);
+        if let (Some(Interaction::Object(object)), Some(Interaction::Light(light))) =
+            (lit, interactions.back())
+        {
+            if !light.light.illuminates(object.object.id()) {
+                return None;
+            }
+        }
+        // Shadow catchers and holdouts seen by the camera are left for the
+        // photograph.
+        if interactions.front().is_some_and(Interaction::is_camera)
+            && interactions
+                .get(1)
+                .is_some_and(|i| i.is_shadow_catcher() || i.is_holdout())
+        {
+            return None;
+        }
+        let mut vertices = free_vertices.pop().unwrap_or_default();
+        let mut labels = PathLabels::new();
+        let mut roughness = 1.0;
         let mut pixel_coordinates: Option<Point2> = None;
-        let mut area_pdf: Option<f64> = None;
+        let mut area_pdf = PdfValue::Delta;
         let mut previous_geometry: Option<Geometry> = None;
-        let mut previous_object_sampling_pdf: Option<f64> = None;
+        let mut previous_object_sampling_pdf: Option<Float> = None;
+        let mut connection_ratio = 1.0;
         for (index, interaction) in interactions.iter().enumerate() {
             let next_geometry = interactions.get(index + 1).map(Interaction::geometry);
             match interaction {
@@ -336,28 +955,39 @@ impl<'a> Path {
                     pixel_coordinates = Some(camera_interaction.pixel_coordinates);
                     let point = camera_interaction.geometry.point;
                     let direction = next_geometry?.point - point;
-                    let importance = camera_interaction.camera.importance(point, direction);
-                    let normal = camera_interaction.geometry.normal;
+                    let camera_geometry = &camera_interaction.geometry;
+                    let importance = camera_interaction
+                        .camera
+                        .importance(camera_geometry, direction);
+                    let normal = camera_geometry.normal;
                     let next_normal = next_geometry?.normal;
                     let geometry_term = util::geometry_term(direction, normal, next_normal);
                     let throughput = importance * geometry_term;
-                    let positional_pdf = camera_interaction.camera.positional_pdf(point);
-                    let directional_pdf = camera_interaction.camera.directional_pdf(direction);
+                    let positional_pdf = camera_interaction.camera.positional_pdf(camera_geometry);
+                    let directional_pdf = camera_interaction
+                        .camera
+                        .directional_pdf(camera_geometry, direction);
                     area_pdf = directional_pdf
                         .map(|p| p * util::direction_to_area(direction, next_normal));
+                    let delta = positional_pdf.is_delta();
                     let vertex = match technique.path_type(index) {
                         PathType::Camera => Vertex {
+                            point,
                             throughput,
-                            forward_pdf: positional_pdf,
-                            reverse_pdf: None,
+                            forward_pdf: Density::new(positional_pdf, None),
+                            reverse_pdf: Density::unknown(),
+                            delta,
                         },
                         PathType::Light => Vertex {
+                            point,
                             throughput,
-                            forward_pdf: None,
-                            reverse_pdf: positional_pdf,
+                            forward_pdf: Density::unknown(),
+                            reverse_pdf: Density::new(positional_pdf, None),
+                            delta,
                         },
                     };
                     vertices.push(vertex);
+                    labels.push(Label::Camera);
                 }
                 Interaction::Light(light_interaction) => {
                     let point = light_interaction.geometry.point;
@@ -368,42 +998,54 @@ impl<'a> Path {
                     let positional_pdf = light_interaction.light.positional_pdf(point);
                     let directional_pdf =
                         light_interaction.light.directional_pdf(normal, direction);
+                    let delta = positional_pdf.is_delta();
+                    // Sampling the light for lighting the vertex before it
+                    // takes a camera vertex besides that one.
+                    if index >= 2 {
+                        let connection_pdf = light_interaction.light.positional_pdf_from(
+                            previous_geometry?.point,
+                            &light_interaction.geometry,
+                        );
+                        if let (PdfValue::Finite(from), PdfValue::Finite(alone)) =
+                            (connection_pdf, positional_pdf)
+                        {
+                            if alone > 0.0 {
+                                connection_ratio = from / alone;
+                            }
+                        }
+                    }
                     let vertex = match technique.path_type(index) {
                         PathType::Camera => Vertex {
+                            point,
                             throughput,
-                            forward_pdf: area_pdf,
-                            reverse_pdf: sampling_pdf
-                                .and_then(|p1| positional_pdf.map(|p2| p1 * p2)),
+                            forward_pdf: Density::new(area_pdf, None),
+                            reverse_pdf: Density::new(positional_pdf, Some(sampling_pdf)),
+                            delta,
                         },
                         PathType::Light => Vertex {
+                            point,
                             throughput,
-                            forward_pdf: sampling_pdf
-                                .and_then(|p1| positional_pdf.map(|p2| p1 * p2)),
-                            reverse_pdf: area_pdf,
+                            forward_pdf: Density::new(positional_pdf, Some(sampling_pdf)),
+                            reverse_pdf: Density::new(area_pdf, None),
+                            delta,
                         },
                     };
                     vertices.push(vertex);
+                    labels.push(Label::Light);
                     let previous_vertex = &mut vertices[index - 1];
                     let previous_normal = previous_geometry?.normal;
                     let direction_to_area = util::direction_to_area(direction, previous_normal);
                     area_pdf = directional_pdf.map(|p| p * direction_to_area);
                     match technique.path_type(index - 1) {
                         PathType::Camera => {
-                            previous_vertex.reverse_pdf = area_pdf;
+                            previous_vertex.reverse_pdf = Density::new(area_pdf, None);
                         }
                         PathType::Light => {
-                            previous_vertex.forward_pdf = area_pdf;
+                            previous_vertex.forward_pdf = Density::new(area_pdf, None);
                         }
                     }
                 }
                 Interaction::Object(object_interaction) => {
-                    let combine = |area: Option<f64>, sampling: Option<f64>| {
-                        if area.is_some() {
-                            area.map(|a| a * sampling.unwrap_or(1.0))
-                        } else {
-                            sampling
-                        }
-                    };
                     let point = object_interaction.geometry.point;
                     let normal = object_interaction.geometry.normal;
                     let next_normal = next_geometry?.normal;
@@ -414,23 +1056,42 @@ impl<'a> Path {
                         geometry_term,
                         path_type: technique.path_type(index),
                     };
-                    let reflectance = object_interaction.reflectance(wo, wi, context);
-                    let throughput = reflectance * geometry_term;
+                    let transmittance = object_interaction.transmittance(wi);
+                    // A specular vertex takes the value of the lobe that was
+                    // sampled, rather than matching the directions again.
+                    let throughput = match object_interaction.sample {
+                        Some(sample) if sample.is_specular => sample.value.mul(transmittance),
+                        _ => {
+                            let reflectance = object_interaction.reflectance(wo, wi, context);
+                            reflectance.mul(transmittance) * geometry_term
+                        }
+                    };
                     let current_object_sampling_pdf =
                         object_interaction.sampling_pdf(wo, wi, technique.path_type(index));
+                    let delta = object_interaction
+                        .pdf(wo, wi, technique.path_type(index))
+                        .is_delta();
                     let vertex = match technique.path_type(index) {
                         PathType::Camera => Vertex {
+                            point,
                             throughput,
-                            forward_pdf: combine(area_pdf, previous_object_sampling_pdf),
-                            reverse_pdf: None,
+                            forward_pdf: Density::new(area_pdf, previous_object_sampling_pdf),
+                            reverse_pdf: Density::unknown(),
+                            delta,
                         },
                         PathType::Light => Vertex {
+                            point,
                             throughput,
-                            forward_pdf: None,
-                            reverse_pdf: combine(area_pdf, previous_object_sampling_pdf),
+                            forward_pdf: Density::unknown(),
+                            reverse_pdf: Density::new(area_pdf, previous_object_sampling_pdf),
+                            delta,
                         },
                     };
                     vertices.push(vertex);
+                    labels.push(object_interaction.label(wo, wi, context));
+                    if index == 1 {
+                        roughness = object_interaction.get_bsdf().roughness(wo, wi, context);
+                    }
                     let previous_vertex = &mut vertices[index - 1];
                     let previous_normal = previous_geometry?.normal;
                     let previous_directional_pdf = object_interaction.pdf(wo, wi, PathType::Light);
@@ -440,11 +1101,11 @@ impl<'a> Path {
                     match technique.path_type(index - 1) {
                         PathType::Camera => {
                             previous_vertex.reverse_pdf =
-                                combine(previous_area_pdf, current_object_sampling_pdf);
+                                Density::new(previous_area_pdf, current_object_sampling_pdf);
                         }
                         PathType::Light => {
                             previous_vertex.forward_pdf =
-                                combine(previous_area_pdf, current_object_sampling_pdf);
+                                Density::new(previous_area_pdf, current_object_sampling_pdf);
                         }
                     }
                     let next_normal = next_geometry?.normal;
@@ -458,15 +1119,63 @@ impl<'a> Path {
             previous_geometry = Some(interaction.geometry());
         }
 
+        if let Some(Interaction::Camera(camera_interaction)) = interactions.front() {
+            if let Some(polarization) = camera_interaction.camera.polarization() {
+                let factor = Path::polarization_factor(interactions, &vertices, polarization);
+                vertices[0].throughput = vertices[0].throughput.mul(factor);
+            }
+        }
+
         let path = Path {
             vertices,
+            labels,
             technique,
             pixel_coordinates: pixel_coordinates?,
+            manifold_factor: None,
+            manifold_connection: Path::is_manifold_connection(&labels, interactions),
+            connection_ratio,
+            roughness,
         };
 
         Some(path)
     }
 
+    // A record of the path for the log of a debugged pixel, with a line for
+    // the path and one for each vertex, from the camera to the light.
+    fn describe(&self, counted: bool) -> String {
+        let spectrum = |s: Spectrum| format!("({:.6e}, {:.6e}, {:.6e})", s.r, s.g, s.b);
+        let density = |d: Density| match d.pdf {
+            PdfValue::Delta => format!("delta x {:.6e}", d.probability),
+            PdfValue::Finite(p) => format!("{:.6e} x {:.6e}", p, d.probability),
+        };
+        let mut record = format!(
+            "path length {} camera {} light {} pixel ({:.4}, {:.4}) pdf {:.6e} weight {:.6e} contribution {}{}\n",
+            self.vertices.len(),
+            self.technique.camera,
+            self.technique.light,
+            self.pixel_coordinates.x,
+            self.pixel_coordinates.y,
+            self.pdf(),
+            self.weight(),
+            spectrum(self.contribution().spectrum),
+            if counted { "" } else { " (left to manifold next event estimation)" },
+        );
+        for (vertex, label) in self.vertices.iter().zip(self.labels.iter()) {
+            record.push_str(&format!(
+                "  {:?} at ({:.6}, {:.6}, {:.6}) throughput {} forward {} reverse {}{}\n",
+                label,
+                vertex.point.x,
+                vertex.point.y,
+                vertex.point.z,
+                spectrum(vertex.throughput),
+                density(vertex.forward_pdf),
+                density(vertex.reverse_pdf),
+                if vertex.delta { " delta" } else { "" },
+            ));
+        }
+        record
+    }
+
     pub fn contribution(&self) -> Contribution {
         let p = self.pdf();
         if p == 0.0 {
@@ -483,12 +1192,12 @@ impl<'a> Path {
             return Contribution::empty();
         }
 
-        let c = t * w / p;
+        let c = t * w * self.manifold_factor.unwrap_or(1.0) / p;
 
         Contribution {
-            scalar: c.luminance(),
-            spectrum: c,
-            pixel_coordinates: self.pixel_coordinates,
+            labels: self.labels,
+            roughness: self.roughness,
+            ..Contribution::new(c, self.pixel_coordinates, self.vertices.len())
         }
     }
 
@@ -499,43 +1208,142 @@ impl<'a> Path {
             .fold(Spectrum::fill(1.0), |acc, t| acc.mul(t))
     }
 
-    pub fn pdf(&self) -> f64 {
-        self.vertices
+    pub fn pdf(&self) -> Float {
+        let pdf = self
+            .vertices
             .iter()
-            .map(|v| v.forward_pdf.unwrap_or(1.0))
-            .fold(1.0, |a, b| a * b)
+            .map(|v| v.forward_pdf.value())
+            .fold(1.0, |a, b| a * b);
+        pdf * self.connection_scale(self.technique.camera)
+    }
+
+    // The factor by which the density of the strategy with the given number
+    // of camera vertices differs from that of its vertices, for the light
+    // vertex that it samples for lighting the vertex before it.
+    fn connection_scale(&self, camera: usize) -> Float {
+        let n = self.vertices.len();
+        if camera >= 2 && camera + 1 == n {
+            self.connection_ratio
+        } else {
+            1.0
+        }
     }
 
-    pub fn weight(&self) -> f64 {
+    pub fn weight(&self) -> Float {
+        if self.manifold_factor.is_some() {
+            return 1.0;
+        }
+
+        // The strategy with the given number of camera vertices connects
+        // the last camera vertex to the first light vertex, or hits an end
+        // point of the path when either subpath is empty.
+        let n = self.vertices.len();
+        let connectable = |i: usize| i == 0 || i == n - 1 || !self.vertices[i].delta;
+        let possible = |camera: usize| match camera {
+            0 => !self.vertices[0].delta,
+            c if c == n => !self.vertices[n - 1].delta,
+            c => connectable(c - 1) && connectable(c),
+        };
+
         let mut product = 1.0;
         let mut sum = 0.0;
 
-        for vertex in self.vertices[0..self.technique.camera].iter().rev() {
-            if let Some(w) = vertex.weight() {
-                product = product * w;
-                sum = sum + product;
+        for i in (0..self.technique.camera).rev() {
+            product *= self.vertices[i].weight();
+            if possible(i) {
+                sum += product * self.connection_scale(i);
             }
         }
 
         product = 1.0;
 
-        if self.technique.light >= 1 {
-            for vertex in self.vertices[self.technique.camera..].iter() {
-                if let Some(w) = vertex.weight() {
-                    product = product * w;
-                    sum = sum + product;
-                }
+        for i in self.technique.camera..n {
+            product *= self.vertices[i].weight();
+            if possible(i + 1) {
+                sum += product * self.connection_scale(i + 1);
             }
         }
 
-        1.0 / (1.0 + sum)
+        let own = self.connection_scale(self.technique.camera);
+        own / (own + sum)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Contribution, PathType, Technique};
-    use crate::{sampler::test::MockSampler, spectrum::RgbSpectrum, vector::Point2};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{
+        Contribution, Density, Path, PathType, Technique, TechniqueCount, TechniqueStatistics,
+        Vertex, TECHNIQUE_STREAM,
+    };
+    use crate::{
+        lpe::PathLabels,
+        pdf::PdfValue,
+        rng::RngKind,
+        sampler::{test::MockSampler, MutationParameters, MutationType, Sampler},
+        spectrum::{RgbSpectrum, Spectrum},
+        vector::{Point2, Point3},
+    };
+
+    fn vertex(forward: PdfValue, reverse: PdfValue, delta: bool) -> Vertex {
+        Vertex {
+            point: Point3::new(0.0, 0.0, 0.0),
+            throughput: Spectrum::fill(1.0),
+            forward_pdf: Density::new(forward, None),
+            reverse_pdf: Density::new(reverse, None),
+            delta,
+        }
+    }
+
+    fn path(vertices: Vec<Vertex>, technique: Technique) -> Path {
+        Path {
+            vertices,
+            labels: PathLabels::new(),
+            technique,
+            pixel_coordinates: Point2::new(0.0, 0.0),
+            manifold_factor: None,
+            manifold_connection: false,
+            connection_ratio: 1.0,
+            roughness: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_path_weight() {
+        use PdfValue::{Delta, Finite};
+
+        // A pinhole cannot be hit, which leaves connecting to the camera,
+        // connecting to the light and hitting the light.
+        let diffuse = path(
+            vec![
+                vertex(Delta, Finite(0.5), true),
+                vertex(Finite(2.0), Finite(4.0), false),
+                vertex(Finite(1.0), Finite(0.5), false),
+            ],
+            Technique::new(2, 1),
+        );
+        assert_eq!(diffuse.weight(), 1.0 / 3.5);
+        // A light sampled for lighting the surface, at twice the density of
+        // sampling it on its own, weighs the connection to it twice as much.
+        let cone = Path {
+            connection_ratio: 2.0,
+            ..diffuse
+        };
+        assert_eq!(cone.weight(), 2.0 / 4.5);
+        assert_eq!(cone.pdf(), 2.0 * 2.0 * 1.0);
+
+        // Nothing can connect at a mirror, so only hitting the light remains.
+        let specular = path(
+            vec![
+                vertex(Delta, Finite(0.5), true),
+                vertex(Finite(2.0), Delta, true),
+                vertex(Delta, Finite(0.1), false),
+            ],
+            Technique::new(3, 0),
+        );
+        assert_eq!(specular.weight(), 1.0);
+    }
 
     #[test]
     fn test_technique_sample() {
@@ -557,6 +1365,74 @@ mod tests {
         assert_eq!(technique.light, 0);
     }
 
+    #[test]
+    fn test_technique_statistics() {
+        let count = |sampled, contributed| TechniqueCount {
+            sampled,
+            contributed,
+        };
+        let mut statistics = TechniqueStatistics::default();
+        statistics.record(Technique::new(1, 2), true);
+        statistics.record(Technique::new(1, 2), false);
+        statistics.record(Technique::new(0, 3), false);
+        let mut other = TechniqueStatistics::default();
+        other.record(Technique::new(2, 2), true);
+        other.record(Technique::new(1, 2), true);
+        statistics.add(&other);
+        let lengths: Vec<_> = statistics.by_path_length().collect();
+        assert_eq!(lengths.len(), 2);
+        assert_eq!(
+            lengths[0],
+            (3, &[count(1, 0), count(3, 2), count(0, 0), count(0, 0)][..])
+        );
+        assert_eq!(lengths[1].0, 4);
+        assert_eq!(lengths[1].1[2], count(1, 1));
+    }
+
+    #[test]
+    fn test_technique_mutate() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut sampler = Path::sampler(&MutationParameters::default(), RngKind::default());
+        sampler.start_stream(TECHNIQUE_STREAM);
+        let r = sampler.sample(0.0..4.0);
+        for _ in 0..8 {
+            sampler.mutate();
+            assert_eq!(
+                Technique::mutate(3, &mut sampler, &mut rng),
+                MutationType::Proposal
+            );
+            sampler.start_stream(TECHNIQUE_STREAM);
+            let s = sampler.sample(0.0..4.0);
+            assert_ne!(s.floor(), r.floor());
+            assert!((s.fract() - r.fract()).abs() < 1e-9);
+            sampler.reject();
+        }
+    }
+
+    #[test]
+    fn test_stratified_techniques() {
+        let mut parameters = MutationParameters {
+            large_step_probability: 1.0,
+            ..MutationParameters::default()
+        };
+        Path::stratify_techniques(&mut parameters, true);
+        let mut sampler = Path::sampler(&parameters, RngKind::default());
+        // The six techniques of paths of length five are each taken by 100
+        // of 600 large steps, give or take the discrepancy of the sequence.
+        let mut counts = [0usize; 6];
+        for _ in 0..600 {
+            assert_eq!(sampler.mutate(), MutationType::LargeStep);
+            sampler.start_stream(TECHNIQUE_STREAM);
+            counts[Technique::sample(5, &mut sampler).camera] += 1;
+            sampler.accept();
+        }
+        assert!(
+            counts.iter().all(|count| count.abs_diff(100) <= 2),
+            "{:?}",
+            counts
+        );
+    }
+
     #[test]
     fn test_technique_path_type() {
         let technique = Technique::new(2, 2);
@@ -569,20 +1445,12 @@ mod tests {
     #[test]
     fn test_contribution_acceptance() {
         let spectrum1 = RgbSpectrum::fill(0.1);
-        let current = Contribution {
-            scalar: spectrum1.luminance(),
-            spectrum: spectrum1,
-            pixel_coordinates: Point2::new(100.0, 100.0),
-        };
+        let current = Contribution::new(spectrum1, Point2::new(100.0, 100.0), 2);
 
         let spectrum2 = RgbSpectrum::fill(0.05);
-        let proposed = Contribution {
-            scalar: spectrum2.luminance(),
-            spectrum: spectrum2,
-            pixel_coordinates: Point2::new(100.0, 100.0),
-        };
+        let proposed = Contribution::new(spectrum2, Point2::new(100.0, 100.0), 2);
 
-        let a = Contribution::acceptance(current, proposed);
+        let a = Contribution::acceptance(current, proposed, 1.0);
         assert_eq!(a, 0.5);
     }
 }
@@ -1,13 +1,47 @@
 use rand::{distributions::Distribution, Rng};
 
+use crate::types::Float;
+
+// The density of a sampled quantity. A delta distribution, such as that of
+// a specular lobe or of a pinhole, has no finite density, and a vertex it
+// generates cannot be sampled by any strategy that connects to it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PdfValue {
+    Delta,
+    Finite(Float),
+}
+
+impl PdfValue {
+    pub fn is_delta(self) -> bool {
+        self == PdfValue::Delta
+    }
+
+    // The factor the density contributes to the density of a path, where a
+    // delta contributes one, since it cancels with the delta in the
+    // throughput.
+    pub fn value(self) -> Float {
+        match self {
+            PdfValue::Delta => 1.0,
+            PdfValue::Finite(p) => p,
+        }
+    }
+
+    pub fn map(self, f: impl FnOnce(Float) -> Float) -> PdfValue {
+        match self {
+            PdfValue::Delta => PdfValue::Delta,
+            PdfValue::Finite(p) => PdfValue::Finite(f(p)),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Pdf {
-    pdf: Vec<f64>,
-    cdf: Vec<f64>,
+    pdf: Vec<Float>,
+    cdf: Vec<Float>,
 }
 
 impl Pdf {
-    pub fn new(h: &Vec<f64>) -> Pdf {
+    pub fn new(h: &[Float]) -> Pdf {
         let mut pdf = vec![0.0; h.len()];
         let mut cdf = vec![0.0; h.len()];
         cdf[0] = h[0];
@@ -16,30 +50,40 @@ impl Pdf {
         }
         for k in 0..h.len() {
             pdf[k] = h[k] / cdf[cdf.len() - 1];
-            cdf[k] = cdf[k] / cdf[cdf.len() - 1];
+            cdf[k] /= cdf[cdf.len() - 1];
         }
         Pdf { pdf, cdf }
     }
 
-    pub fn value(&self, i: usize) -> f64 {
+    pub fn value(&self, i: usize) -> Float {
         self.pdf[i]
     }
+
+    // Inverts the distribution function at `r`, giving the index it falls
+    // in and where within that index, so that a uniform sample maps
+    // continuously to a sample of the piecewise-constant density.
+    pub fn invert(&self, r: Float) -> (usize, Float) {
+        let k = usize::min(self.cdf.partition_point(|c| *c <= r), self.cdf.len() - 1);
+        let start = if k == 0 { 0.0 } else { self.cdf[k - 1] };
+        let offset = if self.pdf[k] > 0.0 {
+            ((r - start) / self.pdf[k]).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        (k, offset)
+    }
 }
 
 impl Distribution<usize> for Pdf {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> usize {
-        let r = rng.gen_range(0.0..1.0);
-        for k in 0..self.cdf.len() {
-            if r <= self.cdf[k] {
-                return k;
-            }
-        }
-        0
+        self.invert(rng.gen_range(0.0..1.0)).0
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use rand::{distributions::Distribution, thread_rng};
+
     use super::Pdf;
 
     #[test]
@@ -52,4 +96,25 @@ mod tests {
         assert_eq!(pdf.value(3), 0.15);
         assert_eq!(pdf.value(4), 0.05);
     }
+
+    #[test]
+    fn test_pdf_invert() {
+        let pdf = Pdf::new(&[1.0, 0.0, 3.0]);
+        assert_eq!(pdf.invert(0.125), (0, 0.5));
+        assert_eq!(pdf.invert(0.625), (2, 0.5));
+    }
+
+    #[test]
+    fn test_pdf_sample() {
+        let h = vec![0.0, 1.0, 0.0, 3.0];
+        let pdf = Pdf::new(&h);
+        let mut counts = [0; 4];
+        let mut rng = thread_rng();
+        for _ in 0..4000 {
+            counts[pdf.sample(&mut rng)] += 1;
+        }
+        assert_eq!(counts[0], 0);
+        assert_eq!(counts[2], 0);
+        assert!(counts[3] > 2 * counts[1]);
+    }
 }
@@ -0,0 +1,381 @@
+use std::ops::{Add, Div, Mul, Sub};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{camera::AngleUnitConfig, spectrum::Spectrum, types::Float, util, vector::Vector3};
+
+// A Stokes vector (I, Q, U, V) of light travelling along a direction d,
+// in a frame whose x axis is perpendicular to d, with y = d × x. Q is
+// positive for light polarized along x, U for light polarized halfway
+// between x and y, and V = 2 Im(Ex Ey*).
+pub type Stokes = [Float; 4];
+
+// A Mueller matrix, which acts on Stokes vectors in the frame of the
+// s-polarization of an interface: the x axis is normal to the plane of
+// incidence, both before and after scattering.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mueller([[Float; 4]; 4]);
+
+impl Mueller {
+    // An ideal mirror, which reflects both polarizations fully, with the
+    // opposite signs of a perfect conductor.
+    pub fn mirror() -> Mueller {
+        Mueller::reflection(Complex::new(-1.0, 0.0), Complex::new(1.0, 0.0))
+    }
+
+    // The reflection off an interface with the relative complex index of
+    // refraction eta + ik, from the side the cosine is positive on.
+    pub fn fresnel_reflection(cos_theta_i: Float, eta: Float, k: Float) -> Mueller {
+        let (rs, rp, _, _) = fresnel_amplitudes(cos_theta_i, Complex::new(eta, k));
+        Mueller::reflection(rs, rp)
+    }
+
+    // The transmission through an interface between dielectrics with the
+    // relative index of refraction eta, which is taken from the other side
+    // for a negative cosine, as for reflectance.
+    pub fn fresnel_transmission(cos_theta_i: Float, eta: Float) -> Mueller {
+        let (cos_theta_i, eta) = if cos_theta_i < 0.0 {
+            (-cos_theta_i, 1.0 / eta)
+        } else {
+            (cos_theta_i, eta)
+        };
+        let eta = Complex::new(eta, 0.0);
+        let (_, _, ts, tp) = fresnel_amplitudes(cos_theta_i, eta);
+        let sin2_theta_t = (1.0 - util::sqr(cos_theta_i)) / util::sqr(eta.re);
+        if sin2_theta_t >= 1.0 {
+            return Mueller([[0.0; 4]; 4]);
+        }
+        let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+        let factor = eta.re * cos_theta_t / cos_theta_i;
+        let s = factor * ts.norm_sqr();
+        let p = factor * tp.norm_sqr();
+        let c = (s * p).sqrt();
+        Mueller([
+            [(s + p) / 2.0, (s - p) / 2.0, 0.0, 0.0],
+            [(s - p) / 2.0, (s + p) / 2.0, 0.0, 0.0],
+            [0.0, 0.0, c, 0.0],
+            [0.0, 0.0, 0.0, c],
+        ])
+    }
+
+    fn reflection(rs: Complex, rp: Complex) -> Mueller {
+        let s = rs.norm_sqr();
+        let p = rp.norm_sqr();
+        let z = rs * rp.conj();
+        Mueller([
+            [(s + p) / 2.0, (s - p) / 2.0, 0.0, 0.0],
+            [(s - p) / 2.0, (s + p) / 2.0, 0.0, 0.0],
+            [0.0, 0.0, z.re, -z.im],
+            [0.0, 0.0, z.im, z.re],
+        ])
+    }
+
+    // The fraction of unpolarized light that is scattered, which the scalar
+    // Fresnel factors of the BSDFs already account for.
+    pub fn intensity(&self) -> Float {
+        self.0[0][0]
+    }
+
+    fn apply(&self, stokes: Stokes) -> Stokes {
+        let m = &self.0;
+        let row = |i: usize| (0..4).map(|j| m[i][j] * stokes[j]).sum();
+        [row(0), row(1), row(2), row(3)]
+    }
+}
+
+// The polarization of the light along a path, as it travels from the light
+// to the camera, in each channel. The intensity is relative to that of
+// unpolarized transport, whose factors the vertices of the path already
+// carry.
+#[derive(Copy, Clone, Debug)]
+pub struct PolarizedLight {
+    stokes: [Stokes; 3],
+    // The x axis of the frame of the Stokes vectors, once polarized.
+    frame: Option<Vector3>,
+}
+
+impl PolarizedLight {
+    pub fn unpolarized() -> PolarizedLight {
+        PolarizedLight {
+            stokes: [[1.0, 0.0, 0.0, 0.0]; 3],
+            frame: None,
+        }
+    }
+
+    // Diffuse scattering leaves the light unpolarized, keeping its relative
+    // intensity.
+    pub fn depolarize(&mut self) {
+        for stokes in &mut self.stokes {
+            *stokes = [stokes[0], 0.0, 0.0, 0.0];
+        }
+        self.frame = None;
+    }
+
+    // Scatters light arriving along the direction at a surface with the
+    // given normal, by the Mueller matrix of each channel.
+    pub fn scatter(&mut self, direction: Vector3, normal: Vector3, mueller: &[Mueller; 3]) {
+        let direction = direction.norm();
+        let s = direction.cross(normal);
+        // At normal incidence, any axis will do.
+        let s = if s.len() > 1e-9 {
+            s.norm()
+        } else {
+            self.frame
+                .unwrap_or_else(|| util::orthonormal_basis(direction).0)
+        };
+        if let Some(x) = self.frame {
+            self.rotate(x, s, direction);
+        }
+        for (stokes, mueller) in self.stokes.iter_mut().zip(mueller) {
+            let intensity = mueller.intensity();
+            *stokes = if intensity > 0.0 {
+                mueller.apply(*stokes).map(|v| v / intensity)
+            } else {
+                [0.0; 4]
+            };
+        }
+        self.frame = Some(s);
+    }
+
+    // The relative intensity reaching the camera along the direction, seen
+    // through a linear polarizer with the given transmission axis, if any,
+    // which passes half of unpolarized light.
+    pub fn measure(&self, direction: Vector3, polarizer: Option<Vector3>) -> Spectrum {
+        let intensity = |stokes: &Stokes| match (polarizer, self.frame) {
+            (None, _) => stokes[0],
+            (Some(_), None) => stokes[0] / 2.0,
+            (Some(axis), Some(x)) => {
+                let psi = angle(x, axis, direction.norm());
+                (stokes[0] + stokes[1] * (2.0 * psi).cos() + stokes[2] * (2.0 * psi).sin()) / 2.0
+            }
+        };
+        let [r, g, b] = self.stokes.each_ref().map(intensity);
+        Spectrum { r, g, b }
+    }
+
+    // Expresses the Stokes vectors in the frame with the new x axis.
+    fn rotate(&mut self, x: Vector3, new_x: Vector3, direction: Vector3) {
+        let phi = angle(x, new_x, direction);
+        let (sin, cos) = (2.0 * phi).sin_cos();
+        for stokes in &mut self.stokes {
+            let (q, u) = (stokes[1], stokes[2]);
+            stokes[1] = q * cos + u * sin;
+            stokes[2] = -q * sin + u * cos;
+        }
+    }
+}
+
+// The angle from the axis a to the projection of b onto the plane normal to
+// the direction, counterclockwise about it.
+fn angle(a: Vector3, b: Vector3, direction: Vector3) -> Float {
+    let b = b - direction * b.dot(direction);
+    Float::atan2(a.cross(b).dot(direction), a.dot(b))
+}
+
+// The Fresnel amplitude coefficients (rs, rp, ts, tp) for the relative
+// index of refraction, in the convention in which rp tends to 1 for a
+// perfect conductor.
+fn fresnel_amplitudes(cos_theta_i: Float, eta: Complex) -> (Complex, Complex, Complex, Complex) {
+    let cos_i = Complex::new(cos_theta_i, 0.0);
+    let sin2_i = Complex::new(1.0 - util::sqr(cos_theta_i), 0.0);
+    let cos_t = (Complex::new(1.0, 0.0) - sin2_i / (eta * eta)).sqrt();
+    let two_cos_i = cos_i * 2.0;
+    let s = cos_i + eta * cos_t;
+    let p = eta * cos_i + cos_t;
+    (
+        (cos_i - eta * cos_t) / s,
+        (eta * cos_i - cos_t) / p,
+        two_cos_i / s,
+        two_cos_i / p,
+    )
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Complex {
+    re: Float,
+    im: Float,
+}
+
+impl Complex {
+    fn new(re: Float, im: Float) -> Complex {
+        Complex { re, im }
+    }
+
+    fn conj(self) -> Complex {
+        Complex::new(self.re, -self.im)
+    }
+
+    fn norm_sqr(self) -> Float {
+        self.re * self.re + self.im * self.im
+    }
+
+    // The principal square root.
+    fn sqrt(self) -> Complex {
+        let r = self.norm_sqr().sqrt();
+        let re = ((r + self.re) / 2.0).max(0.0).sqrt();
+        let im = ((r - self.re) / 2.0).max(0.0).sqrt();
+        Complex::new(re, if self.im < 0.0 { -im } else { im })
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, rhs: Complex) -> Complex {
+        Complex::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, rhs: Complex) -> Complex {
+        Complex::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, rhs: Complex) -> Complex {
+        Complex::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+impl Mul<Float> for Complex {
+    type Output = Complex;
+
+    fn mul(self, rhs: Float) -> Complex {
+        Complex::new(self.re * rhs, self.im * rhs)
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+
+    fn div(self, rhs: Complex) -> Complex {
+        let d = rhs.norm_sqr();
+        let n = self * rhs.conj();
+        Complex::new(n.re / d, n.im / d)
+    }
+}
+
+// Polarization-aware transport for a camera, which optionally looks through
+// a linear polarizer in front of its lens.
+#[derive(Debug)]
+pub struct Polarization {
+    // The transmission axis of the polarizer.
+    pub polarizer: Option<Vector3>,
+}
+
+impl Polarization {
+    // The angle of the polarizer is counterclockwise from the horizontal of
+    // the image, whose horizontal and vertical axes are given.
+    pub fn configure(
+        config: &PolarizationConfig,
+        horizontal: Vector3,
+        vertical: Vector3,
+    ) -> Polarization {
+        let polarizer = config.polarizer.as_ref().map(|polarizer| {
+            let angle = polarizer.unit.radians(polarizer.angle);
+            horizontal * angle.cos() + vertical * angle.sin()
+        });
+        Polarization { polarizer }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PolarizationConfig {
+    polarizer: Option<PolarizerConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PolarizerConfig {
+    angle: Float,
+    unit: AngleUnitConfig,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mueller, PolarizedLight};
+    use crate::{approx::loosen, types::Float, util, vector::Vector3};
+
+    #[test]
+    fn test_fresnel_mueller() {
+        for cos_theta in [1.0, 0.7, 0.2] {
+            let r = Mueller::fresnel_reflection(cos_theta, 1.5, 0.0).intensity();
+            assert!(util::equals(
+                r,
+                util::fresnel_dielectric(cos_theta, 1.5),
+                loosen(1e-9)
+            ));
+            let t = Mueller::fresnel_transmission(cos_theta, 1.5).intensity();
+            assert!(util::equals(t, 1.0 - r, loosen(1e-9)));
+            let r = Mueller::fresnel_reflection(cos_theta, 0.2, 3.9).intensity();
+            assert!(util::equals(
+                r,
+                util::fresnel_conductor(cos_theta, 0.2, 3.9),
+                loosen(1e-9)
+            ));
+        }
+        // Total internal reflection reflects both polarizations fully.
+        let r = Mueller::fresnel_reflection(0.5, 1.0 / 1.5, 0.0);
+        assert!(util::equals(r.intensity(), 1.0, loosen(1e-9)));
+        assert!(util::equals(r.0[0][1], 0.0, loosen(1e-9)));
+    }
+
+    #[test]
+    fn test_brewster_reflection() {
+        // Light reflected at Brewster's angle is polarized normal to the
+        // plane of incidence.
+        let eta: Float = 1.5;
+        let theta = eta.atan();
+        let normal = Vector3::new(0.0, 1.0, 0.0);
+        let incoming = Vector3::new(theta.sin(), -theta.cos(), 0.0);
+        let outgoing = Vector3::new(theta.sin(), theta.cos(), 0.0);
+        let mueller = Mueller::fresnel_reflection(theta.cos(), eta, 0.0);
+        let mut light = PolarizedLight::unpolarized();
+        light.scatter(incoming, normal, &[mueller; 3]);
+        let s = Vector3::new(0.0, 0.0, 1.0);
+        let p = outgoing.cross(s);
+        assert!(util::equals(
+            light.measure(outgoing, None).g,
+            1.0,
+            loosen(1e-9)
+        ));
+        assert!(util::equals(
+            light.measure(outgoing, Some(s)).g,
+            1.0,
+            loosen(1e-9)
+        ));
+        assert!(util::equals(
+            light.measure(outgoing, Some(p)).g,
+            0.0,
+            loosen(1e-9)
+        ));
+        let diagonal = (s + p).norm();
+        assert!(util::equals(
+            light.measure(outgoing, Some(diagonal)).g,
+            0.5,
+            loosen(1e-9)
+        ));
+
+        // A second reflection in a perpendicular plane of incidence sees the
+        // light as p-polarized, and so reflects none of it.
+        let normal = -outgoing * theta.cos() + s * theta.sin();
+        let mut twice = light;
+        twice.scatter(outgoing, normal, &[mueller; 3]);
+        let next = util::reflect(-outgoing, normal);
+        assert!(util::equals(twice.measure(next, None).g, 0.0, loosen(1e-9)));
+
+        light.depolarize();
+        assert!(util::equals(
+            light.measure(outgoing, Some(s)).g,
+            0.5,
+            loosen(1e-9)
+        ));
+    }
+}
@@ -0,0 +1,498 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{parallel, spectrum::Spectrum, types::Float};
+
+const REFERENCE_TEMPERATURE: Float = 6504.0;
+
+// The display transform shared by all LDR writers. Scene-referred values are
+// first exposed and white balanced (`expose`), which is where scene-referred
+// effects such as bloom belong, and then tone mapped and gamma encoded into
+// [0, 1] (`encode`).
+#[derive(Debug)]
+pub struct DisplayTransform {
+    exposure: Float,
+    white_balance: Spectrum,
+    gamma: Float,
+    tone_mapping: bool,
+}
+
+impl DisplayTransform {
+    pub fn new(exposure: Float, white_balance: Option<Float>, gamma: Float) -> DisplayTransform {
+        DisplayTransform {
+            exposure: Float::powf(2.0, exposure),
+            white_balance: white_balance
+                .map(white_balance_gains)
+                .unwrap_or(Spectrum::fill(1.0)),
+            gamma,
+            tone_mapping: true,
+        }
+    }
+
+    // Writes values in [0, 1] as they are, for images of data rather than
+    // of light.
+    pub fn linear() -> DisplayTransform {
+        DisplayTransform {
+            tone_mapping: false,
+            ..DisplayTransform::new(0.0, None, 1.0)
+        }
+    }
+
+    pub fn exposure(&self) -> Float {
+        self.exposure
+    }
+
+    pub fn expose(&self, rgb: Spectrum) -> Spectrum {
+        rgb.mul(self.white_balance) * self.exposure
+    }
+
+    pub fn encode(&self, rgb: Spectrum) -> Spectrum {
+        let encode = |value: Float| {
+            let tone_mapped_value = if self.tone_mapping {
+                1.0 - Float::exp(-Float::max(0.0, value))
+            } else {
+                value.clamp(0.0, 1.0)
+            };
+            Float::powf(tone_mapped_value, 1.0 / self.gamma)
+        };
+        Spectrum {
+            r: encode(rgb.r),
+            g: encode(rgb.g),
+            b: encode(rgb.b),
+        }
+    }
+}
+
+// Channel gains that render a scene lit by a black body of the given
+// temperature as neutral, normalized to preserve luminance. The Planckian
+// locus uses the cubic spline approximation of Kim et al.
+#[allow(clippy::excessive_precision)]
+pub fn white_balance_gains(temperature: Float) -> Spectrum {
+    let white = |t: Float| -> Spectrum {
+        let t = t.clamp(1667.0, 25000.0);
+        let (t2, t3) = (t * t, t * t * t);
+        let x = if t <= 4000.0 {
+            -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.179910
+        } else {
+            -3.0258469e9 / t3 + 2.1070379e6 / t2 + 0.2226347e3 / t + 0.240390
+        };
+        let (x2, x3) = (x * x, x * x * x);
+        let y = if t <= 2222.0 {
+            -1.1063814 * x3 - 1.34811020 * x2 + 2.18555832 * x - 0.20219683
+        } else if t <= 4000.0 {
+            -0.9549476 * x3 - 1.37418593 * x2 + 2.09137015 * x - 0.16748867
+        } else {
+            3.0817580 * x3 - 5.87338670 * x2 + 3.75112997 * x - 0.37001483
+        };
+        Spectrum::from_xyz(x / y, 1.0, (1.0 - x - y) / y)
+    };
+    let reference = white(REFERENCE_TEMPERATURE);
+    let illuminant = white(temperature);
+    let gains = Spectrum {
+        r: reference.r / illuminant.r,
+        g: reference.g / illuminant.g,
+        b: reference.b / illuminant.b,
+    };
+    gains / gains.luminance()
+}
+
+// Maps a value in [0, 1] to a blue-cyan-green-yellow-red ramp.
+pub fn heat_map(t: Float) -> Spectrum {
+    let t = t.clamp(0.0, 1.0) * 4.0;
+    let segment = Float::min(t.floor(), 3.0);
+    let f = t - segment;
+    match segment as usize {
+        0 => Spectrum {
+            r: 0.0,
+            g: f,
+            b: 1.0,
+        },
+        1 => Spectrum {
+            r: 0.0,
+            g: 1.0,
+            b: 1.0 - f,
+        },
+        2 => Spectrum {
+            r: f,
+            g: 1.0,
+            b: 0.0,
+        },
+        _ => Spectrum {
+            r: 1.0,
+            g: 1.0 - f,
+            b: 0.0,
+        },
+    }
+}
+
+const HISTOGRAM_BINS: usize = 128;
+const HISTOGRAM_MIN_EV: Float = -16.0;
+const HISTOGRAM_MAX_EV: Float = 16.0;
+
+// Picks an exposure from a histogram of log luminance. The darkest and
+// brightest pixels (outside the percentile range) are ignored so that a few
+// fireflies or a black background don't skew the average, and the remaining
+// geometric mean is mapped to the key value.
+#[derive(Debug)]
+pub struct AutoExposure {
+    key: Float,
+    low_percentile: Float,
+    high_percentile: Float,
+}
+
+impl AutoExposure {
+    pub fn configure(config: &AutoExposureConfig) -> AutoExposure {
+        AutoExposure::new(
+            config.key.unwrap_or(0.18),
+            config.low_percentile.unwrap_or(0.5),
+            config.high_percentile.unwrap_or(0.95),
+        )
+    }
+
+    pub fn new(key: Float, low_percentile: Float, high_percentile: Float) -> AutoExposure {
+        let low_percentile = low_percentile.clamp(0.0, 1.0);
+        AutoExposure {
+            key,
+            low_percentile,
+            high_percentile: high_percentile.clamp(low_percentile, 1.0),
+        }
+    }
+
+    // The factor by which the pixels should be scaled.
+    pub fn scale(&self, pixels: &[Spectrum]) -> Float {
+        let bin_width = (HISTOGRAM_MAX_EV - HISTOGRAM_MIN_EV) / HISTOGRAM_BINS as Float;
+        let mut histogram = [0usize; HISTOGRAM_BINS];
+        let mut count = 0;
+        for pixel in pixels {
+            let luminance = pixel.luminance();
+            if luminance.is_finite() && luminance > 0.0 {
+                let ev = Float::log2(luminance).clamp(HISTOGRAM_MIN_EV, HISTOGRAM_MAX_EV);
+                let bin = ((ev - HISTOGRAM_MIN_EV) / bin_width) as usize;
+                histogram[usize::min(bin, HISTOGRAM_BINS - 1)] += 1;
+                count += 1;
+            }
+        }
+        if count == 0 {
+            return 1.0;
+        }
+
+        let low = self.low_percentile * count as Float;
+        let high = self.high_percentile * count as Float;
+        let mut seen = 0.0;
+        let mut sum = 0.0;
+        let mut weight = 0.0;
+        for (i, n) in histogram.iter().enumerate() {
+            let n = *n as Float;
+            let included = Float::min(seen + n, high) - Float::max(seen, low);
+            if included > 0.0 {
+                let ev = HISTOGRAM_MIN_EV + (i as Float + 0.5) * bin_width;
+                sum += included * ev;
+                weight += included;
+            }
+            seen += n;
+        }
+        if weight == 0.0 {
+            return 1.0;
+        }
+        self.key / Float::powf(2.0, sum / weight)
+    }
+}
+
+#[derive(Debug)]
+pub struct Bloom {
+    threshold: Float,
+    intensity: Float,
+    levels: usize,
+}
+
+impl Bloom {
+    pub fn configure(config: &BloomConfig) -> Bloom {
+        Bloom::new(
+            config.threshold,
+            config.intensity,
+            config.levels.unwrap_or(5),
+        )
+    }
+
+    pub fn new(threshold: Float, intensity: Float, levels: usize) -> Bloom {
+        Bloom {
+            threshold,
+            intensity: intensity.clamp(0.0, 1.0),
+            levels: usize::max(1, levels),
+        }
+    }
+
+    // The energy above the threshold is redistributed over a sum of
+    // progressively wider Gaussians rather than added on top, so the
+    // total energy of the image is preserved.
+    pub fn apply(&self, pixels: &mut [Spectrum], width: usize, height: usize) {
+        let bright: Vec<Spectrum> = pixels.iter().map(|p| self.bright_pass(*p)).collect();
+
+        let mut pyramid = vec![Buffer {
+            pixels: bright.clone(),
+            width,
+            height,
+        }];
+        for _ in 0..self.levels {
+            let previous = &pyramid[pyramid.len() - 1];
+            if previous.width == 1 && previous.height == 1 {
+                break;
+            }
+            let next = previous.blur().downsample();
+            pyramid.push(next);
+        }
+
+        // A single pixel has nowhere to spread its light.
+        let levels = pyramid.len() - 1;
+        if levels == 0 {
+            return;
+        }
+        let mut accumulated = pyramid[levels].blur();
+        for level in pyramid[1..levels].iter().rev() {
+            let upsampled = accumulated.upsample(level.width, level.height);
+            accumulated = level.blur().add(&upsampled);
+        }
+        let bloom = accumulated.upsample(width, height);
+
+        let s = self.intensity / levels as Float;
+        for i in 0..pixels.len() {
+            pixels[i] = pixels[i] + (s * bloom.pixels[i]) + (-self.intensity * bright[i]);
+        }
+    }
+
+    fn bright_pass(&self, pixel: Spectrum) -> Spectrum {
+        let luminance = pixel.luminance();
+        if luminance > self.threshold {
+            pixel * ((luminance - self.threshold) / luminance)
+        } else {
+            Spectrum::black()
+        }
+    }
+}
+
+struct Buffer {
+    pixels: Vec<Spectrum>,
+    width: usize,
+    height: usize,
+}
+
+impl Buffer {
+    fn get(&self, x: isize, y: isize) -> Spectrum {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        self.pixels[y * self.width + x]
+    }
+
+    // The pixels of a buffer of the given size, computed a row at a time in
+    // parallel.
+    fn map(
+        &self,
+        width: usize,
+        height: usize,
+        f: impl Fn(isize, isize) -> Spectrum + Sync,
+    ) -> Vec<Spectrum> {
+        parallel::map(height, |y| {
+            (0..width)
+                .map(|x| f(x as isize, y as isize))
+                .collect::<Vec<Spectrum>>()
+        })
+        .concat()
+    }
+
+    // Separable 5-tap binomial filter, a close approximation of a Gaussian
+    // with a standard deviation of one pixel.
+    fn blur(&self) -> Buffer {
+        const WEIGHTS: [Float; 5] = [1.0 / 16.0, 4.0 / 16.0, 6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+        let convolve = |buffer: &Buffer, dx: isize, dy: isize| -> Buffer {
+            let pixels = buffer.map(buffer.width, buffer.height, |x, y| {
+                let mut sum = Spectrum::black();
+                for (i, w) in WEIGHTS.iter().enumerate() {
+                    let o = i as isize - 2;
+                    sum = sum + *w * buffer.get(x + o * dx, y + o * dy);
+                }
+                sum
+            });
+            Buffer {
+                pixels,
+                width: buffer.width,
+                height: buffer.height,
+            }
+        };
+        convolve(&convolve(self, 1, 0), 0, 1)
+    }
+
+    fn downsample(&self) -> Buffer {
+        let width = self.width.div_ceil(2);
+        let height = self.height.div_ceil(2);
+        let pixels = self.map(width, height, |x, y| {
+            let sum = self.get(2 * x, 2 * y)
+                + self.get(2 * x + 1, 2 * y)
+                + self.get(2 * x, 2 * y + 1)
+                + self.get(2 * x + 1, 2 * y + 1);
+            sum * 0.25
+        });
+        Buffer {
+            pixels,
+            width,
+            height,
+        }
+    }
+
+    fn upsample(&self, width: usize, height: usize) -> Buffer {
+        let sx = self.width as Float / width as Float;
+        let sy = self.height as Float / height as Float;
+        let pixels = self.map(width, height, |x, y| {
+            let u = (x as Float + 0.5) * sx - 0.5;
+            let v = (y as Float + 0.5) * sy - 0.5;
+            let x0 = u.floor();
+            let y0 = v.floor();
+            let fx = u - x0;
+            let fy = v - y0;
+            let (x0, y0) = (x0 as isize, y0 as isize);
+            let top = (1.0 - fx) * self.get(x0, y0) + fx * self.get(x0 + 1, y0);
+            let bottom = (1.0 - fx) * self.get(x0, y0 + 1) + fx * self.get(x0 + 1, y0 + 1);
+            (1.0 - fy) * top + fy * bottom
+        });
+        Buffer {
+            pixels,
+            width,
+            height,
+        }
+    }
+
+    fn add(&self, other: &Buffer) -> Buffer {
+        Buffer {
+            pixels: self
+                .pixels
+                .iter()
+                .zip(&other.pixels)
+                .map(|(a, b)| *a + *b)
+                .collect(),
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AutoExposureConfig {
+    pub key: Option<Float>,
+    pub low_percentile: Option<Float>,
+    pub high_percentile: Option<Float>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BloomConfig {
+    pub threshold: Float,
+    pub intensity: Float,
+    pub levels: Option<usize>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{heat_map, white_balance_gains, AutoExposure, Bloom, DisplayTransform};
+    use crate::{
+        approx::{loosen, ApproxEq},
+        spectrum::Spectrum,
+        types::Float,
+    };
+
+    #[test]
+    fn test_white_balance_gains() {
+        let neutral = white_balance_gains(6504.0);
+        assert!(neutral.approx_eq(Spectrum::fill(1.0), loosen(1e-12)));
+        let tungsten = white_balance_gains(3200.0);
+        assert!(tungsten.b > tungsten.g && tungsten.g > tungsten.r);
+        assert!((tungsten.luminance() - 1.0).abs() < loosen(1e-12));
+    }
+
+    #[test]
+    fn test_display_transform() {
+        let display = DisplayTransform::new(1.0, None, 1.0);
+        let exposed = display.expose(Spectrum::fill(0.5));
+        assert_eq!(exposed, Spectrum::fill(1.0));
+        let encoded = display.encode(exposed);
+        assert!(encoded.approx_eq(Spectrum::fill(1.0 - Float::exp(-1.0)), loosen(1e-12)));
+        let gamma = DisplayTransform::new(0.0, None, 2.0);
+        let encoded = gamma.encode(Spectrum::fill(Float::ln(4.0 / 3.0)));
+        assert!(encoded.approx_eq(Spectrum::fill(0.5), loosen(1e-12)));
+        let linear = DisplayTransform::linear();
+        assert_eq!(linear.encode(Spectrum::fill(0.25)), Spectrum::fill(0.25));
+        assert_eq!(linear.encode(Spectrum::fill(2.0)), Spectrum::fill(1.0));
+    }
+
+    #[test]
+    fn test_heat_map() {
+        assert_eq!(
+            heat_map(0.0),
+            Spectrum {
+                r: 0.0,
+                g: 0.0,
+                b: 1.0
+            }
+        );
+        assert_eq!(
+            heat_map(0.5),
+            Spectrum {
+                r: 0.0,
+                g: 1.0,
+                b: 0.0
+            }
+        );
+        assert_eq!(
+            heat_map(1.0),
+            Spectrum {
+                r: 1.0,
+                g: 0.0,
+                b: 0.0
+            }
+        );
+    }
+
+    #[test]
+    fn test_auto_exposure() {
+        let auto_exposure = AutoExposure::new(0.18, 0.0, 1.0);
+        let scale = auto_exposure.scale(&[Spectrum::fill(0.5); 16]);
+        assert!((scale * 0.5 / 0.18 - 1.0).abs() < 0.1);
+
+        let auto_exposure = AutoExposure::new(0.18, 0.1, 0.9);
+        let mut pixels = vec![Spectrum::fill(0.5); 100];
+        pixels[0] = Spectrum::fill(1e6);
+        pixels[1] = Spectrum::black();
+        let outliers = auto_exposure.scale(&pixels);
+        assert_eq!(outliers, scale);
+
+        assert_eq!(auto_exposure.scale(&[Spectrum::black()]), 1.0);
+    }
+
+    #[test]
+    fn test_bloom_below_threshold() {
+        let bloom = Bloom::new(1.0, 0.5, 4);
+        let mut pixels = vec![Spectrum::fill(0.5); 64];
+        bloom.apply(&mut pixels, 8, 8);
+        assert!(pixels.iter().all(|p| *p == Spectrum::fill(0.5)));
+    }
+
+    #[test]
+    fn test_bloom_spreads_energy() {
+        let bloom = Bloom::new(1.0, 0.5, 4);
+        let width = 16;
+        let height = 16;
+        let mut pixels = vec![Spectrum::black(); width * height];
+        pixels[8 * width + 8] = Spectrum::fill(101.0);
+        let before: Float = pixels.iter().map(|p| p.luminance()).sum();
+        bloom.apply(&mut pixels, width, height);
+        let after: Float = pixels.iter().map(|p| p.luminance()).sum();
+        assert!(pixels[8 * width + 8].luminance() < 101.0);
+        assert!(pixels[8 * width + 10].luminance() > 0.0);
+        assert!(pixels.iter().all(|p| p.r >= 0.0));
+        assert!((before - after).abs() / before < 0.05);
+    }
+
+    #[test]
+    fn test_bloom_single_pixel() {
+        let bloom = Bloom::new(1.0, 0.5, 4);
+        let mut pixels = vec![Spectrum::fill(101.0)];
+        bloom.apply(&mut pixels, 1, 1);
+        assert_eq!(pixels[0], Spectrum::fill(101.0));
+    }
+}
@@ -0,0 +1,109 @@
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Instant,
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static CALLS: [AtomicU64; SUBSYSTEM_COUNT] = [const { AtomicU64::new(0) }; SUBSYSTEM_COUNT];
+static NANOS: [AtomicU64; SUBSYSTEM_COUNT] = [const { AtomicU64::new(0) }; SUBSYSTEM_COUNT];
+
+const SUBSYSTEM_COUNT: usize = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Subsystem {
+    Intersection,
+    BsdfEvaluation,
+    PathConnection,
+    Splatting,
+}
+
+impl Subsystem {
+    const ALL: [Subsystem; SUBSYSTEM_COUNT] = [
+        Subsystem::Intersection,
+        Subsystem::BsdfEvaluation,
+        Subsystem::PathConnection,
+        Subsystem::Splatting,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Subsystem::Intersection => "intersection",
+            Subsystem::BsdfEvaluation => "BSDF evaluation",
+            Subsystem::PathConnection => "path connection",
+            Subsystem::Splatting => "splatting",
+        }
+    }
+}
+
+pub struct Timing {
+    pub subsystem: Subsystem,
+    pub calls: u64,
+    pub seconds: f64,
+}
+
+// Timing is off unless enabled, in which case each timed call costs two
+// reads of the clock.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn reset() {
+    for i in 0..SUBSYSTEM_COUNT {
+        CALLS[i].store(0, Ordering::Relaxed);
+        NANOS[i].store(0, Ordering::Relaxed);
+    }
+}
+
+// Records the time until the returned timer is dropped against the
+// subsystem. Times are inclusive, so that of a subsystem calling another
+// counts both.
+pub fn time(subsystem: Subsystem) -> Timer {
+    Timer {
+        subsystem,
+        start: enabled().then(Instant::now),
+    }
+}
+
+pub fn timings() -> Vec<Timing> {
+    Subsystem::ALL
+        .iter()
+        .map(|&subsystem| Timing {
+            subsystem,
+            calls: CALLS[subsystem as usize].load(Ordering::Relaxed),
+            seconds: NANOS[subsystem as usize].load(Ordering::Relaxed) as f64 * 1e-9,
+        })
+        .collect()
+}
+
+pub struct Timer {
+    subsystem: Subsystem,
+    start: Option<Instant>,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            let index = self.subsystem as usize;
+            CALLS[index].fetch_add(1, Ordering::Relaxed);
+            NANOS[index].fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{time, timings, Subsystem};
+
+    #[test]
+    fn test_disabled_timer() {
+        // Other tests may run concurrently, so only the disabled state is
+        // checked: nothing is recorded.
+        let before = timings()[Subsystem::Splatting as usize].calls;
+        drop(time(Subsystem::Splatting));
+        assert_eq!(timings()[Subsystem::Splatting as usize].calls, before);
+    }
+}
@@ -1,10 +1,204 @@
+use std::{
+    io::{self, IsTerminal},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{image::Image, types::Float};
+
+const BAR_WIDTH: usize = 30;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+// Without a terminal to redraw on, progress is logged at every tenth of a
+// phase, so that redirected output stays readable.
+const LOG_STEPS: u64 = 10;
+
 pub fn report(message: &str) {
     eprintln!("{}", message);
 }
 
-pub fn report_progress(percentage: f64) {
-    eprint!("\rProgress: {:.2}%", percentage * 100.0);
-    if percentage == 1.0 {
-        eprintln!()
+// Where an integrator reports its messages and the progress of each phase of
+// a render, so that an embedder can show them as it likes. A phase is started,
+// updated with the total work done from any thread, and finished, one phase at
+// a time. The image may be offered as it converges, with the scale of the
+// samples taken so far, and is offered last once the samples are all taken.
+pub trait ProgressSink: Sync {
+    fn report(&self, message: &str);
+    fn start(&self, phase: Phase, total: u64);
+    fn update(&self, count: u64);
+    fn finish(&self);
+    fn snapshot(&self, _image: &Image, _scale: Float, _last: bool) {}
+}
+
+// Messages and progress bars on stderr, for the command line.
+#[derive(Default)]
+pub struct TerminalProgress {
+    progress: Mutex<Option<Progress>>,
+}
+
+impl ProgressSink for TerminalProgress {
+    fn report(&self, message: &str) {
+        report(message);
+    }
+
+    fn start(&self, phase: Phase, total: u64) {
+        *self.progress.lock().unwrap() = Some(Progress::start(phase, total));
+    }
+
+    fn update(&self, count: u64) {
+        if let Some(progress) = self.progress.lock().unwrap().as_mut() {
+            progress.update(count);
+        }
+    }
+
+    fn finish(&self) {
+        if let Some(progress) = self.progress.lock().unwrap().take() {
+            progress.finish();
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Phase {
+    Bootstrap,
+    ChainInitialization,
+    BurnIn,
+    Integration,
+    Preview,
+}
+
+impl Phase {
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Bootstrap => "Bootstrap",
+            Phase::ChainInitialization => "Chain initialization",
+            Phase::BurnIn => "Burn-in",
+            Phase::Integration => "Integration",
+            Phase::Preview => "Preview",
+        }
+    }
+
+    fn unit(self) -> &'static str {
+        match self {
+            Phase::Bootstrap | Phase::BurnIn | Phase::Integration | Phase::Preview => "samples",
+            Phase::ChainInitialization => "chains",
+        }
+    }
+}
+
+struct Progress {
+    phase: Phase,
+    total: u64,
+    count: u64,
+    start: Instant,
+    last_drawn_at: Instant,
+    logged_steps: u64,
+    terminal: bool,
+}
+
+impl Progress {
+    fn start(phase: Phase, total: u64) -> Progress {
+        let now = Instant::now();
+        let progress = Progress {
+            phase,
+            total,
+            count: 0,
+            start: now,
+            last_drawn_at: now,
+            logged_steps: 0,
+            terminal: io::stderr().is_terminal(),
+        };
+        if progress.terminal {
+            eprint!("\r{}\x1b[K", progress.line(now));
+        } else {
+            eprintln!("{}...", phase.name());
+        }
+        progress
+    }
+
+    fn update(&mut self, count: u64) {
+        self.count = count;
+        if self.terminal {
+            let now = Instant::now();
+            if now - self.last_drawn_at >= REDRAW_INTERVAL {
+                eprint!("\r{}\x1b[K", self.line(now));
+                self.last_drawn_at = now;
+            }
+        } else if let Some(steps) = (self.count.min(self.total) * LOG_STEPS).checked_div(self.total)
+        {
+            if steps > self.logged_steps && steps < LOG_STEPS {
+                eprintln!("{}", self.line(Instant::now()));
+                self.logged_steps = steps;
+            }
+        }
+    }
+
+    fn finish(self) {
+        let line = self.line(Instant::now());
+        if self.terminal {
+            eprintln!("\r{}\x1b[K", line);
+        } else {
+            eprintln!("{}", line);
+        }
+    }
+
+    fn line(&self, now: Instant) -> String {
+        let fraction = if self.total == 0 {
+            1.0
+        } else {
+            (self.count as f64 / self.total as f64).min(1.0)
+        };
+        let elapsed = (now - self.start).as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            self.count as f64 / elapsed
+        } else {
+            0.0
+        };
+        format!(
+            "{:<20} [{}] {:5.1}%  {}/{} {}  {} {}/s",
+            self.phase.name(),
+            bar(fraction),
+            fraction * 100.0,
+            self.count,
+            self.total,
+            self.phase.unit(),
+            abbreviate(rate),
+            self.phase.unit()
+        )
+    }
+}
+
+fn bar(fraction: f64) -> String {
+    let filled = ((fraction * BAR_WIDTH as f64) as usize).min(BAR_WIDTH);
+    format!("{}{}", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled))
+}
+
+fn abbreviate(value: f64) -> String {
+    if value >= 1e9 {
+        format!("{:.2}G", value / 1e9)
+    } else if value >= 1e6 {
+        format!("{:.2}M", value / 1e6)
+    } else if value >= 1e3 {
+        format!("{:.2}k", value / 1e3)
+    } else {
+        format!("{:.0}", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{abbreviate, bar, BAR_WIDTH};
+
+    #[test]
+    fn test_bar() {
+        assert_eq!(bar(0.0), "-".repeat(BAR_WIDTH));
+        assert_eq!(bar(1.0), "#".repeat(BAR_WIDTH));
+        assert_eq!(bar(0.5).matches('#').count(), BAR_WIDTH / 2);
+    }
+
+    #[test]
+    fn test_abbreviate() {
+        assert_eq!(abbreviate(950.0), "950");
+        assert_eq!(abbreviate(1234.0), "1.23k");
+        assert_eq!(abbreviate(2_500_000.0), "2.50M");
     }
 }
@@ -1,9 +1,22 @@
-use crate::vector::{Point3, Vector3};
+use crate::{
+    geometry::{Geometry, SurfaceId},
+    types::Float,
+    vector::{Point3, Vector3},
+};
 
+// Hits closer to the origin than this are taken to be the surface the ray
+// leaves rather than another surface.
+pub const RAY_EPSILON: Float = 1e-4;
+
+// A ray, with the interval of distances along it at which it may hit, and
+// the surface it leaves, if any.
 #[derive(Copy, Clone, Debug)]
 pub struct Ray {
     pub origin: Point3,
     pub direction: Vector3,
+    pub t_min: Float,
+    pub t_max: Float,
+    pub surface: Option<SurfaceId>,
 }
 
 impl Ray {
@@ -11,6 +24,37 @@ impl Ray {
         Ray {
             origin,
             direction: direction.norm(),
+            t_min: RAY_EPSILON,
+            t_max: Float::INFINITY,
+            surface: None,
+        }
+    }
+
+    // A ray leaving the point of a surface, which its shape excludes exactly
+    // rather than by the epsilon, so that the ray neither finds the point
+    // again through rounding nor misses surfaces close by.
+    pub fn leaving(geometry: &Geometry, direction: Vector3) -> Ray {
+        match geometry.surface {
+            Some(surface) => Ray {
+                t_min: 0.0,
+                surface: Some(surface),
+                ..Ray::new(geometry.point, direction)
+            },
+            None => Ray::new(geometry.point, direction),
         }
     }
+
+    pub fn contains(&self, t: Float) -> bool {
+        t > self.t_min && t < self.t_max
+    }
+}
+
+// What a ray is cast for, which decides the objects it may hit: rays leaving
+// or reaching the camera, rays connecting a point to a light, and the rays
+// of the bounces in between.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RayKind {
+    Camera,
+    Shadow,
+    Indirect,
 }
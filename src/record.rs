@@ -0,0 +1,166 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::Error,
+    path::{Path, PathArena},
+    progress::ProgressSink,
+    scene::Scene,
+    types::Float,
+};
+
+// A proposal of a Markov chain, with the primary sample values from which
+// `Path::generate` makes its paths again, for reproducing a problematic path
+// outside of the render that found it.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct RecordedProposal {
+    pub path_length: usize,
+    pub pixel: [Float; 2],
+    pub contribution: Float,
+    pub values: Vec<Float>,
+}
+
+// Writes the recorded proposals as a YAML sequence, one item at a time, so
+// that a render that is interrupted keeps those recorded so far.
+pub struct ProposalRecorder {
+    path: String,
+    writer: BufWriter<File>,
+    count: usize,
+}
+
+impl ProposalRecorder {
+    pub fn create(path: &str) -> Result<ProposalRecorder, Error> {
+        let file = File::create(path).map_err(Error::io(path))?;
+        Ok(ProposalRecorder {
+            path: String::from(path),
+            writer: BufWriter::new(file),
+            count: 0,
+        })
+    }
+
+    pub fn record(&mut self, proposal: &RecordedProposal) -> Result<(), Error> {
+        let source = serde_yaml::to_string(&[proposal]).map_err(|e| Error::Checkpoint {
+            path: self.path.clone(),
+            message: e.to_string(),
+        })?;
+        self.count += 1;
+        self.writer
+            .write_all(source.as_bytes())
+            .map_err(Error::io(&self.path))
+    }
+
+    // Gives the number of proposals recorded.
+    pub fn finish(mut self) -> Result<usize, Error> {
+        if self.count == 0 {
+            self.writer
+                .write_all(b"[]\n")
+                .map_err(Error::io(&self.path))?;
+        }
+        self.writer.flush().map_err(Error::io(&self.path))?;
+        Ok(self.count)
+    }
+}
+
+pub fn read(path: &str) -> Result<Vec<RecordedProposal>, Error> {
+    let source = fs::read_to_string(path).map_err(Error::io(path))?;
+    serde_yaml::from_str(&source).map_err(|e| Error::Checkpoint {
+        path: String::from(path),
+        message: e.to_string(),
+    })
+}
+
+// Generates the paths of each recorded proposal again and logs them, with
+// their vertices, densities, throughputs and MIS weights, reporting those
+// that no longer match their recording. The scene and its settings must be
+// those of the render that recorded them.
+pub fn replay(
+    scene: &Scene,
+    path: &str,
+    log_path: &str,
+    progress: &dyn ProgressSink,
+) -> Result<(), Error> {
+    let proposals = read(path)?;
+    let file = File::create(log_path).map_err(Error::io(log_path))?;
+    let mut writer = BufWriter::new(file);
+    let mut arena = PathArena::default();
+    let mut mismatches = 0;
+    for (index, proposal) in proposals.iter().enumerate() {
+        let (contribution, record) =
+            Path::replay(scene, &proposal.values, proposal.path_length, &mut arena);
+        let pixel = contribution.pixel_coordinates;
+        let matched = contribution.scalar == proposal.contribution
+            && (contribution.is_empty() || [pixel.x, pixel.y] == proposal.pixel);
+        if !matched {
+            mismatches += 1;
+            progress.report(&format!(
+                "proposal {} recorded a contribution of {:.6e} at ({:.4}, {:.4}) but replays {:.6e} at ({:.4}, {:.4})",
+                index,
+                proposal.contribution,
+                proposal.pixel[0],
+                proposal.pixel[1],
+                contribution.scalar,
+                pixel.x,
+                pixel.y
+            ));
+        }
+        let header = format!(
+            "proposal {} path length {} pixel ({:.4}, {:.4}) contribution {:.6e}\n",
+            index, proposal.path_length, pixel.x, pixel.y, contribution.scalar
+        );
+        writer
+            .write_all(header.as_bytes())
+            .and_then(|_| writer.write_all(record.as_bytes()))
+            .map_err(Error::io(log_path))?;
+    }
+    writer.flush().map_err(Error::io(log_path))?;
+    progress.report(&format!(
+        "replayed {} proposals into {}, {} of them mismatched",
+        proposals.len(),
+        log_path,
+        mismatches
+    ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::{read, ProposalRecorder, RecordedProposal};
+
+    #[test]
+    fn test_proposal_recorder() {
+        let path = env::temp_dir().join("mmlt-test-proposals.yml");
+        let path = path.to_str().unwrap();
+        let proposals = [
+            RecordedProposal {
+                path_length: 3,
+                pixel: [1.5, 2.25],
+                contribution: 4.0,
+                values: vec![0.1, 0.7, 1.0 / 3.0],
+            },
+            RecordedProposal {
+                path_length: 2,
+                pixel: [0.0, 0.0],
+                contribution: 0.5,
+                values: Vec::new(),
+            },
+        ];
+        let mut recorder = ProposalRecorder::create(path).unwrap();
+        for proposal in &proposals {
+            recorder.record(proposal).unwrap();
+        }
+        assert_eq!(recorder.finish().unwrap(), 2);
+        assert_eq!(read(path).unwrap(), proposals);
+
+        ProposalRecorder::create(path).unwrap().finish().unwrap();
+        assert!(read(path).unwrap().is_empty());
+        fs::write(path, "- path_length: 2\n").unwrap();
+        assert!(read(path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
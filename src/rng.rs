@@ -0,0 +1,114 @@
+use rand::{Error, RngCore, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use rand_pcg::Pcg32;
+use rand_xoshiro::Xoshiro256PlusPlus;
+
+// The random number generators a render can be driven by. ChaCha, which is
+// what `StdRng` is, is the default; PCG32 and xoshiro are faster, with
+// smaller states, and their statistical quality is ample for sampling.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum RngKind {
+    Pcg32,
+    #[default]
+    ChaCha,
+    Xoshiro,
+}
+
+impl RngKind {
+    pub fn parse(name: &str) -> Option<RngKind> {
+        match name {
+            "pcg32" => Some(RngKind::Pcg32),
+            "chacha" => Some(RngKind::ChaCha),
+            "xoshiro" => Some(RngKind::Xoshiro),
+            _ => None,
+        }
+    }
+
+    // Every generator of a render is seeded from a single number, so that
+    // the render can be reproduced.
+    pub fn seeded(self, seed: u64) -> Generator {
+        match self {
+            RngKind::Pcg32 => Generator::Pcg32(Pcg32::seed_from_u64(seed)),
+            RngKind::ChaCha => Generator::ChaCha(Box::new(ChaCha12Rng::seed_from_u64(seed))),
+            RngKind::Xoshiro => Generator::Xoshiro(Xoshiro256PlusPlus::seed_from_u64(seed)),
+        }
+    }
+
+    pub fn entropy_seeded(self) -> Generator {
+        self.seeded(rand::random())
+    }
+}
+
+// A generator of one of the kinds, dispatched without a virtual call. The
+// ChaCha state, with its buffered block, is ten times the size of the others.
+#[derive(Clone, Debug)]
+pub enum Generator {
+    Pcg32(Pcg32),
+    ChaCha(Box<ChaCha12Rng>),
+    Xoshiro(Xoshiro256PlusPlus),
+}
+
+impl RngCore for Generator {
+    fn next_u32(&mut self) -> u32 {
+        match self {
+            Generator::Pcg32(rng) => rng.next_u32(),
+            Generator::ChaCha(rng) => rng.next_u32(),
+            Generator::Xoshiro(rng) => rng.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            Generator::Pcg32(rng) => rng.next_u64(),
+            Generator::ChaCha(rng) => rng.next_u64(),
+            Generator::Xoshiro(rng) => rng.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match self {
+            Generator::Pcg32(rng) => rng.fill_bytes(dest),
+            Generator::ChaCha(rng) => rng.fill_bytes(dest),
+            Generator::Xoshiro(rng) => rng.fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        match self {
+            Generator::Pcg32(rng) => rng.try_fill_bytes(dest),
+            Generator::ChaCha(rng) => rng.try_fill_bytes(dest),
+            Generator::Xoshiro(rng) => rng.try_fill_bytes(dest),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::RngKind;
+
+    #[test]
+    fn test_rng_kinds() {
+        // The default is the generator renders used before the choice, so
+        // that their seeds still reproduce them.
+        let mut default = RngKind::default().seeded(7);
+        let mut std = StdRng::seed_from_u64(7);
+        for _ in 0..4 {
+            assert_eq!(default.gen::<u64>(), std.gen::<u64>());
+        }
+
+        for kind in [RngKind::Pcg32, RngKind::ChaCha, RngKind::Xoshiro] {
+            let (mut a, mut b) = (kind.seeded(3), kind.seeded(3));
+            let values: Vec<f64> = (0..8).map(|_| a.gen_range(0.0..1.0)).collect();
+            assert!(values.iter().all(|v| (0.0..1.0).contains(v)));
+            assert_eq!(
+                values,
+                (0..8).map(|_| b.gen_range(0.0..1.0)).collect::<Vec<_>>()
+            );
+            assert_ne!(kind.seeded(4).gen::<u64>(), kind.seeded(3).gen::<u64>());
+        }
+        assert_eq!(RngKind::parse("pcg32"), Some(RngKind::Pcg32));
+        assert_eq!(RngKind::parse("mt19937"), None);
+    }
+}
@@ -1,15 +1,95 @@
-use crate::util;
-use rand::{thread_rng, Rng, RngCore};
+use crate::{
+    rng::{Generator, RngKind},
+    types::Float,
+    util,
+};
+use rand::Rng;
 use std::ops::Range;
 
 pub trait Sampler {
     fn start_stream(&mut self, index: usize);
-    fn sample(&mut self, range: Range<f64>) -> f64;
+    fn sample(&mut self, range: Range<Float>) -> Float;
+    // Marks whether the samples that follow are those of the direction in
+    // which a light emits, which a perturbation moves alone.
+    fn set_emission(&mut self, _emission: bool) {}
+}
+
+// The parameters of the mutations, whose best values depend on the scene.
+#[derive(Clone, Debug)]
+pub struct MutationParameters {
+    pub large_step_probability: Float,
+    pub sigma: Float,
+    // Factors of sigma for the samples of each stream, so that one subpath
+    // may be explored more boldly than another. Missing streams are unscaled.
+    pub stream_scales: Vec<Float>,
+    // The stream whose first two samples large steps take from a stratified
+    // sequence rather than independently, if any.
+    pub stratified_stream: Option<usize>,
+    // The stream whose first sample alone large steps take from a stratified
+    // sequence, if any.
+    pub stratified_sample: Option<usize>,
+}
+
+impl Default for MutationParameters {
+    fn default() -> MutationParameters {
+        MutationParameters {
+            large_step_probability: 0.3,
+            sigma: 0.01,
+            stream_scales: Vec::new(),
+            stratified_stream: None,
+            stratified_sample: None,
+        }
+    }
+}
+
+// The generalized golden ratios of the R1 and R2 sequences, whose points
+// cover the unit interval and the unit square evenly however many are taken.
+#[allow(clippy::excessive_precision)]
+const R1: [Float; 1] = [0.6180339887498949];
+#[allow(clippy::excessive_precision)]
+const R2: [Float; 2] = [0.7548776662466927, 0.5698402909980532];
+
+// The R1 or R2 sequence shifted by a random offset, so that each of its
+// points is still uniform, with the number of points taken so far.
+//
+// Successive points depend on one another, so large steps drawn this way
+// are not the independent proposals that their acceptance assumes, and
+// detailed balance holds only approximately; a cycle through the
+// techniques would be no worse in that respect. The shifted sequence is
+// used because it serves film positions and techniques alike, and because
+// over the random offset each point is uniform, as an independent large
+// step would be. Small steps are never stratified.
+struct Stratification {
+    stream_index: usize,
+    ratios: &'static [Float],
+    offset: [Float; 2],
+    count: u64,
+}
+
+impl Stratification {
+    fn new(stream_index: usize, ratios: &'static [Float], rng: &mut Generator) -> Stratification {
+        let mut offset = [0.0; 2];
+        for value in &mut offset[..ratios.len()] {
+            *value = rng.gen_range(0.0..1.0);
+        }
+        Stratification {
+            stream_index,
+            ratios,
+            offset,
+            count: 0,
+        }
+    }
+
+    fn value(&self, dimension: usize) -> Float {
+        let value = self.offset[dimension] + self.count as Float * self.ratios[dimension];
+        value - value.floor()
+    }
 }
 
 pub struct MmltSampler {
-    pub large_step_probability: f64,
-    sigma: f64,
+    pub large_step_probability: Float,
+    sigma: Float,
+    stream_scales: Vec<Float>,
     stream_count: usize,
     stream_index: usize,
     sample_index: usize,
@@ -17,18 +97,21 @@ pub struct MmltSampler {
     iteration: u64,
     large_step_at: u64,
     mutation_type: MutationType,
-    rng: Box<dyn RngCore>,
+    // Whether the samples being drawn are those of the emission direction.
+    emission: bool,
+    stratifications: Vec<Stratification>,
+    rng: Generator,
 }
 
 struct Sample {
-    value: f64,
-    backup_value: f64,
+    value: Float,
+    backup_value: Float,
     modified_at: u64,
     backup_modified_at: u64,
 }
 
 impl Sample {
-    fn new(value: f64) -> Sample {
+    fn new(value: Float) -> Sample {
         Sample {
             value,
             backup_value: value,
@@ -52,13 +135,50 @@ impl Sample {
 pub enum MutationType {
     LargeStep,
     SmallStep,
+    // An explicit proposal for every primary sample.
+    Proposal,
+    // A small step confined to the samples of the emission direction.
+    Perturbation,
 }
 
 impl MmltSampler {
-    pub fn new(stream_count: usize) -> MmltSampler {
+    pub fn new(stream_count: usize, parameters: &MutationParameters, kind: RngKind) -> MmltSampler {
+        MmltSampler::with_rng(stream_count, parameters, kind.entropy_seeded())
+    }
+
+    // A seeded sampler produces the same primary samples, and therefore the
+    // same path, every time, so a state can be stored as just its seed.
+    pub fn seeded(
+        stream_count: usize,
+        parameters: &MutationParameters,
+        kind: RngKind,
+        seed: u64,
+    ) -> MmltSampler {
+        MmltSampler::with_rng(stream_count, parameters, kind.seeded(seed))
+    }
+
+    // Replaces the generator of the mutations to come, leaving the current
+    // state as it is.
+    pub fn reseed(&mut self, rng: Generator) {
+        self.rng = rng;
+    }
+
+    fn with_rng(
+        stream_count: usize,
+        parameters: &MutationParameters,
+        mut rng: Generator,
+    ) -> MmltSampler {
+        let mut stratifications = Vec::new();
+        if let Some(stream_index) = parameters.stratified_stream {
+            stratifications.push(Stratification::new(stream_index, &R2, &mut rng));
+        }
+        if let Some(stream_index) = parameters.stratified_sample {
+            stratifications.push(Stratification::new(stream_index, &R1, &mut rng));
+        }
         MmltSampler {
-            large_step_probability: 0.3,
-            sigma: 0.01,
+            large_step_probability: parameters.large_step_probability,
+            sigma: parameters.sigma,
+            stream_scales: parameters.stream_scales.clone(),
             stream_count,
             stream_index: 0,
             sample_index: 0,
@@ -66,14 +186,19 @@ impl MmltSampler {
             iteration: 0,
             large_step_at: 0,
             mutation_type: MutationType::SmallStep,
-            rng: Box::new(thread_rng()),
+            emission: false,
+            stratifications,
+            rng,
         }
     }
 
     pub fn mutate(&mut self) -> MutationType {
-        self.iteration = self.iteration + 1;
+        self.iteration += 1;
         let r = self.rng.gen_range(0.0..1.0);
         self.mutation_type = if r < self.large_step_probability {
+            for stratification in &mut self.stratifications {
+                stratification.count += 1;
+            }
             MutationType::LargeStep
         } else {
             MutationType::SmallStep
@@ -81,6 +206,47 @@ impl MmltSampler {
         self.mutation_type
     }
 
+    // Confines the current mutation, which must already have been started
+    // with `mutate`, to the samples marked as those of the emission
+    // direction.
+    pub fn perturb(&mut self) -> MutationType {
+        self.mutation_type = MutationType::Perturbation;
+        self.mutation_type
+    }
+
+    // The primary sample values of the current state. Samples invalidated by
+    // an earlier large step are regenerated here, just as they would be on
+    // their next use.
+    pub fn values(&mut self) -> Vec<Float> {
+        for sample in &mut self.samples {
+            if sample.modified_at < self.large_step_at {
+                sample.value = self.rng.gen_range(0.0..1.0);
+                sample.modified_at = self.large_step_at;
+            }
+        }
+        self.samples.iter().map(|sample| sample.value).collect()
+    }
+
+    // The primary sample values of the current state as they stand. Unlike
+    // `values`, the samples invalidated by an earlier large step are left
+    // alone, which leaves the random number generator alone too; the current
+    // path did not use them, so the values replay it all the same.
+    pub fn current_values(&self) -> Vec<Float> {
+        self.samples.iter().map(|sample| sample.value).collect()
+    }
+
+    // Replaces the current mutation, which must already have been started
+    // with `mutate`, by an explicit proposal for every primary sample.
+    pub fn propose(&mut self, values: &[Float]) -> MutationType {
+        self.mutation_type = MutationType::Proposal;
+        for (sample, value) in self.samples.iter_mut().zip(values) {
+            sample.backup();
+            sample.value = *value;
+            sample.modified_at = self.iteration;
+        }
+        self.mutation_type
+    }
+
     pub fn accept(&mut self) {
         if self.mutation_type == MutationType::LargeStep {
             self.large_step_at = self.iteration;
@@ -93,7 +259,7 @@ impl MmltSampler {
                 sample.restore();
             }
         }
-        self.iteration = self.iteration - 1;
+        self.iteration -= 1;
     }
 }
 
@@ -106,8 +272,13 @@ impl Sampler for MmltSampler {
         self.sample_index = 0;
     }
 
-    fn sample(&mut self, range: Range<f64>) -> f64 {
+    fn set_emission(&mut self, emission: bool) {
+        self.emission = emission;
+    }
+
+    fn sample(&mut self, range: Range<Float>) -> Float {
         let index = self.stream_count * self.sample_index + self.stream_index;
+        let new = index >= self.samples.len();
 
         while index >= self.samples.len() {
             let value = self.rng.gen_range(0.0..1.0);
@@ -117,42 +288,230 @@ impl Sampler for MmltSampler {
 
         let sample = &mut self.samples[index];
 
+        if self.mutation_type == MutationType::Proposal && sample.modified_at == self.iteration {
+            self.sample_index += 1;
+            return sample.value * (range.end - range.start) + range.start;
+        }
+
         if sample.modified_at < self.large_step_at {
             sample.value = self.rng.gen_range(0.0..1.0);
             sample.modified_at = self.large_step_at;
         }
 
+        if self.mutation_type == MutationType::Perturbation && !self.emission && !new {
+            self.sample_index += 1;
+            return sample.value * (range.end - range.start) + range.start;
+        }
+
         sample.backup();
 
         match self.mutation_type {
-            MutationType::SmallStep => {
-                let n = (self.iteration - sample.modified_at) as f64;
+            MutationType::SmallStep | MutationType::Perturbation => {
+                let n = (self.iteration - sample.modified_at) as Float;
                 let normal_value =
-                    f64::sqrt(2.0) * util::erf_inv(2.0 * self.rng.gen_range(0.0..1.0) - 1.0);
-                let effective_sigma = self.sigma * n.sqrt();
-                sample.value = sample.value + normal_value * effective_sigma;
+                    Float::sqrt(2.0) * util::erf_inv(2.0 * self.rng.gen_range(0.0..1.0) - 1.0);
+                let scale = self
+                    .stream_scales
+                    .get(self.stream_index)
+                    .copied()
+                    .unwrap_or(1.0);
+                let effective_sigma = self.sigma * scale * n.sqrt();
+                sample.value += normal_value * effective_sigma;
                 sample.value = sample.value - sample.value.floor();
             }
-            MutationType::LargeStep => sample.value = self.rng.gen_range(0.0..1.0),
+            MutationType::LargeStep => {
+                sample.value = match self.stratifications.iter().find(|stratification| {
+                    stratification.stream_index == self.stream_index
+                        && self.sample_index < stratification.ratios.len()
+                }) {
+                    Some(stratification) => stratification.value(self.sample_index),
+                    None => self.rng.gen_range(0.0..1.0),
+                }
+            }
+            // Samples beyond the proposal are new, and already uniform.
+            MutationType::Proposal => {}
         };
 
         sample.modified_at = self.iteration;
 
-        self.sample_index = self.sample_index + 1;
+        self.sample_index += 1;
 
         sample.value * (range.end - range.start) + range.start
     }
 }
 
+// Replays fixed primary samples, laid out as in `MmltSampler`. Samples beyond
+// the end are fixed as well, so that evaluations are deterministic.
+pub struct ReplaySampler {
+    values: Vec<Float>,
+    stream_count: usize,
+    stream_index: usize,
+    sample_index: usize,
+}
+
+impl ReplaySampler {
+    pub fn new(stream_count: usize, values: Vec<Float>) -> ReplaySampler {
+        ReplaySampler {
+            values,
+            stream_count,
+            stream_index: 0,
+            sample_index: 0,
+        }
+    }
+}
+
+impl Sampler for ReplaySampler {
+    fn start_stream(&mut self, index: usize) {
+        self.stream_index = index;
+        self.sample_index = 0;
+    }
+
+    fn sample(&mut self, range: Range<Float>) -> Float {
+        let index = self.stream_count * self.sample_index + self.stream_index;
+        self.sample_index += 1;
+        let value = self.values.get(index).copied().unwrap_or(0.5);
+        value * (range.end - range.start) + range.start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MmltSampler, MutationParameters, MutationType, ReplaySampler, Sampler};
+    use crate::rng::RngKind;
+
+    #[test]
+    fn test_seeded_sampler() {
+        let parameters = MutationParameters::default();
+        for kind in [RngKind::Pcg32, RngKind::ChaCha, RngKind::Xoshiro] {
+            let mut a = MmltSampler::seeded(2, &parameters, kind, 42);
+            let mut b = MmltSampler::seeded(2, &parameters, kind, 42);
+            for stream in [0, 1, 0] {
+                a.start_stream(stream);
+                b.start_stream(stream);
+                for _ in 0..4 {
+                    assert_eq!(a.sample(0.0..1.0), b.sample(0.0..1.0));
+                }
+            }
+            // Reseeded, they keep the same state but mutate it differently.
+            b.reseed(kind.seeded(43));
+            assert_eq!(a.values(), b.values());
+            a.mutate();
+            b.mutate();
+            a.start_stream(0);
+            b.start_stream(0);
+            assert_ne!(a.sample(0.0..1.0), b.sample(0.0..1.0));
+        }
+    }
+
+    #[test]
+    fn test_propose_and_replay() {
+        let mut sampler = MmltSampler::new(2, &MutationParameters::default(), RngKind::default());
+        sampler.start_stream(0);
+        sampler.sample(0.0..1.0);
+        sampler.start_stream(1);
+        sampler.sample(0.0..1.0);
+        let values = sampler.values();
+        assert_eq!(values.len(), 2);
+
+        let mut replay = ReplaySampler::new(2, values.clone());
+        replay.start_stream(1);
+        assert_eq!(replay.sample(0.0..2.0), 2.0 * values[1]);
+        assert_eq!(replay.sample(0.0..1.0), 0.5);
+
+        assert_eq!(sampler.mutate(), sampler.mutation_type);
+        sampler.propose(&[0.25, 0.75]);
+        sampler.start_stream(1);
+        assert_eq!(sampler.sample(0.0..1.0), 0.75);
+        sampler.start_stream(0);
+        assert_eq!(sampler.sample(0.0..1.0), 0.25);
+        assert_eq!(sampler.mutation_type, MutationType::Proposal);
+        sampler.reject();
+        assert_eq!(sampler.values(), values);
+    }
+
+    #[test]
+    fn test_stream_scales() {
+        let parameters = MutationParameters {
+            stream_scales: vec![0.0, 1.0],
+            ..MutationParameters::default()
+        };
+        let mut sampler = MmltSampler::new(2, &parameters, RngKind::default());
+        for stream in 0..2 {
+            sampler.start_stream(stream);
+            sampler.sample(0.0..1.0);
+        }
+        let values = sampler.values();
+        while sampler.mutate() != MutationType::SmallStep {
+            sampler.reject();
+        }
+        sampler.start_stream(0);
+        assert_eq!(sampler.sample(0.0..1.0), values[0]);
+        sampler.start_stream(1);
+        assert_ne!(sampler.sample(0.0..1.0), values[1]);
+    }
+
+    #[test]
+    fn test_stratified_large_steps() {
+        let parameters = MutationParameters {
+            large_step_probability: 1.0,
+            stratified_stream: Some(1),
+            ..MutationParameters::default()
+        };
+        let mut sampler = MmltSampler::new(2, &parameters, RngKind::default());
+        // Every row and column of an 8 × 8 grid is covered by 64 steps.
+        let (mut rows, mut columns) = ([false; 8], [false; 8]);
+        for _ in 0..64 {
+            assert_eq!(sampler.mutate(), MutationType::LargeStep);
+            sampler.start_stream(1);
+            let x = sampler.sample(0.0..8.0) as usize;
+            let y = sampler.sample(0.0..8.0) as usize;
+            columns[x] = true;
+            rows[y] = true;
+            sampler.accept();
+        }
+        assert!(rows.iter().chain(&columns).all(|covered| *covered));
+    }
+
+    #[test]
+    fn test_perturbation() {
+        let mut sampler = MmltSampler::new(2, &MutationParameters::default(), RngKind::default());
+        // The second sample of the second stream is of the emission
+        // direction, between samples that are not.
+        let draw = |sampler: &mut MmltSampler| {
+            sampler.start_stream(0);
+            let mut drawn = vec![sampler.sample(0.0..1.0)];
+            sampler.start_stream(1);
+            drawn.push(sampler.sample(0.0..1.0));
+            sampler.set_emission(true);
+            drawn.push(sampler.sample(0.0..1.0));
+            sampler.set_emission(false);
+            drawn.push(sampler.sample(0.0..1.0));
+            drawn
+        };
+        let values = draw(&mut sampler);
+        let state = sampler.values();
+        sampler.mutate();
+        assert_eq!(sampler.perturb(), MutationType::Perturbation);
+        let perturbed = draw(&mut sampler);
+        assert_eq!(perturbed[0], values[0]);
+        assert_eq!(perturbed[1], values[1]);
+        assert_ne!(perturbed[2], values[2]);
+        assert_eq!(perturbed[3], values[3]);
+        sampler.reject();
+        assert_eq!(sampler.values(), state);
+    }
+}
+
 #[cfg(test)]
 pub mod test {
     use rand::{thread_rng, Rng};
 
     use super::Sampler;
+    use crate::types::Float;
     use std::{collections::VecDeque, ops::Range};
 
     pub struct MockSampler {
-        samples: VecDeque<f64>,
+        samples: VecDeque<Float>,
     }
 
     impl MockSampler {
@@ -162,7 +521,7 @@ pub mod test {
             }
         }
 
-        pub fn add(&mut self, sample: f64) {
+        pub fn add(&mut self, sample: Float) {
             self.samples.push_back(sample)
         }
     }
@@ -172,7 +531,7 @@ pub mod test {
             // nothing
         }
 
-        fn sample(&mut self, range: Range<f64>) -> f64 {
+        fn sample(&mut self, range: Range<Float>) -> Float {
             let r = self
                 .samples
                 .pop_front()
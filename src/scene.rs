@@ -1,18 +1,30 @@
-use std::fs::File;
-use std::io;
+use std::fs;
 
 use serde::{Deserialize, Serialize};
 
-use crate::image::ImageConfig;
-use crate::light::LightConfig;
+use crate::image::{FilterConfig, ImageConfig};
+use crate::light::{LightConfig, LightOverrides};
+use crate::material::MaterialConfig;
 use crate::object::ObjectConfig;
+use crate::spectrum::SpectrumConfig;
 use crate::{
-    camera::{Camera, CameraConfig},
+    bounds::Bounds3,
+    bvh::Bvh,
+    camera::{Camera, CameraConfig, NamedCameraConfig},
+    debug::PixelDebug,
+    environment::{EnvironmentLight, EnvironmentLightConfig},
+    error::Error,
     interaction::Interaction,
     light::Light,
+    memory,
     object::Object,
-    ray::Ray,
-    sampler::Sampler,
+    path::MutationConfig,
+    profile::{self, Subsystem},
+    ray::{Ray, RayKind},
+    sampler::{MutationParameters, ReplaySampler, Sampler},
+    shape::{Placement, TransformConfig},
+    template,
+    types::Float,
 };
 
 pub struct Scene {
@@ -20,88 +32,543 @@ pub struct Scene {
     pub lights: Vec<Box<dyn Light>>,
     pub objects: Vec<Box<dyn Object>>,
     pub image_config: ImageConfig,
+    pub manifold_next_event_estimation: bool,
+    pub debug_pixel: Option<PixelDebug>,
+    // The roughness to which specular vertices away from the camera are
+    // widened, if any.
+    pub regularization: Option<Float>,
+    // The most consecutive specular bounces of a subpath, beyond which it
+    // is terminated.
+    pub max_specular_depth: Option<usize>,
+    pub mutation_parameters: MutationParameters,
+    // The heap memory taken by the lights and objects, if it is counted.
+    pub geometry_bytes: usize,
+    pub bounds: Bounds3,
+    // The hierarchy over the lights and then the objects, through which rays
+    // find them.
+    bvh: Bvh,
+    // Whether the last light is an environment, which the hierarchy leaves
+    // out.
+    environment: bool,
+}
+
+// The lights and objects of a scene under construction, to which other parts
+// of the scene may refer by ID.
+pub struct SceneGeometry<'a> {
+    pub lights: &'a [Box<dyn Light>],
+    pub objects: &'a [Box<dyn Object>],
+    pub bounds: Bounds3,
+}
+
+impl SceneGeometry<'_> {
+    // The distance along the ray to the first point of the object or light
+    // with the given ID, or to the center of its bounds projected onto the
+    // ray when the ray misses it.
+    pub fn distance_along(&self, id: &str, ray: Ray) -> Option<Float> {
+        let object = self.objects.iter().find(|object| object.id() == id);
+        let light = self.lights.iter().find(|light| light.id() == id);
+        let hit = match (object, light) {
+            (Some(object), _) => object.intersect(ray),
+            (None, Some(light)) => light.intersect(ray),
+            (None, None) => return None,
+        };
+        match hit {
+            Some(interaction) => Some(interaction.distance()),
+            None => Some((self.bounds_of(id)?.center() - ray.origin).dot(ray.direction)),
+        }
+    }
+
+    pub fn bounds_of(&self, id: &str) -> Option<Bounds3> {
+        let object = self.objects.iter().find(|object| object.id() == id);
+        let light = self.lights.iter().find(|light| light.id() == id);
+        object
+            .map(|object| object.bounds())
+            .or_else(|| light.map(|light| light.bounds()))
+    }
 }
 
 impl SceneConfig {
-    pub fn configure(self: SceneConfig) -> Scene {
-        let camera = Box::new(self.camera.configure(self.image.width, self.image.height));
-        let lights = self
+    // The camera is the one with the given name, or without a name the
+    // unnamed camera if there is one and otherwise the first named camera.
+    pub fn configure(
+        mut self: SceneConfig,
+        camera: Option<&str>,
+        overrides: &LightOverrides,
+    ) -> Result<Scene, String> {
+        let defaults = self.defaults.take().unwrap_or_default();
+        let mut ids = Vec::new();
+        self.for_each_light(&mut |light| {
+            light.apply(overrides);
+            ids.push(String::from(light.id()));
+        });
+        if let Some(id) = overrides.ids().find(|id| !ids.contains(id)) {
+            return Err(format!("no light with ID '{}'", id));
+        }
+        if self.image.filter.is_none() {
+            self.image.filter = defaults.filter;
+        }
+        let cameras = self.cameras.unwrap_or_default();
+        let camera = match camera {
+            Some(name) => cameras
+                .into_iter()
+                .find(|c| c.name == name)
+                .map(|c| c.camera)
+                .ok_or_else(|| format!("no camera named '{}'", name))?,
+            None => self
+                .camera
+                .or_else(|| cameras.into_iter().next().map(|c| c.camera))
+                .ok_or("the scene has no camera")?,
+        };
+        let mut light_configs: Vec<(&LightConfig, Placement)> = self
             .lights
             .iter()
-            .map(|c| c.configure(self.lights.len()))
+            .map(|c| (c, Placement::identity()))
+            .collect();
+        let mut object_configs: Vec<(&ObjectConfig, Placement)> = self
+            .objects
+            .iter()
+            .map(|c| (c, Placement::identity()))
+            .collect();
+        for group in self.groups.iter().flatten() {
+            group.flatten(
+                Placement::identity(),
+                &mut light_configs,
+                &mut object_configs,
+            )?;
+        }
+        light_configs.retain(|(c, _)| c.is_enabled());
+        // The environment surrounds the scene, and is configured once the
+        // scene and its camera are.
+        let environments: Vec<(&EnvironmentLightConfig, Placement)> = light_configs
+            .iter()
+            .filter_map(|(c, placement)| match c {
+                LightConfig::Environment(config) => Some((config, *placement)),
+                _ => None,
+            })
+            .collect();
+        if environments.len() > 1 {
+            return Err(String::from(
+                "the scene has more than one environment light",
+            ));
+        }
+        light_configs.retain(|(c, _)| !matches!(c, LightConfig::Environment(_)));
+        let light_count = light_configs.len() + environments.len();
+        let allocated = memory::allocated();
+        let mut lights: Vec<Box<dyn Light>> = light_configs
+            .iter()
+            .map(|(c, placement)| c.configure(light_count, defaults.spectrum.as_ref(), *placement))
+            .collect::<Result<_, _>>()?;
+        let objects: Vec<Box<dyn Object>> = object_configs
+            .iter()
+            .map(|(c, placement)| c.configure(defaults.material.as_ref(), *placement))
+            .collect::<Result<_, _>>()?;
+        // The lights come first among the primitives of the hierarchy, and
+        // then the objects.
+        let primitive_bounds: Vec<Bounds3> = lights
+            .iter()
+            .map(|light| light.bounds())
+            .chain(objects.iter().map(|object| object.bounds()))
             .collect();
-        let objects = self.objects.iter().map(|c| c.configure()).collect();
-        Scene {
+        let bvh = Bvh::build(&primitive_bounds);
+        let geometry_bytes = memory::allocated().saturating_sub(allocated);
+        for (light, _) in &light_configs {
+            if let Some(id) = light
+                .linked_ids()
+                .find(|id| !objects.iter().any(|object| object.id() == *id))
+            {
+                return Err(format!(
+                    "light '{}' is linked to unknown object '{}'",
+                    light.id(),
+                    id
+                ));
+            }
+        }
+        let bounds = bvh.bounds();
+        let geometry = SceneGeometry {
+            lights: &lights,
+            objects: &objects,
+            bounds,
+        };
+        let camera = camera.configure(self.image.width, self.image.height, &geometry)?;
+        if let Some((config, placement)) = environments.first() {
+            let origin = camera
+                .sample_interaction(&mut ReplaySampler::new(1, Vec::new()))
+                .geometry()
+                .point;
+            let environment =
+                EnvironmentLight::configure(config, light_count, *placement, bounds, origin)?;
+            lights.push(Box::new(environment));
+        }
+        Ok(Scene {
             camera,
             lights,
             objects,
             image_config: self.image,
-        }
+            manifold_next_event_estimation: false,
+            debug_pixel: None,
+            regularization: None,
+            max_specular_depth: None,
+            mutation_parameters: self
+                .mutation
+                .as_ref()
+                .map(MutationConfig::configure)
+                .unwrap_or_default(),
+            geometry_bytes,
+            bounds,
+            bvh,
+            environment: !environments.is_empty(),
+        })
     }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SceneConfig {
     pub image: ImageConfig,
-    pub camera: CameraConfig,
+    pub camera: Option<CameraConfig>,
+    pub cameras: Option<Vec<NamedCameraConfig>>,
     pub lights: Vec<LightConfig>,
     pub objects: Vec<ObjectConfig>,
+    pub mutation: Option<MutationConfig>,
+    pub defaults: Option<DefaultsConfig>,
+    pub groups: Option<Vec<GroupConfig>>,
+}
+
+// Lights, objects and nested groups placed together, as the parts of an
+// assembly, by a transform applied after their own and before those of the
+// enclosing groups.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GroupConfig {
+    pub transform: Option<TransformConfig>,
+    pub lights: Option<Vec<LightConfig>>,
+    pub objects: Option<Vec<ObjectConfig>>,
+    pub groups: Option<Vec<GroupConfig>>,
+}
+
+impl GroupConfig {
+    fn for_each_light(&mut self, f: &mut impl FnMut(&mut LightConfig)) {
+        self.lights.iter_mut().flatten().for_each(&mut *f);
+        for group in self.groups.iter_mut().flatten() {
+            group.for_each_light(f);
+        }
+    }
+
+    // Adds the lights and objects of the group and its subgroups, each with
+    // its placement in the scene.
+    fn flatten<'a>(
+        &'a self,
+        parent: Placement,
+        lights: &mut Vec<(&'a LightConfig, Placement)>,
+        objects: &mut Vec<(&'a ObjectConfig, Placement)>,
+    ) -> Result<(), String> {
+        let placement = match &self.transform {
+            Some(transform) => Placement::configure(transform)?.within(parent),
+            None => parent,
+        };
+        lights.extend(self.lights.iter().flatten().map(|c| (c, placement)));
+        objects.extend(self.objects.iter().flatten().map(|c| (c, placement)));
+        for group in self.groups.iter().flatten() {
+            group.flatten(placement, lights, objects)?;
+        }
+        Ok(())
+    }
+}
+
+// Settings applied to the parts of the scene that omit them.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct DefaultsConfig {
+    pub material: Option<MaterialConfig>,
+    pub filter: Option<FilterConfig>,
+    // The emitted spectrum of lights.
+    pub spectrum: Option<SpectrumConfig>,
+}
+
+impl SceneConfig {
+    fn for_each_light(&mut self, f: &mut impl FnMut(&mut LightConfig)) {
+        self.lights.iter_mut().for_each(&mut *f);
+        for group in self.groups.iter_mut().flatten() {
+            group.for_each_light(f);
+        }
+    }
+
+    pub fn load(path: &str, definitions: &[(String, String)]) -> Result<SceneConfig, Error> {
+        let source = fs::read_to_string(path).map_err(Error::io(path))?;
+        let source =
+            template::substitute(&source, definitions).map_err(|message| Error::Scene {
+                path: String::from(path),
+                location: None,
+                message,
+            })?;
+        serde_yaml::from_str(&source).map_err(|e| Error::scene(path, e))
+    }
 }
 
 impl Scene {
-    pub fn load(path: String) -> Result<Scene, String> {
-        let file = File::open(path).map_err(|e: io::Error| e.to_string())?;
-        let config: SceneConfig =
-            serde_yaml::from_reader(file).map_err(|e: serde_yaml::Error| e.to_string())?;
-        let scene = config.configure();
-        Ok(scene)
+    pub fn load(
+        path: String,
+        camera: Option<&str>,
+        definitions: &[(String, String)],
+        overrides: &LightOverrides,
+    ) -> Result<Scene, Error> {
+        let config = SceneConfig::load(&path, definitions)?;
+        config
+            .configure(camera, overrides)
+            .map_err(|message| Error::Scene {
+                path: path.clone(),
+                location: None,
+                message,
+            })
     }
 
-    pub fn intersect(&self, ray: Ray) -> Option<Interaction> {
+    // Each hit narrows the interval of the ray, so that only closer hits
+    // are found afterwards, and the hierarchy skips the lights and objects
+    // beyond them. Cameras and objects hidden from rays of the kind are
+    // passed through, and rays that escape the scene reach the environment,
+    // if there is one.
+    pub fn intersect(&self, mut ray: Ray, kind: RayKind) -> Option<Interaction<'_>> {
+        let _timer = profile::time(Subsystem::Intersection);
         let mut result: Option<Interaction> = None;
 
-        if let Some(candidate) = self.camera.intersect(ray) {
-            if let Some(ref best) = result {
-                if candidate.distance() < best.distance() {
-                    result = Some(candidate);
-                }
-            } else {
-                result = Some(candidate);
-            }
+        let camera = Some(&self.camera).filter(|camera| camera.is_visible(kind));
+        if let Some(candidate) = camera.and_then(|camera| camera.intersect(ray)) {
+            ray.t_max = candidate.distance();
+            result = Some(candidate);
         }
 
-        for light in &self.lights {
-            if let Some(candidate) = light.intersect(ray) {
-                if let Some(ref best) = result {
-                    if candidate.distance() < best.distance() {
-                        result = Some(candidate);
-                    }
-                } else {
-                    result = Some(candidate);
-                }
+        let light_count = self.lights.len() - self.environment as usize;
+        self.bvh.traverse(&mut ray, |index, ray| {
+            let candidate = match index.checked_sub(light_count) {
+                None => self.lights[index].intersect(*ray),
+                Some(index) => Some(&self.objects[index])
+                    .filter(|o| o.is_visible(kind))
+                    .and_then(|o| o.intersect(*ray)),
+            };
+            if let Some(candidate) = candidate {
+                ray.t_max = candidate.distance();
+                result = Some(candidate);
             }
-        }
+        });
 
-        for object in &self.objects {
-            if let Some(candidate) = object.intersect(ray) {
-                if let Some(ref best) = result {
-                    if candidate.distance() < best.distance() {
-                        result = Some(candidate);
-                    }
-                } else {
-                    result = Some(candidate);
-                }
-            }
+        if result.is_none() && self.environment {
+            result = self.lights.last().and_then(|light| light.intersect(ray));
         }
-
         result
     }
 
-    pub fn sample_light(&self, sampler: &mut impl Sampler) -> &(dyn Light) {
+    pub fn sample_light(&self, sampler: &mut impl Sampler) -> &dyn Light {
         let start = 0.0;
-        let end = self.lights.len() as f64;
+        let end = self.lights.len() as Float;
         let r = sampler.sample(start..end);
         let i = r.floor() as usize;
         self.lights[i].as_ref()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::SceneConfig;
+    use crate::{
+        approx::ApproxEq,
+        light::LightOverrides,
+        ray::{Ray, RayKind},
+        sampler::test::MockSampler,
+        vector::{Point3, Vector3},
+    };
+
+    const SCENE: &str = "
+image: { width: 4, height: 4, filter: { type: box } }
+cameras:
+  - name: front
+    type: pinhole
+    origin: { x: 0.0, y: 0.0, z: 10.0 }
+    look_at: { x: 0.0, y: 0.0, z: 0.0 }
+    field_of_view: { value: 40.0, unit: degrees }
+  - name: side
+    type: pinhole
+    origin: { x: 10.0, y: 0.0, z: 0.0 }
+    look_at: { x: 0.0, y: 0.0, z: 0.0 }
+    field_of_view: { value: 40.0, unit: degrees }
+lights: []
+objects: []
+";
+
+    fn camera_origin(camera: Option<&str>) -> Result<Point3, String> {
+        let config = serde_yaml::from_str::<SceneConfig>(SCENE).unwrap();
+        let scene = config.configure(camera, &LightOverrides::default())?;
+        let mut sampler = MockSampler::new();
+        sampler.add(0.5);
+        sampler.add(0.5);
+        Ok(scene
+            .camera
+            .sample_interaction(&mut sampler)
+            .geometry()
+            .point)
+    }
+
+    const DEFAULTS_SCENE: &str = "
+image: { width: 4, height: 4 }
+camera:
+  type: pinhole
+  origin: { x: 0.0, y: 0.0, z: 10.0 }
+  look_at: { x: 0.0, y: 0.0, z: 0.0 }
+  field_of_view: { value: 40.0, unit: degrees }
+defaults:
+  material:
+    type: matte
+    texture: { type: constant, spectrum: { r: 0.5, g: 0.5, b: 0.5 } }
+  filter: { type: box }
+  spectrum: { r: 1.0, g: 1.0, b: 1.0 }
+lights:
+  - id: light
+    type: diffuse_area
+    shape: { type: sphere, center: { x: 0.0, y: 5.0, z: 0.0 }, radius: 1.0 }
+objects:
+  - id: ball
+    type: geometric
+    shape: { type: sphere, center: { x: 0.0, y: 0.0, z: 0.0 }, radius: 1.0 }
+";
+
+    #[test]
+    fn test_defaults() {
+        let config = serde_yaml::from_str::<SceneConfig>(DEFAULTS_SCENE).unwrap();
+        let scene = config.configure(None, &LightOverrides::default()).unwrap();
+        assert!(scene.image_config.filter.is_some());
+        assert_eq!(scene.lights.len(), 1);
+        assert_eq!(scene.objects.len(), 1);
+
+        let without = DEFAULTS_SCENE.replace("  material:", "  unused:");
+        let config = serde_yaml::from_str::<SceneConfig>(&without).unwrap();
+        assert!(config.configure(None, &LightOverrides::default()).is_err());
+    }
+
+    #[test]
+    fn test_groups() {
+        let source = DEFAULTS_SCENE.replace(
+            "lights:",
+            "
+groups:
+  - transform: { translation: { x: 10.0, y: 0.0, z: 0.0 }, scale: 2.0 }
+    lights:
+      - id: lamp
+        type: diffuse_area
+        shape: { type: sphere, center: { x: 0.0, y: 1.0, z: 0.0 }, radius: 0.5 }
+    groups:
+      - transform: { translation: { x: 0.0, y: 0.0, z: 1.0 } }
+        objects:
+          - id: stand
+            type: geometric
+            shape: { type: sphere, center: { x: 0.0, y: 0.0, z: 0.0 }, radius: 1.0 }
+lights:",
+        );
+        let config = serde_yaml::from_str::<SceneConfig>(&source).unwrap();
+        let scene = config.configure(None, &LightOverrides::default()).unwrap();
+        assert_eq!(scene.lights.len(), 2);
+        assert_eq!(scene.objects.len(), 2);
+        let lamp = scene.lights[1].bounds();
+        assert!(lamp.min.approx_eq(Point3::new(9.0, 1.0, -1.0), 1e-9));
+        assert!(lamp.max.approx_eq(Point3::new(11.0, 3.0, 1.0), 1e-9));
+        let stand = scene.objects[1].bounds();
+        assert!(stand.min.approx_eq(Point3::new(8.0, -2.0, 0.0), 1e-9));
+        assert!(stand.max.approx_eq(Point3::new(12.0, 2.0, 4.0), 1e-9));
+    }
+
+    #[test]
+    fn test_light_overrides() {
+        let source = DEFAULTS_SCENE.replace(
+            "objects:",
+            "  - id: fill
+    type: diffuse_area
+    enabled: false
+    shape: { type: sphere, center: { x: 0.0, y: -5.0, z: 0.0 }, radius: 1.0 }
+objects:",
+        );
+        let configure = |overrides: &LightOverrides| {
+            serde_yaml::from_str::<SceneConfig>(&source)
+                .unwrap()
+                .configure(None, overrides)
+        };
+        assert_eq!(
+            configure(&LightOverrides::default()).unwrap().lights.len(),
+            1
+        );
+        let solo = LightOverrides {
+            scales: Vec::new(),
+            solo: vec![String::from("fill"), String::from("light")],
+        };
+        assert_eq!(configure(&solo).unwrap().lights.len(), 2);
+        let unknown = LightOverrides {
+            scales: vec![(String::from("lamp"), 2.0)],
+            solo: Vec::new(),
+        };
+        assert!(configure(&unknown).is_err());
+
+        let linked = source.replace("    enabled: false\n", "    exclude: [ball]\n");
+        assert!(serde_yaml::from_str::<SceneConfig>(&linked)
+            .unwrap()
+            .configure(None, &LightOverrides::default())
+            .is_ok());
+        let linked = linked.replace("[ball]", "[wall]");
+        assert!(serde_yaml::from_str::<SceneConfig>(&linked)
+            .unwrap()
+            .configure(None, &LightOverrides::default())
+            .is_err());
+    }
+
+    #[test]
+    fn test_visibility() {
+        let source = DEFAULTS_SCENE.replace(
+            "    type: geometric\n",
+            "    type: geometric\n    visible_to_camera: false\n    casts_shadow: false\n",
+        );
+        let config = serde_yaml::from_str::<SceneConfig>(&source).unwrap();
+        let scene = config.configure(None, &LightOverrides::default()).unwrap();
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        assert!(scene.intersect(ray, RayKind::Camera).is_none());
+        assert!(scene.intersect(ray, RayKind::Shadow).is_none());
+        let ball = scene.intersect(ray, RayKind::Indirect).unwrap();
+        assert_eq!(ball.id(), "ball");
+    }
+
+    #[test]
+    fn test_environment() {
+        let path = env::temp_dir().join("mmlt-test-environment.hdr");
+        let mut bytes = b"#?RADIANCE\n\n-Y 2 +X 4\n".to_vec();
+        bytes.extend([128; 4].repeat(8));
+        fs::write(&path, bytes).unwrap();
+        let environment = format!(
+            "  - id: sky\n    type: environment\n    path: {}\nobjects:",
+            path.display()
+        );
+        let source = DEFAULTS_SCENE.replace("objects:", &environment);
+        let scene = serde_yaml::from_str::<SceneConfig>(&source)
+            .unwrap()
+            .configure(None, &LightOverrides::default())
+            .unwrap();
+        assert_eq!(scene.lights.len(), 2);
+        assert_eq!(scene.lights[0].sampling_pdf(), 0.5);
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(scene.intersect(ray, RayKind::Camera).unwrap().id(), "ball");
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vector3::new(1.0, 0.0, 0.0));
+        let sky = scene.intersect(ray, RayKind::Shadow).unwrap();
+        assert!(sky.is_environment());
+        assert!(sky.geometry().normal.dot(ray.direction) < 0.0);
+
+        let twice = source.replace("objects:", &environment.replace("sky", "sun"));
+        assert!(serde_yaml::from_str::<SceneConfig>(&twice)
+            .unwrap()
+            .configure(None, &LightOverrides::default())
+            .is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_camera_selection() {
+        assert_eq!(camera_origin(None).unwrap(), Point3::new(0.0, 0.0, 10.0));
+        assert_eq!(
+            camera_origin(Some("side")).unwrap(),
+            Point3::new(10.0, 0.0, 0.0)
+        );
+        assert!(camera_origin(Some("top")).is_err());
+    }
+}
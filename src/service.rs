@@ -0,0 +1,1038 @@
+use std::{
+    env, fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde_yaml::Value;
+
+use crate::{
+    config::{Config, ServeConfig},
+    error::Error,
+    image::{Image, Preview},
+    interrupt::Cancellation,
+    job::Job,
+    progress::{report, Phase, ProgressSink},
+    template,
+    types::Float,
+    websocket::{self, Opcode},
+};
+
+// A render in progress writes its image at most this often, and updates its
+// preview for streaming at most this often.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(2);
+const PREVIEW_INTERVAL: Duration = Duration::from_millis(500);
+// Streams send the tiles of the preview that have changed, at this size.
+const STREAM_TILE_SIZE: usize = 32;
+// Larger submissions are refused, as are requests whose request line and
+// headers are longer than this.
+const MAX_BODY_SIZE: usize = 64 << 20;
+const MAX_HEADER_SIZE: u64 = 64 << 10;
+// A client that sends nothing for this long is disconnected, so that slow
+// or stalled clients cannot hold on to the thread of their connection.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+// Connections beyond this many at once are refused, so that many slow
+// clients cannot exhaust the threads and memory of the service.
+const MAX_CONNECTIONS: usize = 64;
+// The keys of a scene whose values name files to read.
+const ASSET_KEYS: [&str; 2] = ["path", "aperture"];
+const FORMATS: [&str; 5] = ["png", "exr", "pfm", "ppm", "tif"];
+// The flags a submission may give, which set the quality of its render.
+// Flags that name files are left to whoever starts the service, as a client
+// could otherwise read or overwrite any file the service can.
+const SUBMISSION_FLAGS: [&str; 20] = [
+    "min-path-length",
+    "max-path-length",
+    "initial-sample-count",
+    "average-samples-per-pixel",
+    "chain-count",
+    "burn-in",
+    "langevin-probability",
+    "caustic-perturbation-probability",
+    "technique-mutation-probability",
+    "manifold-next-event-estimation",
+    "regularization",
+    "max-specular-depth",
+    "sigma",
+    "large-step-probability",
+    "stratify-film",
+    "stratify-techniques",
+    "seed",
+    "rng",
+    "threads",
+    "define",
+];
+
+// `mmlt serve` exposes the renderer over HTTP, for a web front-end or the
+// controller of a render farm:
+//
+//   POST   /jobs              submits the scene in the body, with flags as
+//                             query parameters, e.g. `?max-path-length=8`,
+//                             and the format of the image as `format`
+//   GET    /jobs              the status of every job
+//   GET    /jobs/{id}         the status of a job, with its progress and the
+//                             messages and statistics of its render
+//   GET    /jobs/{id}/image   the image so far, or the final image
+//   GET    /jobs/{id}/stream  a WebSocket of the preview as it converges
+//   DELETE /jobs/{id}         cancels a job
+//
+// Jobs are rendered one at a time, in the order submitted, as each render
+// takes every thread.
+pub fn serve(config: &ServeConfig) -> Result<(), Error> {
+    fs::create_dir_all(&config.directory).map_err(Error::io(&config.directory))?;
+    let listener = TcpListener::bind(&config.address).map_err(Error::io(&config.address))?;
+    let (sender, receiver) = mpsc::channel::<Arc<ServiceJob>>();
+    let service = Arc::new(Service {
+        directory: config.directory.clone(),
+        render_args: config.render_args.clone(),
+        variables: env::vars().collect(),
+        allow_origin: config.allow_origin.clone(),
+        asset_directory: config.asset_directory.clone(),
+        jobs: Mutex::new(Vec::new()),
+        queue: sender,
+    });
+    thread::spawn(move || {
+        for job in receiver {
+            job.run();
+        }
+    });
+    report(&format!("listening on {}", config.address));
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let Some(slot) = ConnectionSlot::acquire(&connections) else {
+            let response = Response::error(503, "too many connections");
+            let _ = response.write(&stream, service.allow_origin.as_deref());
+            continue;
+        };
+        let service = Arc::clone(&service);
+        thread::spawn(move || {
+            let _slot = slot;
+            if let Err(e) = service.respond(stream) {
+                report(&format!("request failed: {}", e));
+            }
+        });
+    }
+    Ok(())
+}
+
+// A connection counted against MAX_CONNECTIONS for as long as it is held,
+// even if its thread panics.
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+    fn acquire(connections: &Arc<AtomicUsize>) -> Option<ConnectionSlot> {
+        connections
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                (count < MAX_CONNECTIONS).then_some(count + 1)
+            })
+            .ok()?;
+        Some(ConnectionSlot(Arc::clone(connections)))
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+struct Service {
+    directory: String,
+    render_args: Vec<String>,
+    // The environment of the service, whose MMLT_ variables each job takes
+    // as settings.
+    variables: Vec<(String, String)>,
+    // The origin whose pages may call the service from a browser, if any.
+    allow_origin: Option<String>,
+    // The directory under which submitted scenes may read files, without
+    // which they may not read any.
+    asset_directory: Option<String>,
+    jobs: Mutex<Vec<Arc<ServiceJob>>>,
+    queue: mpsc::Sender<Arc<ServiceJob>>,
+}
+
+impl Service {
+    fn respond(&self, stream: TcpStream) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut reader = BufReader::new(&stream);
+        let request = match Request::read(&mut reader) {
+            Ok(request) => request,
+            Err(message) => {
+                return Response::error(400, &message).write(&stream, self.allow_origin.as_deref())
+            }
+        };
+        // The stream takes over the connection rather than responding.
+        if let Some(key) = request.header("sec-websocket-key") {
+            if let ("GET", ["jobs", id, "stream"]) = (request.method.as_str(), &*request.segments())
+            {
+                if let Some(job) = self.job(id) {
+                    if !self.allows_origin(request.header("origin")) {
+                        return Response::error(403, "origin not allowed")
+                            .write(&stream, self.allow_origin.as_deref());
+                    }
+                    let upgrade = request.header("upgrade");
+                    let version = request.header("sec-websocket-version");
+                    if !websocket::handshake(&stream, upgrade, version, key)? {
+                        return Ok(());
+                    }
+                    // A viewer need not send anything while it watches.
+                    stream.set_read_timeout(None)?;
+                    let frames = websocket::read_frames(stream.try_clone()?);
+                    let result = job.stream(&stream, &frames);
+                    // Ends the reader of the frames, should the client not
+                    // close the connection itself.
+                    let _ = stream.shutdown(Shutdown::Both);
+                    return result;
+                }
+            }
+        }
+        self.handle(&request)
+            .write(&stream, self.allow_origin.as_deref())
+    }
+
+    // WebSockets are not bound by the origin policy of browsers, so a
+    // page from another origin could otherwise watch renders that it could
+    // not poll for. Clients other than browsers send no origin.
+    fn allows_origin(&self, origin: Option<&str>) -> bool {
+        match (&self.allow_origin, origin) {
+            (Some(allowed), Some(origin)) => allowed == origin,
+            _ => true,
+        }
+    }
+
+    fn job(&self, id: &str) -> Option<Arc<ServiceJob>> {
+        let id: usize = id.parse().ok()?;
+        self.jobs.lock().unwrap().get(id.checked_sub(1)?).cloned()
+    }
+
+    fn handle(&self, request: &Request) -> Response {
+        let job = |id| self.job(id);
+        match (request.method.as_str(), &*request.segments()) {
+            ("GET", ["jobs"]) => {
+                let jobs = self.jobs.lock().unwrap();
+                let statuses: Vec<String> = jobs.iter().map(|job| job.status()).collect();
+                Response::json(200, format!("[{}]", statuses.join(", ")))
+            }
+            ("POST", ["jobs"]) => self.submit(request),
+            ("GET", ["jobs", id]) => match job(id) {
+                Some(job) => Response::json(200, job.status()),
+                None => Response::error(404, "no such job"),
+            },
+            ("DELETE", ["jobs", id]) => match job(id) {
+                Some(job) => {
+                    job.cancel();
+                    Response::json(202, job.status())
+                }
+                None => Response::error(404, "no such job"),
+            },
+            ("GET", ["jobs", id, "image"]) => match job(id) {
+                Some(job) => job.image(),
+                None => Response::error(404, "no such job"),
+            },
+            ("GET", ["jobs", id, "stream"]) => match job(id) {
+                Some(_) => Response::error(400, "expected a WebSocket upgrade"),
+                None => Response::error(404, "no such job"),
+            },
+            (_, ["jobs"] | ["jobs", _] | ["jobs", _, "image" | "stream"]) => {
+                Response::error(405, "method not allowed")
+            }
+            _ => Response::error(404, "not found"),
+        }
+    }
+
+    fn submit(&self, request: &Request) -> Response {
+        let mut jobs = self.jobs.lock().unwrap();
+        let id = jobs.len() + 1;
+        let mut format = "png";
+        let mut flags = Vec::new();
+        for (name, value) in &request.query {
+            match name.as_str() {
+                "format" => match FORMATS.iter().find(|f| *f == value) {
+                    Some(f) => format = f,
+                    None => return Response::error(400, &format!("unknown format '{}'", value)),
+                },
+                // Each value is one argument, which cannot be taken for a
+                // flag, so that a value cannot smuggle in flags outside of
+                // the list.
+                name if SUBMISSION_FLAGS.contains(&name) => {
+                    if value.starts_with("--") {
+                        return Response::error(
+                            400,
+                            &format!("the value of --{} cannot be a flag", name),
+                        );
+                    }
+                    flags.push(format!("--{}", name));
+                    flags.push(value.clone());
+                }
+                _ => {
+                    return Response::error(400, &format!("--{} cannot be submitted", name));
+                }
+            }
+        }
+        let path = |suffix: &str| format!("{}/job-{}{}", self.directory, id, suffix);
+        let scene_path = path(".yml");
+        let image_path = path(&format!(".{}", format));
+        let partial_path = path(&format!(".partial.{}", format));
+        let args = ["mmlt", "--scene", &scene_path, "--image", &partial_path]
+            .into_iter()
+            .map(String::from)
+            .chain(self.render_args.iter().cloned())
+            .chain(flags)
+            .collect();
+        let config = match Config::parse_with_env(args, self.variables.iter().cloned()) {
+            Ok(config) => config,
+            Err(e) => return Response::error(400, &e.to_string()),
+        };
+        let directory = self.asset_directory.as_deref();
+        if let Err(message) = check_assets(&request.body, &config.definitions, directory) {
+            return Response::error(400, &message);
+        }
+        if let Err(e) = fs::write(&scene_path, &request.body) {
+            return Response::error(500, &format!("{}: {}", scene_path, e));
+        }
+        let job = Arc::new(ServiceJob {
+            id,
+            config,
+            job: Job {
+                scene_path,
+                image_path: partial_path,
+                camera: None,
+            },
+            image_path,
+            cancellation: Cancellation::new(),
+            status: Mutex::new(JobStatus::default()),
+        });
+        jobs.push(Arc::clone(&job));
+        if self.queue.send(job).is_err() {
+            return Response::error(500, "the render queue has stopped");
+        }
+        Response::json(201, format!("{{\"id\": {}}}", id))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum JobState {
+    #[default]
+    Queued,
+    Running,
+    Finished,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    fn name(self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Finished => "finished",
+            JobState::Failed => "failed",
+            JobState::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Default)]
+struct JobStatus {
+    state: JobState,
+    phase: Option<Phase>,
+    count: u64,
+    total: u64,
+    messages: Vec<String>,
+    error: Option<String>,
+    started_at: Option<Instant>,
+    elapsed: Option<Duration>,
+    snapshot_at: Option<Instant>,
+    has_image: bool,
+    preview_at: Option<Instant>,
+    preview: Option<Arc<Preview>>,
+}
+
+impl JobStatus {
+    fn is_done(&self) -> bool {
+        matches!(
+            self.state,
+            JobState::Finished | JobState::Failed | JobState::Cancelled
+        )
+    }
+}
+
+// A submitted render, which reports its progress to its status. Its image is
+// written beside the one served and moved over it once complete, so that a
+// request never reads a partly written image.
+struct ServiceJob {
+    id: usize,
+    config: Config,
+    job: Job,
+    image_path: String,
+    cancellation: Cancellation,
+    status: Mutex<JobStatus>,
+}
+
+impl ServiceJob {
+    fn run(&self) {
+        {
+            let mut status = self.status.lock().unwrap();
+            if status.state == JobState::Cancelled {
+                return;
+            }
+            status.state = JobState::Running;
+            status.started_at = Some(Instant::now());
+        }
+        // A render that panics fails its job rather than the worker, so that
+        // the jobs queued after it still run.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            crate::render(&self.config, &self.job, self, &self.cancellation)
+                .and_then(|()| self.publish())
+        }));
+        let mut status = self.status.lock().unwrap();
+        status.elapsed = status.started_at.map(|start| start.elapsed());
+        status.state = match result {
+            Ok(Ok(())) if self.cancellation.is_cancelled() => JobState::Cancelled,
+            Ok(Ok(())) => JobState::Finished,
+            Ok(Err(e)) => {
+                status.error = Some(e.to_string());
+                JobState::Failed
+            }
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                    .unwrap_or("unknown error");
+                status.error = Some(format!("the render panicked: {}", message));
+                JobState::Failed
+            }
+        };
+    }
+
+    fn publish(&self) -> Result<(), Error> {
+        fs::rename(&self.job.image_path, &self.image_path).map_err(Error::io(&self.image_path))?;
+        self.status.lock().unwrap().has_image = true;
+        Ok(())
+    }
+
+    fn cancel(&self) {
+        self.cancellation.cancel();
+        let mut status = self.status.lock().unwrap();
+        if status.state == JobState::Queued {
+            status.state = JobState::Cancelled;
+        }
+    }
+
+    fn image(&self) -> Response {
+        if !self.status.lock().unwrap().has_image {
+            return Response::error(404, "no image yet");
+        }
+        match fs::read(&self.image_path) {
+            Ok(body) => Response {
+                status: 200,
+                content_type: match self.image_path.rsplit('.').next() {
+                    Some("png") => "image/png",
+                    Some("exr") => "image/x-exr",
+                    _ => "application/octet-stream",
+                },
+                body,
+            },
+            Err(e) => Response::error(500, &format!("{}: {}", self.image_path, e)),
+        }
+    }
+
+    // Sends the size of the image as a text message, then each tile of the
+    // preview as it changes as a binary message: its x, y, width and height
+    // as big-endian 16-bit integers, followed by its pixels as RGB bytes, row
+    // by row. Once the job is done, its status is sent and the stream closed.
+    // Between updates, pings from the client are answered, and the stream
+    // ends as soon as the client closes it or goes away.
+    fn stream(
+        &self,
+        mut stream: impl Write,
+        frames: &mpsc::Receiver<(Opcode, Vec<u8>)>,
+    ) -> std::io::Result<()> {
+        let mut sent: Option<Arc<Preview>> = None;
+        loop {
+            let (preview, done) = {
+                let status = self.status.lock().unwrap();
+                (status.preview.clone(), status.is_done())
+            };
+            if let Some(preview) = preview {
+                if sent
+                    .as_ref()
+                    .is_none_or(|sent| !Arc::ptr_eq(sent, &preview))
+                {
+                    if sent.is_none() {
+                        let size = format!(
+                            "{{\"width\": {}, \"height\": {}}}",
+                            preview.width, preview.height
+                        );
+                        websocket::write_frame(&mut stream, Opcode::Text, size.as_bytes())?;
+                    }
+                    for tile in changed_tiles(sent.as_deref(), &preview) {
+                        websocket::write_frame(&mut stream, Opcode::Binary, &tile)?;
+                    }
+                    sent = Some(preview);
+                }
+            }
+            if done {
+                websocket::write_frame(&mut stream, Opcode::Text, self.status().as_bytes())?;
+                return websocket::write_frame(&mut stream, Opcode::Close, &[]);
+            }
+            match frames.recv_timeout(PREVIEW_INTERVAL / 2) {
+                Ok((Opcode::Ping, payload)) => {
+                    websocket::write_frame(&mut stream, Opcode::Pong, &payload)?
+                }
+                Ok((Opcode::Close, _)) => {
+                    return websocket::write_frame(&mut stream, Opcode::Close, &[])
+                }
+                Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+
+    fn status(&self) -> String {
+        let status = self.status.lock().unwrap();
+        let elapsed = status
+            .elapsed
+            .or_else(|| status.started_at.map(|start| start.elapsed()))
+            .unwrap_or_default();
+        let messages: Vec<String> = status.messages.iter().map(|m| quote(m)).collect();
+        format!(
+            "{{\"id\": {}, \"state\": \"{}\", \"phase\": {}, \"completed\": {}, \"total\": {}, \
+             \"elapsed_seconds\": {:.1}, \"image\": {}, \"messages\": [{}], \"error\": {}}}",
+            self.id,
+            status.state.name(),
+            status
+                .phase
+                .map_or(String::from("null"), |p| quote(p.name())),
+            status.count,
+            status.total,
+            elapsed.as_secs_f64(),
+            status.has_image,
+            messages.join(", "),
+            status.error.as_deref().map_or(String::from("null"), quote),
+        )
+    }
+}
+
+impl ProgressSink for ServiceJob {
+    fn report(&self, message: &str) {
+        self.status
+            .lock()
+            .unwrap()
+            .messages
+            .push(String::from(message));
+    }
+
+    fn start(&self, phase: Phase, total: u64) {
+        let mut status = self.status.lock().unwrap();
+        status.phase = Some(phase);
+        status.count = 0;
+        status.total = total;
+    }
+
+    fn update(&self, count: u64) {
+        self.status.lock().unwrap().count = count;
+    }
+
+    fn finish(&self) {
+        let mut status = self.status.lock().unwrap();
+        status.count = status.total;
+    }
+
+    // The image written last is the final one, so only the preview is
+    // brought up to date when the samples are all taken.
+    fn snapshot(&self, image: &Image, scale: Float, last: bool) {
+        let now = Instant::now();
+        let due = |at: Option<Instant>, interval| at.is_none_or(|at| now - at >= interval);
+        let (preview_due, snapshot_due) = {
+            let mut status = self.status.lock().unwrap();
+            let preview_due = last || due(status.preview_at, PREVIEW_INTERVAL);
+            let snapshot_due = !last && due(status.snapshot_at, SNAPSHOT_INTERVAL);
+            if preview_due {
+                status.preview_at = Some(now);
+            }
+            if snapshot_due {
+                status.snapshot_at = Some(now);
+            }
+            (preview_due, snapshot_due)
+        };
+        if preview_due {
+            let preview = Arc::new(image.preview(scale));
+            self.status.lock().unwrap().preview = Some(preview);
+        }
+        if !snapshot_due {
+            return;
+        }
+        let result = image
+            .write_scaled(self.job.image_path.clone(), scale)
+            .and_then(|()| self.publish());
+        if let Err(e) = result {
+            self.report(&format!("could not write a snapshot: {}", e));
+        }
+    }
+}
+
+// The tiles of a preview whose pixels differ from those of the one sent
+// before, each as a binary message.
+fn changed_tiles(sent: Option<&Preview>, preview: &Preview) -> Vec<Vec<u8>> {
+    let mut tiles = Vec::new();
+    for y in (0..preview.height).step_by(STREAM_TILE_SIZE) {
+        for x in (0..preview.width).step_by(STREAM_TILE_SIZE) {
+            let width = usize::min(STREAM_TILE_SIZE, preview.width - x);
+            let height = usize::min(STREAM_TILE_SIZE, preview.height - y);
+            let pixels = preview.region(x, y, width, height);
+            if sent.is_some_and(|sent| sent.region(x, y, width, height) == pixels) {
+                continue;
+            }
+            let mut tile: Vec<u8> = [x, y, width, height]
+                .iter()
+                .flat_map(|v| (*v as u16).to_be_bytes())
+                .collect();
+            tile.extend(pixels);
+            tiles.push(tile);
+        }
+    }
+    tiles
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn read(reader: &mut impl BufRead) -> Result<Request, String> {
+        // The request line and the headers are read through one limit.
+        let mut head = Read::take(&mut *reader, MAX_HEADER_SIZE);
+        let mut read_line = |line: &mut String| {
+            head.read_line(line).map_err(|e| e.to_string())?;
+            if head.limit() == 0 && !line.ends_with('\n') {
+                return Err(String::from("request header too large"));
+            }
+            Ok(())
+        };
+        let mut line = String::new();
+        read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+            return Err(String::from("malformed request line"));
+        };
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let mut headers = Vec::new();
+        loop {
+            let mut header = String::new();
+            read_line(&mut header)?;
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                headers.push((name.to_ascii_lowercase(), String::from(value.trim())));
+            }
+        }
+        let content_length = match headers.iter().find(|(name, _)| name == "content-length") {
+            Some((_, value)) => value.parse().map_err(|_| "malformed content length")?,
+            None => 0,
+        };
+        if content_length > MAX_BODY_SIZE {
+            return Err(String::from("request body too large"));
+        }
+        // The body grows as it arrives, rather than being allocated whole
+        // before the client has sent any of it.
+        let mut body = Vec::new();
+        Read::take(&mut *reader, content_length as u64)
+            .read_to_end(&mut body)
+            .map_err(|e| e.to_string())?;
+        if body.len() != content_length {
+            return Err(String::from("request body shorter than its content length"));
+        }
+        Ok(Request {
+            method: String::from(method),
+            path: decode(path)?,
+            query: parse_query(query)?,
+            headers,
+            body,
+        })
+    }
+
+    // Header names are lowercase.
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn segments(&self) -> Vec<&str> {
+        self.path.trim_matches('/').split('/').collect()
+    }
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn json(status: u16, body: String) -> Response {
+        Response {
+            status,
+            content_type: "application/json",
+            body: body.into_bytes(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response::json(status, format!("{{\"error\": {}}}", quote(message)))
+    }
+
+    fn write(&self, mut stream: impl Write, allow_origin: Option<&str>) -> std::io::Result<()> {
+        let reason = match self.status {
+            200 => "OK",
+            201 => "Created",
+            202 => "Accepted",
+            400 => "Bad Request",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            503 => "Service Unavailable",
+            _ => "Internal Server Error",
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n",
+            self.status,
+            reason,
+            self.content_type,
+            self.body.len()
+        )?;
+        if let Some(origin) = allow_origin {
+            write!(stream, "Access-Control-Allow-Origin: {}\r\n", origin)?;
+        }
+        write!(stream, "Connection: close\r\n\r\n")?;
+        stream.write_all(&self.body)?;
+        stream.flush()
+    }
+}
+
+fn parse_query(query: &str) -> Result<Vec<(String, String)>, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((decode(name)?, decode(value)?))
+        })
+        .collect()
+}
+
+// Decodes the percent escapes of a URL, and the pluses of a query that stand
+// for spaces.
+fn decode(text: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        match byte {
+            b'%' => {
+                let hex = tail
+                    .get(..2)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or("malformed percent escape")?;
+                bytes.push(hex);
+                rest = &tail[2..];
+            }
+            b'+' => {
+                bytes.push(b' ');
+                rest = tail;
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| String::from("malformed UTF-8 in URL"))
+}
+
+// Submitted scenes may only read files under the asset directory of the
+// service, once their variables are substituted, and none without one, as a
+// client could otherwise read any file the service can through a texture.
+fn check_assets(
+    scene: &[u8],
+    definitions: &[(String, String)],
+    directory: Option<&str>,
+) -> Result<(), String> {
+    let source = std::str::from_utf8(scene).map_err(|_| "the scene is not UTF-8")?;
+    let source = template::substitute(source, definitions)?;
+    let value: Value = serde_yaml::from_str(&source).map_err(|e| e.to_string())?;
+    let mut paths = Vec::new();
+    collect_assets(&value, &mut paths);
+    let Some(path) = paths.first() else {
+        return Ok(());
+    };
+    let Some(directory) = directory else {
+        return Err(format!(
+            "the scene cannot read {}, as the service has no --asset-directory",
+            path
+        ));
+    };
+    let directory = fs::canonicalize(directory).map_err(|e| format!("{}: {}", directory, e))?;
+    for path in paths {
+        // Symbolic links and `..` are resolved before the comparison.
+        match fs::canonicalize(path) {
+            Ok(resolved) if resolved.starts_with(&directory) => {}
+            _ => return Err(format!("{} is not in the asset directory", path)),
+        }
+    }
+    Ok(())
+}
+
+fn collect_assets<'a>(value: &'a Value, paths: &mut Vec<&'a str>) {
+    match value {
+        Value::Mapping(mapping) => {
+            for (key, value) in mapping {
+                match (key.as_str(), value.as_str()) {
+                    (Some(key), Some(path)) if ASSET_KEYS.contains(&key) => paths.push(path),
+                    _ => collect_assets(value, paths),
+                }
+            }
+        }
+        Value::Sequence(values) => values.iter().for_each(|value| collect_assets(value, paths)),
+        Value::Tagged(tagged) => collect_assets(&tagged.value, paths),
+        _ => {}
+    }
+}
+
+fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Arc, Mutex,
+        },
+    };
+
+    use super::{
+        changed_tiles, check_assets, decode, parse_query, quote, ConnectionSlot, JobState,
+        JobStatus, Request, Service, ServiceJob, MAX_CONNECTIONS,
+    };
+    use crate::{
+        config::Config, image::Preview, interrupt::Cancellation, job::Job, websocket::Opcode,
+    };
+
+    #[test]
+    fn test_parse_request() {
+        let raw = "POST /jobs?max-path-length=8&define=radius%3D2+3 HTTP/1.1\r\n\
+                   Content-Length: 5\r\n\r\nhello";
+        let request = Request::read(&mut raw.as_bytes()).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/jobs");
+        assert_eq!(
+            request.query,
+            [
+                (String::from("max-path-length"), String::from("8")),
+                (String::from("define"), String::from("radius=2 3"))
+            ]
+        );
+        assert_eq!(request.body, b"hello");
+        assert!(decode("%zz").is_err());
+        assert!(parse_query("").unwrap().is_empty());
+        assert_eq!(quote("a \"b\"\n"), "\"a \\\"b\\\"\\n\"");
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(1 << 17));
+        assert!(Request::read(&mut long.as_bytes()).is_err());
+        let short = "POST /jobs HTTP/1.1\r\nContent-Length: 1000000\r\n\r\nhello";
+        assert!(Request::read(&mut short.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_connection_slots() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let mut slots: Vec<ConnectionSlot> = (0..MAX_CONNECTIONS)
+            .map(|_| ConnectionSlot::acquire(&connections).unwrap())
+            .collect();
+        assert!(ConnectionSlot::acquire(&connections).is_none());
+        slots.pop();
+        assert!(ConnectionSlot::acquire(&connections).is_some());
+        drop(slots);
+        assert_eq!(connections.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_check_assets() {
+        let directory = env::temp_dir().join("mmlt-test-assets");
+        fs::create_dir_all(&directory).unwrap();
+        let mesh = directory.join("mesh.obj");
+        fs::write(&mesh, "").unwrap();
+        let scene = "objects:\n  - shape: { type: mesh, path: ${mesh} }\n";
+        let definitions = [(String::from("mesh"), mesh.to_string_lossy().into_owned())];
+        let allowed = directory.to_str();
+        assert!(check_assets(scene.as_bytes(), &definitions, allowed).is_ok());
+        assert!(check_assets(scene.as_bytes(), &definitions, None).is_err());
+        let outside = [(String::from("mesh"), String::from("/etc/passwd"))];
+        assert!(check_assets(scene.as_bytes(), &outside, allowed).is_err());
+        let escape = directory.join("../mmlt-test-assets/../../etc/passwd");
+        let escape = [(String::from("mesh"), escape.to_string_lossy().into_owned())];
+        assert!(check_assets(scene.as_bytes(), &escape, allowed).is_err());
+        assert!(check_assets(b"objects: []\n", &[], None).is_ok());
+    }
+
+    #[test]
+    fn test_changed_tiles() {
+        let preview = |value| Preview {
+            width: 40,
+            height: 8,
+            pixels: vec![value; 40 * 8 * 3],
+        };
+        let first = preview(0);
+        let tiles = changed_tiles(None, &first);
+        assert_eq!(tiles.len(), 2);
+        assert_eq!(tiles[1][..8], [0, 32, 0, 0, 0, 8, 0, 8]);
+        assert_eq!(tiles[1].len(), 8 + 8 * 8 * 3);
+        let mut second = preview(0);
+        second.pixels[3 * 39] = 1;
+        let tiles = changed_tiles(Some(&first), &second);
+        assert_eq!(tiles.len(), 1);
+        assert_eq!(tiles[0][..2], [0, 32]);
+    }
+
+    #[test]
+    fn test_handle() {
+        let directory = env::temp_dir().join("mmlt-test-service");
+        fs::create_dir_all(&directory).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let service = Service {
+            directory: directory.to_string_lossy().into_owned(),
+            render_args: Vec::new(),
+            variables: Vec::new(),
+            allow_origin: None,
+            asset_directory: None,
+            jobs: Mutex::new(Vec::new()),
+            queue: sender,
+        };
+        let request = |raw: &str| Request::read(&mut raw.as_bytes()).unwrap();
+        let submit = request("POST /jobs?average-samples-per-pixel=4 HTTP/1.1\r\n\r\n");
+        assert_eq!(service.handle(&submit).status, 201);
+        assert_eq!(receiver.try_recv().unwrap().id, 1);
+        let invalid = request("POST /jobs?max-path-length=x HTTP/1.1\r\n\r\n");
+        assert_eq!(service.handle(&invalid).status, 400);
+        let checkpoint = request("POST /jobs?checkpoint=%2Fetc%2Fpasswd HTTP/1.1\r\n\r\n");
+        assert_eq!(service.handle(&checkpoint).status, 400);
+        let replay = request("POST /jobs?replay-proposals=a.yml HTTP/1.1\r\n\r\n");
+        assert_eq!(service.handle(&replay).status, 400);
+        let smuggled = request("POST /jobs?seed=1+--checkpoint+%2Ftmp%2Fa HTTP/1.1\r\n\r\n");
+        assert_eq!(service.handle(&smuggled).status, 400);
+        let flag = request("POST /jobs?define=--checkpoint HTTP/1.1\r\n\r\n");
+        assert_eq!(service.handle(&flag).status, 400);
+        assert!(receiver.try_recv().is_err());
+        let status = service.handle(&request("GET /jobs/1 HTTP/1.1\r\n\r\n"));
+        let body = String::from_utf8(status.body).unwrap();
+        assert!(body.contains("\"state\": \"queued\""));
+        let image = service.handle(&request("GET /jobs/1/image HTTP/1.1\r\n\r\n"));
+        assert_eq!(image.status, 404);
+        // A viewer of the queued job that pings and closes its stream, or goes
+        // away, is let go.
+        let job = service.job("1").unwrap();
+        let (frames, viewer) = mpsc::channel();
+        frames.send((Opcode::Ping, b"hi".to_vec())).unwrap();
+        frames.send((Opcode::Close, Vec::new())).unwrap();
+        let mut stream = Vec::new();
+        job.stream(&mut stream, &viewer).unwrap();
+        assert_eq!(stream, [0x8A, 2, b'h', b'i', 0x88, 0]);
+        drop(frames);
+        job.stream(Vec::new(), &viewer).unwrap();
+        let cancel = service.handle(&request("DELETE /jobs/1 HTTP/1.1\r\n\r\n"));
+        assert!(String::from_utf8(cancel.body)
+            .unwrap()
+            .contains("cancelled"));
+        assert_eq!(
+            service
+                .handle(&request("GET /jobs/2 HTTP/1.1\r\n\r\n"))
+                .status,
+            404
+        );
+        assert_eq!(
+            service
+                .handle(&request("PUT /jobs HTTP/1.1\r\n\r\n"))
+                .status,
+            405
+        );
+    }
+
+    #[test]
+    fn test_allows_origin() {
+        let (sender, _receiver) = mpsc::channel();
+        let mut service = Service {
+            directory: String::new(),
+            render_args: Vec::new(),
+            variables: Vec::new(),
+            allow_origin: None,
+            asset_directory: None,
+            jobs: Mutex::new(Vec::new()),
+            queue: sender,
+        };
+        assert!(service.allows_origin(Some("http://a.test")));
+        service.allow_origin = Some("http://a.test".to_string());
+        assert!(service.allows_origin(Some("http://a.test")));
+        assert!(!service.allows_origin(Some("http://b.test")));
+        assert!(service.allows_origin(None));
+    }
+
+    #[test]
+    fn test_run_panicking_job() {
+        let args = ["mmlt", "--scene", "scenes/scene-1.yml", "--image", "a.pfm"];
+        let mut config = Config::parse_with_env(args.map(String::from).to_vec(), []).unwrap();
+        // Past the validation of the command line, a render with no path
+        // lengths panics.
+        config.max_path_length = Some(1);
+        let job = ServiceJob {
+            id: 1,
+            config,
+            job: Job {
+                scene_path: String::from("scenes/scene-1.yml"),
+                image_path: String::from("a.pfm"),
+                camera: None,
+            },
+            image_path: String::from("a.pfm"),
+            cancellation: Cancellation::new(),
+            status: Mutex::new(JobStatus::default()),
+        };
+        job.run();
+        let status = job.status.lock().unwrap();
+        assert_eq!(status.state, JobState::Failed);
+        assert!(status.error.as_deref().unwrap().contains("panicked"));
+    }
+}
@@ -0,0 +1,151 @@
+use std::fs::File;
+
+use serde_yaml::Value;
+
+use crate::error::Error;
+
+const SETTINGS_FLAG: &str = "--settings";
+const VARIABLE_PREFIX: &str = "MMLT_";
+
+// Render settings kept apart from the scene, in a YAML file given with
+// `--settings` and in environment variables, so that one scene can be
+// rendered with different quality presets. A setting is named after its flag,
+// as in `max_path_length: 10` or `MMLT_MAX_PATH_LENGTH=10`, and a flag of
+// several values takes a list, or in a variable values separated by spaces.
+// The settings become flags ahead of those of the command line, which take
+// precedence over the variables, which take precedence over the file.
+pub fn expand(
+    args: Vec<String>,
+    variables: impl IntoIterator<Item = (String, String)>,
+) -> Result<Vec<String>, Error> {
+    let mut args = args.into_iter();
+    let mut expanded: Vec<String> = args.next().into_iter().collect();
+    let mut flags = Vec::new();
+    let mut settings_path = None;
+    while let Some(arg) = args.next() {
+        if arg == SETTINGS_FLAG {
+            let path = args
+                .next()
+                .ok_or_else(|| Error::Cli(String::from("no argument for --settings provided")))?;
+            settings_path = Some(path);
+        } else {
+            flags.push(arg);
+        }
+    }
+    if let Some(path) = settings_path {
+        expanded.extend(load(&path)?);
+    }
+    let mut variables: Vec<(String, String)> = variables
+        .into_iter()
+        .filter_map(|(name, value)| {
+            let name = name.strip_prefix(VARIABLE_PREFIX)?.to_lowercase();
+            Some((name, value))
+        })
+        .collect();
+    variables.sort();
+    for (name, value) in variables {
+        expanded.push(flag(&name));
+        expanded.extend(value.split_whitespace().map(String::from));
+    }
+    expanded.extend(flags);
+    Ok(expanded)
+}
+
+fn load(path: &str) -> Result<Vec<String>, Error> {
+    let file = File::open(path).map_err(Error::io(path))?;
+    let settings: Value = serde_yaml::from_reader(file).map_err(|e| Error::scene(path, e))?;
+    flags(&settings).map_err(|message| Error::Scene {
+        path: String::from(path),
+        location: None,
+        message,
+    })
+}
+
+fn flags(settings: &Value) -> Result<Vec<String>, String> {
+    let settings = match settings {
+        Value::Mapping(settings) => settings,
+        Value::Null => return Ok(Vec::new()),
+        _ => return Err(String::from("settings must be a mapping")),
+    };
+    let mut flags = Vec::new();
+    for (name, value) in settings {
+        let name = name.as_str().ok_or("setting names must be strings")?;
+        flags.push(flag(name));
+        match value {
+            Value::Sequence(values) => {
+                for value in values {
+                    flags.push(scalar(name, value)?);
+                }
+            }
+            value => flags.push(scalar(name, value)?),
+        }
+    }
+    Ok(flags)
+}
+
+fn flag(name: &str) -> String {
+    format!("--{}", name.replace('_', "-"))
+}
+
+fn scalar(name: &str, value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(value) => Ok(value.clone()),
+        Value::Number(value) => Ok(value.to_string()),
+        Value::Bool(value) => Ok(value.to_string()),
+        _ => Err(format!("invalid value for setting '{}'", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expand, flags};
+
+    #[test]
+    fn test_expand() {
+        let settings = serde_yaml::from_str(
+            "
+max_path_length: 10
+write_checkpoint: true
+debug_pixel: [3, 4]
+",
+        )
+        .unwrap();
+        assert_eq!(
+            flags(&settings).unwrap(),
+            [
+                "--max-path-length",
+                "10",
+                "--write-checkpoint",
+                "true",
+                "--debug-pixel",
+                "3",
+                "4"
+            ]
+        );
+
+        let args = ["mmlt", "--scene", "a.yml", "--threads", "2"].map(String::from);
+        let variables = [
+            ("MMLT_THREADS", "8"),
+            ("HOME", "/root"),
+            ("MMLT_DEBUG_PIXEL", "1 2"),
+        ]
+        .map(|(name, value)| (String::from(name), String::from(value)));
+        assert_eq!(
+            expand(args.to_vec(), variables).unwrap(),
+            [
+                "mmlt",
+                "--debug-pixel",
+                "1",
+                "2",
+                "--threads",
+                "8",
+                "--scene",
+                "a.yml",
+                "--threads",
+                "2"
+            ]
+        );
+        let args = ["mmlt", "--settings"].map(String::from);
+        assert!(expand(args.to_vec(), []).is_err());
+    }
+}
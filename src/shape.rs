@@ -1,25 +1,118 @@
-use std::{f64::consts::PI, fmt};
+use std::{fmt, sync::Arc};
+
+use crate::types::consts::PI;
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    geometry::Geometry,
-    ray::Ray,
+    bounds::Bounds3,
+    bvh::{self, Bvh, BvhNodes},
+    camera::AngleUnitConfig,
+    error::Error,
+    geometry::{Geometry, SurfaceId},
+    obj::ObjMesh,
+    packed::PackedMesh,
+    ray::{Ray, RAY_EPSILON},
     sampler::Sampler,
+    types::Float,
     util,
-    vector::{Point3, Point3Config},
+    vector::{Matrix4, Point2, Point3, Point3Config, Quaternion, Vector3, Vector3Config},
 };
 
-pub trait Shape: fmt::Debug {
-    fn area(&self) -> f64;
+// The squared sine of the half angle of a cone below which one minus its
+// cosine is found from a series, as about 1.5 degrees.
+const SMALL_CONE_SIN2: Float = 0.00068523;
+
+pub trait Shape: fmt::Debug + Send + Sync {
+    fn area(&self) -> Float;
     fn sample_geometry(&self, sampler: &mut dyn Sampler) -> Geometry;
     fn intersect(&self, ray: Ray) -> Option<Geometry>;
+    fn bounds(&self) -> Bounds3;
+
+    // A point sampled for lighting the given reference point, by default
+    // uniformly as from anywhere.
+    fn sample_from(&self, _reference: Point3, sampler: &mut dyn Sampler) -> Geometry {
+        self.sample_geometry(sampler)
+    }
+
+    // The density by area of sampling the point from the reference point.
+    fn pdf_from(&self, _reference: Point3, _geometry: &Geometry) -> Float {
+        1.0 / self.area()
+    }
+}
+
+// The shapes, dispatched statically on the hot path of intersection. A new
+// shape implements `Shape` and joins the enum, or, until it is worth a
+// variant of its own, is shared as `Custom` and dispatched dynamically.
+#[derive(Clone, Debug)]
+pub enum AnyShape {
+    Sphere(Sphere),
+    Mesh(Box<TriangleMesh>),
+    Transformed(Box<Transformed>),
+    #[allow(dead_code)]
+    Custom(Arc<dyn Shape>),
+}
+
+impl Shape for AnyShape {
+    fn area(&self) -> Float {
+        match self {
+            AnyShape::Sphere(shape) => shape.area(),
+            AnyShape::Mesh(shape) => shape.area(),
+            AnyShape::Transformed(shape) => shape.area(),
+            AnyShape::Custom(shape) => shape.area(),
+        }
+    }
+
+    fn sample_geometry(&self, sampler: &mut dyn Sampler) -> Geometry {
+        match self {
+            AnyShape::Sphere(shape) => shape.sample_geometry(sampler),
+            AnyShape::Mesh(shape) => shape.sample_geometry(sampler),
+            AnyShape::Transformed(shape) => shape.sample_geometry(sampler),
+            AnyShape::Custom(shape) => shape.sample_geometry(sampler),
+        }
+    }
+
+    fn intersect(&self, ray: Ray) -> Option<Geometry> {
+        match self {
+            AnyShape::Sphere(shape) => shape.intersect(ray),
+            AnyShape::Mesh(shape) => shape.intersect(ray),
+            AnyShape::Transformed(shape) => shape.intersect(ray),
+            AnyShape::Custom(shape) => shape.intersect(ray),
+        }
+    }
+
+    fn bounds(&self) -> Bounds3 {
+        match self {
+            AnyShape::Sphere(shape) => shape.bounds(),
+            AnyShape::Mesh(shape) => shape.bounds(),
+            AnyShape::Transformed(shape) => shape.bounds(),
+            AnyShape::Custom(shape) => shape.bounds(),
+        }
+    }
+
+    fn sample_from(&self, reference: Point3, sampler: &mut dyn Sampler) -> Geometry {
+        match self {
+            AnyShape::Sphere(shape) => shape.sample_from(reference, sampler),
+            AnyShape::Mesh(shape) => shape.sample_from(reference, sampler),
+            AnyShape::Transformed(shape) => shape.sample_from(reference, sampler),
+            AnyShape::Custom(shape) => shape.sample_from(reference, sampler),
+        }
+    }
+
+    fn pdf_from(&self, reference: Point3, geometry: &Geometry) -> Float {
+        match self {
+            AnyShape::Sphere(shape) => shape.pdf_from(reference, geometry),
+            AnyShape::Mesh(shape) => shape.pdf_from(reference, geometry),
+            AnyShape::Transformed(shape) => shape.pdf_from(reference, geometry),
+            AnyShape::Custom(shape) => shape.pdf_from(reference, geometry),
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Sphere {
     center: Point3,
-    radius: f64,
+    radius: Float,
 }
 
 impl Sphere {
@@ -27,24 +120,115 @@ impl Sphere {
         Sphere::new(Point3::configure(&config.center), config.radius)
     }
 
-    pub fn new(center: Point3, radius: f64) -> Sphere {
+    pub fn new(center: Point3, radius: Float) -> Sphere {
         Sphere { center, radius }
     }
+
+    // The geometry of a point on the sphere.
+    pub fn geometry(&self, point: Point3) -> Geometry {
+        let direction = point - self.center;
+        let (dpdu, dpdv) = self.derivatives(point);
+        Geometry {
+            point,
+            direction,
+            normal: direction.norm(),
+            t: 0.0,
+            uv: self.uv(point),
+            dpdu,
+            dpdv,
+            surface: Some(SurfaceId::of(self)),
+        }
+    }
+
+    // The cone of directions in which the sphere is seen from the reference
+    // point, as the squares of the sine of its half angle and one minus its
+    // cosine, or nothing from inside the sphere. One minus the cosine of a
+    // small cone is taken from its sine, which keeps its precision.
+    fn cone(&self, reference: Point3) -> Option<(Float, Float)> {
+        let to_center = self.center - reference;
+        let distance_squared = to_center.dot(to_center);
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return None;
+        }
+        let sin2_theta_max = radius_squared / distance_squared;
+        let one_minus_cos_theta_max = if sin2_theta_max < SMALL_CONE_SIN2 {
+            sin2_theta_max / 2.0
+        } else {
+            1.0 - util::safe_sqrt(1.0 - sin2_theta_max)
+        };
+        Some((sin2_theta_max, one_minus_cos_theta_max))
+    }
+
+    // Longitude about the y axis, and latitude down from its top.
+    fn uv(&self, point: Point3) -> Point2 {
+        let d = (point - self.center) / self.radius;
+        Point2::new(
+            0.5 + d.z.atan2(d.x) / (2.0 * PI),
+            d.y.clamp(-1.0, 1.0).acos() / PI,
+        )
+    }
+
+    // The derivatives of the point with respect to its surface coordinates,
+    // as the longitude turns through 2π and the latitude through π.
+    fn derivatives(&self, point: Point3) -> (Vector3, Vector3) {
+        let d = (point - self.center) / self.radius;
+        let phi = d.z.atan2(d.x);
+        let cos_theta = d.y.clamp(-1.0, 1.0);
+        let sin_theta = util::safe_sqrt(1.0 - cos_theta * cos_theta);
+        let dpdu = Vector3::new(-sin_theta * phi.sin(), 0.0, sin_theta * phi.cos())
+            * (2.0 * PI * self.radius);
+        let dpdv = Vector3::new(cos_theta * phi.cos(), -sin_theta, cos_theta * phi.sin())
+            * (PI * self.radius);
+        (dpdu, dpdv)
+    }
 }
 
 impl Shape for Sphere {
-    fn area(&self) -> f64 {
+    fn area(&self) -> Float {
         4.0 * PI * self.radius * self.radius
     }
 
     fn sample_geometry(&self, sampler: &mut dyn Sampler) -> Geometry {
         let direction = util::uniform_sample_sphere(sampler) * self.radius;
-        let point = self.center + direction;
-        Geometry {
-            point,
-            direction,
-            normal: direction.norm(),
-        }
+        self.geometry(self.center + direction)
+    }
+
+    // Uniformly over the cone of directions in which the sphere is seen,
+    // so that no point is sampled on the far side, which the near side
+    // hides. The direction is turned into the angle it makes at the center
+    // with the reference point, which gives the point without intersecting
+    // the sphere.
+    fn sample_from(&self, reference: Point3, sampler: &mut dyn Sampler) -> Geometry {
+        let Some((sin2_theta_max, one_minus_cos_theta_max)) = self.cone(reference) else {
+            return self.sample_geometry(sampler);
+        };
+        let u1 = sampler.sample(0.0..1.0);
+        let u2 = sampler.sample(0.0..1.0);
+        let (sin2_theta, cos_theta) = if sin2_theta_max < SMALL_CONE_SIN2 {
+            let sin2_theta = sin2_theta_max * u1;
+            (sin2_theta, util::safe_sqrt(1.0 - sin2_theta))
+        } else {
+            let cos_theta = 1.0 - one_minus_cos_theta_max * u1;
+            (1.0 - cos_theta * cos_theta, cos_theta)
+        };
+        let sin_theta_max = sin2_theta_max.sqrt();
+        let cos_alpha = sin2_theta / sin_theta_max
+            + cos_theta * util::safe_sqrt(1.0 - sin2_theta / sin2_theta_max);
+        let sin_alpha = util::safe_sqrt(1.0 - cos_alpha * cos_alpha);
+        let phi = 2.0 * PI * u2;
+        let (x, y, z) = util::orthonormal_basis(reference - self.center);
+        let normal = x * (sin_alpha * phi.cos()) + y * (sin_alpha * phi.sin()) + z * cos_alpha;
+        self.geometry(self.center + normal * self.radius)
+    }
+
+    fn pdf_from(&self, reference: Point3, geometry: &Geometry) -> Float {
+        let Some((_, one_minus_cos_theta_max)) = self.cone(reference) else {
+            return 1.0 / self.area();
+        };
+        let direction = reference - geometry.point;
+        let solid_angle_pdf = 1.0 / (2.0 * PI * one_minus_cos_theta_max);
+        solid_angle_pdf * util::direction_to_area(direction, geometry.normal)
     }
 
     fn intersect(&self, ray: Ray) -> Option<Geometry> {
@@ -55,27 +239,508 @@ impl Shape for Sphere {
             return None;
         }
         det = det.sqrt();
-        let threshold = 1e-4;
-        let mut t = b - det;
-        if t <= threshold {
-            t = b + det;
-            if t <= threshold {
-                return None;
-            }
+        let t = if ray.surface == Some(SurfaceId::of(self)) {
+            // Leaving the sphere, one root is the point left, and the other,
+            // found exactly, is where the ray leaves it again when going in.
+            2.0 * b
+        } else if b - det > ray.t_min {
+            b - det
+        } else {
+            b + det
+        };
+        if !ray.contains(t) {
+            return None;
         }
 
         let point = ray.origin + ray.direction * t;
         let normal = (point - self.center).norm();
         let direction = ray.direction * t;
+        let (dpdu, dpdv) = self.derivatives(point);
 
         let geometry = Geometry {
             point,
             normal,
             direction,
+            t,
+            uv: self.uv(point),
+            dpdu,
+            dpdv,
+            surface: Some(SurfaceId::of(self)),
         };
 
         Some(geometry)
     }
+
+    fn bounds(&self) -> Bounds3 {
+        let r = Vector3::new(self.radius, self.radius, self.radius);
+        Bounds3::new(self.center - r, self.center + r)
+    }
+}
+
+// Triangles, found along a ray through a bounding volume hierarchy over
+// them. Faces are flat, facing the side from which their corners run
+// counterclockwise, and take the surface coordinates of their corners, or
+// without them the corners of the unit square (0, 0), (1, 0) and (1, 1).
+#[derive(Clone, Debug)]
+pub struct TriangleMesh {
+    storage: MeshStorage,
+    area: Float,
+    bounds: Bounds3,
+}
+
+// The triangles of a mesh, held in memory, or read as they are needed from a
+// file packed by `mmlt pack` for meshes larger than memory.
+#[derive(Clone, Debug)]
+enum MeshStorage {
+    Memory {
+        positions: Vec<Point3>,
+        uvs: Vec<Point2>,
+        triangles: Vec<Triangle>,
+        // The areas of the triangles up to and including each, for sampling
+        // them by area.
+        cumulative_areas: Vec<Float>,
+        bvh: Bvh,
+    },
+    Packed(PackedMesh),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Triangle {
+    pub positions: [usize; 3],
+    pub uvs: Option<[usize; 3]>,
+}
+
+impl TriangleMesh {
+    pub fn configure(config: &MeshConfig) -> Result<TriangleMesh, String> {
+        if PackedMesh::is_packed(&config.path) {
+            return TriangleMesh::open(&config.path);
+        }
+        let obj = ObjMesh::load(&config.path)?;
+        TriangleMesh::from_obj(obj).map_err(|e| format!("{}: {}", config.path, e))
+    }
+
+    pub fn open(path: &str) -> Result<TriangleMesh, String> {
+        let packed = PackedMesh::open(path)?;
+        let count = packed.triangle_count();
+        Ok(TriangleMesh {
+            area: packed.cumulative_area(count - 1),
+            bounds: packed.node(0).bounds,
+            storage: MeshStorage::Packed(packed),
+        })
+    }
+
+    pub fn from_obj(obj: ObjMesh) -> Result<TriangleMesh, String> {
+        let triangles = obj
+            .triangles
+            .iter()
+            .map(|corners| Triangle {
+                positions: corners.map(|c| c.position),
+                uvs: corners[0].uv.map(|_| corners.map(|c| c.uv.unwrap_or(0))),
+            })
+            .collect();
+        TriangleMesh::new(obj.positions, obj.uvs, triangles)
+    }
+
+    fn new(
+        positions: Vec<Point3>,
+        uvs: Vec<Point2>,
+        triangles: Vec<Triangle>,
+    ) -> Result<TriangleMesh, String> {
+        if triangles.is_empty() {
+            return Err(String::from("the mesh has no triangles"));
+        }
+        let bounds: Vec<Bounds3> = triangles
+            .iter()
+            .map(|triangle| triangle_bounds(&positions, triangle))
+            .collect();
+        let bvh = Bvh::build(&bounds);
+        let mut total = 0.0;
+        let cumulative_areas = triangles
+            .iter()
+            .map(|triangle| {
+                let [p0, p1, p2] = triangle.positions.map(|i| positions[i]);
+                total += 0.5 * (p1 - p0).cross(p2 - p0).len();
+                total
+            })
+            .collect();
+        Ok(TriangleMesh {
+            area: total,
+            bounds: bvh.bounds(),
+            storage: MeshStorage::Memory {
+                positions,
+                uvs,
+                triangles,
+                cumulative_areas,
+                bvh,
+            },
+        })
+    }
+
+    // Writes the mesh to a file from which it can be rendered without
+    // holding it in memory.
+    pub fn pack(&self, path: &str) -> Result<(), Error> {
+        match &self.storage {
+            MeshStorage::Memory {
+                positions,
+                uvs,
+                triangles,
+                cumulative_areas,
+                bvh,
+            } => PackedMesh::write(path, positions, uvs, triangles, cumulative_areas, bvh),
+            MeshStorage::Packed(_) => Err(Error::Cli(String::from("the mesh is packed already"))),
+        }
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        match &self.storage {
+            MeshStorage::Memory { triangles, .. } => triangles.len(),
+            MeshStorage::Packed(packed) => packed.triangle_count(),
+        }
+    }
+
+    fn triangle(&self, index: usize) -> Triangle {
+        match &self.storage {
+            MeshStorage::Memory { triangles, .. } => triangles[index],
+            MeshStorage::Packed(packed) => packed.triangle(index),
+        }
+    }
+
+    fn cumulative_area(&self, index: usize) -> Float {
+        match &self.storage {
+            MeshStorage::Memory {
+                cumulative_areas, ..
+            } => cumulative_areas[index],
+            MeshStorage::Packed(packed) => packed.cumulative_area(index),
+        }
+    }
+
+    fn corners(&self, index: usize) -> [Point3; 3] {
+        let corners = self.triangle(index).positions;
+        match &self.storage {
+            MeshStorage::Memory { positions, .. } => corners.map(|i| positions[i]),
+            MeshStorage::Packed(packed) => corners.map(|i| packed.position(i)),
+        }
+    }
+
+    fn corner_uvs(&self, index: usize) -> [Point2; 3] {
+        let corners = self.triangle(index).uvs;
+        match (corners, &self.storage) {
+            (Some(corners), MeshStorage::Memory { uvs, .. }) => corners.map(|i| uvs[i]),
+            (Some(corners), MeshStorage::Packed(packed)) => corners.map(|i| packed.uv(i)),
+            (None, _) => [
+                Point2::new(0.0, 0.0),
+                Point2::new(1.0, 0.0),
+                Point2::new(1.0, 1.0),
+            ],
+        }
+    }
+
+    // The point of the triangle with the given barycentric coordinates of its
+    // second and third corners.
+    fn geometry(&self, index: usize, b1: Float, b2: Float, t: Float) -> Geometry {
+        let [p0, p1, p2] = self.corners(index);
+        let [uv0, uv1, uv2] = self.corner_uvs(index);
+        let b0 = 1.0 - b1 - b2;
+        let (e1, e2) = (p1 - p0, p2 - p0);
+        let normal = e1.cross(e2).norm();
+        let (du1, dv1) = (uv1.x - uv0.x, uv1.y - uv0.y);
+        let (du2, dv2) = (uv2.x - uv0.x, uv2.y - uv0.y);
+        let determinant = du1 * dv2 - dv1 * du2;
+        let (dpdu, dpdv) = if determinant.abs() < 1e-12 {
+            let (u, v, _) = util::orthonormal_basis(normal);
+            (u, v)
+        } else {
+            (
+                (e1 * dv2 - e2 * dv1) / determinant,
+                (e2 * du1 - e1 * du2) / determinant,
+            )
+        };
+        Geometry {
+            point: p0 * b0 + p1 * b1 + p2 * b2,
+            normal,
+            direction: normal,
+            t,
+            uv: Point2::new(
+                b0 * uv0.x + b1 * uv1.x + b2 * uv2.x,
+                b0 * uv0.y + b1 * uv1.y + b2 * uv2.y,
+            ),
+            dpdu,
+            dpdv,
+            surface: Some(SurfaceId::element(self, index)),
+        }
+    }
+
+    // The distance along the ray to the triangle and the barycentric
+    // coordinates of the hit, by the Möller-Trumbore test.
+    fn intersect_triangle(&self, index: usize, ray: &Ray) -> Option<(Float, Float, Float)> {
+        let origin = ray.surface.and_then(|surface| surface.index_within(self));
+        if origin == Some(index) {
+            return None;
+        }
+        let [p0, p1, p2] = self.corners(index);
+        let (e1, e2) = (p1 - p0, p2 - p0);
+        let p = ray.direction.cross(e2);
+        let determinant = e1.dot(p);
+        if determinant == 0.0 {
+            return None;
+        }
+        let s = ray.origin - p0;
+        let b1 = s.dot(p) / determinant;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let q = s.cross(e1);
+        let b2 = ray.direction.dot(q) / determinant;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = e2.dot(q) / determinant;
+        // A ray leaving a triangle at an edge or a corner it shares with this
+        // one finds this one again at about no distance through rounding, so
+        // this one is only hit beyond the epsilon of rays, as without the
+        // exclusion of the origin.
+        if t <= RAY_EPSILON && origin.is_some_and(|origin| self.adjacent(origin, index)) {
+            return None;
+        }
+        ray.contains(t).then_some((t, b1, b2))
+    }
+
+    // Whether the triangles share a corner, by position, so that triangles
+    // whose corners are repeated in the file are neighbours all the same.
+    fn adjacent(&self, a: usize, b: usize) -> bool {
+        let corners = self.corners(b);
+        self.corners(a)
+            .iter()
+            .any(|corner| corners.contains(corner))
+    }
+
+    // The nearest triangle the ray hits among those the hierarchy visits.
+    fn intersect_nodes(&self, nodes: &impl BvhNodes, mut ray: Ray) -> Option<Geometry> {
+        let mut hit = None;
+        bvh::traverse(nodes, &mut ray, |triangle, ray| {
+            if let Some((t, b1, b2)) = self.intersect_triangle(triangle, ray) {
+                ray.t_max = t;
+                hit = Some((triangle, t, b1, b2));
+            }
+        });
+        let (triangle, t, b1, b2) = hit?;
+        Some(Geometry {
+            point: ray.origin + ray.direction * t,
+            direction: ray.direction * t,
+            ..self.geometry(triangle, b1, b2, t)
+        })
+    }
+}
+
+impl Shape for TriangleMesh {
+    fn area(&self) -> Float {
+        self.area
+    }
+
+    // The first triangle whose cumulative area exceeds a sampled share of
+    // the total, found by bisection, which reads few of a packed mesh.
+    fn sample_geometry(&self, sampler: &mut dyn Sampler) -> Geometry {
+        let r = sampler.sample(0.0..self.area());
+        let (mut low, mut high) = (0, self.triangle_count() - 1);
+        while low < high {
+            let middle = (low + high) / 2;
+            if self.cumulative_area(middle) <= r {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        let index = low;
+        let s = sampler.sample(0.0..1.0).sqrt();
+        let b2 = sampler.sample(0.0..1.0) * s;
+        self.geometry(index, s - b2, b2, 0.0)
+    }
+
+    fn intersect(&self, ray: Ray) -> Option<Geometry> {
+        match &self.storage {
+            MeshStorage::Memory { bvh, .. } => self.intersect_nodes(bvh, ray),
+            MeshStorage::Packed(packed) => self.intersect_nodes(packed, ray),
+        }
+    }
+
+    fn bounds(&self) -> Bounds3 {
+        self.bounds
+    }
+}
+
+fn triangle_bounds(positions: &[Point3], triangle: &Triangle) -> Bounds3 {
+    triangle
+        .positions
+        .iter()
+        .map(|&i| Bounds3::new(positions[i], positions[i]))
+        .fold(Bounds3::empty(), |bounds, b| bounds.union(b))
+}
+
+// A shape placed by a rotation, a uniform scale and a translation, which
+// change its area by the square of the scale but leave uniform sampling
+// uniform.
+#[derive(Clone, Debug)]
+pub struct Transformed {
+    shape: Box<AnyShape>,
+    object_to_world: Matrix4,
+    world_to_object: Matrix4,
+    scale: Float,
+}
+
+impl Transformed {
+    pub fn new(shape: AnyShape, object_to_world: Matrix4, scale: Float) -> Transformed {
+        Transformed {
+            shape: Box::new(shape),
+            object_to_world,
+            world_to_object: object_to_world.inverse().unwrap(),
+            scale,
+        }
+    }
+
+    // Surface coordinates stay those of the shape, so that textures move
+    // with it.
+    fn to_world(&self, geometry: Geometry) -> Geometry {
+        Geometry {
+            point: self.object_to_world.transform_point(geometry.point),
+            normal: self
+                .object_to_world
+                .transform_normal(geometry.normal)
+                .norm(),
+            direction: self.object_to_world.transform_vector(geometry.direction),
+            dpdu: self.object_to_world.transform_vector(geometry.dpdu),
+            dpdv: self.object_to_world.transform_vector(geometry.dpdv),
+            ..geometry
+        }
+    }
+}
+
+impl Shape for Transformed {
+    fn area(&self) -> Float {
+        self.shape.area() * self.scale * self.scale
+    }
+
+    fn sample_geometry(&self, sampler: &mut dyn Sampler) -> Geometry {
+        self.to_world(self.shape.sample_geometry(sampler))
+    }
+
+    fn sample_from(&self, reference: Point3, sampler: &mut dyn Sampler) -> Geometry {
+        let reference = self.world_to_object.transform_point(reference);
+        self.to_world(self.shape.sample_from(reference, sampler))
+    }
+
+    fn pdf_from(&self, reference: Point3, geometry: &Geometry) -> Float {
+        let reference = self.world_to_object.transform_point(reference);
+        let geometry = Geometry {
+            point: self.world_to_object.transform_point(geometry.point),
+            normal: self
+                .world_to_object
+                .transform_normal(geometry.normal)
+                .norm(),
+            ..*geometry
+        };
+        self.shape.pdf_from(reference, &geometry) / (self.scale * self.scale)
+    }
+
+    fn intersect(&self, ray: Ray) -> Option<Geometry> {
+        // Distances along the ray scale with the length of the transformed
+        // direction, and so does its interval.
+        let direction = self.world_to_object.transform_vector(ray.direction);
+        let scale = direction.len();
+        let ray = Ray {
+            origin: self.world_to_object.transform_point(ray.origin),
+            direction: direction / scale,
+            t_min: ray.t_min * scale,
+            t_max: ray.t_max * scale,
+            ..ray
+        };
+        let geometry = self.shape.intersect(ray)?;
+        Some(Geometry {
+            t: geometry.t / scale,
+            ..self.to_world(geometry)
+        })
+    }
+
+    fn bounds(&self) -> Bounds3 {
+        let Bounds3 { min, max } = self.shape.bounds();
+        (0..8)
+            .map(|corner| {
+                let x = if corner & 1 == 0 { min.x } else { max.x };
+                let y = if corner & 2 == 0 { min.y } else { max.y };
+                let z = if corner & 4 == 0 { min.z } else { max.z };
+                self.object_to_world.transform_point(Point3::new(x, y, z))
+            })
+            .fold(Bounds3::empty(), |bounds, p| {
+                bounds.union(Bounds3::new(p, p))
+            })
+    }
+}
+
+// A rotation, a uniform scale and a translation, such as the transform of a
+// shape composed with those of the groups that contain it. Composing two
+// placements gives another, so that the shapes of nested groups are still
+// transformed only once.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Placement {
+    object_to_world: Matrix4,
+    scale: Float,
+}
+
+impl Placement {
+    pub fn identity() -> Placement {
+        Placement {
+            object_to_world: Matrix4::identity(),
+            scale: 1.0,
+        }
+    }
+
+    pub fn configure(config: &TransformConfig) -> Result<Placement, String> {
+        let scale = config.scale.unwrap_or(1.0);
+        if scale <= 0.0 {
+            return Err(String::from("the scale of a transform must be positive"));
+        }
+        let translation = config
+            .translation
+            .as_ref()
+            .map(Vector3::configure)
+            .unwrap_or(Vector3::new(0.0, 0.0, 0.0));
+        let rotation = config
+            .rotation
+            .as_ref()
+            .map(RotationConfig::configure)
+            .unwrap_or(Quaternion::new(1.0, 0.0, 0.0, 0.0));
+        Ok(Placement {
+            object_to_world: Matrix4::translation(translation)
+                * Matrix4::rotation(rotation)
+                * Matrix4::scaling(Vector3::new(scale, scale, scale)),
+            scale,
+        })
+    }
+
+    // This placement applied within the given parent.
+    pub fn within(self, parent: Placement) -> Placement {
+        Placement {
+            object_to_world: parent.object_to_world * self.object_to_world,
+            scale: parent.scale * self.scale,
+        }
+    }
+
+    // The transform of directions given in a frame turned by the rotation
+    // and then placed, up to their lengths.
+    pub fn orient(self, rotation: Quaternion) -> Matrix4 {
+        self.object_to_world * Matrix4::rotation(rotation)
+    }
+
+    pub fn place(self, shape: AnyShape) -> AnyShape {
+        if self == Placement::identity() {
+            shape
+        } else {
+            AnyShape::Transformed(Box::new(Transformed::new(
+                shape,
+                self.object_to_world,
+                self.scale,
+            )))
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -83,32 +748,84 @@ impl Shape for Sphere {
 #[serde(rename_all = "snake_case")]
 pub enum ShapeConfig {
     Sphere(SphereConfig),
+    Mesh(MeshConfig),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SphereConfig {
     center: Point3Config,
-    radius: f64,
+    radius: Float,
+    transform: Option<TransformConfig>,
+}
+
+// A triangle mesh read from a Wavefront OBJ file.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MeshConfig {
+    path: String,
+    transform: Option<TransformConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TransformConfig {
+    translation: Option<Vector3Config>,
+    rotation: Option<RotationConfig>,
+    scale: Option<Float>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RotationConfig {
+    axis: Vector3Config,
+    angle: Float,
+    unit: AngleUnitConfig,
+}
+
+impl RotationConfig {
+    pub fn configure(&self) -> Quaternion {
+        Quaternion::from_axis_angle(
+            Vector3::configure(&self.axis),
+            self.unit.radians(self.angle),
+        )
+    }
 }
 
 impl ShapeConfig {
-    pub fn configure(&self) -> Box<dyn Shape> {
+    // The shape, placed by its own transform and then by the given one.
+    pub fn configure(&self, parent: Placement) -> Result<AnyShape, String> {
         match self {
-            ShapeConfig::Sphere(c) => Box::new(Sphere::configure(c)),
+            ShapeConfig::Sphere(c) => {
+                let sphere = AnyShape::Sphere(Sphere::configure(c));
+                let placement = match &c.transform {
+                    Some(transform) => Placement::configure(transform)?.within(parent),
+                    None => parent,
+                };
+                Ok(placement.place(sphere))
+            }
+            ShapeConfig::Mesh(c) => {
+                let mesh = AnyShape::Mesh(Box::new(TriangleMesh::configure(c)?));
+                let placement = match &c.transform {
+                    Some(transform) => Placement::configure(transform)?.within(parent),
+                    None => parent,
+                };
+                Ok(placement.place(mesh))
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::f64::consts::PI;
+    use super::{AnyShape, Shape, Sphere, Transformed, TriangleMesh};
+    use std::sync::Arc;
 
-    use super::{Shape, Sphere};
     use crate::{
-        approx::ApproxEq,
+        approx::{loosen, ApproxEq, STEP},
+        bounds::Bounds3,
         geometry::Geometry,
+        obj::ObjMesh,
         ray::Ray,
-        vector::{Point3, Vector3},
+        sampler::test::MockSampler,
+        types::{consts::PI, Float},
+        vector::{Matrix4, Point2, Point3, Quaternion, Vector3},
     };
 
     #[test]
@@ -118,11 +835,12 @@ mod tests {
         let sphere = Sphere::new(center, radius);
         let area = sphere.area();
         assert_eq!(area, 16.0 * PI);
+        assert_eq!(AnyShape::Custom(Arc::new(sphere)).area(), 16.0 * PI);
     }
 
     #[test]
     fn test_sphere_insersect() {
-        let tolerance = 1e-8;
+        let tolerance = loosen(1e-8);
 
         let center = Point3::new(10.0, 0.0, 0.0);
         let radius = 1.0;
@@ -135,6 +853,11 @@ mod tests {
             point: Point3::new(9.0, 0.0, 0.0),
             normal: Vector3::new(-1.0, 0.0, 0.0),
             direction: Vector3::new(9.0, 0.0, 0.0),
+            t: 9.0,
+            uv: Point2::new(1.0, 0.5),
+            dpdu: Vector3::new(0.0, 0.0, -2.0 * PI),
+            dpdv: Vector3::new(0.0, -PI, 0.0),
+            surface: None,
         };
         assert!(actual.approx_eq(expected, tolerance));
 
@@ -148,6 +871,9 @@ mod tests {
             point: center + offset,
             normal: offset,
             direction: center + offset,
+            t: (center + offset).len(),
+            uv: Point2::new(0.125, offset.y.acos() / PI),
+            ..actual
         };
         assert!(actual.approx_eq(expected, tolerance));
 
@@ -163,7 +889,283 @@ mod tests {
             point: center + offset,
             normal: offset.norm(),
             direction: center + offset - origin,
+            t: (center + offset - origin).len(),
+            uv: Point2::new(0.875, offset.norm().y.acos() / PI),
+            ..actual
         };
         assert!(actual.approx_eq(expected, tolerance));
     }
+
+    #[test]
+    fn test_sphere_derivatives() {
+        let sphere = Sphere::new(Point3::new(1.0, 2.0, 3.0), 2.0);
+        let h = STEP;
+        for direction in [
+            Vector3::new(1.0, 1.0, 1.0),
+            Vector3::new(-1.0, 0.5, 0.2),
+            Vector3::new(0.3, -1.0, -0.7),
+        ] {
+            let point = sphere.center + direction.norm() * sphere.radius;
+            let uv = sphere.uv(point);
+            let (dpdu, dpdv) = sphere.derivatives(point);
+            let normal = direction.norm();
+            assert!(dpdu.dot(normal).abs() < loosen(1e-8) && dpdv.dot(normal).abs() < loosen(1e-8));
+            let du = sphere.uv(point + dpdu * h);
+            let dv = sphere.uv(point + dpdv * h);
+            assert!(du.approx_eq(Point2::new(uv.x + h, uv.y), loosen(1e-9)));
+            assert!(dv.approx_eq(Point2::new(uv.x, uv.y + h), loosen(1e-9)));
+        }
+    }
+
+    #[test]
+    fn test_transformed_sphere() {
+        let tolerance = loosen(1e-8);
+        let sphere = AnyShape::Sphere(Sphere::new(Point3::new(0.0, 0.0, 1.0), 1.0));
+        let rotation = Quaternion::from_axis_angle(Vector3::new(0.0, 1.0, 0.0), PI / 2.0);
+        let object_to_world = Matrix4::translation(Vector3::new(10.0, 0.0, 0.0))
+            * Matrix4::rotation(rotation)
+            * Matrix4::scaling(Vector3::new(2.0, 2.0, 2.0));
+        let shape = Transformed::new(sphere, object_to_world, 2.0);
+        assert!((shape.area() - 16.0 * PI).abs() < tolerance);
+
+        let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        let actual = shape.intersect(ray).unwrap();
+        let expected = Geometry {
+            point: Point3::new(10.0, 0.0, 0.0),
+            normal: Vector3::new(-1.0, 0.0, 0.0),
+            direction: Vector3::new(10.0, 0.0, 0.0),
+            t: 10.0,
+            uv: Point2::new(0.25, 0.5),
+            dpdu: Vector3::new(0.0, 0.0, -4.0 * PI),
+            dpdv: Vector3::new(0.0, -2.0 * PI, 0.0),
+            surface: None,
+        };
+        assert!(actual.approx_eq(expected, tolerance));
+
+        let bounds = shape.bounds();
+        let expected = Bounds3::new(Point3::new(10.0, -2.0, -2.0), Point3::new(14.0, 2.0, 2.0));
+        assert!(bounds.min.approx_eq(expected.min, tolerance));
+        assert!(bounds.max.approx_eq(expected.max, tolerance));
+    }
+
+    #[test]
+    fn test_sphere_leaving() {
+        let sphere = Sphere::new(Point3::new(10.0, 0.0, 0.0), 1.0);
+        let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        let geometry = sphere.intersect(ray).unwrap();
+        // Rounded to just inside the sphere, the point is still not found
+        // again, and the far side is found exactly.
+        let inside = Geometry {
+            point: geometry.point + Vector3::new(1e-9, 0.0, 0.0),
+            ..geometry
+        };
+        let inward = Ray::leaving(&inside, Vector3::new(1.0, 0.0, 0.0));
+        let far = sphere.intersect(inward).unwrap();
+        assert!(far
+            .point
+            .approx_eq(Point3::new(11.0, 0.0, 0.0), loosen(1e-8)));
+        let outward = Ray::leaving(&inside, Vector3::new(-1.0, 0.0, 0.0));
+        assert!(sphere.intersect(outward).is_none());
+        // Another sphere is found however close.
+        let other = Sphere::new(Point3::new(8.0 - 1e-6, 0.0, 0.0), 1.0);
+        assert!(other.intersect(outward).is_some());
+    }
+
+    #[test]
+    fn test_sphere_intersect_interval() {
+        let sphere = Sphere::new(Point3::new(10.0, 0.0, 0.0), 1.0);
+        let mut ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        ray.t_max = 8.5;
+        assert!(sphere.intersect(ray).is_none());
+        // Beyond the near side, the far side is found.
+        ray.t_min = 9.5;
+        ray.t_max = Float::INFINITY;
+        let geometry = sphere.intersect(ray).unwrap();
+        assert!(geometry
+            .point
+            .approx_eq(Point3::new(11.0, 0.0, 0.0), loosen(1e-8)));
+    }
+
+    const CUBE: &str = "
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+v 0 0 1
+v 1 0 1
+v 1 1 1
+v 0 1 1
+f 1 4 3 2
+f 5 6 7 8
+f 1 2 6 5
+f 4 8 7 3
+f 1 5 8 4
+f 2 3 7 6
+";
+
+    #[test]
+    fn test_sphere_sample_from() {
+        let tolerance = loosen(1e-8);
+        let center = Point3::new(1.0, 2.0, 3.0);
+        let sphere = Sphere::new(center, 1.0);
+        let sample = |shape: &dyn Shape, reference: Point3, u1: Float, u2: Float| {
+            let mut sampler = MockSampler::new();
+            sampler.add(u1);
+            sampler.add(u2);
+            shape.sample_from(reference, &mut sampler)
+        };
+
+        // Points are sampled only on the side seen from the reference point,
+        // at densities that match how the samples spread over the sphere.
+        for reference in [Point3::new(1.0, 2.0, 7.0), Point3::new(1e3, 2.0, 3.0)] {
+            for i in 0..64 {
+                let u1 = ((i % 8) as Float + 0.5) / 8.0;
+                let u2 = ((i / 8) as Float + 0.5) / 8.0;
+                let geometry = sample(&sphere, reference, u1, u2);
+                assert!(((geometry.point - center).len() - 1.0).abs() < tolerance);
+                let to_reference = reference - geometry.point;
+                assert!(geometry.normal.dot(to_reference) > 0.0);
+                let hit = sphere
+                    .intersect(Ray::new(reference, to_reference * -1.0))
+                    .unwrap();
+                assert!(hit
+                    .point
+                    .approx_eq(geometry.point, loosen(1e-9) * to_reference.len()));
+                let h = STEP;
+                let du1 = sample(&sphere, reference, u1 + h, u2).point
+                    - sample(&sphere, reference, u1 - h, u2).point;
+                let du2 = sample(&sphere, reference, u1, u2 + h).point
+                    - sample(&sphere, reference, u1, u2 - h).point;
+                let area = du1.cross(du2).len() / (4.0 * h * h);
+                let pdf = sphere.pdf_from(reference, &geometry);
+                assert!((area * pdf - 1.0).abs() < 1e-3);
+            }
+        }
+
+        // From inside, points are sampled as from anywhere.
+        let inside = Point3::new(1.0, 2.5, 3.0);
+        let geometry = sample(&sphere, inside, 0.3, 0.6);
+        assert_eq!(sphere.pdf_from(inside, &geometry), 1.0 / sphere.area());
+
+        // A placed sphere samples the points of the sphere it is placed as.
+        let placed = Transformed::new(
+            AnyShape::Sphere(Sphere::new(Point3::new(0.0, 0.0, 0.0), 0.5)),
+            Matrix4::translation(Vector3::new(1.0, 2.0, 3.0))
+                * Matrix4::scaling(Vector3::new(2.0, 2.0, 2.0)),
+            2.0,
+        );
+        let reference = Point3::new(4.0, 2.0, 3.0);
+        let geometry = sample(&placed, reference, 0.3, 0.6);
+        assert!(geometry
+            .point
+            .approx_eq(sample(&sphere, reference, 0.3, 0.6).point, tolerance));
+        let pdf = placed.pdf_from(reference, &geometry);
+        assert!((pdf - sphere.pdf_from(reference, &geometry)).abs() < tolerance * pdf);
+    }
+
+    #[test]
+    fn test_triangle_mesh() {
+        let tolerance = loosen(1e-12);
+        let cube = TriangleMesh::from_obj(ObjMesh::parse(CUBE).unwrap()).unwrap();
+        assert!((cube.area() - 6.0).abs() < tolerance);
+        let expected = Bounds3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+        assert_eq!(cube.bounds(), expected);
+
+        let ray = Ray::new(Point3::new(0.25, 0.5, -1.0), Vector3::new(0.0, 0.0, 1.0));
+        let front = cube.intersect(ray).unwrap();
+        assert!(front
+            .point
+            .approx_eq(Point3::new(0.25, 0.5, 0.0), tolerance));
+        assert!(front
+            .normal
+            .approx_eq(Vector3::new(0.0, 0.0, -1.0), tolerance));
+        assert!((front.t - 1.0).abs() < tolerance);
+        // Leaving the front face, the ray finds the back one.
+        let back = cube.intersect(Ray::leaving(&front, ray.direction)).unwrap();
+        assert!(back.point.approx_eq(Point3::new(0.25, 0.5, 1.0), tolerance));
+        assert!(back
+            .normal
+            .approx_eq(Vector3::new(0.0, 0.0, 1.0), tolerance));
+        let miss = Ray::new(Point3::new(1.5, 0.5, -1.0), Vector3::new(0.0, 0.0, 1.0));
+        assert!(cube.intersect(miss).is_none());
+        // Neighbouring faces are still found away from the corners they share.
+        let side = cube
+            .intersect(Ray::leaving(&front, Vector3::new(1.0, 0.0, 1.0)))
+            .unwrap();
+        assert!(side.point.approx_eq(Point3::new(1.0, 0.5, 0.75), tolerance));
+
+        // A ray leaving a square at the edge its triangles share, a little
+        // below it through rounding, does not find the other triangle there.
+        let square = TriangleMesh::from_obj(
+            ObjMesh::parse("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n").unwrap(),
+        )
+        .unwrap();
+        let mut edge = square.geometry(0, 0.0, 0.5, 1.0);
+        edge.point = Point3::new(0.5, 0.5, -1e-9);
+        assert!(square
+            .intersect(Ray::leaving(&edge, Vector3::new(0.0, 0.0, 1.0)))
+            .is_none());
+
+        // Sampled points lie on the face they take the normal of.
+        for r in [0.05, 0.3, 0.55, 0.95] {
+            let mut sampler = MockSampler::new();
+            sampler.add(r);
+            sampler.add(0.5);
+            sampler.add(0.5);
+            let sampled = cube.sample_geometry(&mut sampler);
+            let n = sampled.normal;
+            let side = Float::max(n.x + n.y + n.z, 0.0);
+            assert!((sampled.point.dot(n) - side).abs() < tolerance);
+            assert!((n.x.abs() + n.y.abs() + n.z.abs() - 1.0).abs() < tolerance);
+            assert!(sampled.dpdu.cross(sampled.dpdv).dot(n) > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_triangle_mesh_hierarchy() {
+        // A grid of squares over the unit square, enough for the hierarchy
+        // to split them several times.
+        let n = 16;
+        let mut source = String::new();
+        for y in 0..=n {
+            for x in 0..=n {
+                source += &format!(
+                    "v {} {} 0\n",
+                    x as Float / n as Float,
+                    y as Float / n as Float
+                );
+                source += &format!(
+                    "vt {} {}\n",
+                    x as Float / n as Float,
+                    y as Float / n as Float
+                );
+            }
+        }
+        for y in 0..n {
+            for x in 0..n {
+                let i = y * (n + 1) + x + 1;
+                let (a, b, c, d) = (i, i + 1, i + n + 2, i + n + 1);
+                source += &format!("f {a}/{a} {b}/{b} {c}/{c} {d}/{d}\n");
+            }
+        }
+        let grid = TriangleMesh::from_obj(ObjMesh::parse(&source).unwrap()).unwrap();
+        assert!((grid.area() - 1.0).abs() < loosen(1e-12));
+        for i in 0..100 {
+            let x = (i as Float * 0.618034).fract();
+            let y = (i as Float * 0.414214).fract();
+            let origin = Point3::new(0.3, 0.6, 2.0);
+            let ray = Ray::new(origin, Point3::new(x, y, 0.0) - origin);
+            let geometry = grid.intersect(ray).unwrap();
+            assert!(geometry
+                .point
+                .approx_eq(Point3::new(x, y, 0.0), loosen(1e-9)));
+            assert!(geometry.uv.approx_eq(Point2::new(x, y), loosen(1e-9)));
+            assert!(geometry
+                .dpdu
+                .approx_eq(Vector3::new(1.0, 0.0, 0.0), loosen(1e-9)));
+            assert!(geometry
+                .dpdv
+                .approx_eq(Vector3::new(0.0, 1.0, 0.0), loosen(1e-9)));
+        }
+    }
 }
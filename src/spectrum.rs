@@ -2,7 +2,7 @@ use std::ops::{Add, Div, Mul};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{approx::ApproxEq, util};
+use crate::{approx::ApproxEq, types::Float, util};
 
 pub type Spectrum = RgbSpectrum;
 
@@ -14,17 +14,30 @@ const LUMINANCE_WEIGHT: RgbSpectrum = RgbSpectrum {
 
 #[derive(Copy, Clone, Debug)]
 pub struct RgbSpectrum {
-    pub r: f64,
-    pub g: f64,
-    pub b: f64,
+    pub r: Float,
+    pub g: Float,
+    pub b: Float,
 }
 
 impl RgbSpectrum {
-    pub fn configure(config: &RgbSpectrumConfig) -> RgbSpectrum {
+    pub fn configure(config: &SpectrumConfig) -> RgbSpectrum {
+        match config {
+            SpectrumConfig::Rgb(config) => RgbSpectrum {
+                r: config.r,
+                g: config.g,
+                b: config.b,
+            },
+            SpectrumConfig::Color(color) => color.spectrum,
+        }
+    }
+
+    // Linear sRGB primaries with a D65 white point.
+    #[allow(clippy::excessive_precision)]
+    pub fn from_xyz(x: Float, y: Float, z: Float) -> RgbSpectrum {
         RgbSpectrum {
-            r: config.r,
-            g: config.g,
-            b: config.b,
+            r: 3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+            g: -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+            b: 0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
         }
     }
 
@@ -36,7 +49,7 @@ impl RgbSpectrum {
         self.r == 0.0 && self.g == 0.0 && self.b == 0.0
     }
 
-    pub fn fill(v: f64) -> RgbSpectrum {
+    pub fn fill(v: Float) -> RgbSpectrum {
         RgbSpectrum { r: v, g: v, b: v }
     }
 
@@ -48,43 +61,51 @@ impl RgbSpectrum {
         }
     }
 
-    pub fn luminance(&self) -> f64 {
+    pub fn exp(&self) -> RgbSpectrum {
+        RgbSpectrum {
+            r: self.r.exp(),
+            g: self.g.exp(),
+            b: self.b.exp(),
+        }
+    }
+
+    pub fn luminance(&self) -> Float {
         self.r * LUMINANCE_WEIGHT.r + self.g * LUMINANCE_WEIGHT.g + self.b * LUMINANCE_WEIGHT.b
     }
 
-    pub fn to_rgb(&self) -> RgbSpectrum {
-        self.clone()
+    pub fn to_rgb(self) -> RgbSpectrum {
+        self
     }
 
     pub fn has_nans(&self) -> bool {
         self.r.is_nan() || self.g.is_nan() || self.b.is_nan()
     }
 
-    pub fn max(&self) -> f64 {
-        f64::max(f64::max(self.r, self.g), self.b)
+    pub fn max(&self) -> Float {
+        Float::max(Float::max(self.r, self.g), self.b)
     }
 
-    pub fn try_clamp(&self, limit: Option<f64>) -> RgbSpectrum {
-        if limit.is_some() {
-            self.clamp(limit.unwrap())
+    pub fn try_clamp(&self, limit: Option<Float>) -> RgbSpectrum {
+        if let Some(limit) = limit {
+            self.clamp(limit)
         } else {
-            self.clone()
+            *self
         }
     }
 
-    pub fn clamp(&self, limit: f64) -> RgbSpectrum {
+    pub fn clamp(&self, limit: Float) -> RgbSpectrum {
         let max = self.max();
         if max > limit {
             let scale = limit / max;
             scale * (*self)
         } else {
-            self.clone()
+            *self
         }
     }
 }
 
 impl ApproxEq for RgbSpectrum {
-    fn approx_eq(&self, other: Self, tolerance: f64) -> bool {
+    fn approx_eq(&self, other: Self, tolerance: Float) -> bool {
         util::equals(self.r, other.r, tolerance)
             && util::equals(self.g, other.g, tolerance)
             && util::equals(self.b, other.b, tolerance)
@@ -102,9 +123,9 @@ impl Add<RgbSpectrum> for RgbSpectrum {
     }
 }
 
-impl Mul<f64> for RgbSpectrum {
+impl Mul<Float> for RgbSpectrum {
     type Output = RgbSpectrum;
-    fn mul(self, rhs: f64) -> Self::Output {
+    fn mul(self, rhs: Float) -> Self::Output {
         RgbSpectrum {
             r: self.r * rhs,
             g: self.g * rhs,
@@ -113,7 +134,7 @@ impl Mul<f64> for RgbSpectrum {
     }
 }
 
-impl Mul<RgbSpectrum> for f64 {
+impl Mul<RgbSpectrum> for Float {
     type Output = RgbSpectrum;
     fn mul(self, rhs: RgbSpectrum) -> Self::Output {
         RgbSpectrum {
@@ -124,9 +145,9 @@ impl Mul<RgbSpectrum> for f64 {
     }
 }
 
-impl Div<f64> for RgbSpectrum {
+impl Div<Float> for RgbSpectrum {
     type Output = RgbSpectrum;
-    fn div(self, rhs: f64) -> Self::Output {
+    fn div(self, rhs: Float) -> Self::Output {
         RgbSpectrum {
             r: self.r / rhs,
             g: self.g / rhs,
@@ -141,34 +162,150 @@ impl PartialEq for RgbSpectrum {
     }
 }
 
-pub type SpectrumConfig = RgbSpectrumConfig;
+// A spectrum is given by its linear RGB components, or by a color string.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum SpectrumConfig {
+    Rgb(RgbSpectrumConfig),
+    Color(ColorConfig),
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RgbSpectrumConfig {
-    pub r: f64,
-    pub g: f64,
-    pub b: f64,
+    pub r: Float,
+    pub g: Float,
+    pub b: Float,
+}
+
+// A color named after a preset or written as a hex sRGB value, such as
+// `#ffcc00`, whose components are decoded to linear ones. The illuminants are
+// white points in linear sRGB of unit luminance, and the metals are the
+// reflectances of their polished surfaces at normal incidence.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(try_from = "String", into = "String")]
+pub struct ColorConfig {
+    source: String,
+    spectrum: RgbSpectrum,
+}
+
+impl ColorConfig {
+    pub fn parse(source: &str) -> Result<ColorConfig, String> {
+        let spectrum = match source.strip_prefix('#') {
+            Some(hex) => parse_hex(hex).ok_or_else(|| format!("invalid hex color '{}'", source))?,
+            None => preset(source).ok_or_else(|| format!("unknown color '{}'", source))?,
+        };
+        Ok(ColorConfig {
+            source: String::from(source),
+            spectrum,
+        })
+    }
+}
+
+impl TryFrom<String> for ColorConfig {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<ColorConfig, String> {
+        ColorConfig::parse(&source)
+    }
+}
+
+impl From<ColorConfig> for String {
+    fn from(color: ColorConfig) -> String {
+        color.source
+    }
+}
+
+fn preset(name: &str) -> Option<RgbSpectrum> {
+    let rgb = |r, g, b| RgbSpectrum { r, g, b };
+    let spectrum = match name {
+        "black" => RgbSpectrum::black(),
+        "white" | "d65" => RgbSpectrum::fill(1.0),
+        "d50" => RgbSpectrum::from_xyz(0.96422, 1.0, 0.82521),
+        "d75" => RgbSpectrum::from_xyz(0.94972, 1.0, 1.22638),
+        "a" => RgbSpectrum::from_xyz(1.09850, 1.0, 0.35585),
+        "aluminum" => rgb(0.913, 0.922, 0.924),
+        "copper" => rgb(0.955, 0.637, 0.538),
+        "gold" => rgb(1.000, 0.766, 0.336),
+        "silver" => rgb(0.972, 0.960, 0.915),
+        _ => return None,
+    };
+    Some(spectrum)
+}
+
+fn parse_hex(hex: &str) -> Option<RgbSpectrum> {
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let component = |i: usize| {
+        let value = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+        Some(srgb_to_linear(value as Float / 255.0))
+    };
+    Some(RgbSpectrum {
+        r: component(0)?,
+        g: component(1)?,
+        b: component(2)?,
+    })
+}
+
+fn srgb_to_linear(value: Float) -> Float {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::spectrum::{Spectrum, LUMINANCE_WEIGHT};
+    use crate::{
+        approx::ApproxEq,
+        spectrum::{Spectrum, LUMINANCE_WEIGHT},
+        types::Float,
+    };
 
-    use super::{RgbSpectrum, RgbSpectrumConfig};
+    use super::{RgbSpectrum, RgbSpectrumConfig, SpectrumConfig};
 
     #[test]
     fn test_rgb_spectrum_configure() {
-        let config = RgbSpectrumConfig {
+        let config = SpectrumConfig::Rgb(RgbSpectrumConfig {
             r: 1.0,
             g: 1.0,
             b: 1.0,
-        };
+        });
         let spectrum = RgbSpectrum::configure(&config);
         assert_eq!(spectrum.r, 1.0);
         assert_eq!(spectrum.g, 1.0);
         assert_eq!(spectrum.b, 1.0);
     }
 
+    #[test]
+    fn test_color_configure() {
+        let configure = |source: &str| {
+            let config: SpectrumConfig = serde_yaml::from_str(source).unwrap();
+            RgbSpectrum::configure(&config)
+        };
+        assert_eq!(
+            configure("{ r: 0.5, g: 0.25, b: 1.0 }"),
+            RgbSpectrum {
+                r: 0.5,
+                g: 0.25,
+                b: 1.0
+            }
+        );
+        assert_eq!(configure("d65"), RgbSpectrum::fill(1.0));
+        assert!((configure("d50").luminance() - 1.0).abs() < 1e-4);
+        assert_eq!(configure("'#ffffff'"), RgbSpectrum::fill(1.0));
+        assert!(configure("'#808080'").approx_eq(RgbSpectrum::fill(0.2158605), 1e-6));
+        assert!(serde_yaml::from_str::<SpectrumConfig>("'#80808'").is_err());
+        assert!(serde_yaml::from_str::<SpectrumConfig>("mauve").is_err());
+    }
+
+    #[test]
+    fn test_rgb_spectrum_from_xyz() {
+        let white = RgbSpectrum::from_xyz(0.95047, 1.0, 1.08883);
+        assert!(white.approx_eq(RgbSpectrum::fill(1.0), 1e-4));
+    }
+
     #[test]
     fn test_rgb_spectrum_black() {
         let spectrum = RgbSpectrum::black();
@@ -199,6 +336,21 @@ mod tests {
         assert_eq!(s3, RgbSpectrum::fill(6.0));
     }
 
+    #[test]
+    fn test_rgb_spectrum_exp() {
+        let spectrum = RgbSpectrum {
+            r: 0.0,
+            g: 1.0,
+            b: -1.0,
+        };
+        let expected = RgbSpectrum {
+            r: 1.0,
+            g: Float::exp(1.0),
+            b: Float::exp(-1.0),
+        };
+        assert_eq!(spectrum.exp(), expected);
+    }
+
     #[test]
     fn test_rgb_spectrum_luminance() {
         let spectrum = RgbSpectrum::fill(2.0);
@@ -0,0 +1,62 @@
+// Scene files are templates whose variables, written `${name}`, are replaced
+// by the values given with `--define name=value` before the file is parsed,
+// so that a sweep over a parameter needs a single scene file. A variable may
+// have a value for when it is not defined, as in `${radius:-1.0}`, and `$$`
+// stands for a literal `$`.
+pub fn substitute(source: &str, definitions: &[(String, String)]) -> Result<String, String> {
+    let mut result = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("$$") {
+            result.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let end = after
+                .find('}')
+                .ok_or("unterminated variable in scene template")?;
+            let (name, default) = match after[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&after[..end], None),
+            };
+            let value = definitions
+                .iter()
+                .rev()
+                .find(|(n, _)| n == name)
+                .map(|(_, value)| value.as_str())
+                .or(default)
+                .ok_or_else(|| format!("undefined variable '{}' in scene template", name))?;
+            result.push_str(value);
+            rest = &after[end + 1..];
+        } else {
+            result.push('$');
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::substitute;
+
+    #[test]
+    fn test_substitute() {
+        let definitions = [
+            (String::from("radius"), String::from("2.5")),
+            (String::from("radius"), String::from("3.0")),
+        ];
+        assert_eq!(
+            substitute("radius: ${radius}", &definitions).unwrap(),
+            "radius: 3.0"
+        );
+        assert_eq!(
+            substitute("{ r: ${red:-0.5}, g: $$1, b: $ }", &definitions).unwrap(),
+            "{ r: 0.5, g: $1, b: $ }"
+        );
+        assert!(substitute("${red}", &definitions).is_err());
+        assert!(substitute("${radius", &definitions).is_err());
+    }
+}
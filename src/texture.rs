@@ -1,16 +1,41 @@
 use core::fmt;
 
 use crate::{
+    cache,
     geometry::Geometry,
     spectrum::{Spectrum, SpectrumConfig},
+    types::{consts::PI, Float},
+    util,
+    vector::{Point2, Point3, Point3Config, Vector3, Vector3Config},
 };
 
 use serde::{Deserialize, Serialize};
 
-pub trait Texture: fmt::Debug {
+pub trait Texture: fmt::Debug + Send + Sync {
     fn evaluate(&self, geometry: Geometry) -> Spectrum;
 }
 
+// The textures, dispatched statically on the hot path of shading. A new
+// texture implements `Texture` and joins the enum, or, until it is worth a
+// variant of its own, is boxed as `Custom` and dispatched dynamically.
+#[derive(Debug)]
+pub enum AnyTexture {
+    Constant(ConstantTexture),
+    Image(ImageTexture),
+    #[allow(dead_code)]
+    Custom(Box<dyn Texture>),
+}
+
+impl Texture for AnyTexture {
+    fn evaluate(&self, geometry: Geometry) -> Spectrum {
+        match self {
+            AnyTexture::Constant(texture) => texture.evaluate(geometry),
+            AnyTexture::Image(texture) => texture.evaluate(geometry),
+            AnyTexture::Custom(texture) => texture.evaluate(geometry),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ConstantTexture {
     value: Spectrum,
@@ -32,11 +57,153 @@ impl Texture for ConstantTexture {
     }
 }
 
+// A PNG image mapped onto an object by its surface coordinates, with u
+// across the image width and v down its height. Texels are read through the
+// shared texture cache, so the image is only decoded where it is seen.
+#[derive(Debug)]
+pub struct ImageTexture {
+    texture: usize,
+    width: usize,
+    height: usize,
+    projection: Projection,
+}
+
+impl ImageTexture {
+    pub fn configure(config: &ImageTextureConfig) -> Result<ImageTexture, String> {
+        let gamma = config.gamma.unwrap_or(2.2);
+        let (texture, width, height) = cache::textures().register(&config.path, gamma)?;
+        let projection = match &config.projection {
+            Some(projection) => Projection::configure(projection)?,
+            None => Projection::Uv,
+        };
+        Ok(ImageTexture {
+            texture,
+            width,
+            height,
+            projection,
+        })
+    }
+
+    fn texel(&self, uv: Point2) -> Spectrum {
+        let x = ((uv.x * self.width as Float) as usize).min(self.width - 1);
+        let y = ((uv.y * self.height as Float) as usize).min(self.height - 1);
+        cache::textures().texel(self.texture, x, y)
+    }
+}
+
+impl Texture for ImageTexture {
+    fn evaluate(&self, geometry: Geometry) -> Spectrum {
+        self.projection
+            .lookups(&geometry)
+            .into_iter()
+            .filter(|(_, weight)| *weight > 0.0)
+            .fold(Spectrum::black(), |sum, (uv, weight)| {
+                sum + self.texel(uv) * weight
+            })
+    }
+}
+
+// Where a texture is looked up: at the surface coordinates of the shape, or
+// at a projection of the point in the scene, for shapes whose coordinates do
+// not suit the texture. Planar and triplanar projections repeat the image
+// every `size` scene units.
+#[derive(Debug, PartialEq)]
+enum Projection {
+    Uv,
+    // Onto a plane, with the image across u and up v.
+    Planar { u: Vector3, v: Vector3, size: Float },
+    // Onto a sphere about the center, as a sphere shape maps its surface.
+    Spherical { center: Point3 },
+    // Onto the three planes of the axes, blended by how squarely the surface
+    // faces each, more sharply for a higher sharpness.
+    Triplanar { size: Float, sharpness: Float },
+}
+
+impl Projection {
+    fn configure(config: &ProjectionConfig) -> Result<Projection, String> {
+        let size = |size: Option<Float>| match size.unwrap_or(1.0) {
+            size if size > 0.0 => Ok(size),
+            _ => Err(String::from("texture projection size must be positive")),
+        };
+        Ok(match config {
+            ProjectionConfig::Uv => Projection::Uv,
+            ProjectionConfig::Planar(c) => {
+                let normal = c
+                    .normal
+                    .as_ref()
+                    .map_or(Vector3::new(0.0, 0.0, 1.0), Vector3::configure);
+                if normal.is_zero() {
+                    return Err(String::from("planar projection normal must be nonzero"));
+                }
+                let (u, v, _) = util::orthonormal_basis(normal);
+                Projection::Planar {
+                    u,
+                    v,
+                    size: size(c.size)?,
+                }
+            }
+            ProjectionConfig::Spherical(c) => Projection::Spherical {
+                center: c
+                    .center
+                    .as_ref()
+                    .map_or(Point3::new(0.0, 0.0, 0.0), Point3::configure),
+            },
+            ProjectionConfig::Triplanar(c) => Projection::Triplanar {
+                size: size(c.size)?,
+                sharpness: c.sharpness.unwrap_or(4.0),
+            },
+        })
+    }
+
+    // The coordinates at which to look up the texture, with their weights,
+    // of which only triplanar projection has more than one.
+    fn lookups(&self, geometry: &Geometry) -> [(Point2, Float); 3] {
+        let single = |uv| [(uv, 1.0), (uv, 0.0), (uv, 0.0)];
+        let point = geometry.point;
+        match *self {
+            Projection::Uv => single(geometry.uv),
+            Projection::Planar { u, v, size } => single(planar(point, u, v, size)),
+            Projection::Spherical { center } => {
+                let d = (point - center).norm();
+                single(Point2::new(
+                    0.5 + d.z.atan2(d.x) / (2.0 * PI),
+                    d.y.clamp(-1.0, 1.0).acos() / PI,
+                ))
+            }
+            Projection::Triplanar { size, sharpness } => {
+                let n = geometry.normal.norm();
+                let weights = [n.x, n.y, n.z].map(|c| c.abs().powf(sharpness));
+                let total: Float = weights.iter().sum();
+                let (x, y, z) = (
+                    Vector3::new(1.0, 0.0, 0.0),
+                    Vector3::new(0.0, 1.0, 0.0),
+                    Vector3::new(0.0, 0.0, 1.0),
+                );
+                [
+                    (planar(point, z, y, size), weights[0] / total),
+                    (planar(point, x, z, size), weights[1] / total),
+                    (planar(point, x, y, size), weights[2] / total),
+                ]
+            }
+        }
+    }
+}
+
+// The coordinates of the point in the repeating tiles of a plane, with v
+// running down the image as it does up the plane.
+fn planar(point: Point3, u: Vector3, v: Vector3, size: Float) -> Point2 {
+    Point2::new(
+        (point.dot(u) / size).rem_euclid(1.0),
+        1.0 - (point.dot(v) / size).rem_euclid(1.0),
+    )
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 pub enum TextureConfig {
     Constant(ConstantTextureConfig),
+    Image(ImageTextureConfig),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -44,10 +211,47 @@ pub struct ConstantTextureConfig {
     spectrum: SpectrumConfig,
 }
 
+// The gamma decodes the stored values to linear ones.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImageTextureConfig {
+    path: String,
+    gamma: Option<Float>,
+    projection: Option<ProjectionConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum ProjectionConfig {
+    Uv,
+    Planar(PlanarProjectionConfig),
+    Spherical(SphericalProjectionConfig),
+    Triplanar(TriplanarProjectionConfig),
+}
+
+// The plane faces +z by default.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PlanarProjectionConfig {
+    normal: Option<Vector3Config>,
+    size: Option<Float>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SphericalProjectionConfig {
+    center: Option<Point3Config>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TriplanarProjectionConfig {
+    size: Option<Float>,
+    sharpness: Option<Float>,
+}
+
 impl TextureConfig {
-    pub fn configure(&self) -> Box<dyn Texture> {
+    pub fn configure(&self) -> Result<AnyTexture, String> {
         match self {
-            TextureConfig::Constant(c) => Box::new(ConstantTexture::configure(&c)),
+            TextureConfig::Constant(c) => Ok(AnyTexture::Constant(ConstantTexture::configure(c))),
+            TextureConfig::Image(c) => Ok(AnyTexture::Image(ImageTexture::configure(c)?)),
         }
     }
 }
@@ -55,22 +259,24 @@ impl TextureConfig {
 #[cfg(test)]
 mod tests {
     use crate::{
+        approx::ApproxEq,
         geometry::Geometry,
-        spectrum::{Spectrum, SpectrumConfig},
+        spectrum::{RgbSpectrumConfig, Spectrum, SpectrumConfig},
         texture::Texture,
-        vector::{Point3, Vector3},
+        types::consts::PI,
+        vector::{Point2, Point3, Vector3},
     };
 
-    use super::{ConstantTexture, ConstantTextureConfig};
+    use super::{AnyTexture, ConstantTexture, ConstantTextureConfig, Projection, ProjectionConfig};
 
     #[test]
     fn test_constant_texture_configure() {
         let config = ConstantTextureConfig {
-            spectrum: SpectrumConfig {
+            spectrum: SpectrumConfig::Rgb(RgbSpectrumConfig {
                 r: 1.0,
                 g: 1.0,
                 b: 1.0,
-            },
+            }),
         };
         let texture = ConstantTexture::configure(&config);
         assert_eq!(texture.value, Spectrum::fill(1.0));
@@ -91,7 +297,48 @@ mod tests {
             point: Point3::new(0.0, 0.0, 0.0),
             normal: Vector3::new(0.0, 0.0, 0.0),
             direction: Vector3::new(0.0, 0.0, 0.0),
+            t: 0.0,
+            uv: Point2::new(0.0, 0.0),
+            dpdu: Vector3::new(0.0, 0.0, 0.0),
+            dpdv: Vector3::new(0.0, 0.0, 0.0),
+            surface: None,
         };
         assert_eq!(texture.evaluate(geometry), spectrum);
+        let texture = AnyTexture::Custom(Box::new(texture));
+        assert_eq!(texture.evaluate(geometry), spectrum);
+    }
+
+    #[test]
+    fn test_projection_lookups() {
+        let geometry = Geometry {
+            point: Point3::new(2.25, -0.5, 1.0),
+            normal: Vector3::new(0.0, 0.0, 1.0),
+            direction: Vector3::new(0.0, 0.0, 1.0),
+            t: 0.0,
+            uv: Point2::new(0.1, 0.2),
+            dpdu: Vector3::new(0.0, 0.0, 0.0),
+            dpdv: Vector3::new(0.0, 0.0, 0.0),
+            surface: None,
+        };
+        let projection = |source| {
+            Projection::configure(&serde_yaml::from_str::<ProjectionConfig>(source).unwrap())
+                .unwrap()
+        };
+        let uv = |projection: Projection| projection.lookups(&geometry)[0];
+        assert_eq!(uv(projection("type: uv")), (Point2::new(0.1, 0.2), 1.0));
+        let (planar, _) = uv(projection("{ type: planar, size: 2.0 }"));
+        assert!(planar.approx_eq(Point2::new(0.125, 0.25), 1e-9));
+        let (spherical, _) = uv(projection("type: spherical"));
+        let d = geometry.point.norm();
+        assert!(spherical.approx_eq(
+            Point2::new(0.5 + d.z.atan2(d.x) / (2.0 * PI), d.y.acos() / PI),
+            1e-9
+        ));
+        // Facing +z, only the plane of x and y is seen.
+        let lookups = projection("type: triplanar").lookups(&geometry);
+        assert_eq!(lookups.map(|(_, weight)| weight), [0.0, 0.0, 1.0]);
+        assert!(lookups[2].0.approx_eq(Point2::new(0.25, 0.5), 1e-9));
+        let invalid = serde_yaml::from_str::<ProjectionConfig>("{ type: planar, size: 0.0 }");
+        assert!(Projection::configure(&invalid.unwrap()).is_err());
     }
 }
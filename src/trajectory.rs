@@ -0,0 +1,73 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use crate::{error::Error, path::Contribution, types::narrow};
+
+// The version of the magic number changes with the layout of a record, so
+// that a reader cannot mistake the 14-byte records of the first version,
+// which had no chain index, for those of the current one.
+const MAGIC: &[u8; 8] = b"MMLTTRJ2";
+
+// A compact binary log of the non-empty states accepted by the Markov
+// chains. After an 8-byte magic number, each record is 16 bytes, all
+// little-endian: the pixel coordinates and scalar contribution as f32s
+// followed by the path length and the chain index as u16s.
+pub struct TrajectoryWriter {
+    path: String,
+    writer: BufWriter<File>,
+}
+
+impl TrajectoryWriter {
+    pub fn create(path: &str) -> Result<TrajectoryWriter, Error> {
+        let file = File::create(path).map_err(Error::io(path))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(MAGIC).map_err(Error::io(path))?;
+        Ok(TrajectoryWriter {
+            path: String::from(path),
+            writer,
+        })
+    }
+
+    pub fn record(&mut self, contribution: &Contribution, chain: usize) -> Result<(), Error> {
+        let mut record = [0u8; 16];
+        record[0..4].copy_from_slice(&narrow(contribution.pixel_coordinates.x).to_le_bytes());
+        record[4..8].copy_from_slice(&narrow(contribution.pixel_coordinates.y).to_le_bytes());
+        record[8..12].copy_from_slice(&narrow(contribution.scalar).to_le_bytes());
+        record[12..14].copy_from_slice(&(contribution.path_length as u16).to_le_bytes());
+        record[14..16].copy_from_slice(&(chain as u16).to_le_bytes());
+        self.writer
+            .write_all(&record)
+            .map_err(Error::io(&self.path))
+    }
+
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.writer.flush().map_err(Error::io(&self.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::TrajectoryWriter;
+    use crate::{path::Contribution, spectrum::Spectrum, vector::Point2};
+
+    #[test]
+    fn test_trajectory_writer() {
+        let path = env::temp_dir().join("mmlt-test-trajectory.bin");
+        let path = path.to_str().unwrap();
+        let mut writer = TrajectoryWriter::create(path).unwrap();
+        let contribution = Contribution::new(Spectrum::fill(2.0), Point2::new(1.5, 2.5), 3);
+        writer.record(&contribution, 1).unwrap();
+        writer.finish().unwrap();
+        let bytes = fs::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(bytes.len(), 8 + 16);
+        assert_eq!(&bytes[0..8], b"MMLTTRJ2");
+        assert_eq!(&bytes[8..12], &1.5f32.to_le_bytes());
+        assert_eq!(&bytes[20..22], &3u16.to_le_bytes());
+        assert_eq!(&bytes[22..24], &1u16.to_le_bytes());
+    }
+}
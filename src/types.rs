@@ -1,3 +1,28 @@
+// The precision of the math core: double by default, and single with the
+// `f32` feature, which trades accuracy for speed and memory.
+#[cfg(not(feature = "f32"))]
+pub type Float = f64;
+#[cfg(feature = "f32")]
+pub type Float = f32;
+
+#[cfg(feature = "f32")]
+pub use std::f32::consts;
+#[cfg(not(feature = "f32"))]
+pub use std::f64::consts;
+
+// Widens a value to double precision, in which images and checkpoints are
+// accumulated and stored whatever the precision of the math core.
+pub fn widen(x: impl Into<f64>) -> f64 {
+    x.into()
+}
+
+// Narrows a value to single precision, in which trajectories and 32-bit
+// images are stored; the cast does nothing with the `f32` feature.
+#[allow(clippy::unnecessary_cast)]
+pub fn narrow(x: Float) -> f32 {
+    x as f32
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum PathType {
     Camera,
@@ -1,24 +1,28 @@
-use std::f64::consts::PI;
+use crate::{
+    sampler::Sampler,
+    types::{consts::PI, Float},
+    vector::Vector3,
+};
 
-use crate::{sampler::Sampler, vector::Vector3};
-
-pub fn direction_to_area(direction: Vector3, normal: Vector3) -> f64 {
+pub fn direction_to_area(direction: Vector3, normal: Vector3) -> Float {
     let d2 = direction.dot(direction);
     let x = normal.dot(direction) / (d2 * d2.sqrt());
     x.abs()
 }
 
-pub fn geometry_term(direction: Vector3, normal1: Vector3, normal2: Vector3) -> f64 {
+pub fn geometry_term(direction: Vector3, normal1: Vector3, normal2: Vector3) -> Float {
     let d2 = direction.dot(direction);
     let x = (normal1.dot(direction) * normal2.dot(direction)) / (d2 * d2);
     x.abs()
 }
 
-pub fn erf_inv(x: f64) -> f64 {
+// The coefficients are those published, more precise than single precision.
+#[allow(clippy::excessive_precision)]
+pub fn erf_inv(x: Float) -> Float {
     let x = x.clamp(-0.99999, 0.99999);
-    let mut w = -f64::ln((1.0 - x) * (1.0 + x));
+    let mut w = -Float::ln((1.0 - x) * (1.0 + x));
     if w < 5.0 {
-        w = w - 2.5;
+        w -= 2.5;
         let mut p = 2.81022636e-08;
         p = 3.43273939e-07 + p * w;
         p = -3.5233877e-06 + p * w;
@@ -30,7 +34,7 @@ pub fn erf_inv(x: f64) -> f64 {
         p = 1.50140941 + p * w;
         p * x
     } else {
-        w = f64::sqrt(w) - 3.0;
+        w = Float::sqrt(w) - 3.0;
         let mut p = -0.000200214257;
         p = 0.000100950558 + p * w;
         p = 0.00134934322 + p * w;
@@ -44,7 +48,7 @@ pub fn erf_inv(x: f64) -> f64 {
     }
 }
 
-pub fn concentric_sample_disk(sampler: &mut dyn Sampler) -> (f64, f64) {
+pub fn concentric_sample_disk(sampler: &mut dyn Sampler) -> (Float, Float) {
     let u1 = sampler.sample(0.0..1.0);
     let u2 = sampler.sample(0.0..1.0);
 
@@ -79,7 +83,7 @@ pub fn cosine_sample_hemisphere(n: Vector3, sampler: &mut dyn Sampler) -> Vector
     let (nx, ny, nz) = orthonormal_basis(n);
 
     // Compute the coordinates in this new orthonormal basis relative to the normal vector nz
-    let z = f64::max(0.0, 1.0 - x * x - y * y).sqrt();
+    let z = Float::max(0.0, 1.0 - x * x - y * y).sqrt();
 
     nx * x + ny * y + nz * z
 }
@@ -103,7 +107,7 @@ pub fn same_hemisphere(n: Vector3, v1: Vector3, v2: Vector3) -> bool {
     v1.dot(n).is_sign_positive() == v2.dot(n).is_sign_positive()
 }
 
-pub fn abs_cos_theta(n: Vector3, v: Vector3) -> f64 {
+pub fn abs_cos_theta(n: Vector3, v: Vector3) -> Float {
     n.norm().dot(v.norm()).abs()
 }
 
@@ -111,12 +115,12 @@ pub fn uniform_sample_sphere(sampler: &mut dyn Sampler) -> Vector3 {
     let u1 = sampler.sample(0.0..1.0);
     let u2 = sampler.sample(0.0..1.0);
     let z = 1.0 - 2.0 * u1;
-    let r = f64::max(0.0, 1.0 - z * z).sqrt();
+    let r = Float::max(0.0, 1.0 - z * z).sqrt();
     let phi = 2.0 * PI * u2;
     Vector3::new(r * phi.cos(), r * phi.sin(), z)
 }
 
-pub fn equals(a: f64, b: f64, tolerance: f64) -> bool {
+pub fn equals(a: Float, b: Float, tolerance: Float) -> bool {
     (a - b).abs() < tolerance
 }
 
@@ -124,15 +128,15 @@ pub fn reflect(d: Vector3, n: Vector3) -> Vector3 {
     -d + (2.0 * d.dot(n) * n)
 }
 
-pub fn gaussian(x: f64, sigma: f64) -> f64 {
-    f64::exp(-(x * x) / (2.0 * sigma * sigma))
+pub fn gaussian(x: Float, sigma: Float) -> Float {
+    Float::exp(-(x * x) / (2.0 * sigma * sigma))
 }
 
-pub fn safe_sqrt(x: f64) -> f64 {
-    f64::max(0.0, x).sqrt()
+pub fn safe_sqrt(x: Float) -> Float {
+    Float::max(0.0, x).sqrt()
 }
 
-pub fn refract(wi: Vector3, mut n: Vector3, mut eta: f64) -> Option<Vector3> {
+pub fn refract(wi: Vector3, mut n: Vector3, mut eta: Float) -> Option<Vector3> {
     let mut cos_theta_i = cos_theta(n, wi);
 
     if cos_theta_i < 0.0 {
@@ -141,7 +145,7 @@ pub fn refract(wi: Vector3, mut n: Vector3, mut eta: f64) -> Option<Vector3> {
         n = -n;
     }
 
-    let sin2_theta_i = f64::max(0.0, 1.0 - sqr(cos_theta_i));
+    let sin2_theta_i = Float::max(0.0, 1.0 - sqr(cos_theta_i));
     let sin2_theta_t = sin2_theta_i / sqr(eta);
     if sin2_theta_t >= 1.0 {
         return None;
@@ -154,15 +158,15 @@ pub fn refract(wi: Vector3, mut n: Vector3, mut eta: f64) -> Option<Vector3> {
     Some(wt)
 }
 
-pub fn sqr(x: f64) -> f64 {
+pub fn sqr(x: Float) -> Float {
     x * x
 }
 
-pub fn cos_theta(a: Vector3, b: Vector3) -> f64 {
+pub fn cos_theta(a: Vector3, b: Vector3) -> Float {
     a.norm().dot(b.norm())
 }
 
-pub fn fresnel_dielectric(mut cos_theta_i: f64, mut eta: f64) -> f64 {
+pub fn fresnel_dielectric(mut cos_theta_i: Float, mut eta: Float) -> Float {
     cos_theta_i = cos_theta_i.clamp(-1.0, 1.0);
 
     if cos_theta_i < 0.0 {
@@ -185,14 +189,40 @@ pub fn fresnel_dielectric(mut cos_theta_i: f64, mut eta: f64) -> f64 {
     (sqr(r_parallel) + sqr(r_perpendicular)) / 2.0
 }
 
+// The Fresnel reflectance of a conductor with the complex index of
+// refraction eta + ik, averaged over both polarizations.
+pub fn fresnel_conductor(cos_theta_i: Float, eta: Float, k: Float) -> Float {
+    let cos2_theta_i = sqr(cos_theta_i.abs().min(1.0));
+    let sin2_theta_i = 1.0 - cos2_theta_i;
+    let eta2 = sqr(eta);
+    let k2 = sqr(k);
+
+    let t0 = eta2 - k2 - sin2_theta_i;
+    let a2_plus_b2 = safe_sqrt(sqr(t0) + 4.0 * eta2 * k2);
+    let t1 = a2_plus_b2 + cos2_theta_i;
+    let a = safe_sqrt(0.5 * (a2_plus_b2 + t0));
+    let t2 = 2.0 * cos_theta_i.abs().min(1.0) * a;
+    let r_perpendicular = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2_theta_i * a2_plus_b2 + sqr(sin2_theta_i);
+    let t4 = t2 * sin2_theta_i;
+    let r_parallel = r_perpendicular * (t3 - t4) / (t3 + t4);
+
+    (r_parallel + r_perpendicular) / 2.0
+}
+
 #[cfg(test)]
 mod tests {
     use super::{
         concentric_sample_disk, cosine_sample_hemisphere, direction_to_area, erf_inv,
-        geometry_term, orthonormal_basis, reflect, refract,
+        fresnel_conductor, fresnel_dielectric, geometry_term, orthonormal_basis, reflect, refract,
+    };
+    use crate::{
+        approx::{loosen, ApproxEq},
+        sampler::test::MockSampler,
+        types::{consts::PI, Float},
+        vector::Vector3,
     };
-    use crate::{approx::ApproxEq, sampler::test::MockSampler, vector::Vector3};
-    use std::f64::consts::PI;
 
     #[test]
     fn test_orthonormal_basis() {
@@ -236,9 +266,9 @@ mod tests {
     fn test_direction_to_area() {
         let d = Vector3::new(10.0, 0.0, 0.0);
         let angle = PI / 4.0;
-        let n = Vector3::new(-f64::cos(angle), f64::sin(angle), 0.0).norm();
+        let n = Vector3::new(-Float::cos(angle), Float::sin(angle), 0.0).norm();
         let a = direction_to_area(d, n);
-        let e = (f64::cos(angle) / (d.len() * d.len())).abs();
+        let e = (Float::cos(angle) / (d.len() * d.len())).abs();
         assert!(a - e < 1e-8);
     }
 
@@ -247,10 +277,10 @@ mod tests {
         let d = Vector3::new(10.0, 0.0, 0.0);
         let angle1 = PI / 4.0;
         let angle2 = PI / 3.0;
-        let n1 = Vector3::new(f64::cos(angle1), -f64::sin(angle1), 0.0).norm();
-        let n2 = Vector3::new(-f64::cos(angle2), f64::sin(angle2), 0.0).norm();
+        let n1 = Vector3::new(Float::cos(angle1), -Float::sin(angle1), 0.0).norm();
+        let n2 = Vector3::new(-Float::cos(angle2), Float::sin(angle2), 0.0).norm();
         let g = geometry_term(d, n1, n2);
-        let e = ((f64::cos(angle1) * f64::cos(angle2)) / (d.len() * d.len())).abs();
+        let e = ((Float::cos(angle1) * Float::cos(angle2)) / (d.len() * d.len())).abs();
         assert!(g - e < 1e-8);
     }
 
@@ -260,7 +290,7 @@ mod tests {
         sampler.add(0.2);
         sampler.add(0.7);
         let (x, y) = concentric_sample_disk(&mut sampler);
-        assert!(f64::sqrt(x * x + y * y) < 1.0);
+        assert!(Float::sqrt(x * x + y * y) < 1.0);
     }
 
     #[test]
@@ -296,20 +326,35 @@ mod tests {
 
         eta = 1.6;
         let mut theta_i = 30.0 * PI / 180.0;
-        wi = Vector3::new(-f64::sin(theta_i), f64::cos(theta_i), 0.0);
+        wi = Vector3::new(-Float::sin(theta_i), Float::cos(theta_i), 0.0);
         wt = refract(wi, n, eta);
         assert!(wt.is_some());
         let theta_t = 18.20996 * PI / 180.0;
-        expected = Vector3::new(f64::sin(theta_t), -f64::cos(theta_t), 0.0);
+        expected = Vector3::new(Float::sin(theta_t), -Float::cos(theta_t), 0.0);
         assert!(wt.unwrap().approx_eq(expected, 1e-6));
 
         eta = 1.8;
         theta_i = 20.0 * PI / 180.0;
-        wi = Vector3::new(-f64::sin(theta_i), f64::cos(theta_i), 0.0);
+        wi = Vector3::new(-Float::sin(theta_i), Float::cos(theta_i), 0.0);
         wt = refract(wi, n, eta);
         assert!(wt.is_some());
         let theta_t = 10.95344 * PI / 180.0;
-        expected = Vector3::new(f64::sin(theta_t), -f64::cos(theta_t), 0.0);
+        expected = Vector3::new(Float::sin(theta_t), -Float::cos(theta_t), 0.0);
         assert!(wt.unwrap().approx_eq(expected, 1e-6));
     }
+
+    #[test]
+    fn test_fresnel_conductor() {
+        // At normal incidence, the reflectance has a closed form.
+        let (eta, k) = (0.2, 3.9);
+        let expected = ((eta - 1.0) * (eta - 1.0) + k * k) / ((eta + 1.0) * (eta + 1.0) + k * k);
+        assert!((fresnel_conductor(1.0, eta, k) - expected).abs() < loosen(1e-12));
+        // Without absorption, a conductor is a dielectric.
+        for cos_theta in [0.1, 0.5, 0.9] {
+            let r = fresnel_conductor(cos_theta, 1.5, 0.0);
+            assert!((r - fresnel_dielectric(cos_theta, 1.5)).abs() < loosen(1e-12));
+        }
+        // At grazing incidence, everything is reflected.
+        assert!((fresnel_conductor(0.0, eta, k) - 1.0).abs() < loosen(1e-12));
+    }
 }
@@ -8,15 +8,16 @@ use serde::Deserialize;
 use serde::Serialize;
 
 use crate::approx::ApproxEq;
+use crate::types::Float;
 use crate::util;
 
 pub type Point3 = Vector3;
 
 #[derive(Copy, Clone, Debug)]
 pub struct Vector3 {
-    pub x: f64,
-    pub y: f64,
-    pub z: f64,
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
 }
 
 impl Vector3 {
@@ -28,11 +29,11 @@ impl Vector3 {
         }
     }
 
-    pub fn new(x: f64, y: f64, z: f64) -> Vector3 {
+    pub fn new(x: Float, y: Float, z: Float) -> Vector3 {
         Vector3 { x, y, z }
     }
 
-    pub fn dot(&self, rhs: Vector3) -> f64 {
+    pub fn dot(&self, rhs: Vector3) -> Float {
         self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
     }
 
@@ -45,7 +46,7 @@ impl Vector3 {
         }
     }
 
-    pub fn len(&self) -> f64 {
+    pub fn len(&self) -> Float {
         (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
     }
 
@@ -86,10 +87,10 @@ impl Sub<Vector3> for Vector3 {
     }
 }
 
-impl Mul<f64> for Vector3 {
+impl Mul<Float> for Vector3 {
     type Output = Vector3;
 
-    fn mul(self, rhs: f64) -> Vector3 {
+    fn mul(self, rhs: Float) -> Vector3 {
         Vector3 {
             x: self.x * rhs,
             y: self.y * rhs,
@@ -98,7 +99,7 @@ impl Mul<f64> for Vector3 {
     }
 }
 
-impl Mul<Vector3> for f64 {
+impl Mul<Vector3> for Float {
     type Output = Vector3;
 
     fn mul(self, rhs: Vector3) -> Self::Output {
@@ -110,10 +111,10 @@ impl Mul<Vector3> for f64 {
     }
 }
 
-impl Div<f64> for Vector3 {
+impl Div<Float> for Vector3 {
     type Output = Vector3;
 
-    fn div(self, rhs: f64) -> Vector3 {
+    fn div(self, rhs: Float) -> Vector3 {
         Vector3 {
             x: self.x / rhs,
             y: self.y / rhs,
@@ -141,7 +142,7 @@ impl PartialEq for Vector3 {
 }
 
 impl ApproxEq for Vector3 {
-    fn approx_eq(&self, other: Self, tolerance: f64) -> bool {
+    fn approx_eq(&self, other: Self, tolerance: Float) -> bool {
         util::equals(self.x, other.x, tolerance)
             && util::equals(self.y, other.y, tolerance)
             && util::equals(self.z, other.z, tolerance)
@@ -152,17 +153,17 @@ pub type Point3Config = Vector3Config;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Vector3Config {
-    pub x: f64,
-    pub y: f64,
-    pub z: f64,
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
 }
 
 pub type Point2 = Vector2;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Vector2 {
-    pub x: f64,
-    pub y: f64,
+    pub x: Float,
+    pub y: Float,
 }
 
 impl Vector2 {
@@ -173,7 +174,7 @@ impl Vector2 {
         }
     }
 
-    pub fn new(x: f64, y: f64) -> Vector2 {
+    pub fn new(x: Float, y: Float) -> Vector2 {
         Vector2 { x, y }
     }
 }
@@ -189,15 +190,228 @@ impl Sub<Vector2> for Vector2 {
     }
 }
 
+impl ApproxEq for Vector2 {
+    fn approx_eq(&self, other: Self, tolerance: Float) -> bool {
+        util::equals(self.x, other.x, tolerance) && util::equals(self.y, other.y, tolerance)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Vector2Config {
-    pub x: f64,
-    pub y: f64,
+    pub x: Float,
+    pub y: Float,
+}
+
+// An affine or projective transformation of homogeneous coordinates, stored
+// in row-major order and applied to column vectors.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[Float; 4]; 4],
+}
+
+impl Matrix4 {
+    pub fn new(m: [[Float; 4]; 4]) -> Matrix4 {
+        Matrix4 { m }
+    }
+
+    pub fn identity() -> Matrix4 {
+        Matrix4::new([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn translation(offset: Vector3) -> Matrix4 {
+        Matrix4::new([
+            [1.0, 0.0, 0.0, offset.x],
+            [0.0, 1.0, 0.0, offset.y],
+            [0.0, 0.0, 1.0, offset.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn scaling(factors: Vector3) -> Matrix4 {
+        Matrix4::new([
+            [factors.x, 0.0, 0.0, 0.0],
+            [0.0, factors.y, 0.0, 0.0],
+            [0.0, 0.0, factors.z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    // The rotation by a unit quaternion.
+    pub fn rotation(q: Quaternion) -> Matrix4 {
+        let Quaternion { w, x, y, z } = q;
+        Matrix4::new([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+                0.0,
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+                0.0,
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    // The inverse by Gauss-Jordan elimination with partial pivoting, if the
+    // matrix is not singular.
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let mut a = self.m;
+        let mut inverse = Matrix4::identity().m;
+        for column in 0..4 {
+            let pivot = (column..4)
+                .max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))
+                .unwrap();
+            if a[pivot][column] == 0.0 {
+                return None;
+            }
+            a.swap(column, pivot);
+            inverse.swap(column, pivot);
+            let scale = 1.0 / a[column][column];
+            for j in 0..4 {
+                a[column][j] *= scale;
+                inverse[column][j] *= scale;
+            }
+            for i in 0..4 {
+                if i != column {
+                    let factor = a[i][column];
+                    for j in 0..4 {
+                        a[i][j] -= factor * a[column][j];
+                        inverse[i][j] -= factor * inverse[column][j];
+                    }
+                }
+            }
+        }
+        Some(Matrix4::new(inverse))
+    }
+
+    pub fn transform_point(&self, p: Point3) -> Point3 {
+        let m = &self.m;
+        let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        if w == 1.0 {
+            Point3::new(x, y, z)
+        } else {
+            Point3::new(x, y, z) / w
+        }
+    }
+
+    // Vectors are differences of points, so they are not translated.
+    pub fn transform_vector(&self, v: Vector3) -> Vector3 {
+        let m = &self.m;
+        Vector3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    // Normals are transformed by the inverse transpose of the linear part,
+    // which keeps them perpendicular to transformed tangents under
+    // non-uniform scaling and shearing. The inverse transpose is the
+    // cofactor matrix divided by the determinant, and only the sign of the
+    // determinant matters to the direction, so no inverse is needed. The
+    // result is not normalized.
+    pub fn transform_normal(&self, n: Vector3) -> Vector3 {
+        let m = &self.m;
+        let c = |i: usize, j: usize| {
+            let (i1, i2) = ((i + 1) % 3, (i + 2) % 3);
+            let (j1, j2) = ((j + 1) % 3, (j + 2) % 3);
+            m[i1][j1] * m[i2][j2] - m[i1][j2] * m[i2][j1]
+        };
+        let determinant = m[0][0] * c(0, 0) + m[0][1] * c(0, 1) + m[0][2] * c(0, 2);
+        Vector3::new(
+            c(0, 0) * n.x + c(0, 1) * n.y + c(0, 2) * n.z,
+            c(1, 0) * n.x + c(1, 1) * n.y + c(1, 2) * n.z,
+            c(2, 0) * n.x + c(2, 1) * n.y + c(2, 2) * n.z,
+        ) / determinant
+    }
+}
+
+impl Mul<Matrix4> for Matrix4 {
+    type Output = Matrix4;
+
+    // The product applies `rhs` first.
+    fn mul(self, rhs: Matrix4) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Matrix4::new(m)
+    }
+}
+
+impl ApproxEq for Matrix4 {
+    fn approx_eq(&self, other: Self, tolerance: Float) -> bool {
+        self.m
+            .iter()
+            .flatten()
+            .zip(other.m.iter().flatten())
+            .all(|(a, b)| util::equals(*a, *b, tolerance))
+    }
+}
+
+// A rotation, as a unit quaternion w + xi + yj + zk.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Quaternion {
+    pub w: Float,
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
+}
+
+impl Quaternion {
+    pub fn new(w: Float, x: Float, y: Float, z: Float) -> Quaternion {
+        Quaternion { w, x, y, z }
+    }
+
+    // The rotation by the angle, in radians, counterclockwise about the axis.
+    pub fn from_axis_angle(axis: Vector3, angle: Float) -> Quaternion {
+        let a = axis.norm() * (angle / 2.0).sin();
+        Quaternion::new((angle / 2.0).cos(), a.x, a.y, a.z)
+    }
+}
+
+impl Mul<Quaternion> for Quaternion {
+    type Output = Quaternion;
+
+    // The product applies `rhs` first.
+    fn mul(self, rhs: Quaternion) -> Quaternion {
+        Quaternion::new(
+            self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+            self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+        )
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::vector::Vector3;
+    use crate::{
+        approx::{loosen, ApproxEq},
+        types::{consts::PI, Float},
+        util,
+        vector::{Matrix4, Quaternion, Vector3},
+    };
 
     use super::Vector3Config;
 
@@ -230,7 +444,7 @@ mod tests {
     #[test]
     fn test_norm() {
         let v1 = Vector3::new(1.0, 2.0, 3.0);
-        let l1 = f64::sqrt(14.0);
+        let l1 = Float::sqrt(14.0);
         assert_eq!(v1.norm(), Vector3::new(1.0 / l1, 2.0 / l1, 3.0 / l1));
         let v2 = Vector3::new(0.0, 0.0, 2.0);
         assert_eq!(v2.norm(), Vector3::new(0.0, 0.0, 1.0));
@@ -241,7 +455,7 @@ mod tests {
     #[test]
     fn test_len() {
         let v1 = Vector3::new(1.0, 2.0, 3.0);
-        assert_eq!(v1.len(), f64::sqrt(14.0));
+        assert_eq!(v1.len(), Float::sqrt(14.0));
     }
 
     #[test]
@@ -303,4 +517,54 @@ mod tests {
         assert!(v1 == v1);
         assert!(v1 != v2);
     }
+
+    #[test]
+    fn test_matrix_transform() {
+        let tolerance = loosen(1e-12);
+        let q = Quaternion::from_axis_angle(Vector3::new(0.0, 0.0, 1.0), PI / 2.0);
+        let m = Matrix4::translation(Vector3::new(1.0, 2.0, 3.0)) * Matrix4::rotation(q);
+        let p = m.transform_point(Vector3::new(1.0, 0.0, 0.0));
+        assert!(p.approx_eq(Vector3::new(1.0, 3.0, 3.0), tolerance));
+        let v = m.transform_vector(Vector3::new(1.0, 0.0, 0.0));
+        assert!(v.approx_eq(Vector3::new(0.0, 1.0, 0.0), tolerance));
+        let inverse = m.inverse().unwrap();
+        assert!((inverse * m).approx_eq(Matrix4::identity(), tolerance));
+        assert!(Matrix4::scaling(Vector3::new(1.0, 0.0, 1.0))
+            .inverse()
+            .is_none());
+    }
+
+    #[test]
+    fn test_matrix_transform_normal() {
+        // A plane sheared and scaled non-uniformly: the transformed normal
+        // must stay perpendicular to the transformed tangents.
+        let tolerance = loosen(1e-12);
+        let mut m = Matrix4::scaling(Vector3::new(2.0, 0.5, -1.0));
+        m.m[0][1] = 0.7;
+        let normal = Vector3::new(1.0, 1.0, 0.0).norm();
+        let t1 = Vector3::new(1.0, -1.0, 0.0);
+        let t2 = Vector3::new(0.0, 0.0, 1.0);
+        let n = m.transform_normal(normal);
+        assert!(util::equals(n.dot(m.transform_vector(t1)), 0.0, tolerance));
+        assert!(util::equals(n.dot(m.transform_vector(t2)), 0.0, tolerance));
+        // Orientation is preserved, even where the determinant is negative.
+        let p = m.transform_vector(normal);
+        assert!(n.dot(p) > 0.0);
+    }
+
+    #[test]
+    fn test_quaternion_mul() {
+        let tolerance = loosen(1e-12);
+        let x = Vector3::new(1.0, 0.0, 0.0);
+        let z = Vector3::new(0.0, 0.0, 1.0);
+        let q1 = Quaternion::from_axis_angle(z, PI / 6.0);
+        let q2 = Quaternion::from_axis_angle(z, PI / 3.0);
+        let m = Matrix4::rotation(q2 * q1);
+        assert!(m
+            .transform_vector(x)
+            .approx_eq(Vector3::new(0.0, 1.0, 0.0), tolerance));
+        let q3 = Quaternion::from_axis_angle(x, PI / 2.0);
+        let expected = Matrix4::rotation(q3) * Matrix4::rotation(q1);
+        assert!(Matrix4::rotation(q3 * q1).approx_eq(expected, tolerance));
+    }
 }
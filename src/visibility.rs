@@ -0,0 +1,129 @@
+use crate::{
+    geometry::Geometry,
+    interaction::Interaction,
+    ray::{Ray, RayKind},
+    scene::Scene,
+    spectrum::Spectrum,
+    types::Float,
+    vector::Point3,
+};
+
+// How far, relative to the length of a segment, its first hit may be from its
+// end and still be taken to be the end itself.
+const DISTANCE_TOLERANCE: Float = 1e-3;
+
+// Whether two points see each other, for connecting them. A ray is cast from
+// the first toward the second, which is visible if the first thing hit is
+// what is there, found where it is: the hit must be the expected camera,
+// light or object, and not a nearer part of it, such as the front of a
+// sphere whose back was sampled.
+pub struct VisibilityTester<'a> {
+    scene: &'a Scene,
+    from: Geometry,
+    to: Point3,
+    kind: RayKind,
+}
+
+impl<'a> VisibilityTester<'a> {
+    pub fn new(
+        scene: &'a Scene,
+        from: Geometry,
+        to: Point3,
+        kind: RayKind,
+    ) -> VisibilityTester<'a> {
+        VisibilityTester {
+            scene,
+            from,
+            to,
+            kind,
+        }
+    }
+
+    // The interaction at the second point, if it is visible and is the one
+    // with the given id.
+    pub fn test(&self, id: &str) -> Option<Interaction<'a>> {
+        let direction = self.to - self.from.point;
+        let distance = direction.len();
+        let interaction = self
+            .scene
+            .intersect(Ray::leaving(&self.from, direction), self.kind)?;
+        let tolerance = DISTANCE_TOLERANCE * distance.max(1.0);
+        let reached = (interaction.distance() - distance).abs() <= tolerance;
+        (reached && interaction.id() == id).then_some(interaction)
+    }
+
+    // The fraction of light carried along the segment, all of it when the
+    // second point is visible and none otherwise, as the space between
+    // surfaces is empty.
+    pub fn transmittance(&self, id: &str) -> Spectrum {
+        match self.test(id) {
+            Some(_) => Spectrum::fill(1.0),
+            None => Spectrum::black(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VisibilityTester;
+    use crate::{
+        geometry::Geometry,
+        light::LightOverrides,
+        ray::RayKind,
+        scene::{Scene, SceneConfig},
+        spectrum::Spectrum,
+        vector::{Point2, Point3, Vector3},
+    };
+
+    const SCENE: &str = "
+image: { width: 2, height: 2 }
+camera:
+  type: pinhole
+  origin: { x: 0.0, y: 0.0, z: 10.0 }
+  look_at: { x: 0.0, y: 0.0, z: 0.0 }
+  field_of_view: { value: 10.0, unit: degrees }
+lights:
+  - id: light
+    type: diffuse_area
+    shape: { type: sphere, center: { x: 0.0, y: 0.0, z: -10.0 }, radius: 1.0 }
+    spectrum: { r: 1.0, g: 1.0, b: 1.0 }
+objects:
+  - id: ball
+    type: geometric
+    casts_shadow: false
+    shape: { type: sphere, center: { x: 3.0, y: 0.0, z: 0.0 }, radius: 1.0 }
+    material:
+      type: matte
+      texture: { type: constant, spectrum: { r: 0.5, g: 0.5, b: 0.5 } }
+";
+
+    #[test]
+    fn test_visibility_tester() {
+        let scene: Scene = serde_yaml::from_str::<SceneConfig>(SCENE)
+            .unwrap()
+            .configure(None, &LightOverrides::default())
+            .unwrap();
+        let origin = Geometry {
+            point: Point3::new(0.0, 0.0, 0.0),
+            normal: Vector3::new(0.0, 0.0, 1.0),
+            direction: Vector3::new(0.0, 0.0, 1.0),
+            t: 0.0,
+            uv: Point2::new(0.0, 0.0),
+            dpdu: Vector3::new(0.0, 0.0, 0.0),
+            dpdv: Vector3::new(0.0, 0.0, 0.0),
+            surface: None,
+        };
+        let tester = |to, kind| VisibilityTester::new(&scene, origin, to, kind);
+        let front = tester(Point3::new(0.0, 0.0, -9.0), RayKind::Shadow);
+        assert!(front.test("light").is_some());
+        assert!(front.test("ball").is_none());
+        assert_eq!(front.transmittance("light"), Spectrum::fill(1.0));
+        // The back of the light is hidden by its front.
+        let back = tester(Point3::new(0.0, 0.0, -11.0), RayKind::Shadow);
+        assert!(back.test("light").is_none());
+        assert_eq!(back.transmittance("light"), Spectrum::black());
+        let ball = Point3::new(2.0, 0.0, 0.0);
+        assert!(tester(ball, RayKind::Indirect).test("ball").is_some());
+        assert!(tester(ball, RayKind::Shadow).test("ball").is_none());
+    }
+}
@@ -0,0 +1,269 @@
+use std::{
+    io::{self, Read, Write},
+    sync::mpsc,
+    thread,
+};
+
+// The minimum of the WebSocket protocol (RFC 6455) that a server needs to
+// push messages to a browser: the handshake, unmasked frames, and the masked
+// frames of the client, of which only the control frames mean anything.
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const VERSION: &str = "13";
+// Larger frames from the client are taken for a broken connection.
+const MAX_CLIENT_PAYLOAD: u64 = 1 << 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Opcode {
+    Continuation = 0,
+    Text = 1,
+    Binary = 2,
+    Close = 8,
+    Ping = 9,
+    Pong = 10,
+}
+
+impl Opcode {
+    fn from_bits(bits: u8) -> Option<Opcode> {
+        match bits {
+            0 => Some(Opcode::Continuation),
+            1 => Some(Opcode::Text),
+            2 => Some(Opcode::Binary),
+            8 => Some(Opcode::Close),
+            9 => Some(Opcode::Ping),
+            10 => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+}
+
+// Answers an upgrade request with the given Upgrade, Sec-WebSocket-Version
+// and Sec-WebSocket-Key headers, and returns whether the connection is now a
+// WebSocket. A request for another version of the protocol is refused with
+// the version spoken here, as the RFC requires.
+pub fn handshake(
+    mut stream: impl Write,
+    upgrade: Option<&str>,
+    version: Option<&str>,
+    key: &str,
+) -> io::Result<bool> {
+    if !upgrade.is_some_and(|upgrade| upgrade.trim().eq_ignore_ascii_case("websocket")) {
+        write!(
+            stream,
+            "HTTP/1.1 400 Bad Request\r\nConnection: close\r\n\r\n"
+        )?;
+    } else if version.map(str::trim) != Some(VERSION) {
+        write!(
+            stream,
+            "HTTP/1.1 426 Upgrade Required\r\nSec-WebSocket-Version: {}\r\n\
+             Connection: close\r\n\r\n",
+            VERSION
+        )?;
+    } else {
+        write!(
+            stream,
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            accept_key(key)
+        )?;
+        stream.flush()?;
+        return Ok(true);
+    }
+    stream.flush()?;
+    Ok(false)
+}
+
+// Reads the frames of the client on a thread of their own, so that a server
+// busy pushing its messages can answer pings and notice when the client goes
+// away: the channel disconnects once the connection closes or breaks.
+pub fn read_frames(mut stream: impl Read + Send + 'static) -> mpsc::Receiver<(Opcode, Vec<u8>)> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        while let Ok(frame) = read_frame(&mut stream) {
+            if sender.send(frame).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+// Reads a frame of the client, which the RFC requires to be masked.
+pub fn read_frame(mut stream: impl Read) -> io::Result<(Opcode, Vec<u8>)> {
+    let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header)?;
+    let opcode = Opcode::from_bits(header[0] & 0x0F).ok_or_else(|| invalid("unknown opcode"))?;
+    if header[1] & 0x80 == 0 {
+        return Err(invalid("unmasked client frame"));
+    }
+    let length = match header[1] & 0x7F {
+        126 => {
+            let mut length = [0u8; 2];
+            stream.read_exact(&mut length)?;
+            u16::from_be_bytes(length) as u64
+        }
+        127 => {
+            let mut length = [0u8; 8];
+            stream.read_exact(&mut length)?;
+            u64::from_be_bytes(length)
+        }
+        length => length as u64,
+    };
+    if length > MAX_CLIENT_PAYLOAD {
+        return Err(invalid("client frame too large"));
+    }
+    let mut mask = [0u8; 4];
+    stream.read_exact(&mut mask)?;
+    let mut payload = vec![0u8; length as usize];
+    stream.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok((opcode, payload))
+}
+
+pub fn write_frame(mut stream: impl Write, opcode: Opcode, payload: &[u8]) -> io::Result<()> {
+    let mut header = vec![0x80 | opcode as u8];
+    match payload.len() {
+        length @ 0..=125 => header.push(length as u8),
+        length @ 126..=0xFFFF => {
+            header.push(126);
+            header.extend((length as u16).to_be_bytes());
+        }
+        length => {
+            header.push(127);
+            header.extend((length as u64).to_be_bytes());
+        }
+    }
+    stream.write_all(&header)?;
+    stream.write_all(payload)?;
+    stream.flush()
+}
+
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key.trim(), GUID).as_bytes()))
+}
+
+fn sha1(message: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut data = message.to_vec();
+    data.push(0x80);
+    while data.len() % 64 != 56 {
+        data.push(0);
+    }
+    data.extend((message.len() as u64 * 8).to_be_bytes());
+    for block in data.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            (e, d, c, b, a) = (d, c, b.rotate_left(30), a, t);
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+    let mut digest = [0; 20];
+    for (bytes, h) in digest.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{accept_key, base64, handshake, read_frame, write_frame, Opcode};
+
+    #[test]
+    fn test_accept_key() {
+        // The example of RFC 6455.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"a"), "YQ==");
+    }
+
+    #[test]
+    fn test_write_frame() {
+        let mut frame = Vec::new();
+        write_frame(&mut frame, Opcode::Text, b"hi").unwrap();
+        assert_eq!(frame, [0x81, 2, b'h', b'i']);
+        let mut frame = Vec::new();
+        write_frame(&mut frame, Opcode::Binary, &[0; 300]).unwrap();
+        assert_eq!(frame[..4], [0x82, 126, 1, 44]);
+        assert_eq!(frame.len(), 304);
+    }
+
+    #[test]
+    fn test_read_frame() {
+        // The masked "Hello" of RFC 6455.
+        let frame = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        let (opcode, payload) = read_frame(&frame[..]).unwrap();
+        assert_eq!(opcode, Opcode::Text);
+        assert_eq!(payload, b"Hello");
+        let (opcode, payload) = read_frame(&[0x89, 0x80, 1, 2, 3, 4][..]).unwrap();
+        assert_eq!(opcode, Opcode::Ping);
+        assert!(payload.is_empty());
+        assert!(read_frame(&[0x81, 0x05, b'H', b'e', b'l', b'l', b'o'][..]).is_err());
+        assert!(read_frame(&[0x88][..]).is_err());
+    }
+
+    #[test]
+    fn test_handshake() {
+        let key = "dGhlIHNhbXBsZSBub25jZQ==";
+        let mut response = Vec::new();
+        assert!(handshake(&mut response, Some("WebSocket"), Some("13"), key).unwrap());
+        assert!(response.starts_with(b"HTTP/1.1 101"));
+        let mut response = Vec::new();
+        assert!(!handshake(&mut response, None, Some("13"), key).unwrap());
+        assert!(response.starts_with(b"HTTP/1.1 400"));
+        let mut response = Vec::new();
+        assert!(!handshake(&mut response, Some("websocket"), Some("8"), key).unwrap());
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 426"));
+        assert!(response.contains("Sec-WebSocket-Version: 13"));
+    }
+}